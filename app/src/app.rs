@@ -1,16 +1,23 @@
+use common::command::DeviceCommand;
 use common::config::*;
-use egui::{self, Button, Color32, FontFamily, FontId, CollapsingHeader};
+use egui::{self, Button, Color32, FontFamily, FontId, CollapsingHeader, Sense, Vec2};
 use ractor_wormhole::ractor::ActorRef;
 use ractor_wormhole::ractor::thread_local::ThreadLocalActorSpawner;
+use std::collections::VecDeque;
+use std::ops::RangeInclusive;
 use std::sync::{Arc, Mutex};
 
+use web_time::{Instant, Duration};
 
-#[cfg(target_arch = "wasm32")]
-use wasm_bindgen_futures::spawn_local;
-#[cfg(target_arch = "wasm32")]
-use crate::web_bluetooth::Bluetooth;
+use common::dsp::{FFT_LENGTH, SPECTRUM_COLUMNS, channel_led_color, levels_from_bytes};
+use common::status::{DeviceStatus, Telemetry};
+use common::transfer::{self, ConfigControl, MAX_CHUNKED_CONFIG_SIZE};
 
-use web_time::{Instant, Duration};
+use crate::config_file;
+use crate::history::ConfigHistory;
+use crate::preview::MatrixPreview;
+use crate::transport::{self, ConfigTransport, DeviceInfo};
+use crate::user_presets::{self, UserPreset};
 
 // -----------------
 // Shared State Types
@@ -20,23 +27,159 @@ use web_time::{Instant, Duration};
 struct AppState {
     config: Option<AppConfig>,
     last_status: String,
+    /// Every status since the app started, the newest [`STATUS_LOG_LINES`] are kept. Set both
+    /// through [`AppState::set_status`].
+    status_log: VecDeque<String>,
+    /// When the app started, the status log counts from here
+    started: Instant,
     busy: bool,
     conn: ConnectionStatus,
+    /// Connecting to a device from an earlier session is under way, cleared to cancel it
+    auto_connecting: bool,
+    /// Why Bluetooth can't be used here, `None` if it can (or it wasn't checked yet)
+    bluetooth_unavailable: Option<String>,
+    /// Edits are written to the device on their own, [`AUTO_WRITE_DELAY`] after the last one.
+    /// Switched off by the handler when a write fails.
+    auto_write: bool,
     last_update: Option<Instant>,
+    history: ConfigHistory,
+    /// The config last successfully read from or written to the device
+    device_config: Option<AppConfig>,
+    /// The device notified a config that differs from unsaved local edits, see
+    /// [`AppState::device_config_notified`]
+    device_config_changed: bool,
+    /// Read after connecting, `None` if the firmware doesn't have the Device Information Service
+    device_info: Option<DeviceInfo>,
+    /// Read after connecting, `None` if the firmware can't be renamed
+    device_name: Option<String>,
+    /// Whether the LEDs are switched on, `None` if the firmware doesn't have the switch
+    device_enabled: Option<bool>,
+    /// Presets built into the firmware, empty if it doesn't have the preset service
+    device_presets: Vec<String>,
+    /// Names of the preset slots on the device, empty for an unused slot. Empty if the firmware
+    /// doesn't have slots.
+    device_slots: Vec<String>,
+    /// The config versions the device accepts, `None` if the firmware doesn't say
+    device_config_versions: Option<RangeInclusive<u32>>,
+    /// The largest config the device accepts in bytes, `None` if the firmware doesn't say, see
+    /// [`AppState::max_config_size`]
+    device_max_config_size: Option<usize>,
+    /// Result of the last scan, only used on native
+    discovered: Vec<DiscoveredDevice>,
+    /// Channel levels streamed by the device, empty if it doesn't send them
+    device_levels: Vec<f32>,
+    /// When the device last notified the alive counter, `None` if it doesn't send it
+    last_alive: Option<Instant>,
+    /// Last signal strength notified by the device in dBm, `None` if it doesn't send it
+    device_rssi: Option<i8>,
+    /// Last status notified by the device, `None` if it doesn't send it
+    device_status: Option<DeviceStatus>,
+    /// Last audio processing timing notified by the device, `None` if it doesn't send it or no
+    /// audio is processed
+    device_telemetry: Option<Telemetry>,
+    /// Log lines streamed by the device, the newest [`DEVICE_LOG_LINES`] are kept
+    device_log: VecDeque<String>,
+    /// Log level last sent to the device, see [`ConfigTransport::set_log_level`]
+    device_log_level: u8,
+    /// Channel copied in the pattern editor, kept across pattern types
+    channel_clipboard: Option<ChannelConfig>,
 }
 
+/// How many device log lines the log console keeps
+const DEVICE_LOG_LINES: usize = 500;
+
+/// How many status changes the status log keeps
+const STATUS_LOG_LINES: usize = 200;
+
+/// The level the firmware boots with (info)
+const DEFAULT_DEVICE_LOG_LEVEL: u8 = 3;
+
 impl Default for AppState {
     fn default() -> Self {
         Self {
             config: None,
             last_status: "Idle".to_owned(),
+            status_log: VecDeque::new(),
+            started: Instant::now(),
             busy: false,
             conn: ConnectionStatus::Disconnected,
+            auto_connecting: false,
+            bluetooth_unavailable: None,
+            auto_write: false,
             last_update: None,
+            history: ConfigHistory::default(),
+            device_config: None,
+            device_config_changed: false,
+            device_info: None,
+            device_name: None,
+            device_enabled: None,
+            device_presets: Vec::new(),
+            device_slots: Vec::new(),
+            device_config_versions: None,
+            device_max_config_size: None,
+            discovered: Vec::new(),
+            device_levels: Vec::new(),
+            last_alive: None,
+            device_status: None,
+            device_telemetry: None,
+            device_rssi: None,
+            device_log: VecDeque::new(),
+            device_log_level: DEFAULT_DEVICE_LOG_LEVEL,
+            channel_clipboard: None,
         }
     }
 }
 
+impl AppState {
+    /// Show `status`, and keep it in the status log so it doesn't get lost when the next one comes
+    fn set_status(&mut self, status: String) {
+        if self.status_log.len() >= STATUS_LOG_LINES {
+            self.status_log.pop_front();
+        }
+        let t = self.started.elapsed().as_secs();
+        self.status_log.push_back(format!("{:02}:{:02}:{:02} {status}", t / 3600, t / 60 % 60, t % 60));
+        self.last_status = status;
+    }
+
+    /// The largest config that can be written. Firmware that doesn't say predates chunked
+    /// transfers and the larger config characteristic, so it only takes a single short write.
+    fn max_config_size(&self) -> usize {
+        self.device_max_config_size.unwrap_or(LEGACY_MAX_CONFIG_SIZE)
+    }
+
+    /// True if the config in the editor differs from the one on the device
+    fn is_dirty(&self) -> bool {
+        match (&self.config, &self.device_config) {
+            (Some(cfg), Some(device_cfg)) => cfg != device_cfg,
+            _ => false,
+        }
+    }
+    
+    /// The device sent its current config. Taken over into the editor, unless that would throw
+    /// away local edits, in which case the user is asked instead.
+    fn device_config_notified(&mut self, cfg: AppConfig) {
+        if self.device_config.as_ref() == Some(&cfg) {
+            return;
+        }
+        // either nothing to lose, or the confirmation of our own write
+        if !self.is_dirty() || self.config.as_ref() == Some(&cfg) {
+            self.config = Some(cfg.clone());
+        } else {
+            self.device_config_changed = true;
+        }
+        self.device_config = Some(cfg);
+        self.last_update = Some(Instant::now());
+    }
+}
+
+/// A device found by scanning, shown for selection when the platform has no device chooser
+#[derive(Clone, Debug)]
+pub struct DiscoveredDevice {
+    pub id: String,
+    pub name: String,
+    pub rssi: Option<i16>,
+}
+
 #[derive(Clone, Debug)]
 pub enum ConnectionStatus {
     Disconnected,
@@ -52,16 +195,44 @@ pub enum ConnectionStatus {
 #[derive(Debug)]
 enum HandlerMessage {
     Connect,
+    /// Connect to a device from [`AppState::discovered`]
+    ConnectTo(String),
     Disconnect,
     Reconnect,
+    /// Find out whether Bluetooth can be used, see [`AppState::bluetooth_unavailable`]. Sent once
+    /// on startup, before [`HandlerMessage::AutoConnect`].
+    CheckBluetooth,
+    /// Connect to a device from an earlier session if there is one, without asking the user.
+    /// Sent once on startup.
+    AutoConnect,
     Reload,
     Write(AppConfig),
+    /// Write the config once no newer one was scheduled for [`AUTO_WRITE_DELAY`], see
+    /// [`AppState::auto_write`]
+    ScheduleWrite(AppConfig),
+    /// Sent to itself [`AUTO_WRITE_DELAY`] after a [`HandlerMessage::ScheduleWrite`]
+    AutoWrite(u32),
+    /// Erase the config stored on the device and switch it back to the default
+    FactoryReset,
+    SetLogLevel(u8),
+    Command(DeviceCommand),
+    Rename(String),
+    /// Switch the LEDs on or off
+    SetEnabled(bool),
+    /// Apply one of [`AppState::device_presets`] on the device
+    ApplyPreset(u8),
+    /// Save the config the device runs into one of [`AppState::device_slots`], under a name
+    SaveSlot(u8, String),
+    /// Apply the config of one of [`AppState::device_slots`] on the device
+    SelectSlot(u8),
     SetBusy(bool),
     SetStatus(String),
     SetConnected(AppConfig),
     SetBroken(AppConfig),
     SetConfig(AppConfig),
     Heartbeat,
+    /// Sent to itself every [`HEARTBEAT_INTERVAL`] (or [`ALIVE_INTERVAL`]) while the heartbeat is running
+    HeartbeatTick(u32),
     StopHeartbeat,
 }
 
@@ -69,18 +240,48 @@ enum HandlerMessage {
 // Handler Implementation
 // -----------------
 
-#[cfg(target_arch = "wasm32")]
+/// How often the connection is checked while connected
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often the device notifies the alive counter, see `custom_task` in the firmware
+const ALIVE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The connection is considered dropped after missing 3 alive notifications in a row
+const ALIVE_TIMEOUT: Duration = Duration::from_secs(3 * 2 + 1);
+
+/// How long auto-write waits for the edits to settle, so dragging a slider doesn't write every
+/// frame
+const AUTO_WRITE_DELAY: Duration = Duration::from_millis(300);
+
 fn create_handler(state: Arc<Mutex<AppState>>) -> Result<ActorRef<HandlerMessage>, ractor_wormhole::ractor::RactorErr<()>> {
+    #[cfg(target_arch = "wasm32")]
+    let transport = crate::web_bluetooth::Bluetooth::new();
+    #[cfg(any(target_os = "windows", target_os = "macos", target_os = "linux"))]
+    let transport = crate::bluetooth_native::Bluetooth::new();
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    let transport = crate::transport::Unsupported;
+
+    spawn_handler(state, transport)
+}
+
+/// The handler owns the transport and processes one message at a time, so there is never more
+/// than one Bluetooth operation in flight.
+fn spawn_handler<T: ConfigTransport + 'static>(
+    state: Arc<Mutex<AppState>>,
+    mut transport: T,
+) -> Result<ActorRef<HandlerMessage>, ractor_wormhole::ractor::RactorErr<()>> {
     use ractor_wormhole::util::ThreadLocalFnActor;
 
     let spawner = ThreadLocalActorSpawner::new();
     
-    // Create Bluetooth instance on the heap so we can use it across async boundaries
-    let bt = Box::leak(Box::new(Bluetooth::new()));
-    let bt_ptr: *mut Bluetooth = bt as *mut _;
-    
     let (handler, _) = ThreadLocalFnActor::start_fn_instant(spawner, move |mut ctx| async move {
         let mut heartbeat_running = false;
+        // ticks scheduled before the heartbeat was restarted are ignored
+        let mut heartbeat_generation = 0u32;
+        // the latest config scheduled by auto-write, dropped by anything that makes it stale
+        let mut pending_write: Option<AppConfig> = None;
+        // only the tick of the last scheduled write counts
+        let mut write_generation = 0u32;
         
         use ractor_wormhole::deps::futures::StreamExt;
         
@@ -94,7 +295,7 @@ fn create_handler(state: Arc<Mutex<AppState>>) -> Result<ActorRef<HandlerMessage
                 
                 HandlerMessage::SetStatus(status) => {
                     let mut state = state.lock().unwrap();
-                    state.last_status = status;
+                    state.set_status(status);
                     state.last_update = Some(Instant::now());
                 }
                 
@@ -120,267 +321,492 @@ fn create_handler(state: Arc<Mutex<AppState>>) -> Result<ActorRef<HandlerMessage
                     {
                         let mut state = state.lock().unwrap();
                         state.conn = ConnectionStatus::Connecting;
-                        state.last_status = "Connecting...".to_string();
+                        state.discovered.clear();
+                        state.set_status("Connecting...".to_string());
                         state.busy = true;
                         state.last_update = Some(Instant::now());
                     }
                     
-                    let state_clone = state.clone();
-                    let self_actor_ref = ctx.actor_ref.clone();
-                    spawn_local(async move {
-                        let res = unsafe { (&mut *bt_ptr).connect().await };
-                        match res {
-                            Ok(_) => {
-                                match unsafe { (&*bt_ptr).read_config_raw().await } {
-                                    Ok(jsv) => {
-                                        let u8arr = js_sys::Uint8Array::new(&jsv.into());
-                                        let mut vec = vec![0u8; u8arr.length() as usize];
-                                        u8arr.copy_to(&mut vec[..]);
-                                        
-                                        if let Ok(cfg) = postcard::from_bytes::<AppConfig>(&vec) {
-                                            let mut state = state_clone.lock().unwrap();
-                                            state.config = Some(cfg.clone());
-                                            state.last_status = "Connected".to_string();
-                                            state.conn = ConnectionStatus::Connected(cfg);
-                                            state.busy = false;
-                                            state.last_update = Some(Instant::now());
-                                            // connected - start heartbeat
-                                            let _ = self_actor_ref.send_message(HandlerMessage::Heartbeat);
-                                        } else {
-                                            let mut state = state_clone.lock().unwrap();
-                                            state.last_status = "Decode error".to_string();
-                                            state.conn = ConnectionStatus::Broken(AppConfig::default());
-                                            state.busy = false;
-                                            state.last_update = Some(Instant::now());
-                                        }
-                                    }
-                                    Err(e) => {
-                                        let mut state = state_clone.lock().unwrap();
-                                        state.last_status = format!("Read error: {:?}", e);
-                                        state.conn = ConnectionStatus::Broken(AppConfig::default());
-                                        state.busy = false;
-                                        state.last_update = Some(Instant::now());
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                let mut state = state_clone.lock().unwrap();
-                                state.last_status = format!("Connect error: {:?}", e);
-                                state.conn = ConnectionStatus::Broken(AppConfig::default());
-                                state.busy = false;
-                                state.last_update = Some(Instant::now());
+                    match transport.scan().await {
+                        // the platform has its own device chooser
+                        Ok(None) => {
+                            let res = transport.connect(None).await;
+                            connect_finished(&state, &mut transport, res, &ctx.actor_ref).await;
+                        }
+                        // let the user pick one, see ConnectTo
+                        Ok(Some(devices)) => {
+                            let mut state = state.lock().unwrap();
+                            if devices.is_empty() {
+                                state.set_status("No devices found".to_string());
+                                state.conn = ConnectionStatus::Disconnected;
+                            } else {
+                                state.set_status("Select a device".to_string());
+                                state.discovered = devices;
                             }
+                            state.busy = false;
+                            state.last_update = Some(Instant::now());
                         }
-                    });
+                        Err(e) => {
+                            let mut state = state.lock().unwrap();
+                            state.set_status(format!("Scan error: {e}"));
+                            state.conn = ConnectionStatus::Disconnected;
+                            state.busy = false;
+                            state.last_update = Some(Instant::now());
+                        }
+                    }
                 }
                 
+                HandlerMessage::ConnectTo(id) => {
+                    {
+                        let mut state = state.lock().unwrap();
+                        state.discovered.clear();
+                        state.set_status("Connecting...".to_string());
+                        state.busy = true;
+                        state.last_update = Some(Instant::now());
+                    }
+                    
+                    let res = transport.connect(Some(&id)).await;
+                    connect_finished(&state, &mut transport, res, &ctx.actor_ref).await;
+                }
+                
+                HandlerMessage::CheckBluetooth => {
+                    let available = transport.is_available().await;
+                    let mut state = state.lock().unwrap();
+                    if let Err(reason) = &available {
+                        log::warn!("Bluetooth not available: {reason}");
+                        state.set_status(reason.clone());
+                        state.last_update = Some(Instant::now());
+                    }
+                    state.bluetooth_unavailable = available.err();
+                }
+
+                HandlerMessage::AutoConnect => {
+                    if state.lock().unwrap().bluetooth_unavailable.is_some() {
+                        continue;
+                    }
+                    {
+                        let mut state = state.lock().unwrap();
+                        state.conn = ConnectionStatus::Connecting;
+                        state.auto_connecting = true;
+                        state.set_status("Looking for the last device...".to_string());
+                        state.busy = true;
+                        state.last_update = Some(Instant::now());
+                    }
+
+                    let trying = |name: &str| {
+                        let mut state = state.lock().unwrap();
+                        let status = format!("Reconnecting to {name}...");
+                        if state.auto_connecting && state.last_status != status {
+                            state.set_status(status);
+                            state.last_update = Some(Instant::now());
+                        }
+                        state.auto_connecting
+                    };
+                    let found = transport.try_auto_reconnect(&trying).await;
+                    state.lock().unwrap().auto_connecting = false;
+                    match found {
+                        Ok(true) => connect_finished(&state, &mut transport, Ok(()), &ctx.actor_ref).await,
+                        // the user connects with the button as before
+                        found => {
+                            if let Err(e) = found {
+                                log::warn!("Auto reconnect failed: {e}");
+                            }
+                            let mut state = state.lock().unwrap();
+                            state.conn = ConnectionStatus::Disconnected;
+                            state.set_status("Idle".to_string());
+                            state.busy = false;
+                            state.last_update = Some(Instant::now());
+                        }
+                    }
+                }
+
                 HandlerMessage::Disconnect => {
                     heartbeat_running = false;
-                    let state_clone = state.clone();
-                    spawn_local(async move {
-                        let _ = unsafe { (&mut *bt_ptr).disconnect().await };
-                        let mut state = state_clone.lock().unwrap();
-                        state.conn = ConnectionStatus::Disconnected;
-                        state.config = None;
-                        state.last_status = "Disconnected".to_string();
-                        state.last_update = Some(Instant::now());
-                    });
+                    pending_write = None;
+                    let _ = transport.disconnect().await;
+                    let mut state = state.lock().unwrap();
+                    state.conn = ConnectionStatus::Disconnected;
+                    state.config = None;
+                    state.device_config = None;
+                    state.device_config_changed = false;
+                    state.device_info = None;
+                    state.device_name = None;
+                    state.device_enabled = None;
+                    state.device_presets.clear();
+                    state.device_slots.clear();
+                    state.device_config_versions = None;
+                    state.device_max_config_size = None;
+                    state.discovered.clear();
+                    state.device_levels.clear();
+                    state.last_alive = None;
+                    state.device_status = None;
+                    state.device_telemetry = None;
+                    state.device_rssi = None;
+                    state.set_status("Disconnected".to_string());
+                    state.last_update = Some(Instant::now());
                 }
                 
                 HandlerMessage::Reconnect => {
                     {
                         let mut state = state.lock().unwrap();
                         state.busy = true;
-                        state.last_status = "Reconnecting...".to_string();
+                        state.set_status("Reconnecting...".to_string());
                         state.last_update = Some(Instant::now());
                     }
                     
-                    let state_clone = state.clone();
-                    spawn_local(async move {
-                        let res = unsafe { (&mut *bt_ptr).reconnect().await };
-                        match res {
-                            Ok(_) => {
-                                let has_cfg = {
-                                    let state = state_clone.lock().unwrap();
-                                    state.config.is_some()
-                                };
-                                
-                                if !has_cfg {
-                                    match unsafe { (&*bt_ptr).read_config_raw().await } {
-                                        Ok(jsv) => {
-                                            let u8arr = js_sys::Uint8Array::new(&jsv.into());
-                                            let mut vec = vec![0u8; u8arr.length() as usize];
-                                            u8arr.copy_to(&mut vec[..]);
-                                            
-                                            if let Ok(cfg) = postcard::from_bytes::<AppConfig>(&vec) {
-                                                let mut state = state_clone.lock().unwrap();
-                                                state.config = Some(cfg.clone());
-                                                state.last_status = "Connected".to_string();
-                                                state.conn = ConnectionStatus::Connected(cfg);
-                                                state.busy = false;
-                                                state.last_update = Some(Instant::now());
-                                            }
-                                        }
-                                        Err(e) => {
-                                            let mut state = state_clone.lock().unwrap();
-                                            state.last_status = format!("Read error: {:?}", e);
-                                            let cfg = state.config.clone().unwrap_or_default();
-                                            state.conn = ConnectionStatus::Broken(cfg);
-                                            state.busy = false;
-                                            state.last_update = Some(Instant::now());
-                                        }
-                                    }
-                                } else {
-                                    let mut state = state_clone.lock().unwrap();
-                                    let cfg = state.config.clone().unwrap();
-                                    state.last_status = "Connected".to_string();
-                                    state.conn = ConnectionStatus::Connected(cfg);
-                                    state.busy = false;
-                                    state.last_update = Some(Instant::now());
-                                }
-                            }
-                            Err(e) => {
-                                let mut state = state_clone.lock().unwrap();
-                                state.last_status = format!("Reconnect error: {:?}", e);
-                                let cfg = state.config.clone().unwrap_or_default();
-                                state.conn = ConnectionStatus::Broken(cfg);
-                                state.busy = false;
-                                state.last_update = Some(Instant::now());
+                    let has_cfg = state.lock().unwrap().config.is_some();
+                    let res = match transport.reconnect().await {
+                        // nothing to show yet, so read it from the device
+                        Ok(_) if !has_cfg => match transport.read_config().await {
+                            Ok(vec) => Ok(postcard::from_bytes::<AppConfig>(&vec).ok()),
+                            Err(e) => Err(format!("Read error: {e}")),
+                        },
+                        Ok(_) => Ok(None),
+                        Err(e) => Err(format!("Reconnect error: {e}")),
+                    };
+                    if res.is_ok() {
+                        subscribe_notifications(&state, &mut transport).await;
+                    }
+                    
+                    let mut state = state.lock().unwrap();
+                    match res {
+                        Ok(read_cfg) => {
+                            if let Some(cfg) = read_cfg {
+                                state.config = Some(cfg.clone());
+                                state.device_config = Some(cfg);
                             }
+                            let cfg = state.config.clone().unwrap_or_default();
+                            state.set_status("Connected".to_string());
+                            state.conn = ConnectionStatus::Connected(cfg);
                         }
-                    });
+                        Err(e) => {
+                            state.set_status(e);
+                            let cfg = state.config.clone().unwrap_or_default();
+                            state.conn = ConnectionStatus::Broken(cfg);
+                        }
+                    }
+                    state.busy = false;
+                    state.last_update = Some(Instant::now());
                 }
                 
                 HandlerMessage::Reload => {
+                    // the edits it would write are replaced by what the device has
+                    pending_write = None;
                     {
                         let mut state = state.lock().unwrap();
                         state.busy = true;
-                        state.last_status = "Reloading...".to_string();
+                        state.set_status("Reloading...".to_string());
                         state.last_update = Some(Instant::now());
                     }
                     
-                    let state_clone = state.clone();
-                    spawn_local(async move {
-                        match unsafe { (&*bt_ptr).read_config_raw().await } {
-                            Ok(jsv) => {
-                                let u8arr = js_sys::Uint8Array::new(&jsv.into());
-                                let mut vec = vec![0u8; u8arr.length() as usize];
-                                u8arr.copy_to(&mut vec[..]);
-                                
-                                match postcard::from_bytes::<AppConfig>(&vec) {
-                                    Ok(cfg) => {
-                                        let mut state = state_clone.lock().unwrap();
-                                        state.config = Some(cfg);
-                                        state.last_status = "Reload OK".to_string();
-                                        state.busy = false;
-                                        state.last_update = Some(Instant::now());
-                                    }
-                                    Err(e) => {
-                                        let mut state = state_clone.lock().unwrap();
-                                        state.last_status = format!("Decode error: {:?}", e);
-                                        let cfg = state.config.clone().unwrap_or_default();
-                                        state.conn = ConnectionStatus::Broken(cfg);
-                                        state.busy = false;
-                                        state.last_update = Some(Instant::now());
-                                    }
-                                }
+                    let res = transport.read_config().await;
+                    let mut state = state.lock().unwrap();
+                    match res {
+                        Ok(vec) => match postcard::from_bytes::<AppConfig>(&vec) {
+                            Ok(cfg) => {
+                                state.config = Some(cfg.clone());
+                                state.device_config = Some(cfg);
+                                state.device_config_changed = false;
+                                state.set_status("Reload OK".to_string());
                             }
                             Err(e) => {
-                                let mut state = state_clone.lock().unwrap();
-                                state.last_status = format!("Reload error: {:?}", e);
+                                state.set_status(format!("Decode error: {:?}", e));
                                 let cfg = state.config.clone().unwrap_or_default();
                                 state.conn = ConnectionStatus::Broken(cfg);
-                                state.busy = false;
-                                state.last_update = Some(Instant::now());
                             }
+                        },
+                        Err(e) => {
+                            state.set_status(format!("Reload error: {e}"));
+                            let cfg = state.config.clone().unwrap_or_default();
+                            state.conn = ConnectionStatus::Broken(cfg);
                         }
-                    });
+                    }
+                    state.busy = false;
+                    state.last_update = Some(Instant::now());
                 }
                 
                 HandlerMessage::Write(cfg) => {
+                    // written right away instead
+                    pending_write = None;
+                    let max_size = {
+                        let mut state = state.lock().unwrap();
+                        state.busy = true;
+                        state.set_status("Writing...".to_string());
+                        state.last_update = Some(Instant::now());
+                        state.max_config_size()
+                    };
+                    
+                    let bytes = match config_bytes(&cfg, max_size) {
+                        Ok(bytes) => bytes,
+                        Err(e) => {
+                            let mut state = state.lock().unwrap();
+                            state.set_status(e);
+                            state.busy = false;
+                            state.last_update = Some(Instant::now());
+                            continue;
+                        }
+                    };
+                    
+                    let res = write_config_bytes(&transport, &bytes).await;
+                    let mut state = state.lock().unwrap();
+                    match res {
+                        Ok(_) => {
+                            state.device_config = Some(cfg);
+                            state.device_config_changed = false;
+                            state.set_status("Write OK".to_string());
+                        }
+                        Err(e) => {
+                            state.set_status(format!("Write error: {e}"));
+                            let cfg = state.config.clone().unwrap_or_default();
+                            state.conn = ConnectionStatus::Broken(cfg);
+                        }
+                    }
+                    state.busy = false;
+                    state.last_update = Some(Instant::now());
+                }
+                
+                HandlerMessage::ScheduleWrite(cfg) => {
+                    pending_write = Some(cfg);
+                    write_generation = write_generation.wrapping_add(1);
+                    transport::send_after(
+                        ctx.actor_ref.clone(),
+                        AUTO_WRITE_DELAY,
+                        HandlerMessage::AutoWrite(write_generation),
+                    );
+                }
+
+                HandlerMessage::AutoWrite(generation) => {
+                    // a newer edit scheduled its own tick. A write that was in flight meanwhile
+                    // kept this one queued, so it still writes the latest config.
+                    if generation != write_generation {
+                        continue;
+                    }
+                    let Some(cfg) = pending_write.take() else {
+                        continue;
+                    };
+                    let max_size = {
+                        let state = state.lock().unwrap();
+                        let connected = matches!(state.conn, ConnectionStatus::Connected(_));
+                        let written = state.device_config.as_ref() == Some(&cfg);
+                        if !state.auto_write || !connected || written {
+                            continue;
+                        }
+                        state.max_config_size()
+                    };
+
+                    let bytes = match config_bytes(&cfg, max_size) {
+                        Ok(bytes) => bytes,
+                        Err(e) => {
+                            let mut state = state.lock().unwrap();
+                            state.auto_write = false;
+                            state.set_status(format!("Auto-write switched off: {e}"));
+                            state.last_update = Some(Instant::now());
+                            continue;
+                        }
+                    };
+
+                    let res = write_config_bytes(&transport, &bytes).await;
+                    let mut state = state.lock().unwrap();
+                    match res {
+                        // no status, it would flood the log while tuning
+                        Ok(_) => {
+                            state.device_config = Some(cfg);
+                            state.device_config_changed = false;
+                        }
+                        Err(e) => {
+                            state.auto_write = false;
+                            state.set_status(format!("Auto-write switched off, write error: {e}"));
+                            let cfg = state.config.clone().unwrap_or_default();
+                            state.conn = ConnectionStatus::Broken(cfg);
+                        }
+                    }
+                    state.last_update = Some(Instant::now());
+                }
+                
+                HandlerMessage::FactoryReset => {
                     {
                         let mut state = state.lock().unwrap();
                         state.busy = true;
-                        state.last_status = "Writing...".to_string();
+                        state.set_status("Resetting...".to_string());
                         state.last_update = Some(Instant::now());
                     }
                     
-                    let state_clone = state.clone();
-                    if let Ok(bytes) = cfg.to_bytes::<1024>() {
-                        spawn_local(async move {
-                            let u8arr = js_sys::Uint8Array::from(&bytes[..]);
-                            let res = unsafe { (&*bt_ptr).write_config_raw(&u8arr).await };
-                            
-                            match res {
-                                Ok(_) => {
-                                    let mut state = state_clone.lock().unwrap();
-                                    state.last_status = "Write OK".to_string();
-                                    state.busy = false;
-                                    state.last_update = Some(Instant::now());
-                                }
-                                Err(e) => {
-                                    let mut state = state_clone.lock().unwrap();
-                                    state.last_status = format!("Write error: {:?}", e);
-                                    let cfg = state.config.clone().unwrap_or_default();
-                                    state.conn = ConnectionStatus::Broken(cfg);
-                                    state.busy = false;
-                                    state.last_update = Some(Instant::now());
-                                }
-                            }
-                        });
-                    } else {
-                        let mut state = state_clone.lock().unwrap();
-                        state.last_status = "Serialize error".to_string();
-                        state.busy = false;
-                        state.last_update = Some(Instant::now());
+                    let res = transport.factory_reset().await;
+                    let mut state = state.lock().unwrap();
+                    match res {
+                        Ok(_) => {
+                            // the same config the firmware falls back to
+                            let cfg = AppConfig::default();
+                            state.config = Some(cfg.clone());
+                            state.device_config = Some(cfg);
+                            state.device_config_changed = false;
+                            state.set_status("Reset to defaults".to_string());
+                        }
+                        Err(e) => state.set_status(format!("Reset error: {e}")),
                     }
+                    state.busy = false;
+                    state.last_update = Some(Instant::now());
+                }
+                
+                HandlerMessage::SetLogLevel(level) => {
+                    let res = transport.set_log_level(level).await;
+                    let mut state = state.lock().unwrap();
+                    match res {
+                        Ok(_) => state.device_log_level = level,
+                        Err(e) => state.set_status(format!("Log level error: {e}")),
+                    }
+                    state.last_update = Some(Instant::now());
+                }
+                
+                HandlerMessage::ApplyPreset(index) => {
+                    state.lock().unwrap().busy = true;
+                    // the device notifies the new config, which updates the editor
+                    let res = transport.apply_preset(index).await;
+                    let mut state = state.lock().unwrap();
+                    state.set_status(match res {
+                        Ok(_) => "Applied preset on the device".to_string(),
+                        Err(e) => format!("Preset error: {e}"),
+                    });
+                    state.busy = false;
+                    state.last_update = Some(Instant::now());
+                }
+                
+                HandlerMessage::SaveSlot(index, name) => {
+                    state.lock().unwrap().busy = true;
+                    let res = transport.save_slot(index, &name).await;
+                    let slots = transport.read_slot_names().await;
+                    let mut state = state.lock().unwrap();
+                    state.set_status(match res {
+                        Ok(_) => format!("Saved the config as {name}"),
+                        Err(e) => format!("Slot error: {e}"),
+                    });
+                    if let Ok(slots) = slots {
+                        state.device_slots = slots;
+                    }
+                    state.busy = false;
+                    state.last_update = Some(Instant::now());
+                }
+                
+                HandlerMessage::SelectSlot(index) => {
+                    state.lock().unwrap().busy = true;
+                    // like a preset, the device notifies the new config
+                    let res = transport.select_slot(index).await;
+                    let mut state = state.lock().unwrap();
+                    state.set_status(match res {
+                        Ok(_) => "Selected the slot on the device".to_string(),
+                        Err(e) => format!("Slot error: {e}"),
+                    });
+                    state.busy = false;
+                    state.last_update = Some(Instant::now());
+                }
+                
+                HandlerMessage::Rename(name) => {
+                    let res = transport.write_device_name(&name).await;
+                    let mut state = state.lock().unwrap();
+                    match res {
+                        Ok(_) => {
+                            state.set_status(format!("Renamed to {name}, shown after reconnecting"));
+                            state.device_name = Some(name);
+                        }
+                        Err(e) => state.set_status(format!("Rename error: {e}")),
+                    }
+                    state.last_update = Some(Instant::now());
+                }
+                
+                HandlerMessage::SetEnabled(enabled) => {
+                    let res = transport.write_enabled(enabled).await;
+                    let mut state = state.lock().unwrap();
+                    match res {
+                        Ok(_) => {
+                            state.set_status(format!("LEDs switched {}", if enabled { "on" } else { "off" }));
+                            state.device_enabled = Some(enabled);
+                        }
+                        Err(e) => state.set_status(format!("On/off error: {e}")),
+                    }
+                    state.last_update = Some(Instant::now());
+                }
+                
+                HandlerMessage::Command(command) => {
+                    let res = transport.send_command(command).await;
+                    let mut state = state.lock().unwrap();
+                    state.set_status(match res {
+                        Ok(_) => format!("Sent {command:?}"),
+                        Err(e) => format!("Command error: {e}"),
+                    });
+                    state.last_update = Some(Instant::now());
                 }
                 
                 HandlerMessage::Heartbeat => {
-                    if !heartbeat_running {
+                    // without Bluetooth every tick could only fail
+                    let available = state.lock().unwrap().bluetooth_unavailable.is_none();
+                    if available && !heartbeat_running {
                         heartbeat_running = true;
-                        let state_clone = state.clone();
-                        
-                        spawn_local(async move {
-                            let mut interval = gloo_timers::future::IntervalStream::new(5000);
-                            
-                            while (interval.next().await).is_some() {
-                                let should_continue = {
-                                    let state = state_clone.lock().unwrap();
-                                    matches!(state.conn, ConnectionStatus::Connected(_))
-                                };
-                                
-                                if !should_continue {
-                                    break;
-                                }
-                                
-                                let hb_res = unsafe { (&*bt_ptr).heartbeat().await };
-                                if let Err(_e) = hb_res {
-                                    // Attempt reconnect
-                                    let mut reconnected = false;
-                                    for _attempt in 0..3 {
-                                        gloo_timers::future::sleep(Duration::from_millis(1000)).await;
-                                        if unsafe { (&mut *bt_ptr).reconnect().await }.is_ok() {
-                                            reconnected = true;
-                                            let mut state = state_clone.lock().unwrap();
-                                            state.last_status = "Reconnected".to_string();
-                                            state.last_update = Some(Instant::now());
-                                            break;
-                                        }
-                                    }
-                                    
-                                    if !reconnected {
-                                        let mut state = state_clone.lock().unwrap();
-                                        state.last_status = "Connection broken".to_string();
-                                        let cfg = state.config.clone().unwrap_or_default();
-                                        state.conn = ConnectionStatus::Broken(cfg);
-                                        state.last_update = Some(Instant::now());
-                                        break;
-                                    }
-                                }
+                        heartbeat_generation = heartbeat_generation.wrapping_add(1);
+                        transport::send_after(
+                            ctx.actor_ref.clone(),
+                            HEARTBEAT_INTERVAL,
+                            HandlerMessage::HeartbeatTick(heartbeat_generation),
+                        );
+                    }
+                }
+                
+                HandlerMessage::HeartbeatTick(generation) => {
+                    if !heartbeat_running || generation != heartbeat_generation {
+                        continue;
+                    }
+                    
+                    let should_continue = {
+                        let state = state.lock().unwrap();
+                        matches!(state.conn, ConnectionStatus::Connected(_))
+                    };
+                    if !should_continue {
+                        heartbeat_running = false;
+                        continue;
+                    }
+                    
+                    let last_alive = state.lock().unwrap().last_alive;
+                    let alive = match last_alive {
+                        // the device tells us on its own, a read could hang for a long time
+                        Some(last_alive) => last_alive.elapsed() < ALIVE_TIMEOUT,
+                        None => transport.heartbeat().await.is_ok(),
+                    };
+                    if !alive {
+                        // Attempt reconnect
+                        let mut reconnected = false;
+                        for _attempt in 0..3 {
+                            transport::sleep(Duration::from_millis(1000)).await;
+                            if transport.reconnect().await.is_ok() {
+                                reconnected = true;
+                                subscribe_notifications(&state, &mut transport).await;
+                                let mut state = state.lock().unwrap();
+                                state.set_status("Reconnected".to_string());
+                                state.last_update = Some(Instant::now());
+                                break;
                             }
-                        });
+                        }
+                        
+                        if !reconnected {
+                            let mut state = state.lock().unwrap();
+                            state.set_status("Connection broken".to_string());
+                            let cfg = state.config.clone().unwrap_or_default();
+                            state.conn = ConnectionStatus::Broken(cfg);
+                            state.last_update = Some(Instant::now());
+                            heartbeat_running = false;
+                            continue;
+                        }
                     }
+                    
+                    // checking the alive notifications is free, so do it more often
+                    let interval = if state.lock().unwrap().last_alive.is_some() {
+                        ALIVE_INTERVAL
+                    } else {
+                        HEARTBEAT_INTERVAL
+                    };
+                    transport::send_after(
+                        ctx.actor_ref.clone(),
+                        interval,
+                        HandlerMessage::HeartbeatTick(heartbeat_generation),
+                    );
                 }
                 
                 HandlerMessage::StopHeartbeat => {
@@ -393,20 +819,253 @@ fn create_handler(state: Arc<Mutex<AppState>>) -> Result<ActorRef<HandlerMessage
     Ok(handler)
 }
 
-#[cfg(not(target_arch = "wasm32"))]
-fn create_handler(_state: Arc<Mutex<AppState>>) -> Result<ActorRef<HandlerMessage>, ractor_wormhole::ractor::RactorErr<()>> {
-    use ractor_wormhole::util::ThreadLocalFnActor;
-    
-    let spawner = ThreadLocalActorSpawner::new();
-    let (handler, _) = ThreadLocalFnActor::start_fn_instant(spawner, move |mut ctx| async move {
-        use ractor_wormhole::deps::futures::StreamExt;
+fn version_mismatch_message(versions: &RangeInclusive<u32>) -> String {
+    format!(
+        "The device accepts config versions {} to {}, this app uses version {CONFIG_VERSION}. \
+         Update the older one.",
+        versions.start(),
+        versions.end()
+    )
+}
+
+/// Read the config after `connect` and update the state accordingly
+async fn connect_finished<T: ConfigTransport>(
+    state: &Arc<Mutex<AppState>>,
+    transport: &mut T,
+    connect_result: Result<(), String>,
+    self_actor_ref: &ActorRef<HandlerMessage>,
+) {
+    // read first, so a config that fails to decode can be explained
+    let versions = match connect_result {
+        Ok(_) => transport
+            .read_config_versions()
+            .await
+            .inspect_err(|e| log::warn!("No supported config versions: {e}"))
+            .ok(),
+        Err(_) => None,
+    };
+    state.lock().unwrap().device_config_versions = versions.clone();
+    if connect_result.is_ok() {
+        let max_size = transport.read_max_config_size().await;
+        if let Err(e) = &max_size {
+            log::warn!("No max config size, assuming {LEGACY_MAX_CONFIG_SIZE} bytes: {e}");
+        }
+        state.lock().unwrap().device_max_config_size = max_size.ok();
+    }
+
+    let res = match connect_result {
+        Ok(_) => transport.read_config().await.map_err(|e| format!("Read error: {e}")),
+        Err(e) => Err(format!("Connect error: {e}")),
+    };
+    let res = res.and_then(|vec| {
+        postcard::from_bytes::<AppConfig>(&vec).map_err(|_| match (&versions, AppConfig::peek_version(&vec)) {
+            (Some(versions), _) if !versions.contains(&CONFIG_VERSION) => {
+                version_mismatch_message(versions)
+            }
+            // firmware that doesn't list the versions it accepts still sends its own
+            (None, Some(version)) if version != CONFIG_VERSION => {
+                version_mismatch_message(&(version..=version))
+            }
+            _ => "Decode error".to_string(),
+        })
+    });
+    if res.is_ok() {
+        subscribe_notifications(state, transport).await;
         
-        while let Some(_msg) = ctx.rx.next().await {
-            // No-op for non-WASM
+        let device_info = transport.read_device_info().await;
+        if let Err(e) = &device_info {
+            log::warn!("No device information: {e}");
         }
-    })?;
+        state.lock().unwrap().device_info = device_info.ok();
+
+        let device_name = transport.read_device_name().await;
+        if let Err(e) = &device_name {
+            log::warn!("No device name: {e}");
+        }
+        state.lock().unwrap().device_name = device_name.ok();
+
+        let enabled = transport.read_enabled().await;
+        if let Err(e) = &enabled {
+            log::warn!("No on/off switch: {e}");
+        }
+        state.lock().unwrap().device_enabled = enabled.ok();
+
+        let presets = transport.read_preset_names().await;
+        if let Err(e) = &presets {
+            log::warn!("No presets on the device: {e}");
+        }
+        state.lock().unwrap().device_presets = presets.unwrap_or_default();
+
+        let slots = transport.read_slot_names().await;
+        if let Err(e) = &slots {
+            log::warn!("No preset slots on the device: {e}");
+        }
+        state.lock().unwrap().device_slots = slots.unwrap_or_default();
+    }
     
-    Ok(handler)
+    let mut state = state.lock().unwrap();
+    match res {
+        Ok(cfg) => {
+            state.config = Some(cfg.clone());
+            state.device_config = Some(cfg.clone());
+            state.device_config_changed = false;
+            state.set_status("Connected".to_string());
+            state.conn = ConnectionStatus::Connected(cfg);
+            // connected - start heartbeat
+            let _ = self_actor_ref.send_message(HandlerMessage::Heartbeat);
+        }
+        Err(e) => {
+            state.set_status(e);
+            state.conn = ConnectionStatus::Broken(AppConfig::default());
+        }
+    }
+    state.busy = false;
+    state.last_update = Some(Instant::now());
+}
+
+/// Check `cfg` and serialize it for [`write_config_bytes`], the status to show if that fails
+fn config_bytes(cfg: &AppConfig, max_size: usize) -> Result<Vec<u8>, String> {
+    // the device would reject it as well, but without saying why
+    if let Err(ConfigError::TooManyLeds(leds)) = cfg.validate() {
+        return Err(format!("The layout has {leds} LEDs, the device supports 1 to {MAX_LEDS}"));
+    }
+    // or worse, take it cut off
+    let size = cfg.serialized_size();
+    if size > max_size {
+        return Err(format!("The config is {size} bytes, the device takes at most {max_size}"));
+    }
+    cfg.to_bytes::<MAX_CHUNKED_CONFIG_SIZE>()
+        .map(|bytes| bytes.to_vec())
+        .map_err(|_| "Serialize error".to_string())
+}
+
+/// Write a serialized config, in chunks if it doesn't fit into a single write.
+///
+/// Small configs are written directly, so they still work with firmware that doesn't know about
+/// chunked transfers.
+async fn write_config_bytes<T: ConfigTransport>(transport: &T, bytes: &[u8]) -> Result<(), String> {
+    if bytes.len() <= MAX_CONFIG_SIZE {
+        return transport.write_config(bytes).await;
+    }
+    
+    let control = |msg: ConfigControl| msg.to_bytes().map_err(|e| format!("{e:?}"));
+    transport
+        .write_config_control(&control(ConfigControl::Begin { total_len: bytes.len() as u32 })?)
+        .await?;
+    for chunk in transfer::chunks(bytes) {
+        if let Err(e) = transport.write_config(&chunk).await {
+            // don't leave the device waiting for the rest
+            let _ = transport.write_config_control(&control(ConfigControl::Abort)?).await;
+            return Err(e);
+        }
+    }
+    transport.write_config_control(&control(ConfigControl::Commit)?).await
+}
+
+/// Keep `AppState::device_levels`, `AppState::device_config`, `AppState::last_alive`,
+/// `AppState::device_rssi`, `AppState::device_status`, `AppState::device_telemetry` and
+/// `AppState::device_log` up to date
+/// with the notifications the device sends
+async fn subscribe_notifications<T: ConfigTransport>(
+    state: &Arc<Mutex<AppState>>,
+    transport: &mut T,
+) {
+    {
+        let mut state = state.lock().unwrap();
+        state.device_levels.clear();
+        state.last_alive = None;
+        state.device_status = None;
+        state.device_telemetry = None;
+        state.device_rssi = None;
+    }
+    
+    let levels_state = state.clone();
+    let res = transport
+        .subscribe_levels(Box::new(move |data| {
+            if let Ok(levels) = levels_from_bytes(data) {
+                levels_state.lock().unwrap().device_levels = levels.to_vec();
+            }
+        }))
+        .await;
+    // older firmware doesn't have the characteristic, everything else still works without it
+    if let Err(e) = res {
+        log::warn!("Not receiving channel levels: {e}");
+    }
+    
+    let config_state = state.clone();
+    let res = transport
+        .subscribe_config(Box::new(move |data| {
+            if let Ok(cfg) = postcard::from_bytes::<AppConfig>(data) {
+                config_state.lock().unwrap().device_config_notified(cfg);
+            }
+        }))
+        .await;
+    if let Err(e) = res {
+        log::warn!("Not receiving config changes: {e}");
+    }
+    
+    let alive_state = state.clone();
+    let res = transport
+        .subscribe_alive(Box::new(move |_counter| {
+            alive_state.lock().unwrap().last_alive = Some(Instant::now());
+        }))
+        .await;
+    match res {
+        // count the timeout from now, the first notification can take a moment
+        Ok(()) => state.lock().unwrap().last_alive = Some(Instant::now()),
+        // without it the heartbeat falls back to reading the config
+        Err(e) => log::warn!("Not receiving alive notifications: {e}"),
+    }
+    
+    let rssi_state = state.clone();
+    let res = transport
+        .subscribe_rssi(Box::new(move |data| {
+            if let Some(&rssi) = data.first() {
+                rssi_state.lock().unwrap().device_rssi = Some(rssi as i8);
+            }
+        }))
+        .await;
+    if let Err(e) = res {
+        log::warn!("Not receiving RSSI: {e}");
+    }
+    
+    let status_state = state.clone();
+    let res = transport
+        .subscribe_status(Box::new(move |data| {
+            if let Ok(status) = DeviceStatus::from_bytes(data) {
+                status_state.lock().unwrap().device_status = Some(status);
+            }
+        }))
+        .await;
+    if let Err(e) = res {
+        log::warn!("Not receiving device status: {e}");
+    }
+    
+    let telemetry_state = state.clone();
+    let res = transport
+        .subscribe_telemetry(Box::new(move |data| {
+            if let Ok(telemetry) = Telemetry::from_bytes(data) {
+                telemetry_state.lock().unwrap().device_telemetry = Some(telemetry);
+            }
+        }))
+        .await;
+    if let Err(e) = res {
+        log::warn!("Not receiving telemetry: {e}");
+    }
+    
+    let log_state = state.clone();
+    let res = transport
+        .subscribe_log(Box::new(move |data| {
+            let mut state = log_state.lock().unwrap();
+            if state.device_log.len() >= DEVICE_LOG_LINES {
+                state.device_log.pop_front();
+            }
+            state.device_log.push_back(String::from_utf8_lossy(data).into_owned());
+        }))
+        .await;
+    if let Err(e) = res {
+        log::warn!("Not receiving the device log: {e}");
+    }
 }
 
 // -----------------
@@ -417,21 +1076,97 @@ pub struct PartylightApp {
     state: Arc<Mutex<AppState>>,
     handler: ActorRef<HandlerMessage>,
     styled: bool,
+    preview: MatrixPreview,
+    /// Disconnect was clicked with unsaved changes, waiting for the user to confirm
+    confirm_disconnect: bool,
+    /// Reset to defaults was clicked, waiting for the user to confirm
+    confirm_factory_reset: bool,
+    /// Write was clicked while the device doesn't accept the config version of this app
+    confirm_version_write: bool,
+    /// The new name while the rename dialog is open
+    rename: Option<String>,
+    /// The preset slot picked in the dropdown
+    slot: usize,
+    /// Name the config is saved under into [`Self::slot`]
+    slot_name: String,
+    /// Configs saved under a name in the app, see [`user_presets`]
+    user_presets: Vec<UserPreset>,
+    /// The name dialog for [`Self::user_presets`] while it's open
+    preset_name: Option<PresetNameDialog>,
+    /// The config last sent to auto-write, so it's only scheduled once per edit
+    auto_write_sent: Option<AppConfig>,
+    /// The window was closed with unsaved changes, waiting for the user to confirm
+    #[cfg(not(target_arch = "wasm32"))]
+    confirm_close: bool,
+    #[cfg(not(target_arch = "wasm32"))]
+    close_confirmed: bool,
+}
+
+/// Asks for the name of a user preset, to save the current config or to rename one
+struct PresetNameDialog {
+    /// Index into [`PartylightApp::user_presets`], `None` to save the current config
+    renaming: Option<usize>,
+    name: String,
 }
 
 impl Default for PartylightApp {
     fn default() -> Self {
         let state = Arc::new(Mutex::new(AppState::default()));
         let handler = create_handler(state.clone()).expect("Failed to create handler");
+        let _ = handler.send_message(HandlerMessage::CheckBluetooth);
+        let _ = handler.send_message(HandlerMessage::AutoConnect);
+
+        #[cfg(target_arch = "wasm32")]
+        install_unload_warning(state.clone());
+
+        let user_presets = user_presets::load().unwrap_or_else(|e| {
+            state.lock().unwrap().set_status(e);
+            Vec::new()
+        });
         
         Self {
             state,
             handler,
             styled: false,
+            preview: MatrixPreview::default(),
+            confirm_disconnect: false,
+            confirm_factory_reset: false,
+            confirm_version_write: false,
+            rename: None,
+            slot: 0,
+            slot_name: String::new(),
+            user_presets,
+            preset_name: None,
+            auto_write_sent: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            confirm_close: false,
+            #[cfg(not(target_arch = "wasm32"))]
+            close_confirmed: false,
         }
     }
 }
 
+/// Ask the browser to confirm closing the tab while there are unsaved changes
+#[cfg(target_arch = "wasm32")]
+fn install_unload_warning(state: Arc<Mutex<AppState>>) {
+    use wasm_bindgen::{JsCast, closure::Closure};
+
+    let Some(window) = web_sys::window() else {
+        return;
+    };
+    let on_unload = Closure::<dyn FnMut(web_sys::BeforeUnloadEvent)>::new(move |event: web_sys::BeforeUnloadEvent| {
+        let dirty = state.try_lock().map(|state| state.is_dirty()).unwrap_or(false);
+        if dirty {
+            // the browser shows its own generic message, the text is ignored
+            event.prevent_default();
+            event.set_return_value("You have unsaved changes");
+        }
+    });
+    let _ = window.add_event_listener_with_callback("beforeunload", on_unload.as_ref().unchecked_ref());
+    // the listener stays registered for the lifetime of the page
+    on_unload.forget();
+}
+
 pub mod colors {
     use egui::{Color32, Stroke};
 
@@ -453,7 +1188,7 @@ pub mod colors {
     }
 }
 
-#[cfg(target_arch = "wasm32")]
+#[cfg(any(target_arch = "wasm32", target_os = "windows", target_os = "macos", target_os = "linux"))]
 impl PartylightApp {
     pub fn ui(&mut self, ctx: &egui::Context) {
         // Apply styling once
@@ -466,18 +1201,32 @@ impl PartylightApp {
         let mut state = state.lock().unwrap();
         
         egui::CentralPanel::default().show(ctx, |ui| {
-            self.draw_header(ui);
+            self.draw_header(ui, &state);
             ui.add_space(64.0);
             
             // Connection controls
             self.draw_connection_controls(ui, &mut state);
+            self.draw_file_controls(ui, &state);
             
             // Config editor (only when config is loaded)
-            if state.config.is_some() {
+            if let Some(cfg) = &state.config {
+                ui.separator();
+                self.preview.ui(ui, cfg);
+                self.draw_device_levels(ui, &state);
                 ui.separator();
+                self.draw_device_config_changed(ui, &mut state);
+                draw_history_controls(ui, &mut state);
                 self.draw_config_editor(ui, &mut state);
             }
         });
+
+        record_history(ctx, &mut state);
+        self.schedule_auto_write(&state);
+        self.draw_rename_dialog(ctx, &state);
+        self.draw_preset_name_dialog(ctx, &mut state);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        self.confirm_close(ctx, &state);
         
         // Request repaint for animations/updates
         ctx.request_repaint_after(Duration::from_secs(1));
@@ -509,7 +1258,208 @@ impl PartylightApp {
         ctx.set_style(style);
     }
     
-    fn draw_header(&self, ui: &mut egui::Ui) {
+    /// Live bar chart of the levels the device measures, one bar per channel
+    fn draw_device_levels(&self, ui: &mut egui::Ui, state: &AppState) {
+        let Some(cfg) = &state.device_config else {
+            return;
+        };
+        if state.device_levels.is_empty() || !matches!(state.conn, ConnectionStatus::Connected(_)) {
+            return;
+        }
+        
+        CollapsingHeader::new("Device levels")
+            .default_open(true)
+            .show(ui, |ui| {
+                const BAR_WIDTH: f32 = 24.0;
+                const HEIGHT: f32 = 80.0;
+                let channels = cfg.pattern.channels();
+                let (rect, _) = ui.allocate_exact_size(
+                    egui::vec2(BAR_WIDTH * channels.len() as f32, HEIGHT),
+                    egui::Sense::hover(),
+                );
+                let painter = ui.painter_at(rect);
+                painter.rect_filled(rect, 0.0, Color32::from_gray(24));
+                for (i, (ch, level)) in channels.iter().zip(&state.device_levels).enumerate() {
+                    let color = Color32::from_rgb(
+                        (ch.color[0] * 255.0) as u8,
+                        (ch.color[1] * 255.0) as u8,
+                        (ch.color[2] * 255.0) as u8,
+                    );
+                    let left = rect.left() + i as f32 * BAR_WIDTH;
+                    let top = rect.bottom() - level.clamp(0.0, 1.0) * HEIGHT;
+                    let bar = egui::Rect::from_min_max(
+                        egui::pos2(left, top),
+                        egui::pos2(left + BAR_WIDTH, rect.bottom()),
+                    )
+                    .shrink2(egui::vec2(2.0, 0.0));
+                    painter.rect_filled(bar, 0.0, color);
+                    // the LEDs saturate above 1.0, mark it so the sensitivity can be turned down
+                    if *level > 1.0 {
+                        painter.rect_filled(
+                            egui::Rect::from_min_size(bar.min, egui::vec2(bar.width(), 4.0)),
+                            0.0,
+                            colors::PINK,
+                        );
+                    }
+                }
+                
+                // the device sends ~10 updates per second
+                ui.ctx().request_repaint_after(Duration::from_millis(100));
+            });
+    }
+    
+    /// Rows of the device grid for the status the device notifies every second
+    fn draw_device_status(&self, ui: &mut egui::Ui, status: &DeviceStatus) {
+        use common::status::AudioInput;
+
+        let uptime = status.uptime_s;
+        ui.label("Uptime:");
+        ui.label(format!("{}:{:02}:{:02}", uptime / 3600, uptime / 60 % 60, uptime % 60));
+        ui.end_row();
+        ui.label("Free heap:");
+        ui.label(format!("{:.1} kB", status.free_heap as f32 / 1024.0));
+        ui.end_row();
+        ui.label("LED frames:");
+        ui.label(format!("{:.1} /s", status.led_fps))
+            .on_hover_text("Including the blended frames in between the new ones");
+        ui.end_row();
+        ui.label("New frames:");
+        ui.label(format!("{:.1} /s", status.frame_fps));
+        ui.end_row();
+        ui.label("Dropped frames:");
+        ui.label(status.dropped_frames.to_string())
+            .on_hover_text("Replaced by a newer frame before the LEDs showed them, e.g. because of the target FPS");
+        ui.end_row();
+        ui.label("Audio frames:");
+        let audio_rate = format!("{:.1} /s", status.audio_fps);
+        if status.audio_fps == 0.0 {
+            // no audio arriving, the LEDs only show the idle pattern
+            ui.colored_label(Color32::RED, audio_rate);
+        } else {
+            ui.label(audio_rate);
+        }
+        ui.end_row();
+        ui.label("Audio source:");
+        ui.label(match status.audio_input {
+            AudioInput::None => "none",
+            AudioInput::Usb => "USB",
+            AudioInput::I2s => "I2S",
+        });
+        ui.end_row();
+        if status.audio_input == AudioInput::Usb {
+            // both should stay at 0, otherwise the clocks of host and device drift apart
+            ui.label("USB overflows / underruns:");
+            let counts = format!("{} / {}", status.usb_overflows, status.usb_underruns);
+            if status.usb_overflows > 0 || status.usb_underruns > 0 {
+                ui.colored_label(Color32::from_rgb(255, 140, 0), counts);
+            } else {
+                ui.label(counts);
+            }
+            ui.end_row();
+            ui.label("USB packets:");
+            ui.label(format!("{} /s", status.usb_packets));
+            ui.end_row();
+        }
+        ui.label("Dropped audio blocks:");
+        if status.dropped_blocks > 0 {
+            // the FFT can't keep up, e.g. with a large fft_size
+            ui.colored_label(Color32::from_rgb(255, 140, 0), status.dropped_blocks.to_string());
+        } else {
+            ui.label("0");
+        }
+        ui.end_row();
+        ui.label("Power limit:");
+        if status.power_limited {
+            ui.colored_label(Color32::from_rgb(255, 140, 0), "dimming")
+                .on_hover_text("Frames would draw more than the max current, they are shown darker");
+        } else {
+            ui.label("-");
+        }
+        ui.end_row();
+    }
+
+    /// Log console for the lines the device streams
+    fn draw_device_log(&self, ui: &mut egui::Ui, state: &mut AppState) {
+        /// Name of a [`log::LevelFilter`] sent as its number
+        fn log_level_name(level: u8) -> &'static str {
+            match level {
+                0 => "Off",
+                1 => "Error",
+                2 => "Warn",
+                3 => "Info",
+                4 => "Debug",
+                _ => "Trace",
+            }
+        }
+
+        CollapsingHeader::new("Log").show(ui, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Level:");
+                let mut level = state.device_log_level;
+                egui::ComboBox::from_id_salt("device_log_level")
+                    .selected_text(log_level_name(level))
+                    .show_ui(ui, |ui| {
+                        for l in 0..=5 {
+                            ui.selectable_value(&mut level, l, log_level_name(l));
+                        }
+                    });
+                if level != state.device_log_level {
+                    let _ = self.handler.send_message(HandlerMessage::SetLogLevel(level));
+                }
+                if ui.button("Clear").clicked() {
+                    state.device_log.clear();
+                }
+            });
+
+            egui::ScrollArea::vertical()
+                .max_height(200.0)
+                .stick_to_bottom(true)
+                .show(ui, |ui| {
+                    for line in &state.device_log {
+                        ui.monospace(line);
+                    }
+                });
+        });
+        
+        // new lines arrive without any input
+        ui.ctx().request_repaint_after(Duration::from_millis(500));
+    }
+
+    /// Hand each new edit to the handler while [`AppState::auto_write`] is on, it waits for them
+    /// to settle before writing
+    fn schedule_auto_write(&mut self, state: &AppState) {
+        // also after a reload, so the same edit made again is scheduled again
+        let connected = matches!(state.conn, ConnectionStatus::Connected(_));
+        if !state.auto_write || !state.is_dirty() || !connected {
+            self.auto_write_sent = None;
+            return;
+        }
+        if let Some(cfg) = &state.config
+            && self.auto_write_sent.as_ref() != Some(cfg)
+        {
+            self.auto_write_sent = Some(cfg.clone());
+            let _ = self.handler.send_message(HandlerMessage::ScheduleWrite(cfg.clone()));
+        }
+    }
+
+    /// Banner shown when the device config changed while there are unsaved local edits
+    fn draw_device_config_changed(&self, ui: &mut egui::Ui, state: &mut AppState) {
+        if !state.device_config_changed {
+            return;
+        }
+        ui.horizontal(|ui| {
+            ui.colored_label(colors::PINK, "The config on the device changed.");
+            if ui.button("Load it").clicked() {
+                state.config = state.device_config.clone();
+                state.device_config_changed = false;
+            }
+            if ui.button("Keep my changes").clicked() {
+                state.device_config_changed = false;
+            }
+        });
+    }
+    
+    fn draw_header(&self, ui: &mut egui::Ui, state: &AppState) {
         let painter = ui.painter();
         let rect = ui.max_rect();
         let x = rect.left() + 24.0;
@@ -533,43 +1483,627 @@ impl PartylightApp {
             FontId::new(36.0, FontFamily::Name(Arc::from("Cynatar"))),
             Color32::from_rgb(255, 212, 0),
         );
+
+        if let (ConnectionStatus::Connected(_), Some(name)) = (&state.conn, &state.device_name) {
+            painter.text(
+                egui::pos2(rect.right() - 24.0, y),
+                egui::Align2::RIGHT_TOP,
+                name,
+                FontId::proportional(20.0),
+                colors::PINK,
+            );
+        }
+    }
+
+    /// The panels and how they are mounted, and what happens when the frames stop
+    fn draw_device_settings(ui: &mut egui::Ui, cfg: &mut AppConfig) {
+        let mut device = cfg.device();
+        let layout = &mut device.layout;
+        ui.horizontal(|ui| {
+            ui.label("Panels:");
+            ui.add(egui::widgets::DragValue::new(&mut layout.panels).range(1..=MAX_PANELS as u8));
+            if layout.panels > 1 {
+                egui::ComboBox::from_id_salt("panel_arrangement")
+                    .selected_text(panel_arrangement_name(layout.arrangement))
+                    .show_ui(ui, |ui| {
+                        for a in PanelArrangement::ALL {
+                            ui.selectable_value(&mut layout.arrangement, a, panel_arrangement_name(a));
+                        }
+                    })
+                    .response
+                    .on_hover_text("Where the second panel of the chain sits");
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Rotation:");
+            for (i, rotation) in layout.rotations.iter_mut().take(layout.panels as usize).enumerate() {
+                egui::ComboBox::from_id_salt(("panel_rotation", i))
+                    .selected_text(panel_rotation_name(*rotation))
+                    .show_ui(ui, |ui| {
+                        for r in PanelRotation::ALL {
+                            ui.selectable_value(rotation, r, panel_rotation_name(r));
+                        }
+                    })
+                    .response
+                    .on_hover_text("Clockwise, from the strip starting top left and running down");
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Fade out after:");
+            ui.add(egui::widgets::DragValue::new(&mut device.fade_timeout_ms).speed(10.0).suffix(" ms"))
+                .on_hover_text("Without new frames, e.g. once the audio stopped");
+            if device.fade_timeout_ms == 0 {
+                ui.weak("(never)");
+            } else {
+                ui.label("over:");
+                ui.add(egui::widgets::DragValue::new(&mut device.fade_duration_ms).speed(10.0).suffix(" ms"));
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Target FPS:");
+            ui.add(egui::widgets::DragValue::new(&mut device.target_fps).range(0..=120));
+            if device.target_fps == 0 {
+                ui.weak("(every frame)");
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("I2S microphone:");
+            egui::ComboBox::from_id_salt("i2s_sample_rate")
+                .selected_text(i2s_sample_rate_name(device.i2s_sample_rate))
+                .show_ui(ui, |ui| {
+                    for r in I2sSampleRate::ALL {
+                        ui.selectable_value(&mut device.i2s_sample_rate, r, i2s_sample_rate_name(r));
+                    }
+                })
+                .response
+                .on_hover_text("Only applied when the device starts");
+            egui::ComboBox::from_id_salt("i2s_bit_depth")
+                .selected_text(i2s_bit_depth_name(device.i2s_bit_depth))
+                .show_ui(ui, |ui| {
+                    for d in I2sBitDepth::ALL {
+                        ui.selectable_value(&mut device.i2s_bit_depth, d, i2s_bit_depth_name(d));
+                    }
+                })
+                .response
+                .on_hover_text("How many bits of each sample the microphone fills, 24 for the INMP441");
+        });
+        ui.horizontal(|ui| {
+            ui.label("I2S channels:");
+            egui::ComboBox::from_id_salt("i2s_channel_source")
+                .selected_text(i2s_channel_source_name(device.i2s_channel_source))
+                .show_ui(ui, |ui| {
+                    for c in I2sChannelSource::ALL {
+                        ui.selectable_value(&mut device.i2s_channel_source, c, i2s_channel_source_name(c));
+                    }
+                })
+                .response
+                .on_hover_text("A single microphone only sends on one side, it can drive both");
+            ui.checkbox(&mut device.i2s_swap_channels, "Swap left / right")
+                .on_hover_text("For microphones wired to the other word select phase, if the wrong side stays silent");
+        });
+        ui.horizontal(|ui| {
+            ui.label("High-pass:");
+            ui.add(egui::widgets::DragValue::new(&mut device.high_pass_hz).range(0..=200).suffix(" Hz"))
+                .on_hover_text("Takes out the DC offset of the microphone before the FFT");
+            if device.high_pass_hz == 0 {
+                ui.weak("(off)");
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("White balance:")
+                .on_hover_text("Scales every frame, to calibrate LEDs whose white is off");
+            for (gain, name) in device.white_balance.iter_mut().zip(["R", "G", "B"]) {
+                ui.add(egui::Slider::new(gain, 0.0..=1.0).text(name));
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Stereo:");
+            egui::ComboBox::from_id_salt("stereo_mode")
+                .selected_text(stereo_mode_name(device.stereo_mode))
+                .show_ui(ui, |ui| {
+                    for m in StereoMode::ALL {
+                        ui.selectable_value(&mut device.stereo_mode, m, stereo_mode_name(m));
+                    }
+                })
+                .response
+                .on_hover_text("Split: the pattern at half the width on each side, mirrored from the middle");
+        });
+        ui.horizontal(|ui| {
+            ui.label("Overlay:");
+            egui::ComboBox::from_id_salt("overlay")
+                .selected_text(overlay_name(&device.overlay))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut device.overlay, Overlay::None, overlay_name(&Overlay::None));
+                    // keep the period if it's already breathing
+                    if !matches!(device.overlay, Overlay::Breathe { .. }) {
+                        let breathe = Overlay::Breathe { period_ms: 4000 };
+                        ui.selectable_value(&mut device.overlay, breathe, overlay_name(&breathe));
+                    }
+                })
+                .response
+                .on_hover_text("Layered over the pattern");
+            if let Overlay::Breathe { period_ms } = &mut device.overlay {
+                ui.label("every:");
+                ui.add(egui::widgets::DragValue::new(period_ms).speed(50.0).range(500..=u16::MAX).suffix(" ms"));
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Pause advertising after:");
+            ui.add(egui::widgets::DragValue::new(&mut device.advertise_pause_after_min).suffix(" min"))
+                .on_hover_text("Without a connection. Advertising picks up again after 10 min or a restart.");
+            if device.advertise_pause_after_min == 0 {
+                ui.weak("(never)");
+            }
+        });
+        if device != cfg.device() {
+            cfg.set_device(device);
+        }
+    }
+
+    /// Pick a preset slot, to switch to its config or to save the running one into it
+    fn draw_slots(&mut self, ui: &mut egui::Ui, state: &AppState) {
+        fn slot_label(index: usize, name: &str) -> String {
+            if name.is_empty() {
+                format!("{}: (empty)", index + 1)
+            } else {
+                format!("{}: {name}", index + 1)
+            }
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("Slot:");
+            let current = state.device_slots.get(self.slot).map(String::as_str).unwrap_or_default();
+            egui::ComboBox::from_id_salt("preset_slot")
+                .selected_text(slot_label(self.slot, current))
+                .show_ui(ui, |ui| {
+                    for (i, name) in state.device_slots.iter().enumerate() {
+                        if ui.selectable_value(&mut self.slot, i, slot_label(i, name)).clicked() {
+                            self.slot_name = name.clone();
+                        }
+                    }
+                });
+            if ui.add_enabled(!state.busy && !current.is_empty(), Button::new("Select")).clicked() {
+                let _ = self.handler.send_message(HandlerMessage::SelectSlot(self.slot as u8));
+            }
+
+            ui.add(egui::TextEdit::singleline(&mut self.slot_name)
+                .char_limit(common::persist::MAX_SLOT_NAME_SIZE)
+                .hint_text("name")
+                .desired_width(100.0));
+            let valid = common::persist::slot_name_from_bytes(self.slot_name.as_bytes()).is_some();
+            // the device saves the config it runs, not the one in the editor
+            let save = ui.add_enabled(!state.busy && valid && !state.is_dirty(), Button::new("Save"))
+                .on_hover_text("Save the config the device runs into this slot")
+                .on_disabled_hover_text("Write your changes first, the device saves the config it runs");
+            if save.clicked() {
+                let _ = self.handler.send_message(HandlerMessage::SaveSlot(self.slot as u8, self.slot_name.clone()));
+            }
+        });
+    }
+
+    /// Ask for the new name after Rename was clicked
+    fn draw_rename_dialog(&mut self, ctx: &egui::Context, state: &AppState) {
+        use common::persist::{MAX_DEVICE_NAME_SIZE, device_name_from_bytes};
+
+        let Some(name) = &mut self.rename else {
+            return;
+        };
+        let mut close = false;
+        egui::Window::new("Rename device")
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label("Used by the device picker after the device reconnects.");
+                ui.add(egui::TextEdit::singleline(name).char_limit(MAX_DEVICE_NAME_SIZE));
+                // the firmware limits bytes, not characters
+                let valid = device_name_from_bytes(name.as_bytes()).is_some();
+                if !valid {
+                    ui.colored_label(colors::PINK, format!("1 to {MAX_DEVICE_NAME_SIZE} bytes"));
+                }
+                ui.horizontal(|ui| {
+                    if ui.add_enabled(valid && !state.busy, Button::new("Rename")).clicked() {
+                        let _ = self.handler.send_message(HandlerMessage::Rename(name.clone()));
+                        close = true;
+                    }
+                    if ui.button("Cancel").clicked() {
+                        close = true;
+                    }
+                });
+            });
+        if close {
+            self.rename = None;
+        }
     }
     
-    fn draw_connection_controls(&mut self, ui: &mut egui::Ui, state: &AppState) {
-        match &state.conn {
-            ConnectionStatus::Disconnected => {
+    /// A button per user preset, with a menu to rename or delete it, and one to save the current
+    /// config as a new one
+    fn draw_user_presets(&mut self, ui: &mut egui::Ui, state: &mut AppState) {
+        let mut delete = None;
+        for (i, preset) in self.user_presets.iter().enumerate() {
+            match preset.config() {
+                Ok(cfg) => {
+                    if ui.button(&preset.name).clicked() {
+                        let _ = self.handler.send_message(HandlerMessage::SetConfig(cfg));
+                        let _ = self.handler.send_message(HandlerMessage::SetStatus(format!("Loaded preset {}", preset.name)));
+                    }
+                }
+                // kept, so it's not lost for an app that can load it
+                Err(e) => {
+                    ui.add_enabled(false, Button::new(&preset.name)).on_disabled_hover_text(e);
+                }
+            }
+            ui.menu_button("...", |ui| {
+                if ui.button("Rename…").clicked() {
+                    self.preset_name = Some(PresetNameDialog { renaming: Some(i), name: preset.name.clone() });
+                    ui.close_menu();
+                }
+                if ui.button("Delete").clicked() {
+                    delete = Some(i);
+                    ui.close_menu();
+                }
+            });
+        }
+        if let Some(i) = delete {
+            let preset = self.user_presets.remove(i);
+            state.set_status(format!("Deleted preset {}", preset.name));
+            self.store_user_presets(state);
+        }
+        if ui.button("Save current as preset…").clicked() {
+            self.preset_name = Some(PresetNameDialog { renaming: None, name: String::new() });
+        }
+    }
+
+    /// Ask for the name after saving or renaming a user preset was clicked
+    fn draw_preset_name_dialog(&mut self, ctx: &egui::Context, state: &mut AppState) {
+        let Some(dialog) = &mut self.preset_name else {
+            return;
+        };
+        let mut close = false;
+        let mut confirmed = false;
+        let title = if dialog.renaming.is_some() { "Rename preset" } else { "Save preset" };
+        egui::Window::new(title)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.text_edit_singleline(&mut dialog.name);
+                let name = dialog.name.trim();
+                let existing = self.user_presets.iter().position(|p| p.name == name);
+                let valid = match dialog.renaming {
+                    _ if name.is_empty() => false,
+                    Some(i) => existing.is_none_or(|e| e == i),
+                    None => true,
+                };
+                match (dialog.renaming, existing) {
+                    (Some(i), Some(e)) if e != i => {
+                        ui.colored_label(colors::PINK, "There is already a preset with this name");
+                    }
+                    (None, Some(_)) => {
+                        ui.colored_label(colors::PINK, "Replaces the preset with this name");
+                    }
+                    _ => {}
+                }
                 ui.horizontal(|ui| {
-                    if ui.add(Button::new("Connect")).clicked() {
-                        let _ = self.handler.send_message(HandlerMessage::Connect);
+                    let label = if dialog.renaming.is_some() { "Rename" } else { "Save" };
+                    confirmed = ui.add_enabled(valid, Button::new(label)).clicked();
+                    close = confirmed || ui.button("Cancel").clicked();
+                });
+            });
+        if confirmed {
+            let name = dialog.name.trim().to_string();
+            match dialog.renaming {
+                Some(i) => {
+                    state.set_status(format!("Renamed preset {} to {name}", self.user_presets[i].name));
+                    self.user_presets[i].name = name;
+                }
+                None => {
+                    let preset = state.config.as_ref().map(|cfg| UserPreset::new(name.clone(), cfg));
+                    match preset {
+                        Some(Ok(preset)) => {
+                            match self.user_presets.iter_mut().find(|p| p.name == name) {
+                                Some(existing) => *existing = preset,
+                                None => self.user_presets.push(preset),
+                            }
+                            state.set_status(format!("Saved preset {name}"));
+                        }
+                        Some(Err(e)) => state.set_status(e),
+                        None => {}
                     }
+                }
+            }
+            self.store_user_presets(state);
+        }
+        if close {
+            self.preset_name = None;
+        }
+    }
+
+    /// Keep [`Self::user_presets`] for the next session
+    fn store_user_presets(&self, state: &mut AppState) {
+        if let Err(e) = user_presets::save(&self.user_presets) {
+            state.set_status(e);
+        }
+    }
+
+    /// Shown instead of Connect when there's no Bluetooth, configs can still be edited and saved
+    fn draw_bluetooth_unavailable(ui: &mut egui::Ui, reason: &str) {
+        ui.group(|ui| {
+            ui.colored_label(colors::PINK, format!("Can't connect: {reason}."));
+            if cfg!(target_arch = "wasm32") {
+                ui.label(
+                    "Web Bluetooth works in Chrome, Edge and Opera on the desktop and on Android, \
+                     over HTTPS. Firefox and the browsers on iOS don't have it.",
+                );
+                ui.horizontal(|ui| {
+                    ui.label("Or use the desktop app, see");
+                    ui.hyperlink_to("the repository", "https://github.com/0x53A/esp32-partylight");
                 });
+            } else {
+                ui.label("Check that Bluetooth is switched on, then restart the app.");
+            }
+        });
+    }
+
+    /// Four bars like on a phone, followed by the RSSI
+    fn signal_strength(ui: &mut egui::Ui, rssi: i8) {
+        /// RSSI in dBm from which on the signal counts as weak, the connection may start to drop
+        const WEAK_RSSI: i8 = -85;
+
+        let bars = match rssi {
+            -60.. => 4,
+            -70.. => 3,
+            -80.. => 2,
+            _ if rssi >= WEAK_RSSI => 1,
+            _ => 0,
+        };
+        let color = if rssi < WEAK_RSSI {
+            Color32::from_rgb(255, 140, 0)
+        } else {
+            ui.visuals().text_color()
+        };
+
+        let height = ui.text_style_height(&egui::TextStyle::Body);
+        let (rect, response) = ui.allocate_exact_size(egui::Vec2::new(height, height), egui::Sense::hover());
+        let bar_width = rect.width() / 4.0;
+        for i in 0..4 {
+            let bar_height = rect.height() * (i + 1) as f32 / 4.0;
+            let bar = egui::Rect::from_min_max(
+                egui::pos2(rect.left() + i as f32 * bar_width, rect.bottom() - bar_height),
+                egui::pos2(rect.left() + (i as f32 + 0.8) * bar_width, rect.bottom()),
+            );
+            let fill = if i < bars { color } else { ui.visuals().weak_text_color() };
+            ui.painter().rect_filled(bar, 0.0, fill);
+        }
+        let text = format!("{rssi} dBm");
+        if rssi < WEAK_RSSI {
+            ui.colored_label(color, text);
+            response.on_hover_text("Weak signal, move the device or the computer closer");
+        } else {
+            ui.weak(text);
+        }
+    }
+
+    fn draw_connection_controls(&mut self, ui: &mut egui::Ui, state: &mut AppState) {
+        match &state.conn {
+            ConnectionStatus::Disconnected => {
+                if let Some(reason) = &state.bluetooth_unavailable {
+                    Self::draw_bluetooth_unavailable(ui, reason);
+                } else {
+                    ui.horizontal(|ui| {
+                        if ui.add(Button::new("Connect")).clicked() {
+                            let _ = self.handler.send_message(HandlerMessage::Connect);
+                        }
+                    });
+                }
+            }
+            
+            ConnectionStatus::Connecting if !state.discovered.is_empty() => {
+                ui.label("Select a device:");
+                for device in &state.discovered {
+                    let rssi = match device.rssi {
+                        Some(rssi) => format!("{rssi} dBm"),
+                        None => "? dBm".to_string(),
+                    };
+                    if ui.add_enabled(!state.busy, Button::new(format!("{} ({rssi})", device.name))).clicked() {
+                        let _ = self.handler.send_message(HandlerMessage::ConnectTo(device.id.clone()));
+                    }
+                }
+                if ui.button("Cancel").clicked() {
+                    let _ = self.handler.send_message(HandlerMessage::Disconnect);
+                }
             }
             
             ConnectionStatus::Connecting => {
                 ui.horizontal(|ui| {
                     ui.label("Connecting...");
                     ui.add_enabled(false, Button::new("Connect"));
+                    // the device picker comes up with Connect afterwards
+                    if state.auto_connecting && ui.button("Cancel").clicked() {
+                        state.auto_connecting = false;
+                        state.set_status("Cancelling...".to_string());
+                    }
                 });
             }
             
             ConnectionStatus::Connected(_cfg) => {
                 ui.horizontal(|ui| {
                     ui.label("Connected");
+                    if let Some(rssi) = state.device_rssi {
+                        Self::signal_strength(ui, rssi);
+                    }
+                    
+                    if let Some(enabled) = state.device_enabled {
+                        let mut on = enabled;
+                        let text = if enabled { "LEDs on" } else { "LEDs off" };
+                        ui.toggle_value(&mut on, text)
+                            .on_hover_text("Switch the LEDs on or off, the device stays connected");
+                        if on != enabled {
+                            let _ = self.handler.send_message(HandlerMessage::SetEnabled(on));
+                        }
+                    }
                     
                     if ui.add_enabled(!state.busy, Button::new("Reload")).clicked() {
                         let _ = self.handler.send_message(HandlerMessage::Reload);
                     }
                     
-                    if ui.add_enabled(!state.busy, Button::new("Write")).clicked() {
-                        if let Some(cfg) = &state.config {
+                    // nothing to write while the device already has the edited config
+                    let accepted = state.device_config_versions.as_ref().is_none_or(|v| v.contains(&CONFIG_VERSION));
+                    // the device would reject it, or worse, take it cut off
+                    let size = state.config.as_ref().map_or(0, AppConfig::serialized_size);
+                    let fits = size <= state.max_config_size();
+                    if ui.add_enabled(!state.busy && state.is_dirty() && fits, Button::new("Write")).clicked() {
+                        if !accepted {
+                            self.confirm_version_write = true;
+                        } else if let Some(cfg) = &state.config {
                             let _ = self.handler.send_message(HandlerMessage::Write(cfg.clone()));
                         }
                     }
+                    let size_text = format!("{size} / {} bytes", state.max_config_size());
+                    if fits {
+                        ui.weak(size_text);
+                    } else {
+                        ui.colored_label(Color32::RED, size_text)
+                            .on_hover_text("Too large for the device, take out channels or reset device settings");
+                    }
+
+                    // without asking first, the device couldn't read what it gets
+                    ui.add_enabled(accepted, egui::Checkbox::new(&mut state.auto_write, "Auto-write"))
+                        .on_hover_text("Write every change to the device once the edits settle");
+
+                    if state.is_dirty() {
+                        ui.colored_label(Color32::from_rgb(255, 140, 0), "● modified, not written yet");
+                    }
                     
                     if ui.add_enabled(!state.busy, Button::new("Disconnect")).clicked() {
-                        let _ = self.handler.send_message(HandlerMessage::StopHeartbeat);
-                        let _ = self.handler.send_message(HandlerMessage::Disconnect);
+                        if state.is_dirty() {
+                            self.confirm_disconnect = true;
+                        } else {
+                            self.disconnect();
+                        }
+                    }
+                });
+
+                if self.confirm_version_write
+                    && let Some(versions) = &state.device_config_versions
+                {
+                    ui.colored_label(Color32::from_rgb(255, 140, 0), version_mismatch_message(versions));
+                    ui.horizontal(|ui| {
+                        // there's no migration between versions yet, so the config can only be
+                        // sent as it is
+                        if ui.button("Write anyway").clicked() {
+                            self.confirm_version_write = false;
+                            if let Some(cfg) = &state.config {
+                                let _ = self.handler.send_message(HandlerMessage::Write(cfg.clone()));
+                            }
+                        }
+                        if ui.button("Cancel").clicked() {
+                            self.confirm_version_write = false;
+                        }
+                    });
+                }
+
+                if self.confirm_disconnect {
+                    ui.horizontal(|ui| {
+                        ui.colored_label(Color32::from_rgb(255, 140, 0), "The device doesn't have your changes yet, disconnect anyway?");
+                        if ui.button("Disconnect").clicked() {
+                            self.disconnect();
+                        }
+                        if ui.button("Cancel").clicked() {
+                            self.confirm_disconnect = false;
+                        }
+                    });
+                }
+
+                if !state.device_slots.is_empty() {
+                    self.draw_slots(ui, state);
+                }
+
+                CollapsingHeader::new("Device").show(ui, |ui| {
+                    if state.device_info.is_some() || state.device_status.is_some() {
+                        egui::Grid::new("device_info").show(ui, |ui| {
+                            if let Some(info) = &state.device_info {
+                                ui.label("Manufacturer:");
+                                ui.label(&info.manufacturer);
+                                ui.end_row();
+                                ui.label("Firmware:");
+                                ui.label(&info.firmware_revision);
+                                ui.end_row();
+                                ui.label("Hardware:");
+                                ui.label(&info.hardware_revision);
+                                ui.end_row();
+                            }
+                            if let Some(status) = &state.device_status {
+                                self.draw_device_status(ui, status);
+                            }
+                        });
                     }
+
+                    ui.horizontal(|ui| {
+                        if ui.button("Identify").on_hover_text("Blink the matrix white").clicked() {
+                            let _ = self.handler.send_message(HandlerMessage::Command(DeviceCommand::Identify));
+                        }
+                        if ui.button("Demo").on_hover_text("Run the startup animation").clicked() {
+                            let _ = self.handler.send_message(HandlerMessage::Command(DeviceCommand::Demo));
+                        }
+                        if ui.button("Test wiring")
+                            .on_hover_text("Walk a pixel along the rows, starting top left")
+                            .clicked()
+                        {
+                            let _ = self.handler.send_message(HandlerMessage::Command(DeviceCommand::TestPattern));
+                        }
+                        if ui.button("Timing")
+                            .on_hover_text("Repeat a still frame for 10 seconds, to check the data signal with a scope")
+                            .clicked()
+                        {
+                            let _ = self.handler.send_message(HandlerMessage::Command(DeviceCommand::TimingPattern));
+                        }
+                        if ui.button("Freeze")
+                            .on_hover_text("Hold the current frame, e.g. for a photo")
+                            .clicked()
+                        {
+                            let _ = self.handler.send_message(HandlerMessage::Command(DeviceCommand::Freeze));
+                        }
+                        if ui.button("Unfreeze").clicked() {
+                            let _ = self.handler.send_message(HandlerMessage::Command(DeviceCommand::Unfreeze));
+                        }
+                        if ui.button("Reboot").clicked() {
+                            let _ = self.handler.send_message(HandlerMessage::Command(DeviceCommand::Reboot));
+                        }
+                        if let Some(name) = &state.device_name
+                            && ui.button("Rename...").clicked()
+                        {
+                            self.rename = Some(name.clone());
+                        }
+                    });
+
+                    ui.horizontal(|ui| {
+                        ui.label("Noise floor:");
+                        if ui.button("Calibrate")
+                            .on_hover_text("Measure the room for two seconds while nothing plays, the noise is subtracted from then on")
+                            .clicked()
+                        {
+                            let _ = self.handler.send_message(HandlerMessage::Command(DeviceCommand::CalibrateNoiseFloor));
+                        }
+                        if ui.button("Clear").clicked() {
+                            let _ = self.handler.send_message(HandlerMessage::Command(DeviceCommand::ClearNoiseFloor));
+                        }
+                    });
+
+                    self.draw_device_log(ui, state);
+
+                    ui.horizontal(|ui| {
+                        if self.confirm_factory_reset {
+                            ui.colored_label(Color32::from_rgb(255, 140, 0), "Erase the config stored on the device?");
+                            if ui.add_enabled(!state.busy, Button::new("Reset")).clicked() {
+                                self.confirm_factory_reset = false;
+                                let _ = self.handler.send_message(HandlerMessage::FactoryReset);
+                            }
+                            if ui.button("Cancel").clicked() {
+                                self.confirm_factory_reset = false;
+                            }
+                        } else if ui.add_enabled(!state.busy, Button::new("Reset to defaults")).clicked() {
+                            self.confirm_factory_reset = true;
+                        }
+                    });
                 });
             }
             
@@ -599,14 +2133,111 @@ impl PartylightApp {
                 };
                 ui.colored_label(color, format!("({:.1}s ago)", elapsed));
             }
+
+            if let Some(telemetry) = &state.device_telemetry {
+                ui.separator();
+                ui.label(format!(
+                    "FFT: {:.0} /s, {:.2} ms avg, {:.2} ms max",
+                    telemetry.fft_fps,
+                    telemetry.fft_avg_us as f32 / 1000.0,
+                    telemetry.fft_max_us as f32 / 1000.0
+                ))
+                .on_hover_text("How long the device takes to process a block of audio");
+            }
         });
+        draw_status_log(ui, state);
     }
     
-    fn draw_config_editor(&self, ui: &mut egui::Ui, state: &mut AppState) {
+    fn disconnect(&mut self) {
+        self.confirm_disconnect = false;
+        let _ = self.handler.send_message(HandlerMessage::StopHeartbeat);
+        let _ = self.handler.send_message(HandlerMessage::Disconnect);
+    }
+
+    fn draw_file_controls(&self, ui: &mut egui::Ui, state: &AppState) {
+        ui.horizontal(|ui| {
+            if ui.add_enabled(state.config.is_some(), Button::new("Save config…")).clicked() {
+                if let Some(cfg) = &state.config {
+                    let status = config_file::save(cfg).unwrap_or_else(|e| e);
+                    let _ = self.handler.send_message(HandlerMessage::SetStatus(status));
+                }
+            }
+
+            #[cfg(target_arch = "wasm32")]
+            if ui.button("Load config…").clicked() {
+                let handler = self.handler.clone();
+                let res = config_file::load(move |res| match res {
+                    Ok(cfg) => {
+                        let _ = handler.send_message(HandlerMessage::SetConfig(cfg));
+                        let _ = handler.send_message(HandlerMessage::SetStatus("Loaded config from file".to_string()));
+                    }
+                    Err(e) => {
+                        let _ = handler.send_message(HandlerMessage::SetStatus(e));
+                    }
+                });
+                if let Err(e) = res {
+                    let _ = self.handler.send_message(HandlerMessage::SetStatus(e));
+                }
+            }
+
+            #[cfg(not(target_arch = "wasm32"))]
+            if ui.button("Load config…").clicked() {
+                match config_file::load() {
+                    Ok(Some(cfg)) => {
+                        let _ = self.handler.send_message(HandlerMessage::SetConfig(cfg));
+                        let _ = self.handler.send_message(HandlerMessage::SetStatus("Loaded config from file".to_string()));
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        let _ = self.handler.send_message(HandlerMessage::SetStatus(e));
+                    }
+                }
+            }
+        });
+    }
+
+    /// Keep the window open while the device doesn't have the latest changes, until the user confirms
+    #[cfg(not(target_arch = "wasm32"))]
+    fn confirm_close(&mut self, ctx: &egui::Context, state: &AppState) {
+        if ctx.input(|i| i.viewport().close_requested()) && state.is_dirty() && !self.close_confirmed {
+            ctx.send_viewport_cmd(egui::ViewportCommand::CancelClose);
+            self.confirm_close = true;
+        }
+
+        if self.confirm_close {
+            egui::Window::new("Unsaved changes")
+                .collapsible(false)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    ui.label("The device doesn't have your changes yet, close anyway?");
+                    ui.horizontal(|ui| {
+                        if ui.button("Close").clicked() {
+                            self.close_confirmed = true;
+                            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                        }
+                        if ui.button("Cancel").clicked() {
+                            self.confirm_close = false;
+                        }
+                    });
+                });
+        }
+    }
+
+    fn draw_config_editor(&mut self, ui: &mut egui::Ui, state: &mut AppState) {
         
         // only render the editor when we have a config loaded from the device
         if let Some(cfg) = &mut state.config {
             ui.label("Basic settings:");
+            ui.horizontal(|ui| {
+                ui.label("Audio input:");
+                egui::ComboBox::from_id_salt("input_source")
+                    .selected_text(input_source_name(cfg.input_source))
+                    .show_ui(ui, |ui| {
+                        for source in InputSource::ALL {
+                            ui.selectable_value(&mut cfg.input_source, source, input_source_name(source));
+                        }
+                    });
+            });
             ui.horizontal(|ui| {
                 ui.label("Sample count:");
                 let mut sc = cfg.sample_count as u32;
@@ -614,18 +2245,89 @@ impl PartylightApp {
                     cfg.sample_count = sc as usize;
                 }
             });
+            ui.horizontal(|ui| {
+                ui.label("Spectral averages:");
+                ui.add(egui::widgets::DragValue::new(&mut cfg.spectral_averages).range(1..=16))
+                    .on_hover_text("Average the spectra of this many blocks per frame: less noise, fewer frames");
+            });
             
             ui.horizontal(|ui| {
-                ui.label("Use Hann window:");
-                ui.checkbox(&mut cfg.use_hann_window, "");
+                ui.label("Window:");
+                egui::ComboBox::from_id_salt("window_function")
+                    .selected_text(window_function_name(cfg.window_function))
+                    .show_ui(ui, |ui| {
+                        for w in [
+                            WindowFunction::None,
+                            WindowFunction::Hann,
+                            WindowFunction::Hamming,
+                            WindowFunction::BlackmanHarris,
+                        ] {
+                            ui.selectable_value(&mut cfg.window_function, w, window_function_name(w));
+                        }
+                    });
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("When silent:");
+                egui::ComboBox::from_id_salt("idle_pattern")
+                    .selected_text(idle_pattern_name(cfg.idle_pattern))
+                    .show_ui(ui, |ui| {
+                        for p in [IdlePattern::Off, IdlePattern::RainbowCycle, IdlePattern::Breathe] {
+                            ui.selectable_value(&mut cfg.idle_pattern, p, idle_pattern_name(p));
+                        }
+                    });
+                ui.label("threshold:");
+                ui.add(egui::widgets::DragValue::new(&mut cfg.idle_threshold).speed(0.001).range(0.0..=f32::MAX));
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Max current:");
+                ui.add(egui::widgets::DragValue::new(&mut cfg.max_milliamps).speed(10.0).suffix(" mA"));
+                if cfg.max_milliamps == 0 {
+                    ui.weak("(no limit)");
+                }
+            });
+            ui.checkbox(&mut cfg.dither, "Dither dim colors")
+                .on_hover_text("Smoother dim gradients, at the cost of a faint flicker");
+            ui.horizontal(|ui| {
+                ui.label("LEDs:");
+                egui::ComboBox::from_id_salt("led_chipset")
+                    .selected_text(led_chipset_name(cfg.led_chipset))
+                    .show_ui(ui, |ui| {
+                        for c in LedChipset::ALL {
+                            ui.selectable_value(&mut cfg.led_chipset, c, led_chipset_name(c));
+                        }
+                    });
+                ui.label("order:");
+                egui::ComboBox::from_id_salt("color_order")
+                    .selected_text(color_order_name(cfg.color_order))
+                    .show_ui(ui, |ui| {
+                        for o in ColorOrder::ALL {
+                            ui.selectable_value(&mut cfg.color_order, o, color_order_name(o));
+                        }
+                    })
+                    .response
+                    .on_hover_text("Try another order if red shows up as green");
             });
+            Self::draw_device_settings(ui, cfg);
             
             ui.separator();
         }
         
+        if !state.device_presets.is_empty() {
+            ui.label("Apply on the device:");
+            ui.horizontal_wrapped(|ui| {
+                for (i, name) in state.device_presets.iter().enumerate() {
+                    if ui.add_enabled(!state.busy, Button::new(name)).clicked() {
+                        let _ = self.handler.send_message(HandlerMessage::ApplyPreset(i as u8));
+                    }
+                }
+            });
+        }
+
         // Preset buttons
         ui.label("Load preset:");
-        ui.horizontal(|ui| {
+        ui.horizontal_wrapped(|ui| {
             if ui.button("Stripes").clicked() {
                 let _ = self.handler.send_message(HandlerMessage::SetConfig(AppConfig::stripes()));
                 let _ = self.handler.send_message(HandlerMessage::SetStatus("Loaded Stripes preset".to_string()));
@@ -642,6 +2344,11 @@ impl PartylightApp {
                 let _ = self.handler.send_message(HandlerMessage::SetConfig(AppConfig::quarters()));
                 let _ = self.handler.send_message(HandlerMessage::SetStatus("Loaded Quarters preset".to_string()));
             }
+            if ui.button("Stereo bars").clicked() {
+                let _ = self.handler.send_message(HandlerMessage::SetConfig(AppConfig::stereo_bars()));
+                let _ = self.handler.send_message(HandlerMessage::SetStatus("Loaded Stereo bars preset".to_string()));
+            }
+            self.draw_user_presets(ui, state);
         });
         
         ui.separator();
@@ -655,6 +2362,11 @@ impl PartylightApp {
                 NeopixelMatrixPattern::Stripes(_) => 0usize,
                 NeopixelMatrixPattern::Bars(_) => 1usize,
                 NeopixelMatrixPattern::Quarters(_) => 2usize,
+                NeopixelMatrixPattern::VuMeter(_) => 3usize,
+                NeopixelMatrixPattern::Scroller { .. } => 4usize,
+                NeopixelMatrixPattern::PitchColor { .. } => 5usize,
+                NeopixelMatrixPattern::Spectrum(_) => 6usize,
+                NeopixelMatrixPattern::Spectrogram(_) => 7usize,
             };
 
             
@@ -662,52 +2374,182 @@ impl PartylightApp {
                 .selected_text(match pattern_idx {
                     0 => "Stripes",
                     1 => "Bars",
-                    _ => "Quarters",
+                    2 => "Quarters",
+                    3 => "VU meter",
+                    4 => "Scroller",
+                    5 => "Pitch color",
+                    6 => "Spectrum",
+                    _ => "Spectrogram",
                 })
                 .show_ui(ui, |ui| {
                     ui.selectable_value(&mut pattern_idx, 0, "Stripes");
                     ui.selectable_value(&mut pattern_idx, 1, "Bars");
                     ui.selectable_value(&mut pattern_idx, 2, "Quarters");
+                    ui.selectable_value(&mut pattern_idx, 3, "VU meter");
+                    ui.selectable_value(&mut pattern_idx, 4, "Scroller");
+                    ui.selectable_value(&mut pattern_idx, 5, "Pitch color");
+                    ui.selectable_value(&mut pattern_idx, 6, "Spectrum");
+                    ui.selectable_value(&mut pattern_idx, 7, "Spectrogram");
                 });
             
             // Convert pattern if changed
             convert_pattern_if_needed(cfg, pattern_idx);
             
             // Render editor for active pattern
-            self.draw_pattern_editor(ui, cfg);
+            self.draw_pattern_editor(ui, cfg, self.preview.levels(), &mut state.channel_clipboard);
         }
     }
     
-    fn draw_pattern_editor(&self, ui: &mut egui::Ui, cfg: &mut AppConfig) {
+    /// `levels` are the ones of the preview, one per channel
+    fn draw_pattern_editor(&self, ui: &mut egui::Ui, cfg: &mut AppConfig, levels: &[f32], clipboard: &mut Option<ChannelConfig>) {
+        let sample_rate = cfg.sample_rate();
+        let level = |i: usize| levels.get(i).copied().unwrap_or(0.0);
+        // the channel whose gains go to all others
+        let mut gains_from = None;
+        if !cfg.pattern.channels().is_empty() {
+            Self::draw_auto_bands(ui, cfg, sample_rate);
+        }
         match &mut cfg.pattern {
             NeopixelMatrixPattern::Stripes(chs) => {
                 ui.label("Stripes (4 channels)");
                 for (i, ch) in chs.iter_mut().enumerate() {
-                    self.draw_channel_editor(ui, i, ch, "Channel");
+                    if Self::draw_channel_editor(ui, i, ch, "Channel", sample_rate, level(i), clipboard) {
+                        gains_from = Some(i);
+                    }
                 }
             }
             NeopixelMatrixPattern::Bars(chs) => {
                 ui.label("Bars (8 channels)");
                 for (i, ch) in chs.iter_mut().enumerate() {
-                    self.draw_channel_editor(ui, i, ch, "Bar");
+                    if Self::draw_channel_editor(ui, i, ch, "Bar", sample_rate, level(i), clipboard) {
+                        gains_from = Some(i);
+                    }
                 }
             }
             NeopixelMatrixPattern::Quarters(chs) => {
                 ui.label("Quarters (4 channels)");
                 for (i, ch) in chs.iter_mut().enumerate() {
-                    self.draw_channel_editor(ui, i, ch, "Quarter");
+                    if Self::draw_channel_editor(ui, i, ch, "Quarter", sample_rate, level(i), clipboard) {
+                        gains_from = Some(i);
+                    }
                 }
             }
+            NeopixelMatrixPattern::VuMeter(ch) => {
+                ui.label("VU meter (1 channel, covering all bins that should count)");
+                Self::draw_channel_editor(ui, 0, ch, "Meter", sample_rate, level(0), clipboard);
+            }
+            NeopixelMatrixPattern::Scroller { text, channel } => {
+                ui.label("Scroller (1 channel, its strength sets the speed)");
+                ui.horizontal(|ui| {
+                    ui.label("text:");
+                    let mut edited = text.to_string();
+                    if ui.text_edit_singleline(&mut edited).changed() {
+                        *text = scroller_text(&edited);
+                    }
+                });
+                Self::draw_channel_editor(ui, 0, channel, "Scroller", sample_rate, level(0), clipboard);
+            }
+            NeopixelMatrixPattern::Spectrum(channel) => {
+                let (start, _) = channel.bin_range(sample_rate, FFT_LENGTH);
+                ui.label(format!(
+                    "Spectrum (1 channel, a bar per bin from its start: bins {start} - {}, its end is unused)",
+                    start + SPECTRUM_COLUMNS - 1
+                ));
+                Self::draw_channel_editor(ui, 0, channel, "Spectrum", sample_rate, level(0), clipboard);
+            }
+            NeopixelMatrixPattern::Spectrogram(channel) => {
+                let (start, _) = channel.bin_range(sample_rate, FFT_LENGTH);
+                ui.label(format!(
+                    "Spectrogram (1 channel, a column per bin from its start: bins {start} - {}, its end and color are unused)",
+                    start + SPECTRUM_COLUMNS - 1
+                ));
+                Self::draw_channel_editor(ui, 0, channel, "Spectrogram", sample_rate, level(0), clipboard);
+            }
+            NeopixelMatrixPattern::PitchColor { min_bin, max_bin } => {
+                ui.label("Pitch color (no channels, the loudest bin in the range picks the color)");
+                ui.horizontal(|ui| {
+                    ui.label("lowest:");
+                    edit_bin(ui, min_bin, sample_rate);
+                    ui.label("highest:");
+                    edit_bin(ui, max_bin, sample_rate);
+                });
+            }
+        }
+        if let Some(i) = gains_from {
+            let source = cfg.pattern.channels()[i].clone();
+            for ch in cfg.pattern.channels_mut() {
+                ch.copy_gains_from(&source);
+            }
         }
     }
     
-    fn draw_channel_editor(&self, ui: &mut egui::Ui, index: usize, ch: &mut ChannelConfig, label: &str) {
+    /// Spread the channels over a frequency range, optionally kept up to date by the device
+    fn draw_auto_bands(ui: &mut egui::Ui, cfg: &mut AppConfig, sample_rate: u32) {
+        // the range is only stored in the config while the device keeps it
+        let id = egui::Id::new("auto_bands");
+        let mut device = cfg.device();
+        let mut bands = device.auto_bands
+            .or_else(|| ui.data(|d| d.get_temp(id)))
+            .unwrap_or_default();
+        let mut on_device = device.auto_bands.is_some();
+        ui.horizontal(|ui| {
+            ui.label("Bands from");
+            ui.add(egui::widgets::DragValue::new(&mut bands.low_hz).range(0.0..=bands.high_hz).suffix(" Hz"));
+            ui.label("to");
+            ui.add(egui::widgets::DragValue::new(&mut bands.high_hz).range(bands.low_hz..=24_000.0).suffix(" Hz"));
+            egui::ComboBox::from_id_salt("band_spacing")
+                .selected_text(band_spacing_name(bands.spacing))
+                .show_ui(ui, |ui| {
+                    for spacing in BandSpacing::ALL {
+                        ui.selectable_value(&mut bands.spacing, spacing, band_spacing_name(spacing));
+                    }
+                });
+            if ui.button("Auto-assign bands")
+                .on_hover_text("Set the bins of all channels from the range, lowest first, keeping everything else")
+                .clicked()
+            {
+                cfg.assign_bands(bands, sample_rate, FFT_LENGTH);
+            }
+            ui.checkbox(&mut on_device, "keep on the device")
+                .on_hover_text("The device assigns the bands itself, so they stay put when the sample rate changes");
+        });
+        ui.data_mut(|d| d.insert_temp(id, bands));
+        device.auto_bands = on_device.then_some(bands);
+        cfg.set_device(device);
+        // show what the device will run
+        cfg.apply_auto_bands(sample_rate, FFT_LENGTH);
+    }
+
+    /// `sample_rate` is the one of the selected input, see [`AppConfig::sample_rate`], `level` how loud
+    /// the channel is in the preview. Returns whether its gains should go to all channels.
+    fn draw_channel_editor(ui: &mut egui::Ui, index: usize, ch: &mut ChannelConfig, label: &str, sample_rate: u32, level: f32, clipboard: &mut Option<ChannelConfig>) -> bool {
+        let mut gains_to_all = false;
         CollapsingHeader::new(format!("{} {}", label, index)).default_open(true).show(ui, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("Copy").clicked() {
+                    *clipboard = Some(ch.clone());
+                }
+                if let Some(copied) = clipboard.as_ref() {
+                    if ui.button("Paste").on_hover_text("Everything but the bins, they stay as they are").clicked() {
+                        ch.paste_from(copied);
+                    }
+                } else {
+                    ui.add_enabled(false, egui::Button::new("Paste"));
+                }
+                gains_to_all = ui.button("Apply gain settings to all channels")
+                    .on_hover_text("premult, noise_gate, exponent and aggregate, colors and bins stay")
+                    .clicked();
+            });
+
             ui.horizontal(|ui| {
                 ui.label("start:");
-                ui.add(egui::widgets::DragValue::new(&mut ch.start_index));
+                edit_hz(ui, &mut ch.start_hz, &mut ch.start_index, sample_rate);
                 ui.label("end:");
-                ui.add(egui::widgets::DragValue::new(&mut ch.end_index));
+                edit_hz(ui, &mut ch.end_hz, &mut ch.end_index, sample_rate);
+                let (start, end) = ch.bin_range(sample_rate, FFT_LENGTH);
+                let (low_hz, high_hz) = bin_range_hz(start, end, sample_rate, FFT_LENGTH);
+                ui.weak(format!("(bins {start} - {end} ≈ {low_hz:.0} - {high_hz:.0} Hz)"))
+                    .on_hover_text("The frequencies the bins cover, a set Hz value is rounded to the nearest bin");
             });
             
             ui.horizontal(|ui| {
@@ -720,17 +2562,74 @@ impl PartylightApp {
             ui.horizontal(|ui| {
                 ui.label("exponent:");
                 ui.add(egui::widgets::DragValue::new(&mut ch.exponent));
-                ui.label("color (r,g,b):");
-                ui.add(egui::widgets::DragValue::new(&mut ch.color[0]).speed(0.01).range(0.0..=1.0));
-                ui.add(egui::widgets::DragValue::new(&mut ch.color[1]).speed(0.01).range(0.0..=1.0));
-                ui.add(egui::widgets::DragValue::new(&mut ch.color[2]).speed(0.01).range(0.0..=1.0));
+                ui.label("color:");
+                egui::color_picker::color_edit_button_rgb(ui, &mut ch.color);
+                // what the LEDs get right now, dark colors round down to nothing
+                let sent = channel_led_color(ch, level);
+                let (swatch, _) = ui.allocate_exact_size(Vec2::splat(ui.spacing().interact_size.y), Sense::hover());
+                ui.painter().rect_filled(swatch, 2.0, Color32::from_rgb(sent.r, sent.g, sent.b));
+                ui.weak(format!("at {:.0}%: {}, {}, {}", level.clamp(0.0, 1.0) * 100.0, sent.r, sent.g, sent.b))
+                    .on_hover_text("The color the LEDs get at the level of the preview, before the power limit and the white balance");
+            });
+            CollapsingHeader::new("advanced").show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("color (r,g,b):");
+                    for c in &mut ch.color {
+                        ui.add(egui::widgets::DragValue::new(c).speed(0.01).range(0.0..=1.0));
+                    }
+                });
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("color mode:");
+                egui::ComboBox::from_id_salt(("color_mode", label, index))
+                    .selected_text(color_mode_name(&ch.color_mode))
+                    .show_ui(ui, |ui| {
+                        let solid = ColorMode::Solid;
+                        ui.selectable_value(&mut ch.color_mode, solid, color_mode_name(&solid));
+                        // keep the hues if it's already a hue shift
+                        if !matches!(ch.color_mode, ColorMode::HueShift { .. }) {
+                            let hue_shift = ColorMode::HueShift { from_hue: 0.0, to_hue: 60.0 };
+                            ui.selectable_value(&mut ch.color_mode, hue_shift, color_mode_name(&hue_shift));
+                        }
+                    });
+                if let ColorMode::HueShift { from_hue, to_hue } = &mut ch.color_mode {
+                    ui.label("hue from:");
+                    ui.add(egui::widgets::DragValue::new(from_hue).range(0.0..=360.0).suffix("°"));
+                    ui.label("to:");
+                    ui.add(egui::widgets::DragValue::new(to_hue).range(0.0..=360.0).suffix("°"));
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("aggregate:");
+                egui::ComboBox::from_id_salt(("aggregate", label, index))
+                    .selected_text(aggregation_method_name(&ch.aggregate))
+                    .show_ui(ui, |ui| {
+                        for method in AggregationMethod::ALL {
+                            let name = aggregation_method_name(&method);
+                            ui.selectable_value(&mut ch.aggregate, method, name);
+                        }
+                    });
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("source:");
+                egui::ComboBox::from_id_salt(("source", label, index))
+                    .selected_text(audio_source_name(ch.source))
+                    .show_ui(ui, |ui| {
+                        for source in AudioSource::ALL {
+                            ui.selectable_value(&mut ch.source, source, audio_source_name(source));
+                        }
+                    });
             });
         });
+        gains_to_all
     }
 }
 
-// Provide a native (non-wasm) UI stub so the app can still run natively.
-#[cfg(not(target_arch = "wasm32"))]
+// Provide a UI stub for mobile, which has no Bluetooth support yet.
+#[cfg(any(target_os = "android", target_os = "ios"))]
 impl PartylightApp {
     pub fn ui(&mut self, ctx: &egui::Context) {
         let state = self.state.clone();
@@ -738,17 +2637,43 @@ impl PartylightApp {
         
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.label(
-                egui::RichText::new("Diskomator 9000 Pro Max Config Editor (WASM only)")
+                egui::RichText::new("Diskomator 9000 Pro Max Config Editor")
                     .font(egui::FontId::new(
                         22.0,
                         egui::FontFamily::Name(std::sync::Arc::from("Cynatar")),
                     ))
                     .strong(),
             );
-            ui.label("Bluetooth functions are only available when compiled to WebAssembly.");
+            ui.label("Bluetooth functions are not available on mobile yet.");
+
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                if ui.add_enabled(state.config.is_some(), egui::Button::new("Save config…")).clicked() {
+                    if let Some(cfg) = &state.config {
+                        let status = config_file::save(cfg).unwrap_or_else(|e| e);
+                        state.set_status(status);
+                    }
+                }
+                if ui.button("Load config…").clicked() {
+                    match config_file::load() {
+                        Ok(Some(cfg)) => {
+                            state.config = Some(cfg);
+                            state.set_status("Loaded config from file".to_string());
+                        }
+                        Ok(None) => {}
+                        Err(e) => state.set_status(e),
+                    }
+                }
+            });
+            ui.label(format!("Status: {}", state.last_status));
+            draw_status_log(ui, &mut state);
 
             ui.separator();
             
+            if state.config.is_some() {
+                draw_history_controls(ui, &mut state);
+            }
             if let Some(cfg) = &mut state.config {
                 ui.label("Basic settings:");
                 ui.horizontal(|ui| {
@@ -758,13 +2683,243 @@ impl PartylightApp {
                         cfg.sample_count = sc as usize;
                     }
                 });
+
+                ui.separator();
+                self.preview.ui(ui, cfg);
             }
         });
+
+        record_history(ctx, &mut state);
+        self.schedule_auto_write(&state);
     }
 }
 
 // Helpers
 
+/// Every status since the app started, to catch the ones that were only shown for a moment
+fn draw_status_log(ui: &mut egui::Ui, state: &mut AppState) {
+    CollapsingHeader::new(format!("Status log ({})", state.status_log.len()))
+        .id_salt("status_log")
+        .show(ui, |ui| {
+            if ui.button("Clear").clicked() {
+                state.status_log.clear();
+            }
+            egui::ScrollArea::vertical()
+                .id_salt("status_log_scroll")
+                .max_height(150.0)
+                .stick_to_bottom(true)
+                .show(ui, |ui| {
+                    for line in &state.status_log {
+                        ui.monospace(line);
+                    }
+                });
+        });
+}
+
+/// Undo/redo buttons, plus Ctrl+Z / Ctrl+Shift+Z
+fn draw_history_controls(ui: &mut egui::Ui, state: &mut AppState) {
+    let undo_shortcut = egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::Z);
+    let redo_shortcut = egui::KeyboardShortcut::new(
+        egui::Modifiers::COMMAND | egui::Modifiers::SHIFT,
+        egui::Key::Z,
+    );
+    // the Windows convention, also accepted everywhere else
+    let redo_shortcut_alt = egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::Y);
+
+    // check redo first, the undo shortcut would also match with shift held down
+    let mut redo = ui.input_mut(|i| {
+        i.consume_shortcut(&redo_shortcut) || i.consume_shortcut(&redo_shortcut_alt)
+    });
+    let mut undo = !redo && ui.input_mut(|i| i.consume_shortcut(&undo_shortcut));
+
+    ui.horizontal(|ui| {
+        undo |= ui
+            .add_enabled(state.history.can_undo(), Button::new("Undo"))
+            .on_hover_text(ui.ctx().format_shortcut(&undo_shortcut))
+            .clicked();
+        redo |= ui
+            .add_enabled(state.history.can_redo(), Button::new("Redo"))
+            .on_hover_text(ui.ctx().format_shortcut(&redo_shortcut))
+            .clicked();
+    });
+
+    let restored = if undo {
+        state.history.undo()
+    } else if redo {
+        state.history.redo()
+    } else {
+        None
+    };
+    if let Some(cfg) = restored {
+        state.config = Some(cfg);
+    }
+}
+
+/// Snapshot the config for undo, but only once the user is done editing: after a drag stopped
+/// or a text field lost focus, instead of every frame in between.
+/// Configs loaded from presets, files or the device are picked up the same way.
+fn record_history(ctx: &egui::Context, state: &mut AppState) {
+    let editing = ctx.input(|i| i.pointer.any_down()) || ctx.memory(|m| m.focused().is_some());
+    if editing {
+        return;
+    }
+    if let Some(cfg) = &state.config {
+        state.history.record(cfg);
+    }
+}
+
+fn idle_pattern_name(p: IdlePattern) -> &'static str {
+    match p {
+        IdlePattern::Off => "Off",
+        IdlePattern::RainbowCycle => "Rainbow cycle",
+        IdlePattern::Breathe => "Breathe",
+    }
+}
+
+fn input_source_name(s: InputSource) -> &'static str {
+    match s {
+        InputSource::UsbAudio => "USB audio",
+        InputSource::I2s => "Microphone (I2S)",
+        InputSource::Auto => "Auto (USB, else microphone)",
+    }
+}
+
+fn led_chipset_name(c: LedChipset) -> &'static str {
+    match c {
+        LedChipset::Ws2812 => "WS2812 (RGB)",
+        LedChipset::Sk6812Rgbw => "SK6812 (RGBW)",
+    }
+}
+
+fn panel_arrangement_name(a: PanelArrangement) -> &'static str {
+    match a {
+        PanelArrangement::SideBySide => "Side by side",
+        PanelArrangement::Stacked => "Stacked",
+    }
+}
+
+fn panel_rotation_name(r: PanelRotation) -> &'static str {
+    match r {
+        PanelRotation::None => "0°",
+        PanelRotation::Cw90 => "90°",
+        PanelRotation::Cw180 => "180°",
+        PanelRotation::Cw270 => "270°",
+    }
+}
+
+fn stereo_mode_name(m: StereoMode) -> &'static str {
+    match m {
+        StereoMode::Mono => "Mono",
+        StereoMode::LeftRightSplit => "Left / right split",
+        StereoMode::MidSide => "Mid / side split",
+    }
+}
+
+fn i2s_channel_source_name(c: I2sChannelSource) -> &'static str {
+    match c {
+        I2sChannelSource::Both => "Stereo",
+        I2sChannelSource::Left => "Left only",
+        I2sChannelSource::Right => "Right only",
+    }
+}
+
+fn i2s_sample_rate_name(r: I2sSampleRate) -> &'static str {
+    match r {
+        I2sSampleRate::Hz16000 => "16 kHz",
+        I2sSampleRate::Hz32000 => "32 kHz",
+        I2sSampleRate::Hz44100 => "44.1 kHz",
+        I2sSampleRate::Hz48000 => "48 kHz",
+    }
+}
+
+fn i2s_bit_depth_name(d: I2sBitDepth) -> &'static str {
+    match d {
+        I2sBitDepth::Bits16 => "16 bit",
+        I2sBitDepth::Bits18 => "18 bit",
+        I2sBitDepth::Bits20 => "20 bit",
+        I2sBitDepth::Bits24 => "24 bit",
+        I2sBitDepth::Bits32 => "32 bit",
+    }
+}
+
+fn band_spacing_name(s: BandSpacing) -> &'static str {
+    match s {
+        BandSpacing::Log => "Logarithmic",
+        BandSpacing::Mel => "Mel",
+    }
+}
+
+fn overlay_name(o: &Overlay) -> &'static str {
+    match o {
+        Overlay::None => "None",
+        Overlay::Breathe { .. } => "Breathe",
+    }
+}
+
+fn color_order_name(o: ColorOrder) -> &'static str {
+    match o {
+        ColorOrder::Grb => "GRB",
+        ColorOrder::Rgb => "RGB",
+        ColorOrder::Brg => "BRG",
+    }
+}
+
+fn audio_source_name(s: AudioSource) -> &'static str {
+    match s {
+        AudioSource::Left => "Left",
+        AudioSource::Right => "Right",
+        AudioSource::Mono => "Mono (L+R)",
+    }
+}
+
+fn color_mode_name(m: &ColorMode) -> &'static str {
+    match m {
+        ColorMode::Solid => "Solid",
+        ColorMode::HueShift { .. } => "Hue shift",
+    }
+}
+
+fn aggregation_method_name(a: &AggregationMethod) -> &'static str {
+    match a {
+        AggregationMethod::Sum => "Sum",
+        AggregationMethod::Max => "Max",
+        AggregationMethod::Average => "Average",
+        AggregationMethod::RmsNormalized => "RMS (normalized)",
+    }
+}
+
+/// Edit an FFT bin, showing the frequency it stands for
+fn edit_bin(ui: &mut egui::Ui, bin: &mut usize, sample_rate: u32) {
+    ui.add(egui::widgets::DragValue::new(bin).range(0..=FFT_LENGTH / 2 - 1));
+    ui.weak(format!("({:.0} Hz)", bin_to_hz(*bin, sample_rate, FFT_LENGTH)));
+}
+
+/// Edit a frequency in Hz, falling back to the center of the bin if it isn't set yet.
+/// The bin index is kept in sync, so older firmware still gets a sensible range.
+fn edit_hz(ui: &mut egui::Ui, hz: &mut Option<f32>, index: &mut usize, sample_rate: u32) {
+    let mut value = hz.unwrap_or_else(|| bin_to_hz(*index, sample_rate, FFT_LENGTH));
+    let nyquist = sample_rate as f32 / 2.0;
+    let response = ui.add(
+        egui::widgets::DragValue::new(&mut value)
+            .speed(10.0)
+            .range(0.0..=nyquist)
+            .max_decimals(0)
+            .suffix(" Hz"),
+    );
+    if response.changed() {
+        *hz = Some(value);
+        *index = hz_to_bin(value, sample_rate, FFT_LENGTH);
+    }
+}
+
+fn window_function_name(w: WindowFunction) -> &'static str {
+    match w {
+        WindowFunction::None => "None",
+        WindowFunction::Hann => "Hann",
+        WindowFunction::Hamming => "Hamming",
+        WindowFunction::BlackmanHarris => "Blackman-Harris",
+    }
+}
 
     
     fn convert_pattern_if_needed(cfg: &mut AppConfig, selected_idx: usize) {
@@ -784,6 +2939,37 @@ impl PartylightApp {
                 let new = convert_to_quarters(other);
                 cfg.pattern = NeopixelMatrixPattern::Quarters(new);
             }
+            (3, NeopixelMatrixPattern::VuMeter(_)) => {}
+            (3, other) => {
+                // the first channel, or a default one if the pattern has none
+                let new = convert_to_stripes(other)[0].clone();
+                cfg.pattern = NeopixelMatrixPattern::VuMeter(new);
+            }
+            (4, NeopixelMatrixPattern::Scroller { .. }) => {}
+            (5, NeopixelMatrixPattern::PitchColor { .. }) => {}
+            (5, _) => {
+                // about 100 Hz to 6 kHz, where melodies are
+                cfg.pattern = NeopixelMatrixPattern::PitchColor { min_bin: 1, max_bin: 64 };
+            }
+            (4, other) => {
+                let channel = convert_to_stripes(other)[0].clone();
+                cfg.pattern = NeopixelMatrixPattern::Scroller { text: scroller_text("Partylight"), channel };
+            }
+            (6, NeopixelMatrixPattern::Spectrum(_)) => {}
+            (7, NeopixelMatrixPattern::Spectrogram(_)) => {}
+            (6 | 7, other) => {
+                let mut channel = convert_to_stripes(other)[0].clone();
+                // bin 0 is DC, the columns start above it, unless they already did
+                if !matches!(other, NeopixelMatrixPattern::Spectrum(_) | NeopixelMatrixPattern::Spectrogram(_)) {
+                    channel.start_index = 1;
+                    channel.start_hz = None;
+                }
+                cfg.pattern = if selected_idx == 6 {
+                    NeopixelMatrixPattern::Spectrum(channel)
+                } else {
+                    NeopixelMatrixPattern::Spectrogram(channel)
+                };
+            }
             _ => {}
         }
     }
@@ -792,11 +2978,15 @@ impl PartylightApp {
         let mut new = std::array::from_fn(|_| ChannelConfig {
             start_index: 0,
             end_index: 0,
+            start_hz: None,
+            end_hz: None,
             premult: 1.0,
             noise_gate: 0.0,
             exponent: 1,
             color: [1.0, 1.0, 1.0],
             aggregate: AggregationMethod::Sum,
+            source: AudioSource::Left,
+            color_mode: ColorMode::Solid,
         });
         match pattern {
             NeopixelMatrixPattern::Stripes(chs) | NeopixelMatrixPattern::Quarters(chs) => {
@@ -809,6 +2999,13 @@ impl PartylightApp {
                     new[i] = chs[i].clone();
                 }
             }
+            NeopixelMatrixPattern::VuMeter(ch)
+            | NeopixelMatrixPattern::Scroller { channel: ch, .. }
+            | NeopixelMatrixPattern::Spectrum(ch)
+            | NeopixelMatrixPattern::Spectrogram(ch) => {
+                new[0] = ch.clone();
+            }
+            NeopixelMatrixPattern::PitchColor { .. } => {}
         }
         new
     }
@@ -817,11 +3014,15 @@ impl PartylightApp {
         let mut new = std::array::from_fn(|_| ChannelConfig {
             start_index: 0,
             end_index: 0,
+            start_hz: None,
+            end_hz: None,
             premult: 1.0,
             noise_gate: 0.0,
             exponent: 1,
             color: [1.0, 1.0, 1.0],
             aggregate: AggregationMethod::Sum,
+            source: AudioSource::Left,
+            color_mode: ColorMode::Solid,
         });
         match pattern {
             NeopixelMatrixPattern::Stripes(chs) | NeopixelMatrixPattern::Quarters(chs) => {
@@ -834,6 +3035,13 @@ impl PartylightApp {
                     new[i] = chs[i].clone();
                 }
             }
+            NeopixelMatrixPattern::VuMeter(ch)
+            | NeopixelMatrixPattern::Scroller { channel: ch, .. }
+            | NeopixelMatrixPattern::Spectrum(ch)
+            | NeopixelMatrixPattern::Spectrogram(ch) => {
+                new[0] = ch.clone();
+            }
+            NeopixelMatrixPattern::PitchColor { .. } => {}
         }
         new
     }
@@ -842,11 +3050,15 @@ impl PartylightApp {
         let mut new = std::array::from_fn(|_| ChannelConfig {
             start_index: 0,
             end_index: 0,
+            start_hz: None,
+            end_hz: None,
             premult: 1.0,
             noise_gate: 0.0,
             exponent: 1,
             color: [1.0, 1.0, 1.0],
             aggregate: AggregationMethod::Sum,
+            source: AudioSource::Left,
+            color_mode: ColorMode::Solid,
         });
         match pattern {
             NeopixelMatrixPattern::Stripes(chs) | NeopixelMatrixPattern::Quarters(chs) => {
@@ -859,6 +3071,13 @@ impl PartylightApp {
                     new[i] = chs[i].clone();
                 }
             }
+            NeopixelMatrixPattern::VuMeter(ch)
+            | NeopixelMatrixPattern::Scroller { channel: ch, .. }
+            | NeopixelMatrixPattern::Spectrum(ch)
+            | NeopixelMatrixPattern::Spectrogram(ch) => {
+                new[0] = ch.clone();
+            }
+            NeopixelMatrixPattern::PitchColor { .. } => {}
         }
         new
     }
\ No newline at end of file
@@ -0,0 +1,478 @@
+//! Native counterpart of web_bluetooth.rs, using btleplug.
+
+use std::future::Future;
+use std::ops::RangeInclusive;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use btleplug::api::bleuuid::uuid_from_u16;
+use btleplug::api::{
+    Central, Characteristic, Manager as _, Peripheral as _, ScanFilter, WriteType,
+};
+use btleplug::platform::{Adapter, Manager, Peripheral};
+use common::command::DeviceCommand;
+use common::config::config_versions_from_bytes;
+use common::transfer::max_config_size_from_bytes;
+use ractor_wormhole::deps::futures::StreamExt;
+use uuid::Uuid;
+
+use crate::app::DiscoveredDevice;
+use crate::transport::{ConfigTransport, DeviceInfo, NotifyCallback};
+
+const SERVICE_UUID: Uuid = Uuid::from_u128(0xbbafe0b7_bf3a_405a_bff7_d632c44c85f8);
+const CONFIG_CHAR_UUID: Uuid = Uuid::from_u128(0xfa57339a_e7e0_434e_9c98_93a15061e1ff);
+const LEVELS_CHAR_UUID: Uuid = Uuid::from_u128(0x3c9d7e12_4b6a_4f0e_a8d5_6e21f0b4c7a9);
+const ALIVE_CHAR_UUID: Uuid = Uuid::from_u128(0x5e0c2a41_9b7d_4c3e_8f16_2d4b7a9c0e53);
+const RSSI_CHAR_UUID: Uuid = Uuid::from_u128(0xb5d82f3e_7c41_4e96_a0f8_3e1c6d9b2a57);
+const STATUS_CHAR_UUID: Uuid = Uuid::from_u128(0x9a6b3e07_2d14_4c85_b7f9_0e38d5a1c264);
+const TELEMETRY_CHAR_UUID: Uuid = Uuid::from_u128(0x9d3f6b28_e4a1_4c57_b8d2_5a0e7c1f9b36);
+const CONFIG_CONTROL_CHAR_UUID: Uuid = Uuid::from_u128(0x8b3f6d20_1c5e_4a79_b2d4_f07a9e31c6b8);
+const FACTORY_RESET_CHAR_UUID: Uuid = Uuid::from_u128(0xd4a1f7c3_6e82_4b09_9a3d_51c8e2f06b7e);
+const LOG_CHAR_UUID: Uuid = Uuid::from_u128(0x2f8e6c14_7a3b_4d90_b5e2_c1d07f9a8e36);
+const LOG_LEVEL_CHAR_UUID: Uuid = Uuid::from_u128(0x6b1d9e45_3c27_4f8a_a0b6_8e5f2d7c1a93);
+const COMMAND_CHAR_UUID: Uuid = Uuid::from_u128(0xc7e2a9f4_5b18_4d36_9e0c_3f6a8b1d5e27);
+const DEVICE_NAME_CHAR_UUID: Uuid = Uuid::from_u128(0xe3b8c1d6_92f4_4a7e_8d05_b6f1a4c9e273);
+const ENABLED_CHAR_UUID: Uuid = Uuid::from_u128(0xfc578d88_ecca_4d3c_8507_be7c8c9ea8f0);
+const CONFIG_VERSIONS_CHAR_UUID: Uuid = Uuid::from_u128(0x4e7a1c93_b2d5_4f68_8a0e_c3f9d6b2174a);
+const MAX_CONFIG_SIZE_CHAR_UUID: Uuid = Uuid::from_u128(0x7d2e9b41_c6a3_4f85_9e10_b4a8d3f5c762);
+const PRESET_NAMES_CHAR_UUID: Uuid = Uuid::from_u128(0xa8f3d2c5_6e19_4b74_9c0a_2d5e7b1f4c83);
+const APPLY_PRESET_CHAR_UUID: Uuid = Uuid::from_u128(0xf2b6a9e1_4d73_4c08_8e5b_1a9c3f7d2e46);
+const SLOT_NAMES_CHAR_UUID: Uuid = Uuid::from_u128(0x08b3a8f3_5a43_40fc_a471_69d120062154);
+const SAVE_SLOT_CHAR_UUID: Uuid = Uuid::from_u128(0x57396568_b6ad_4184_bdfa_dde3e9f62c13);
+const SELECT_SLOT_CHAR_UUID: Uuid = Uuid::from_u128(0x4c9bd057_5685_488a_acca_b3807a9ddba5);
+
+// Device Information Service
+const MANUFACTURER_NAME_CHAR_UUID: Uuid = uuid_from_u16(0x2a29);
+const FIRMWARE_REVISION_CHAR_UUID: Uuid = uuid_from_u16(0x2a26);
+const HARDWARE_REVISION_CHAR_UUID: Uuid = uuid_from_u16(0x2a27);
+
+/// How long to listen for advertisements
+const SCAN_DURATION: Duration = Duration::from_secs(3);
+
+/// btleplug needs a tokio runtime, but the handler runs on the actor's executor.
+/// So all the Bluetooth work is handed over to this one, which lives as long as the app.
+fn runtime() -> &'static tokio::runtime::Runtime {
+    static RUNTIME: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+            .expect("Failed to create tokio runtime")
+    })
+}
+
+/// Run `fut` on the tokio runtime and wait for it
+async fn on_runtime<T: Send + 'static>(
+    fut: impl Future<Output = Result<T, String>> + Send + 'static,
+) -> Result<T, String> {
+    runtime().spawn(fut).await.map_err(|e| e.to_string())?
+}
+
+pub struct Bluetooth {
+    adapter: Option<Adapter>,
+    /// result of the last scan
+    discovered: Vec<Peripheral>,
+    device: Option<Peripheral>,
+    cfg_char: Option<Characteristic>,
+    // these forward the notifications to the callbacks
+    levels_task: Option<tokio::task::JoinHandle<()>>,
+    config_task: Option<tokio::task::JoinHandle<()>>,
+    alive_task: Option<tokio::task::JoinHandle<()>>,
+    rssi_task: Option<tokio::task::JoinHandle<()>>,
+    status_task: Option<tokio::task::JoinHandle<()>>,
+    telemetry_task: Option<tokio::task::JoinHandle<()>>,
+    log_task: Option<tokio::task::JoinHandle<()>>,
+}
+
+impl Bluetooth {
+    pub fn new() -> Self {
+        Self {
+            adapter: None,
+            discovered: Vec::new(),
+            device: None,
+            cfg_char: None,
+            levels_task: None,
+            config_task: None,
+            alive_task: None,
+            rssi_task: None,
+            status_task: None,
+            telemetry_task: None,
+            log_task: None,
+        }
+    }
+
+    async fn adapter(&mut self) -> Result<Adapter, String> {
+        if let Some(adapter) = &self.adapter {
+            return Ok(adapter.clone());
+        }
+        let adapter = on_runtime(async {
+            let manager = Manager::new().await.map_err(|e| e.to_string())?;
+            manager
+                .adapters()
+                .await
+                .map_err(|e| e.to_string())?
+                .into_iter()
+                .next()
+                .ok_or_else(|| "No Bluetooth adapter found".to_string())
+        })
+        .await?;
+        self.adapter = Some(adapter.clone());
+        Ok(adapter)
+    }
+
+    /// Subscribe to a characteristic and call `on_value` for every notification, until the
+    /// returned task is aborted
+    async fn subscribe(
+        &self,
+        uuid: Uuid,
+        on_value: NotifyCallback,
+    ) -> Result<tokio::task::JoinHandle<()>, String> {
+        let (device, _) = self.connected()?;
+        let mut notifications = on_runtime(async move {
+            let char = device
+                .characteristics()
+                .into_iter()
+                .find(|c| c.uuid == uuid)
+                .ok_or_else(|| format!("Characteristic {uuid} not found"))?;
+            device.subscribe(&char).await.map_err(|e| e.to_string())?;
+            device.notifications().await.map_err(|e| e.to_string())
+        })
+        .await?;
+        Ok(runtime().spawn(async move {
+            // every stream gets the notifications of all characteristics
+            while let Some(n) = notifications.next().await {
+                if n.uuid == uuid {
+                    on_value(&n.value);
+                }
+            }
+        }))
+    }
+
+    /// Read a characteristic that is looked up on demand, as older firmware doesn't have it
+    async fn read_optional(&self, uuid: Uuid) -> Result<Vec<u8>, String> {
+        let (device, _) = self.connected()?;
+        on_runtime(async move {
+            let char = device
+                .characteristics()
+                .into_iter()
+                .find(|c| c.uuid == uuid)
+                .ok_or_else(|| format!("Characteristic {uuid} not found"))?;
+            device.read(&char).await.map_err(|e| e.to_string())
+        })
+        .await
+    }
+
+    /// Write to a characteristic that is looked up on demand, as older firmware doesn't have it
+    async fn write_optional(&self, uuid: Uuid, bytes: &[u8]) -> Result<(), String> {
+        let (device, _) = self.connected()?;
+        let bytes = bytes.to_vec();
+        on_runtime(async move {
+            let char = device
+                .characteristics()
+                .into_iter()
+                .find(|c| c.uuid == uuid)
+                .ok_or_else(|| format!("Characteristic {uuid} not found"))?;
+            device
+                .write(&char, &bytes, WriteType::WithResponse)
+                .await
+                .map_err(|e| e.to_string())
+        })
+        .await
+    }
+
+    fn connected(&self) -> Result<(Peripheral, Characteristic), String> {
+        match (&self.device, &self.cfg_char) {
+            (Some(device), Some(cfg_char)) => Ok((device.clone(), cfg_char.clone())),
+            _ => Err("Not connected".to_string()),
+        }
+    }
+}
+
+impl ConfigTransport for Bluetooth {
+    async fn is_available(&mut self) -> Result<(), String> {
+        self.adapter().await.map(|_| ())
+    }
+
+    /// Scan for devices advertising the config service
+    async fn scan(&mut self) -> Result<Option<Vec<DiscoveredDevice>>, String> {
+        log::info!("bluetooth_native: scan start");
+        let adapter = self.adapter().await?;
+        let (peripherals, mut devices) = on_runtime(async move {
+            adapter
+                .start_scan(ScanFilter {
+                    services: vec![SERVICE_UUID],
+                })
+                .await
+                .map_err(|e| e.to_string())?;
+            tokio::time::sleep(SCAN_DURATION).await;
+            let _ = adapter.stop_scan().await;
+
+            let mut peripherals = Vec::new();
+            let mut devices = Vec::new();
+            for p in adapter.peripherals().await.map_err(|e| e.to_string())? {
+                let Ok(Some(props)) = p.properties().await else {
+                    continue;
+                };
+                // some platforms ignore the filter
+                if !props.services.contains(&SERVICE_UUID) {
+                    continue;
+                }
+                devices.push(DiscoveredDevice {
+                    id: p.id().to_string(),
+                    name: props.local_name.unwrap_or_else(|| "(unnamed)".to_string()),
+                    rssi: props.rssi,
+                });
+                peripherals.push(p);
+            }
+            Ok((peripherals, devices))
+        })
+        .await?;
+
+        // strongest signal first, that's usually the one in front of you
+        devices.sort_by_key(|d| std::cmp::Reverse(d.rssi.unwrap_or(i16::MIN)));
+
+        self.discovered = peripherals;
+        log::info!("bluetooth_native: scan found {} device(s)", devices.len());
+        Ok(Some(devices))
+    }
+
+    /// Connect to a device from the last scan
+    async fn connect(&mut self, id: Option<&str>) -> Result<(), String> {
+        log::info!("bluetooth_native: connect start");
+        let id = id.ok_or("No device selected")?;
+        let device = self
+            .discovered
+            .iter()
+            .find(|p| p.id().to_string() == id)
+            .cloned()
+            .ok_or("Device not found, scan again")?;
+        self.device = Some(device);
+        self.reconnect().await?;
+        log::info!("bluetooth_native: connect complete");
+        Ok(())
+    }
+
+    /// Reconnect to the last device
+    async fn reconnect(&mut self) -> Result<(), String> {
+        let device = self.device.clone().ok_or("No device cached")?;
+        let cfg_char = on_runtime(async move {
+            if !device.is_connected().await.unwrap_or(false) {
+                device.connect().await.map_err(|e| e.to_string())?;
+            }
+            device
+                .discover_services()
+                .await
+                .map_err(|e| e.to_string())?;
+            device
+                .characteristics()
+                .into_iter()
+                .find(|c| c.uuid == CONFIG_CHAR_UUID)
+                .ok_or_else(|| "Config characteristic not found".to_string())
+        })
+        .await?;
+        self.cfg_char = Some(cfg_char);
+        Ok(())
+    }
+
+    async fn try_auto_reconnect(&mut self, _trying: &dyn Fn(&str) -> bool) -> Result<bool, String> {
+        // the devices are only known after a scan
+        Ok(false)
+    }
+
+    async fn read_config(&self) -> Result<Vec<u8>, String> {
+        let (device, cfg_char) = self.connected()?;
+        on_runtime(async move { device.read(&cfg_char).await.map_err(|e| e.to_string()) }).await
+    }
+
+    async fn write_config(&self, bytes: &[u8]) -> Result<(), String> {
+        let (device, cfg_char) = self.connected()?;
+        let bytes = bytes.to_vec();
+        on_runtime(async move {
+            device
+                .write(&cfg_char, &bytes, WriteType::WithResponse)
+                .await
+                .map_err(|e| e.to_string())
+        })
+        .await
+    }
+
+    async fn write_config_control(&self, bytes: &[u8]) -> Result<(), String> {
+        self.write_optional(CONFIG_CONTROL_CHAR_UUID, bytes).await
+    }
+
+    async fn factory_reset(&self) -> Result<(), String> {
+        // the value doesn't matter, any write resets
+        self.write_optional(FACTORY_RESET_CHAR_UUID, &[1]).await
+    }
+
+    async fn set_log_level(&self, level: u8) -> Result<(), String> {
+        self.write_optional(LOG_LEVEL_CHAR_UUID, &[level]).await
+    }
+
+    async fn send_command(&self, command: DeviceCommand) -> Result<(), String> {
+        self.write_optional(COMMAND_CHAR_UUID, &[command as u8]).await
+    }
+
+    async fn read_device_name(&self) -> Result<String, String> {
+        let value = self.read_optional(DEVICE_NAME_CHAR_UUID).await?;
+        Ok(String::from_utf8_lossy(&value).into_owned())
+    }
+
+    async fn write_device_name(&self, name: &str) -> Result<(), String> {
+        self.write_optional(DEVICE_NAME_CHAR_UUID, name.as_bytes()).await
+    }
+
+    async fn read_enabled(&self) -> Result<bool, String> {
+        let value = self.read_optional(ENABLED_CHAR_UUID).await?;
+        Ok(value.first() == Some(&1))
+    }
+
+    async fn write_enabled(&self, enabled: bool) -> Result<(), String> {
+        self.write_optional(ENABLED_CHAR_UUID, &[enabled as u8]).await
+    }
+
+    async fn read_config_versions(&self) -> Result<RangeInclusive<u32>, String> {
+        let value = self.read_optional(CONFIG_VERSIONS_CHAR_UUID).await?;
+        config_versions_from_bytes(&value).ok_or_else(|| "Invalid config versions".to_string())
+    }
+
+    async fn read_max_config_size(&self) -> Result<usize, String> {
+        let value = self.read_optional(MAX_CONFIG_SIZE_CHAR_UUID).await?;
+        max_config_size_from_bytes(&value).ok_or_else(|| "Invalid max config size".to_string())
+    }
+
+    async fn read_preset_names(&self) -> Result<Vec<String>, String> {
+        let value = self.read_optional(PRESET_NAMES_CHAR_UUID).await?;
+        Ok(String::from_utf8_lossy(&value)
+            .lines()
+            .map(str::to_owned)
+            .collect())
+    }
+
+    async fn apply_preset(&self, index: u8) -> Result<(), String> {
+        self.write_optional(APPLY_PRESET_CHAR_UUID, &[index]).await
+    }
+
+    async fn read_slot_names(&self) -> Result<Vec<String>, String> {
+        let value = self.read_optional(SLOT_NAMES_CHAR_UUID).await?;
+        // not lines(), an unused last slot is an empty last line
+        Ok(String::from_utf8_lossy(&value)
+            .split('\n')
+            .map(str::to_owned)
+            .collect())
+    }
+
+    async fn save_slot(&self, index: u8, name: &str) -> Result<(), String> {
+        let bytes: Vec<u8> = [index].into_iter().chain(name.bytes()).collect();
+        self.write_optional(SAVE_SLOT_CHAR_UUID, &bytes).await
+    }
+
+    async fn select_slot(&self, index: u8) -> Result<(), String> {
+        self.write_optional(SELECT_SLOT_CHAR_UUID, &[index]).await
+    }
+
+    async fn read_device_info(&self) -> Result<DeviceInfo, String> {
+        let (device, _) = self.connected()?;
+        on_runtime(async move {
+            let characteristics = device.characteristics();
+            let read_string = async |uuid: Uuid| -> Result<String, String> {
+                let char = characteristics
+                    .iter()
+                    .find(|c| c.uuid == uuid)
+                    .ok_or_else(|| format!("Characteristic {uuid} not found"))?;
+                let value = device.read(char).await.map_err(|e| e.to_string())?;
+                Ok(String::from_utf8_lossy(&value).into_owned())
+            };
+            Ok(DeviceInfo {
+                manufacturer: read_string(MANUFACTURER_NAME_CHAR_UUID).await?,
+                firmware_revision: read_string(FIRMWARE_REVISION_CHAR_UUID).await?,
+                hardware_revision: read_string(HARDWARE_REVISION_CHAR_UUID).await?,
+            })
+        })
+        .await
+    }
+
+    // Heartbeat: do a small read to keep the GATT connection alive
+    async fn heartbeat(&self) -> Result<(), String> {
+        let _ = self.read_config().await?;
+        Ok(())
+    }
+
+    async fn subscribe_levels(&mut self, on_levels: NotifyCallback) -> Result<(), String> {
+        if let Some(task) = self.levels_task.take() {
+            task.abort();
+        }
+        self.levels_task = Some(self.subscribe(LEVELS_CHAR_UUID, on_levels).await?);
+        Ok(())
+    }
+
+    async fn subscribe_config(&mut self, on_config: NotifyCallback) -> Result<(), String> {
+        if let Some(task) = self.config_task.take() {
+            task.abort();
+        }
+        self.config_task = Some(self.subscribe(CONFIG_CHAR_UUID, on_config).await?);
+        Ok(())
+    }
+
+    async fn subscribe_alive(&mut self, on_alive: NotifyCallback) -> Result<(), String> {
+        if let Some(task) = self.alive_task.take() {
+            task.abort();
+        }
+        self.alive_task = Some(self.subscribe(ALIVE_CHAR_UUID, on_alive).await?);
+        Ok(())
+    }
+
+    async fn subscribe_status(&mut self, on_status: NotifyCallback) -> Result<(), String> {
+        if let Some(task) = self.status_task.take() {
+            task.abort();
+        }
+        self.status_task = Some(self.subscribe(STATUS_CHAR_UUID, on_status).await?);
+        Ok(())
+    }
+
+    async fn subscribe_rssi(&mut self, on_rssi: NotifyCallback) -> Result<(), String> {
+        if let Some(task) = self.rssi_task.take() {
+            task.abort();
+        }
+        self.rssi_task = Some(self.subscribe(RSSI_CHAR_UUID, on_rssi).await?);
+        Ok(())
+    }
+
+    async fn subscribe_telemetry(&mut self, on_telemetry: NotifyCallback) -> Result<(), String> {
+        if let Some(task) = self.telemetry_task.take() {
+            task.abort();
+        }
+        self.telemetry_task = Some(self.subscribe(TELEMETRY_CHAR_UUID, on_telemetry).await?);
+        Ok(())
+    }
+
+    async fn subscribe_log(&mut self, on_line: NotifyCallback) -> Result<(), String> {
+        if let Some(task) = self.log_task.take() {
+            task.abort();
+        }
+        self.log_task = Some(self.subscribe(LOG_CHAR_UUID, on_line).await?);
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), String> {
+        log::info!("bluetooth_native: disconnect");
+        let tasks = [
+            self.levels_task.take(),
+            self.config_task.take(),
+            self.alive_task.take(),
+            self.rssi_task.take(),
+            self.status_task.take(),
+            self.telemetry_task.take(),
+            self.log_task.take(),
+        ];
+        for task in tasks.into_iter().flatten() {
+            task.abort();
+        }
+        self.cfg_char = None;
+        if let Some(device) = self.device.take() {
+            on_runtime(async move { device.disconnect().await.map_err(|e| e.to_string()) }).await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,144 @@
+//! Save and load configs as JSON files, so they can be kept around outside the device.
+
+use common::config::{AppConfig, CONFIG_VERSION};
+use common::transfer::MAX_CHUNKED_CONFIG_SIZE;
+
+const FILE_NAME: &str = "partylight-config.json";
+
+pub fn to_json(cfg: &AppConfig) -> Result<String, String> {
+    cfg.to_json().map_err(|e| format!("Failed to serialize config: {e}"))
+}
+
+/// Parse a config and check that the device would accept it
+pub fn from_json(data: &[u8]) -> Result<AppConfig, String> {
+    let cfg = AppConfig::from_json(data).map_err(|e| format!("Not a valid config file: {e}"))?;
+
+    if cfg.config_version != CONFIG_VERSION {
+        return Err(format!(
+            "Config file has version {}, but this app uses version {CONFIG_VERSION}",
+            cfg.config_version
+        ));
+    }
+    if cfg.to_bytes::<MAX_CHUNKED_CONFIG_SIZE>().is_err() {
+        return Err(format!(
+            "Config is too large for the device (max {MAX_CHUNKED_CONFIG_SIZE} bytes)"
+        ));
+    }
+
+    Ok(cfg)
+}
+
+// -----------------------------------------------------------------------------------------------
+// native
+
+/// Ask for a file name and write the config to it. Returns a status message.
+#[cfg(any(target_os = "windows", target_os = "macos", target_os = "linux"))]
+pub fn save(cfg: &AppConfig) -> Result<String, String> {
+    let json = to_json(cfg)?;
+    let Some(path) = rfd::FileDialog::new()
+        .add_filter("JSON", &["json"])
+        .set_file_name(FILE_NAME)
+        .save_file()
+    else {
+        return Ok("Save cancelled".to_string());
+    };
+    std::fs::write(&path, json).map_err(|e| format!("Failed to write {}: {e}", path.display()))?;
+    Ok(format!("Saved config to {}", path.display()))
+}
+
+/// Ask for a file and parse it. `Ok(None)` if the user cancelled.
+#[cfg(any(target_os = "windows", target_os = "macos", target_os = "linux"))]
+pub fn load() -> Result<Option<AppConfig>, String> {
+    let Some(path) = rfd::FileDialog::new()
+        .add_filter("JSON", &["json"])
+        .pick_file()
+    else {
+        return Ok(None);
+    };
+    let data =
+        std::fs::read(&path).map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+    from_json(&data).map(Some)
+}
+
+// no file dialogs on mobile
+
+#[cfg(any(target_os = "android", target_os = "ios"))]
+pub fn save(_cfg: &AppConfig) -> Result<String, String> {
+    Err("Saving configs is not supported on this platform".to_string())
+}
+
+#[cfg(any(target_os = "android", target_os = "ios"))]
+pub fn load() -> Result<Option<AppConfig>, String> {
+    Err("Loading configs is not supported on this platform".to_string())
+}
+
+// -----------------------------------------------------------------------------------------------
+// wasm
+
+/// Offer the config as a download
+#[cfg(target_arch = "wasm32")]
+pub fn save(cfg: &AppConfig) -> Result<String, String> {
+    use wasm_bindgen::JsCast;
+
+    let json = to_json(cfg)?;
+    let js_err = |e: wasm_bindgen::JsValue| format!("Failed to save config: {e:?}");
+
+    let document = web_sys::window()
+        .and_then(|w| w.document())
+        .ok_or("No document")?;
+
+    let parts = js_sys::Array::of1(&json.into());
+    let options = web_sys::BlobPropertyBag::new();
+    options.set_type("application/json");
+    let blob =
+        web_sys::Blob::new_with_str_sequence_and_options(&parts, &options).map_err(js_err)?;
+    let url = web_sys::Url::create_object_url_with_blob(&blob).map_err(js_err)?;
+
+    let anchor: web_sys::HtmlAnchorElement = document
+        .create_element("a")
+        .map_err(js_err)?
+        .unchecked_into();
+    anchor.set_href(&url);
+    anchor.set_download(FILE_NAME);
+    anchor.click();
+
+    let _ = web_sys::Url::revoke_object_url(&url);
+    Ok("Saved config".to_string())
+}
+
+/// Open a file picker, `on_loaded` is called once the user picked a file and it was parsed.
+#[cfg(target_arch = "wasm32")]
+pub fn load(on_loaded: impl FnOnce(Result<AppConfig, String>) + 'static) -> Result<(), String> {
+    use wasm_bindgen::{JsCast, closure::Closure};
+    use wasm_bindgen_futures::JsFuture;
+
+    let js_err = |e: wasm_bindgen::JsValue| format!("Failed to open file picker: {e:?}");
+
+    let document = web_sys::window()
+        .and_then(|w| w.document())
+        .ok_or("No document")?;
+    let input: web_sys::HtmlInputElement = document
+        .create_element("input")
+        .map_err(js_err)?
+        .unchecked_into();
+    input.set_type("file");
+    input.set_accept(".json,application/json");
+
+    let input_clone = input.clone();
+    let on_change = Closure::once_into_js(move || {
+        let Some(file) = input_clone.files().and_then(|files| files.get(0)) else {
+            return;
+        };
+        wasm_bindgen_futures::spawn_local(async move {
+            let result = match JsFuture::from(file.text()).await {
+                Ok(text) => from_json(text.as_string().unwrap_or_default().as_bytes()),
+                Err(e) => Err(format!("Failed to read {}: {e:?}", file.name())),
+            };
+            on_loaded(result);
+        });
+    });
+    input.set_onchange(Some(on_change.unchecked_ref()));
+    input.click();
+
+    Ok(())
+}
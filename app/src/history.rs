@@ -0,0 +1,62 @@
+//! Undo/redo for config edits.
+
+use std::collections::VecDeque;
+
+use common::config::AppConfig;
+
+/// Max number of undo steps kept
+const MAX_UNDO: usize = 50;
+
+#[derive(Clone, Default)]
+pub struct ConfigHistory {
+    undo: VecDeque<AppConfig>,
+    redo: Vec<AppConfig>,
+    /// the last recorded config
+    current: Option<AppConfig>,
+}
+
+impl ConfigHistory {
+    /// Record `cfg` as a new step, if it differs from the last one.
+    ///
+    /// This must only be called once an edit is finished, otherwise every frame of a drag ends up
+    /// as its own step.
+    pub fn record(&mut self, cfg: &AppConfig) {
+        if self.current.as_ref() == Some(cfg) {
+            return;
+        }
+        if let Some(previous) = self.current.replace(cfg.clone()) {
+            if self.undo.len() >= MAX_UNDO {
+                self.undo.pop_front();
+            }
+            self.undo.push_back(previous);
+        }
+        // a new edit after an undo starts a new branch
+        self.redo.clear();
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Returns the config to go back to
+    pub fn undo(&mut self) -> Option<AppConfig> {
+        let previous = self.undo.pop_back()?;
+        if let Some(current) = self.current.replace(previous.clone()) {
+            self.redo.push(current);
+        }
+        Some(previous)
+    }
+
+    /// Returns the config to go forward to
+    pub fn redo(&mut self) -> Option<AppConfig> {
+        let next = self.redo.pop()?;
+        if let Some(current) = self.current.replace(next.clone()) {
+            self.undo.push_back(current);
+        }
+        Some(next)
+    }
+}
@@ -1,6 +1,12 @@
 #![cfg(any(target_os = "android", target_os = "ios"))]
 
 mod app;
+mod config_file;
+mod history;
+mod mic;
+mod preview;
+mod transport;
+mod user_presets;
 
 #[cfg(target_os = "android")]
 use winit::platform::android::activity::AndroidApp;
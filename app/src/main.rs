@@ -2,7 +2,15 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
 mod app;
+#[cfg(any(target_os = "windows", target_os = "macos", target_os = "linux"))]
+mod bluetooth_native;
+mod config_file;
 mod fonts;
+mod history;
+mod mic;
+mod preview;
+mod transport;
+mod user_presets;
 #[cfg(target_arch = "wasm32")]
 mod web_bluetooth;
 
@@ -26,7 +34,7 @@ fn main() -> eframe::Result {
 
     let native_options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
-            .with_inner_size([400.0, 300.0])
+            .with_inner_size([800.0, 900.0])
             .with_min_inner_size([300.0, 220.0])
             .with_icon(
                 // NOTE: Adding an icon is optional
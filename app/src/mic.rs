@@ -0,0 +1,328 @@
+//! Capture the default microphone, so the preview can be driven by real audio.
+//!
+//! On native this uses cpal, in the browser getUserMedia + an AnalyserNode.
+
+use std::sync::{Arc, Mutex};
+
+/// The device samples at 48 kHz, so ask for the same rate to get the same bin spacing
+pub const SAMPLE_RATE: u32 = 48_000;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MicStatus {
+    Off,
+    /// Waiting for the user to grant the permission
+    #[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+    Starting,
+    Running,
+    Error(String),
+}
+
+struct Shared {
+    status: MicStatus,
+    /// most recent samples, oldest first
+    #[cfg(not(target_arch = "wasm32"))]
+    samples: std::collections::VecDeque<f32>,
+}
+
+pub struct MicCapture {
+    shared: Arc<Mutex<Shared>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    stream: Option<cpal::Stream>,
+    #[cfg(target_arch = "wasm32")]
+    web: std::rc::Rc<std::cell::RefCell<Option<web::WebAudio>>>,
+}
+
+impl Default for MicCapture {
+    fn default() -> Self {
+        Self {
+            shared: Arc::new(Mutex::new(Shared {
+                status: MicStatus::Off,
+                #[cfg(not(target_arch = "wasm32"))]
+                samples: Default::default(),
+            })),
+            #[cfg(not(target_arch = "wasm32"))]
+            stream: None,
+            #[cfg(target_arch = "wasm32")]
+            web: Default::default(),
+        }
+    }
+}
+
+impl MicCapture {
+    pub fn status(&self) -> MicStatus {
+        self.shared.lock().unwrap().status.clone()
+    }
+
+    fn set_status(shared: &Mutex<Shared>, status: MicStatus) {
+        shared.lock().unwrap().status = status;
+    }
+}
+
+// -----------------------------------------------------------------------------------------------
+// native
+
+#[cfg(not(target_arch = "wasm32"))]
+impl MicCapture {
+    pub fn start(&mut self) {
+        self.stop();
+        match self.open_stream() {
+            Ok(stream) => {
+                self.stream = Some(stream);
+                Self::set_status(&self.shared, MicStatus::Running);
+            }
+            Err(e) => Self::set_status(&self.shared, MicStatus::Error(e)),
+        }
+    }
+
+    pub fn stop(&mut self) {
+        // dropping the stream closes the device
+        self.stream = None;
+        let mut shared = self.shared.lock().unwrap();
+        shared.samples.clear();
+        shared.status = MicStatus::Off;
+    }
+
+    /// Copy the newest samples into `out`. Returns false if there are not enough yet.
+    pub fn latest_samples(&self, out: &mut [f32]) -> bool {
+        let shared = self.shared.lock().unwrap();
+        if shared.samples.len() < out.len() {
+            return false;
+        }
+        let skip = shared.samples.len() - out.len();
+        for (dst, src) in out.iter_mut().zip(shared.samples.iter().skip(skip)) {
+            *dst = *src;
+        }
+        true
+    }
+
+    fn open_stream(&self) -> Result<cpal::Stream, String> {
+        use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+        let host = cpal::default_host();
+        let device = host
+            .default_input_device()
+            .ok_or_else(|| "No microphone found".to_string())?;
+
+        // prefer 48 kHz, fall back to whatever the device defaults to
+        let supported = device
+            .supported_input_configs()
+            .map_err(|e| format!("Failed to query the microphone: {e}"))?
+            .find(|c| c.min_sample_rate().0 <= SAMPLE_RATE && c.max_sample_rate().0 >= SAMPLE_RATE)
+            .map(|c| c.with_sample_rate(cpal::SampleRate(SAMPLE_RATE)));
+        let supported = match supported {
+            Some(s) => s,
+            None => device
+                .default_input_config()
+                .map_err(|e| format!("Failed to query the microphone: {e}"))?,
+        };
+        if supported.sample_rate().0 != SAMPLE_RATE {
+            log::warn!(
+                "Microphone doesn't support {SAMPLE_RATE} Hz, using {} Hz",
+                supported.sample_rate().0
+            );
+        }
+
+        let sample_format = supported.sample_format();
+        let config: cpal::StreamConfig = supported.into();
+        let channels = config.channels as usize;
+
+        let err_shared = self.shared.clone();
+        let on_error = move |e: cpal::StreamError| {
+            log::error!("Microphone stream error: {e}");
+            Self::set_status(&err_shared, MicStatus::Error(e.to_string()));
+        };
+
+        fn build<T: cpal::SizedSample + 'static>(
+            device: &cpal::Device,
+            config: &cpal::StreamConfig,
+            channels: usize,
+            shared: Arc<Mutex<Shared>>,
+            to_f32: fn(T) -> f32,
+            on_error: impl FnMut(cpal::StreamError) + Send + 'static,
+        ) -> Result<cpal::Stream, cpal::BuildStreamError> {
+            device.build_input_stream(
+                config,
+                move |data: &[T], _: &cpal::InputCallbackInfo| {
+                    let mut shared = shared.lock().unwrap();
+                    // only the first channel is used
+                    for frame in data.chunks(channels) {
+                        if shared.samples.len() >= HISTORY_LENGTH {
+                            shared.samples.pop_front();
+                        }
+                        shared.samples.push_back(to_f32(frame[0]));
+                    }
+                },
+                on_error,
+                None,
+            )
+        }
+
+        let shared = self.shared.clone();
+        let stream = match sample_format {
+            cpal::SampleFormat::F32 => {
+                build::<f32>(&device, &config, channels, shared, |s| s, on_error)
+            }
+            cpal::SampleFormat::I16 => build::<i16>(
+                &device,
+                &config,
+                channels,
+                shared,
+                |s| s as f32 / i16::MAX as f32,
+                on_error,
+            ),
+            cpal::SampleFormat::U16 => build::<u16>(
+                &device,
+                &config,
+                channels,
+                shared,
+                |s| (s as f32 - 32768.0) / 32768.0,
+                on_error,
+            ),
+            other => return Err(format!("Unsupported sample format: {other}")),
+        }
+        .map_err(|e| format!("Failed to open the microphone: {e}"))?;
+
+        stream
+            .play()
+            .map_err(|e| format!("Failed to start the microphone: {e}"))?;
+
+        Ok(stream)
+    }
+}
+
+/// Keep a bit more than one FFT worth of samples
+#[cfg(not(target_arch = "wasm32"))]
+const HISTORY_LENGTH: usize = 2 * common::dsp::FFT_LENGTH;
+
+// -----------------------------------------------------------------------------------------------
+// wasm
+
+#[cfg(target_arch = "wasm32")]
+impl MicCapture {
+    pub fn start(&mut self) {
+        self.stop();
+        Self::set_status(&self.shared, MicStatus::Starting);
+
+        let shared = self.shared.clone();
+        let web = self.web.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            match web::WebAudio::open().await {
+                Ok(audio) => {
+                    // the user might have pressed stop while the permission prompt was open
+                    if shared.lock().unwrap().status != MicStatus::Starting {
+                        audio.close();
+                        return;
+                    }
+                    *web.borrow_mut() = Some(audio);
+                    Self::set_status(&shared, MicStatus::Running);
+                }
+                Err(e) => Self::set_status(&shared, MicStatus::Error(e)),
+            }
+        });
+    }
+
+    pub fn stop(&mut self) {
+        if let Some(audio) = self.web.borrow_mut().take() {
+            audio.close();
+        }
+        Self::set_status(&self.shared, MicStatus::Off);
+    }
+
+    /// Copy the newest samples into `out`. Returns false if there are not enough yet.
+    pub fn latest_samples(&self, out: &mut [f32]) -> bool {
+        match &*self.web.borrow() {
+            Some(audio) => audio.latest_samples(out),
+            None => false,
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod web {
+    use wasm_bindgen::{JsCast, JsValue};
+    use wasm_bindgen_futures::JsFuture;
+    use web_sys::{
+        AnalyserNode, AudioContext, AudioContextOptions, MediaStream, MediaStreamConstraints,
+    };
+
+    use super::SAMPLE_RATE;
+
+    pub struct WebAudio {
+        context: AudioContext,
+        analyser: AnalyserNode,
+        stream: MediaStream,
+    }
+
+    impl WebAudio {
+        pub async fn open() -> Result<Self, String> {
+            let window = web_sys::window().ok_or("No window")?;
+            let media_devices = window.navigator().media_devices().map_err(
+                |_| "Microphone access is not available (the page must be served over https)",
+            )?;
+
+            let constraints = MediaStreamConstraints::new();
+            constraints.set_audio(&JsValue::TRUE);
+            let promise = media_devices
+                .get_user_media_with_constraints(&constraints)
+                .map_err(describe_error)?;
+            let stream: MediaStream = JsFuture::from(promise)
+                .await
+                .map_err(describe_error)?
+                .unchecked_into();
+
+            let options = AudioContextOptions::new();
+            options.set_sample_rate(SAMPLE_RATE as f32);
+            let context =
+                AudioContext::new_with_context_options(&options).map_err(describe_error)?;
+            let source = context
+                .create_media_stream_source(&stream)
+                .map_err(describe_error)?;
+            let analyser = context.create_analyser().map_err(describe_error)?;
+            analyser.set_fft_size(common::dsp::FFT_LENGTH as u32);
+            source
+                .connect_with_audio_node(&analyser)
+                .map_err(describe_error)?;
+
+            Ok(Self {
+                context,
+                analyser,
+                stream,
+            })
+        }
+
+        pub fn latest_samples(&self, out: &mut [f32]) -> bool {
+            let available = self.analyser.fft_size() as usize;
+            if out.len() > available {
+                return false;
+            }
+            let mut buffer = vec![0.0f32; available];
+            self.analyser.get_float_time_domain_data(&mut buffer);
+            out.copy_from_slice(&buffer[available - out.len()..]);
+            true
+        }
+
+        pub fn close(self) {
+            for track in self.stream.get_tracks() {
+                track.unchecked_into::<web_sys::MediaStreamTrack>().stop();
+            }
+            let _ = self.context.close();
+        }
+    }
+
+    /// Turn a rejected promise into something the user can act on
+    fn describe_error(e: JsValue) -> String {
+        let name = js_sys::Reflect::get(&e, &"name".into())
+            .ok()
+            .and_then(|n| n.as_string());
+        match name.as_deref() {
+            Some("NotAllowedError") | Some("SecurityError") => {
+                "Microphone permission was denied".to_string()
+            }
+            Some("NotFoundError") => "No microphone found".to_string(),
+            Some("NotReadableError") => {
+                "The microphone is in use by another application".to_string()
+            }
+            _ => format!("Failed to open the microphone: {e:?}"),
+        }
+    }
+}
@@ -0,0 +1,318 @@
+use common::config::{AppConfig, SAMPLE_RATE_HZ, StereoMode};
+use common::dsp::{
+    ChannelLevels, Dither, FFT_LENGTH, MATRIX_LENGTH, RenderState, SPECTRUM_LENGTH, WindowTable,
+    apply_overlay, channel_levels, limit_power, prepare_fft_input, render_pattern,
+    render_split_frame, side_levels,
+};
+use egui::{CollapsingHeader, Color32, Sense, Vec2};
+use rustfft::{FftPlanner, num_complex::Complex};
+use web_time::{Duration, Instant};
+
+use crate::mic::{MicCapture, MicStatus};
+
+/// Input used to drive the preview
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TestSignal {
+    /// Noise falling off with 3 dB per octave, like most music
+    PinkNoise,
+    /// A kick drum at 120 bpm on top of quiet noise
+    BassHeavy,
+    /// A single tone sweeping from the lowest to the highest bin
+    Sweep,
+    /// Live audio from the default input device
+    Microphone,
+}
+
+impl TestSignal {
+    const ALL: [TestSignal; 4] = [
+        Self::PinkNoise,
+        Self::BassHeavy,
+        Self::Sweep,
+        Self::Microphone,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::PinkNoise => "Pink noise",
+            Self::BassHeavy => "Bass heavy",
+            Self::Sweep => "Sweep",
+            Self::Microphone => "Microphone",
+        }
+    }
+}
+
+/// Simulated 16x16 matrix that renders the config in the editor against a test signal
+pub struct MatrixPreview {
+    signal: TestSignal,
+    gain_db: f32,
+    started: Instant,
+    rng: u32,
+    mic: MicCapture,
+    /// Peak of the last block of microphone samples, 0.0 - 1.0
+    mic_peak: f32,
+    fft_planner: FftPlanner<f32>,
+    render: RenderState,
+    /// The right half while the matrix is split, see [`StereoMode`]
+    render_right: RenderState,
+    window: WindowTable,
+    /// The channel levels of the last frame, see [`Self::levels`]
+    levels: ChannelLevels,
+}
+
+impl Default for MatrixPreview {
+    fn default() -> Self {
+        Self {
+            signal: TestSignal::PinkNoise,
+            gain_db: 0.0,
+            started: Instant::now(),
+            rng: 0x1234_5678,
+            mic: MicCapture::default(),
+            mic_peak: 0.0,
+            fft_planner: FftPlanner::new(),
+            render: RenderState::default(),
+            render_right: RenderState::default(),
+            window: WindowTable::new(),
+            levels: ChannelLevels::new(),
+        }
+    }
+}
+
+/// Amplitude of a loud bin, roughly what the firmware sees from line-level USB audio
+const BASE_AMPLITUDE: f32 = 200.0;
+
+/// The firmware divides the 32-bit USB samples by 2^23, so full scale ends up at 256
+const MIC_SCALE: f32 = 256.0;
+
+/// Peak level above which the meter shows clipping
+const CLIP_LEVEL: f32 = 0.99;
+
+impl MatrixPreview {
+    /// How loud each channel of the pattern is in the preview, for the channel editor
+    pub fn levels(&self) -> &[f32] {
+        &self.levels
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, cfg: &AppConfig) {
+        CollapsingHeader::new("Preview")
+            .default_open(true)
+            .show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_label("Input")
+                        .selected_text(self.signal.name())
+                        .show_ui(ui, |ui| {
+                            for signal in TestSignal::ALL {
+                                ui.selectable_value(&mut self.signal, signal, signal.name());
+                            }
+                        });
+                    ui.add(egui::Slider::new(&mut self.gain_db, -40.0..=20.0).text("dB"));
+                });
+
+                if self.signal == TestSignal::Microphone {
+                    self.mic_ui(ui);
+                } else if self.mic.status() != MicStatus::Off {
+                    // don't keep the microphone open in the background
+                    self.mic.stop();
+                }
+
+                let spectrum = if self.signal == TestSignal::Microphone {
+                    self.mic_spectrum(cfg)
+                } else {
+                    self.synthetic_spectrum()
+                };
+                // the test signals and the microphone are mono, so both sides get the same spectrum
+                self.levels = channel_levels(&spectrum, &spectrum, cfg, SAMPLE_RATE_HZ, None);
+                let t = self.started.elapsed().as_secs_f32();
+                let layout = cfg.layout();
+                let mut colors = match cfg.device().stereo_mode {
+                    StereoMode::Mono => {
+                        let mut colors = render_pattern(
+                            &spectrum,
+                            &spectrum,
+                            cfg,
+                            SAMPLE_RATE_HZ,
+                            None,
+                            &mut self.render,
+                            t,
+                        );
+                        let leds = layout.led_count().min(MATRIX_LENGTH);
+                        limit_power(&mut colors[..leds], cfg.max_milliamps);
+                        colors
+                    }
+                    mode => {
+                        // the side of a mono signal is silent
+                        let side = [0.0; SPECTRUM_LENGTH];
+                        let right = if mode == StereoMode::MidSide {
+                            &side
+                        } else {
+                            &spectrum
+                        };
+                        render_split_frame(
+                            &side_levels(&spectrum, cfg, SAMPLE_RATE_HZ, None),
+                            &side_levels(right, cfg, SAMPLE_RATE_HZ, None),
+                            cfg,
+                            &mut self.render,
+                            &mut self.render_right,
+                            &mut Dither::new(),
+                            t,
+                        )
+                    }
+                };
+                apply_overlay(&mut colors, cfg.device().overlay, t);
+
+                let (width, height) = layout.size();
+                let cell = (ui.available_width() / width as f32).clamp(6.0, 20.0);
+                let (rect, _) = ui.allocate_exact_size(
+                    Vec2::new(cell * width as f32, cell * height as f32),
+                    Sense::hover(),
+                );
+                let painter = ui.painter_at(rect);
+                painter.rect_filled(rect, 0.0, Color32::BLACK);
+                for y in 0..height {
+                    for x in 0..width {
+                        let c = colors[layout.index(x, y)];
+                        // draw unlit LEDs slightly gray so the grid stays visible
+                        let fill = if c.r == 0 && c.g == 0 && c.b == 0 {
+                            Color32::from_gray(24)
+                        } else {
+                            Color32::from_rgb(c.r, c.g, c.b)
+                        };
+                        let led = egui::Rect::from_min_size(
+                            rect.min + Vec2::new(x as f32 * cell, y as f32 * cell),
+                            Vec2::splat(cell),
+                        )
+                        .shrink(1.0);
+                        painter.rect_filled(led, cell * 0.2, fill);
+                    }
+                }
+
+                // ~30 fps is plenty to judge the look, without burning the battery on mobile
+                ui.ctx().request_repaint_after(Duration::from_millis(33));
+            });
+    }
+
+    fn mic_ui(&mut self, ui: &mut egui::Ui) {
+        let status = self.mic.status();
+        ui.horizontal(|ui| {
+            match status {
+                MicStatus::Off | MicStatus::Error(_) => {
+                    if ui.button("Start").clicked() {
+                        self.mic.start();
+                    }
+                }
+                MicStatus::Starting | MicStatus::Running => {
+                    if ui.button("Stop").clicked() {
+                        self.mic.stop();
+                    }
+                }
+            }
+
+            match &status {
+                MicStatus::Off => {}
+                MicStatus::Starting => {
+                    ui.spinner();
+                    ui.label("Waiting for microphone permission...");
+                }
+                MicStatus::Running => {
+                    let clipping = self.mic_peak >= CLIP_LEVEL;
+                    let bar = egui::ProgressBar::new(self.mic_peak)
+                        .desired_width(150.0)
+                        .fill(if clipping {
+                            Color32::RED
+                        } else {
+                            Color32::DARK_GREEN
+                        });
+                    ui.add(bar);
+                    if clipping {
+                        ui.colored_label(Color32::RED, "clipping");
+                    }
+                }
+                MicStatus::Error(e) => {
+                    ui.colored_label(Color32::RED, e);
+                }
+            }
+        });
+    }
+
+    /// FFT of the newest microphone samples, processed the same way as on the device
+    fn mic_spectrum(&mut self, cfg: &AppConfig) -> [f32; SPECTRUM_LENGTH] {
+        let mut power_spectrum = [0.0f32; SPECTRUM_LENGTH];
+
+        let mut samples = vec![0.0f32; cfg.sample_count.clamp(1, FFT_LENGTH)];
+        if !self.mic.latest_samples(&mut samples) {
+            self.mic_peak = 0.0;
+            return power_spectrum;
+        }
+        self.mic_peak = samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+
+        let gain = 10f32.powf(self.gain_db / 20.0) * MIC_SCALE;
+        let fft_input = prepare_fft_input(
+            samples.iter().map(|s| s * gain),
+            cfg.window_function,
+            &mut self.window,
+        );
+
+        let mut buffer = fft_input.map(|re| Complex { re, im: 0.0 });
+        self.fft_planner
+            .plan_fft_forward(FFT_LENGTH)
+            .process(&mut buffer);
+
+        for (p, c) in power_spectrum.iter_mut().zip(buffer.iter()) {
+            *p = c.norm_sqr();
+        }
+        power_spectrum
+    }
+
+    /// Squared magnitudes for the current point in time, in the same scale as the firmware's FFT
+    fn synthetic_spectrum(&mut self) -> [f32; SPECTRUM_LENGTH] {
+        let t = self.started.elapsed().as_secs_f32();
+        let gain = 10f32.powf(self.gain_db / 20.0);
+
+        let mut amplitude = [0.0f32; SPECTRUM_LENGTH];
+        match self.signal {
+            TestSignal::PinkNoise => {
+                for (k, a) in amplitude.iter_mut().enumerate().skip(1) {
+                    *a = BASE_AMPLITUDE / (k as f32).sqrt() * (0.5 + self.random());
+                }
+            }
+            TestSignal::BassHeavy => {
+                let since_beat = t % 0.5;
+                let kick = (-since_beat * 8.0).exp();
+                for (k, a) in amplitude.iter_mut().enumerate().skip(1) {
+                    let noise = 0.3 * BASE_AMPLITUDE / (k as f32).sqrt() * (0.5 + self.random());
+                    let bass = if k <= 4 {
+                        2.0 * BASE_AMPLITUDE * kick / k as f32
+                    } else {
+                        0.0
+                    };
+                    *a = noise + bass;
+                }
+            }
+            // handled by mic_spectrum
+            TestSignal::Microphone => {}
+            TestSignal::Sweep => {
+                // logarithmic sweep over 5 seconds, so each octave gets the same time
+                const PERIOD: f32 = 5.0;
+                let phase = (t % PERIOD) / PERIOD;
+                let bin = ((SPECTRUM_LENGTH - 1) as f32).powf(phase);
+                let center = bin.round() as usize;
+                for (k, a) in amplitude.iter_mut().enumerate().skip(1) {
+                    // main lobe of a hann window is about 2 bins wide
+                    let distance = (k as f32 - bin).abs();
+                    if k.abs_diff(center) <= 1 {
+                        *a = BASE_AMPLITUDE * (1.0 - distance / 2.0).max(0.0);
+                    }
+                }
+            }
+        }
+
+        amplitude.map(|a| (a * gain) * (a * gain))
+    }
+
+    /// xorshift32, returns 0.0 - 1.0
+    fn random(&mut self) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        (self.rng >> 8) as f32 / (1 << 24) as f32
+    }
+}
@@ -0,0 +1,378 @@
+//! The connection to the device, abstracted so the handler in app.rs is the same on every
+//! platform.
+//!
+//! Implemented by `web_bluetooth::Bluetooth` (wasm), `bluetooth_native::Bluetooth` (desktop) and
+//! [`Unsupported`] (mobile).
+
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+use common::command::DeviceCommand;
+use ractor_wormhole::ractor::{ActorRef, Message};
+
+use crate::app::DiscoveredDevice;
+
+/// Contents of the standard Device Information Service
+#[derive(Clone, Debug, Default)]
+pub struct DeviceInfo {
+    pub manufacturer: String,
+    pub firmware_revision: String,
+    pub hardware_revision: String,
+}
+
+/// Called with the raw payload of every notification
+pub type NotifyCallback = Box<dyn Fn(&[u8]) + Send + 'static>;
+
+// only used inside the app with a single threaded executor, so there's no need for Send bounds
+#[allow(async_fn_in_trait)]
+pub trait ConfigTransport {
+    /// Whether Bluetooth can be used at all, the reason for the user if not
+    async fn is_available(&mut self) -> Result<(), String>;
+
+    /// Look for devices the user can choose from.
+    ///
+    /// `None` if the platform shows its own device chooser in [`Self::connect`], like the browser.
+    async fn scan(&mut self) -> Result<Option<Vec<DiscoveredDevice>>, String>;
+
+    /// Connect to a device returned by [`Self::scan`], or let the user pick one if `id` is `None`
+    async fn connect(&mut self, id: Option<&str>) -> Result<(), String>;
+
+    /// Connect to the last device again, without asking the user
+    async fn reconnect(&mut self) -> Result<(), String>;
+
+    /// Connect to a device the user picked in an earlier session, without asking. `Ok(false)` if
+    /// there is none in reach, or the platform doesn't remember them.
+    ///
+    /// `trying` is called with the name of the device before and while it's tried, the attempt
+    /// stops with `Ok(false)` once it returns false.
+    async fn try_auto_reconnect(&mut self, trying: &dyn Fn(&str) -> bool) -> Result<bool, String>;
+
+    async fn read_config(&self) -> Result<Vec<u8>, String>;
+
+    /// Write to the config characteristic, either a whole config or one chunk of a transfer
+    async fn write_config(&self, bytes: &[u8]) -> Result<(), String>;
+
+    /// Write a [`common::transfer::ConfigControl`] to the control characteristic
+    async fn write_config_control(&self, bytes: &[u8]) -> Result<(), String>;
+
+    /// Write the factory reset characteristic, the device then forgets the stored config and
+    /// switches back to the default one. Fails if the firmware doesn't have it yet.
+    async fn factory_reset(&self) -> Result<(), String>;
+
+    /// Set the max level of the log lines the device sends, 0 = off, 1 = error ... 5 = trace.
+    ///
+    /// Fails if the firmware doesn't have the characteristic yet.
+    async fn set_log_level(&self, level: u8) -> Result<(), String>;
+
+    /// Write a one-byte command, fails if the firmware doesn't have the characteristic yet
+    async fn send_command(&self, command: DeviceCommand) -> Result<(), String>;
+
+    /// Read the Device Information Service, fails if the firmware doesn't have it yet
+    async fn read_device_info(&self) -> Result<DeviceInfo, String>;
+
+    /// The config versions the device accepts, fails if the firmware doesn't say yet
+    async fn read_config_versions(&self) -> Result<RangeInclusive<u32>, String>;
+
+    /// The largest config the device accepts in bytes, fails if the firmware doesn't say yet
+    async fn read_max_config_size(&self) -> Result<usize, String>;
+
+    /// Read the name the device advertises, fails if the firmware can't be renamed yet
+    async fn read_device_name(&self) -> Result<String, String>;
+
+    /// Rename the device, it's advertised under the new name after the next disconnect
+    async fn write_device_name(&self, name: &str) -> Result<(), String>;
+
+    /// Whether the LEDs are switched on, fails if the firmware doesn't have the switch yet
+    async fn read_enabled(&self) -> Result<bool, String>;
+
+    /// Switch the LEDs on or off, the device stays connected either way
+    async fn write_enabled(&self, enabled: bool) -> Result<(), String>;
+
+    /// The names of the presets built into the firmware, fails if it doesn't have the preset
+    /// service yet
+    async fn read_preset_names(&self) -> Result<Vec<String>, String>;
+
+    /// Apply and store a built-in preset, the device then notifies the new config
+    async fn apply_preset(&self, index: u8) -> Result<(), String>;
+
+    /// The names of the preset slots, empty for unused ones. Fails if the firmware doesn't have
+    /// slots yet.
+    async fn read_slot_names(&self) -> Result<Vec<String>, String>;
+
+    /// Save the config the device runs into a slot, under `name`
+    async fn save_slot(&self, index: u8, name: &str) -> Result<(), String>;
+
+    /// Apply and store the config saved in a slot, the device then notifies it
+    async fn select_slot(&self, index: u8) -> Result<(), String>;
+
+    /// Small request to keep the connection alive, fails if it dropped
+    async fn heartbeat(&self) -> Result<(), String>;
+
+    /// Get notified about the channel levels measured on the device.
+    ///
+    /// Fails if the firmware doesn't have the characteristic yet.
+    async fn subscribe_levels(&mut self, on_levels: NotifyCallback) -> Result<(), String>;
+
+    /// Get notified whenever the config on the device changes, including by our own writes.
+    ///
+    /// Fails if the firmware doesn't notify the config yet.
+    async fn subscribe_config(&mut self, on_config: NotifyCallback) -> Result<(), String>;
+
+    /// Get notified about the alive counter the device sends every few seconds.
+    ///
+    /// Fails if the firmware doesn't have the characteristic yet.
+    async fn subscribe_alive(&mut self, on_alive: NotifyCallback) -> Result<(), String>;
+
+    /// Get notified about the signal strength of the connection as measured by the device, one
+    /// `i8` in dBm every 2 seconds.
+    ///
+    /// Fails if the firmware doesn't have the characteristic yet.
+    async fn subscribe_rssi(&mut self, on_rssi: NotifyCallback) -> Result<(), String>;
+
+    /// Get notified about the [`common::status::DeviceStatus`] the device sends every second.
+    ///
+    /// Fails if the firmware doesn't have the characteristic yet.
+    async fn subscribe_status(&mut self, on_status: NotifyCallback) -> Result<(), String>;
+
+    /// Get notified about the [`common::status::Telemetry`] the device sends every second while
+    /// it processes audio.
+    ///
+    /// Fails if the firmware doesn't have the characteristic yet.
+    async fn subscribe_telemetry(&mut self, on_telemetry: NotifyCallback) -> Result<(), String>;
+
+    /// Get notified about the log lines of the device, one UTF-8 line per notification.
+    ///
+    /// Fails if the firmware doesn't have the characteristic yet.
+    async fn subscribe_log(&mut self, on_line: NotifyCallback) -> Result<(), String>;
+
+    async fn disconnect(&mut self) -> Result<(), String>;
+}
+
+/// For platforms without Bluetooth support (yet)
+#[cfg(any(target_os = "android", target_os = "ios"))]
+pub struct Unsupported;
+
+#[cfg(any(target_os = "android", target_os = "ios"))]
+impl ConfigTransport for Unsupported {
+    async fn is_available(&mut self) -> Result<(), String> {
+        Err(Self::ERROR.to_string())
+    }
+
+    async fn scan(&mut self) -> Result<Option<Vec<DiscoveredDevice>>, String> {
+        Err(Self::ERROR.to_string())
+    }
+
+    async fn connect(&mut self, _id: Option<&str>) -> Result<(), String> {
+        Err(Self::ERROR.to_string())
+    }
+
+    async fn reconnect(&mut self) -> Result<(), String> {
+        Err(Self::ERROR.to_string())
+    }
+
+    async fn try_auto_reconnect(&mut self, _trying: &dyn Fn(&str) -> bool) -> Result<bool, String> {
+        Ok(false)
+    }
+
+    async fn read_config(&self) -> Result<Vec<u8>, String> {
+        Err(Self::ERROR.to_string())
+    }
+
+    async fn write_config(&self, _bytes: &[u8]) -> Result<(), String> {
+        Err(Self::ERROR.to_string())
+    }
+
+    async fn write_config_control(&self, _bytes: &[u8]) -> Result<(), String> {
+        Err(Self::ERROR.to_string())
+    }
+
+    async fn factory_reset(&self) -> Result<(), String> {
+        Err(Self::ERROR.to_string())
+    }
+
+    async fn set_log_level(&self, _level: u8) -> Result<(), String> {
+        Err(Self::ERROR.to_string())
+    }
+
+    async fn send_command(&self, _command: DeviceCommand) -> Result<(), String> {
+        Err(Self::ERROR.to_string())
+    }
+
+    async fn read_device_info(&self) -> Result<DeviceInfo, String> {
+        Err(Self::ERROR.to_string())
+    }
+
+    async fn read_device_name(&self) -> Result<String, String> {
+        Err(Self::ERROR.to_string())
+    }
+
+    async fn read_config_versions(&self) -> Result<RangeInclusive<u32>, String> {
+        Err(Self::ERROR.to_string())
+    }
+
+    async fn read_max_config_size(&self) -> Result<usize, String> {
+        Err(Self::ERROR.to_string())
+    }
+
+    async fn write_device_name(&self, _name: &str) -> Result<(), String> {
+        Err(Self::ERROR.to_string())
+    }
+
+    async fn read_enabled(&self) -> Result<bool, String> {
+        Err(Self::ERROR.to_string())
+    }
+
+    async fn write_enabled(&self, _enabled: bool) -> Result<(), String> {
+        Err(Self::ERROR.to_string())
+    }
+
+    async fn read_preset_names(&self) -> Result<Vec<String>, String> {
+        Err(Self::ERROR.to_string())
+    }
+
+    async fn apply_preset(&self, _index: u8) -> Result<(), String> {
+        Err(Self::ERROR.to_string())
+    }
+
+    async fn read_slot_names(&self) -> Result<Vec<String>, String> {
+        Err(Self::ERROR.to_string())
+    }
+
+    async fn save_slot(&self, _index: u8, _name: &str) -> Result<(), String> {
+        Err(Self::ERROR.to_string())
+    }
+
+    async fn select_slot(&self, _index: u8) -> Result<(), String> {
+        Err(Self::ERROR.to_string())
+    }
+
+    async fn heartbeat(&self) -> Result<(), String> {
+        Err(Self::ERROR.to_string())
+    }
+
+    async fn subscribe_levels(&mut self, _on_levels: NotifyCallback) -> Result<(), String> {
+        Err(Self::ERROR.to_string())
+    }
+
+    async fn subscribe_config(&mut self, _on_config: NotifyCallback) -> Result<(), String> {
+        Err(Self::ERROR.to_string())
+    }
+
+    async fn subscribe_alive(&mut self, _on_alive: NotifyCallback) -> Result<(), String> {
+        Err(Self::ERROR.to_string())
+    }
+
+    async fn subscribe_rssi(&mut self, _on_rssi: NotifyCallback) -> Result<(), String> {
+        Err(Self::ERROR.to_string())
+    }
+
+    async fn subscribe_status(&mut self, _on_status: NotifyCallback) -> Result<(), String> {
+        Err(Self::ERROR.to_string())
+    }
+
+    async fn subscribe_telemetry(&mut self, _on_telemetry: NotifyCallback) -> Result<(), String> {
+        Err(Self::ERROR.to_string())
+    }
+
+    async fn subscribe_log(&mut self, _on_line: NotifyCallback) -> Result<(), String> {
+        Err(Self::ERROR.to_string())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "ios"))]
+impl Unsupported {
+    const ERROR: &str = "Bluetooth is not supported on this platform";
+}
+
+// -----------------------------------------------------------------------------------------------
+// timers
+//
+// The handler runs on the actor's executor, which is not necessarily tokio, so these don't depend
+// on a specific runtime.
+
+#[cfg(target_arch = "wasm32")]
+pub async fn sleep(duration: Duration) {
+    gloo_timers::future::sleep(duration).await;
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn sleep(duration: Duration) {
+    let (tx, rx) = ractor_wormhole::deps::futures::channel::oneshot::channel();
+    timer_thread::after(duration, move || {
+        let _ = tx.send(());
+    });
+    let _ = rx.await;
+}
+
+/// Send `msg` to `actor` once `delay` has passed
+#[cfg(target_arch = "wasm32")]
+pub fn send_after<T: Message>(actor: ActorRef<T>, delay: Duration, msg: T) {
+    wasm_bindgen_futures::spawn_local(async move {
+        sleep(delay).await;
+        let _ = actor.send_message(msg);
+    });
+}
+
+/// Send `msg` to `actor` once `delay` has passed
+#[cfg(not(target_arch = "wasm32"))]
+pub fn send_after<T: Message>(actor: ActorRef<T>, delay: Duration, msg: T) {
+    timer_thread::after(delay, move || {
+        let _ = actor.send_message(msg);
+    });
+}
+
+/// One thread for all timers, rather than one per timer: the heartbeat and auto-write schedule
+/// new ones all the time, the latter on every frame a slider is dragged
+#[cfg(not(target_arch = "wasm32"))]
+mod timer_thread {
+    use std::sync::OnceLock;
+    use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+    use std::time::{Duration, Instant};
+
+    type Job = Box<dyn FnOnce() + Send>;
+
+    /// Run `job` on the timer thread once `delay` has passed
+    pub fn after(delay: Duration, job: impl FnOnce() + Send + 'static) {
+        static TIMERS: OnceLock<Sender<(Instant, Job)>> = OnceLock::new();
+        let timers = TIMERS.get_or_init(|| {
+            let (tx, rx) = mpsc::channel();
+            std::thread::Builder::new()
+                .name("timers".to_string())
+                .spawn(move || run(rx))
+                .expect("Failed to start the timer thread");
+            tx
+        });
+        let _ = timers.send((Instant::now() + delay, Box::new(job)));
+    }
+
+    fn run(rx: Receiver<(Instant, Job)>) {
+        // only a handful at a time, a list is fine
+        let mut pending: Vec<(Instant, Job)> = Vec::new();
+        loop {
+            let now = Instant::now();
+            let mut i = 0;
+            while i < pending.len() {
+                if pending[i].0 <= now {
+                    let (_, job) = pending.swap_remove(i);
+                    job();
+                } else {
+                    i += 1;
+                }
+            }
+
+            let received = match pending.iter().map(|(due, _)| *due).min() {
+                Some(due) => rx.recv_timeout(due.saturating_duration_since(Instant::now())),
+                None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match received {
+                Ok(timer) => pending.push(timer),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+    }
+}
@@ -0,0 +1,144 @@
+//! Configs saved under a name in the app, kept between sessions: in the local storage of the
+//! browser on the web, in a JSON file in the config directory on the desktop.
+
+use common::config::{AppConfig, CONFIG_VERSION};
+use serde_json::{Value, json};
+
+use crate::config_file;
+
+pub struct UserPreset {
+    pub name: String,
+    /// The config as it was stored, kept as is so a preset this app can't load isn't lost when
+    /// the others are saved
+    stored: Value,
+}
+
+impl UserPreset {
+    pub fn new(name: String, cfg: &AppConfig) -> Result<Self, String> {
+        let stored =
+            serde_json::to_value(cfg).map_err(|e| format!("Failed to serialize config: {e}"))?;
+        Ok(Self { name, stored })
+    }
+
+    /// The config, or why this app can't load it
+    pub fn config(&self) -> Result<AppConfig, String> {
+        // check the version first, an older config may not even parse
+        match self.stored.get("config_version").and_then(Value::as_u64) {
+            Some(version) if version == CONFIG_VERSION as u64 => {}
+            Some(version) => {
+                return Err(format!(
+                    "Saved with config version {version}, but this app uses version {CONFIG_VERSION}"
+                ));
+            }
+            None => return Err("Not a config".to_string()),
+        }
+        config_file::from_json(self.stored.to_string().as_bytes())
+    }
+}
+
+/// The saved presets, in the order they were saved
+pub fn load() -> Result<Vec<UserPreset>, String> {
+    let Some(json) = read()? else {
+        return Ok(Vec::new());
+    };
+    let value: Value =
+        serde_json::from_str(&json).map_err(|e| format!("The saved presets are broken: {e}"))?;
+    let entries = value.as_array().ok_or("The saved presets are not a list")?;
+    Ok(entries
+        .iter()
+        .filter_map(|entry| {
+            let name = entry.get("name")?.as_str()?.to_string();
+            let stored = entry.get("config")?.clone();
+            Some(UserPreset { name, stored })
+        })
+        .collect())
+}
+
+/// Replace the saved presets with `presets`
+pub fn save(presets: &[UserPreset]) -> Result<(), String> {
+    let entries: Vec<Value> = presets
+        .iter()
+        .map(|p| json!({ "name": p.name, "config": p.stored }))
+        .collect();
+    let json = serde_json::to_string_pretty(&entries)
+        .map_err(|e| format!("Failed to serialize the presets: {e}"))?;
+    write(&json)
+}
+
+// -----------------------------------------------------------------------------------------------
+// native
+
+#[cfg(any(target_os = "windows", target_os = "macos", target_os = "linux"))]
+fn path() -> Result<std::path::PathBuf, String> {
+    let home = || std::env::var_os("HOME").map(std::path::PathBuf::from);
+    let dir = if cfg!(target_os = "windows") {
+        std::env::var_os("APPDATA").map(std::path::PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        home().map(|home| home.join("Library/Application Support"))
+    } else {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(std::path::PathBuf::from)
+            .or_else(|| home().map(|home| home.join(".config")))
+    };
+    let dir = dir.ok_or("No config directory to keep the presets in")?;
+    Ok(dir.join("partylight").join("presets.json"))
+}
+
+#[cfg(any(target_os = "windows", target_os = "macos", target_os = "linux"))]
+fn read() -> Result<Option<String>, String> {
+    let path = path()?;
+    match std::fs::read_to_string(&path) {
+        Ok(json) => Ok(Some(json)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("Failed to read {}: {e}", path.display())),
+    }
+}
+
+#[cfg(any(target_os = "windows", target_os = "macos", target_os = "linux"))]
+fn write(json: &str) -> Result<(), String> {
+    let path = path()?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {e}", dir.display()))?;
+    }
+    std::fs::write(&path, json).map_err(|e| format!("Failed to write {}: {e}", path.display()))
+}
+
+// not stored on mobile yet
+
+#[cfg(any(target_os = "android", target_os = "ios"))]
+fn read() -> Result<Option<String>, String> {
+    Ok(None)
+}
+
+#[cfg(any(target_os = "android", target_os = "ios"))]
+fn write(_json: &str) -> Result<(), String> {
+    Err("Saving presets is not supported on this platform".to_string())
+}
+
+// -----------------------------------------------------------------------------------------------
+// wasm
+
+#[cfg(target_arch = "wasm32")]
+const STORAGE_KEY: &str = "partylight-presets";
+
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> Result<web_sys::Storage, String> {
+    web_sys::window()
+        .and_then(|w| w.local_storage().ok().flatten())
+        .ok_or_else(|| "The browser has no local storage".to_string())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read() -> Result<Option<String>, String> {
+    local_storage()?
+        .get_item(STORAGE_KEY)
+        .map_err(|e| format!("Failed to read the presets: {e:?}"))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn write(json: &str) -> Result<(), String> {
+    local_storage()?
+        .set_item(STORAGE_KEY, json)
+        .map_err(|e| format!("Failed to save the presets: {e:?}"))
+}
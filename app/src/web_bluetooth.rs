@@ -1,15 +1,70 @@
+use std::cell::Cell;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+use std::time::Duration;
+
+use common::command::DeviceCommand;
+use common::config::config_versions_from_bytes;
+use common::transfer::max_config_size_from_bytes;
 use js_sys::{Array, Function, Object, Promise, Reflect, Uint8Array};
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::JsFuture;
 use web_sys::{console, window};
 
+use crate::app::DiscoveredDevice;
+use crate::transport::{self, ConfigTransport, DeviceInfo, NotifyCallback};
+
 const SERVICE_UUID: &str = "bbafe0b7-bf3a-405a-bff7-d632c44c85f8";
 const CONFIG_CHAR_UUID: &str = "fa57339a-e7e0-434e-9c98-93a15061e1ff";
+const LEVELS_CHAR_UUID: &str = "3c9d7e12-4b6a-4f0e-a8d5-6e21f0b4c7a9";
+const ALIVE_CHAR_UUID: &str = "5e0c2a41-9b7d-4c3e-8f16-2d4b7a9c0e53";
+const RSSI_CHAR_UUID: &str = "b5d82f3e-7c41-4e96-a0f8-3e1c6d9b2a57";
+const STATUS_CHAR_UUID: &str = "9a6b3e07-2d14-4c85-b7f9-0e38d5a1c264";
+const TELEMETRY_CHAR_UUID: &str = "9d3f6b28-e4a1-4c57-b8d2-5a0e7c1f9b36";
+const CONFIG_CONTROL_CHAR_UUID: &str = "8b3f6d20-1c5e-4a79-b2d4-f07a9e31c6b8";
+const FACTORY_RESET_CHAR_UUID: &str = "d4a1f7c3-6e82-4b09-9a3d-51c8e2f06b7e";
+const LOG_CHAR_UUID: &str = "2f8e6c14-7a3b-4d90-b5e2-c1d07f9a8e36";
+const LOG_LEVEL_CHAR_UUID: &str = "6b1d9e45-3c27-4f8a-a0b6-8e5f2d7c1a93";
+const COMMAND_CHAR_UUID: &str = "c7e2a9f4-5b18-4d36-9e0c-3f6a8b1d5e27";
+const DEVICE_NAME_CHAR_UUID: &str = "e3b8c1d6-92f4-4a7e-8d05-b6f1a4c9e273";
+const ENABLED_CHAR_UUID: &str = "fc578d88-ecca-4d3c-8507-be7c8c9ea8f0";
+const CONFIG_VERSIONS_CHAR_UUID: &str = "4e7a1c93-b2d5-4f68-8a0e-c3f9d6b2174a";
+const MAX_CONFIG_SIZE_CHAR_UUID: &str = "7d2e9b41-c6a3-4f85-9e10-b4a8d3f5c762";
+const PRESET_SERVICE_UUID: &str = "71c4e9a2-3f58-4b16-a0d7-9e2b5c8f1d64";
+const PRESET_NAMES_CHAR_UUID: &str = "a8f3d2c5-6e19-4b74-9c0a-2d5e7b1f4c83";
+const APPLY_PRESET_CHAR_UUID: &str = "f2b6a9e1-4d73-4c08-8e5b-1a9c3f7d2e46";
+const SLOT_NAMES_CHAR_UUID: &str = "08b3a8f3-5a43-40fc-a471-69d120062154";
+const SAVE_SLOT_CHAR_UUID: &str = "57396568-b6ad-4184-bdfa-dde3e9f62c13";
+const SELECT_SLOT_CHAR_UUID: &str = "4c9bd057-5685-488a-acca-b3807a9ddba5";
+
+// standard services and characteristics can be referenced by name
+const DEVICE_INFO_SERVICE: &str = "device_information";
+const MANUFACTURER_NAME_CHAR: &str = "manufacturer_name_string";
+const FIRMWARE_REVISION_CHAR: &str = "firmware_revision_string";
+const HARDWARE_REVISION_CHAR: &str = "hardware_revision_string";
+
+/// Key in the local storage for the id of the device connected last, it's tried first on startup
+const LAST_DEVICE_KEY: &str = "partylight-last-device";
+
+/// How long a known device has to start advertising before auto reconnect moves on
+const ADVERTISEMENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often the wait for an advertisement checks whether it was cancelled
+const ADVERTISEMENT_POLL: Duration = Duration::from_millis(100);
 
 pub struct Bluetooth {
     device: Option<JsValue>,
     server: Option<JsValue>,
+    service: Option<JsValue>,
     cfg_char: Option<JsValue>,
+    // the listeners are kept alive for as long as the notifications are subscribed
+    levels_listener: Option<Closure<dyn FnMut(JsValue)>>,
+    config_listener: Option<Closure<dyn FnMut(JsValue)>>,
+    alive_listener: Option<Closure<dyn FnMut(JsValue)>>,
+    rssi_listener: Option<Closure<dyn FnMut(JsValue)>>,
+    status_listener: Option<Closure<dyn FnMut(JsValue)>>,
+    telemetry_listener: Option<Closure<dyn FnMut(JsValue)>>,
+    log_listener: Option<Closure<dyn FnMut(JsValue)>>,
 }
 
 impl Bluetooth {
@@ -17,10 +72,27 @@ impl Bluetooth {
         Self {
             device: None,
             server: None,
+            service: None,
             cfg_char: None,
+            levels_listener: None,
+            config_listener: None,
+            alive_listener: None,
+            rssi_listener: None,
+            status_listener: None,
+            telemetry_listener: None,
+            log_listener: None,
         }
     }
 
+    /// Services that must be listed in requestDevice to be accessible later
+    fn optional_services() -> Array {
+        Array::of3(
+            &JsValue::from_str(SERVICE_UUID),
+            &JsValue::from_str(PRESET_SERVICE_UUID),
+            &JsValue::from_str(DEVICE_INFO_SERVICE),
+        )
+    }
+
     fn bluetooth_obj() -> Result<JsValue, JsValue> {
         let window = window().ok_or_else(|| JsValue::from_str("no window"))?;
         let nav = window.navigator();
@@ -30,6 +102,30 @@ impl Bluetooth {
         Reflect::get(&nav, &JsValue::from_str("bluetooth"))
     }
 
+    /// `Err` with the reason if the browser can't do Web Bluetooth: it doesn't have
+    /// `navigator.bluetooth` at all (Firefox, Safari, or a page not served over HTTPS), or
+    /// `getAvailability()` says there's no adapter
+    pub async fn is_available() -> Result<(), String> {
+        let bt = Self::bluetooth_obj().map_err(|e| format!("{e:?}"))?;
+        if bt.is_undefined() || bt.is_null() {
+            return Err("This browser doesn't support Web Bluetooth".to_string());
+        }
+        // older browsers don't have it, they find out on connect
+        let Ok(get_availability) = Reflect::get(&bt, &JsValue::from_str("getAvailability"))
+            .and_then(|f| f.dyn_into::<Function>())
+        else {
+            return Ok(());
+        };
+        let available = match get_availability.call0(&bt).and_then(|p| p.dyn_into::<Promise>()) {
+            Ok(promise) => JsFuture::from(promise).await.map_err(|e| format!("{e:?}"))?,
+            Err(e) => return Err(format!("{e:?}")),
+        };
+        if available.as_bool() == Some(false) {
+            return Err("No Bluetooth adapter found, or it's switched off".to_string());
+        }
+        Ok(())
+    }
+
     async fn request_device_with_options(opts: &JsValue) -> Result<JsValue, JsValue> {
         console::log_1(&JsValue::from_str(
             "web_bluetooth: request_device_with_options start",
@@ -97,7 +193,7 @@ impl Bluetooth {
         Reflect::set(
             &opts,
             &JsValue::from_str("optionalServices"),
-            &Array::of1(&JsValue::from_str(SERVICE_UUID)),
+            &Self::optional_services(),
         )?;
 
         let device = match Self::request_device_with_options(&opts.into()).await {
@@ -117,7 +213,7 @@ impl Bluetooth {
                 Reflect::set(
                     &opts2,
                     &JsValue::from_str("optionalServices"),
-                    &Array::of1(&JsValue::from_str(SERVICE_UUID)),
+                    &Self::optional_services(),
                 )?;
                 match Self::request_device_with_options(&opts2.into()).await {
                     Ok(dev) => dev,
@@ -132,7 +228,7 @@ impl Bluetooth {
                         Reflect::set(
                             &opts3,
                             &JsValue::from_str("optionalServices"),
-                            &Array::of1(&JsValue::from_str(SERVICE_UUID)),
+                            &Self::optional_services(),
                         )?;
                         Self::request_device_with_options(&opts3.into()).await?
                     }
@@ -155,7 +251,9 @@ impl Bluetooth {
         let service = Self::get_service(&server, SERVICE_UUID).await?;
         console::log_1(&JsValue::from_str("web_bluetooth: getting characteristic"));
         let cfg = Self::get_characteristic(&service, CONFIG_CHAR_UUID).await?;
+        self.service = Some(service);
         self.cfg_char = Some(cfg);
+        Self::remember_device(&device);
 
         console::log_1(&JsValue::from_str("web_bluetooth: connect complete"));
         Ok(())
@@ -179,39 +277,244 @@ impl Bluetooth {
         console::log_1(&JsValue::from_str(
             "web_bluetooth: reconnect got characteristic",
         ));
+        self.service = Some(service);
         self.cfg_char = Some(cfg);
+        Self::remember_device(device);
         console::log_1(&JsValue::from_str("web_bluetooth: reconnect complete"));
         Ok(())
     }
 
+    /// Connect to the first device the user allowed on an earlier visit that has our service,
+    /// without the device chooser, the one connected last first. `Ok(false)` if none is in reach,
+    /// the browser doesn't have `navigator.bluetooth.getDevices()`, or `trying` stopped it.
+    pub async fn try_auto_reconnect(
+        &mut self,
+        trying: &dyn Fn(&str) -> bool,
+    ) -> Result<bool, JsValue> {
+        console::log_1(&JsValue::from_str("web_bluetooth: try_auto_reconnect start"));
+        let bt = Self::bluetooth_obj()?;
+        let get_devices = Reflect::get(&bt, &JsValue::from_str("getDevices"))?;
+        let Ok(get_devices) = get_devices.dyn_into::<Function>() else {
+            console::log_1(&JsValue::from_str("web_bluetooth: getDevices not supported"));
+            return Ok(false);
+        };
+        let promise: Promise = get_devices.call0(&bt)?.dyn_into()?;
+        let devices: Array = JsFuture::from(promise).await?.dyn_into()?;
+        let mut devices: Vec<JsValue> = devices.iter().collect();
+        if let Some(last) = Self::last_device_id() {
+            devices.sort_by_key(|device| Self::device_id(device).as_ref() != Some(&last));
+        }
+        for device in devices {
+            let name = Reflect::get(&device, &JsValue::from_str("name"))?
+                .as_string()
+                .unwrap_or_else(|| "the last device".to_string());
+            if !trying(&name) {
+                return Ok(false);
+            }
+            // connecting to a device out of reach takes long to fail, so wait for it to show up
+            match Self::wait_for_advertisement(&device, &|| trying(&name)).await {
+                Ok(true) => {}
+                Ok(false) => {
+                    console::log_1(&JsValue::from_str("web_bluetooth: known device not in reach"));
+                    continue;
+                }
+                // the browser can't watch, try to connect right away
+                Err(e) => console::log_2(
+                    &JsValue::from_str("web_bluetooth: can't watch advertisements"),
+                    &e,
+                ),
+            }
+            if !trying(&name) {
+                return Ok(false);
+            }
+            // fails for devices out of reach, and for others without our service
+            self.device = Some(device);
+            match self.reconnect().await {
+                Ok(()) if trying(&name) => return Ok(true),
+                Ok(()) => {
+                    let _ = self.disconnect().await;
+                    return Ok(false);
+                }
+                Err(e) => {
+                    console::log_2(
+                        &JsValue::from_str("web_bluetooth: known device not available"),
+                        &e,
+                    );
+                    let _ = self.disconnect().await;
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Wait until `device` advertises. `Ok(false)` if it doesn't within
+    /// [`ADVERTISEMENT_TIMEOUT`], or once `keep_waiting` returns false. An error if the browser
+    /// can't watch advertisements.
+    async fn wait_for_advertisement(
+        device: &JsValue,
+        keep_waiting: &dyn Fn() -> bool,
+    ) -> Result<bool, JsValue> {
+        let watch: Function =
+            Reflect::get(device, &JsValue::from_str("watchAdvertisements"))?.dyn_into()?;
+        let add_listener: Function =
+            Reflect::get(device, &JsValue::from_str("addEventListener"))?.dyn_into()?;
+        let remove_listener: Function =
+            Reflect::get(device, &JsValue::from_str("removeEventListener"))?.dyn_into()?;
+        let event = JsValue::from_str("advertisementreceived");
+
+        let seen = Rc::new(Cell::new(false));
+        let seen_by_listener = seen.clone();
+        let listener = Closure::<dyn FnMut(JsValue)>::new(move |_| seen_by_listener.set(true));
+        add_listener.call2(device, &event, listener.as_ref())?;
+
+        let abort = web_sys::AbortController::new()?;
+        let opts = Object::new();
+        Reflect::set(&opts, &JsValue::from_str("signal"), &abort.signal())?;
+        let watching = match watch.call1(device, &opts) {
+            Ok(promise) => JsFuture::from(promise.dyn_into::<Promise>()?).await,
+            Err(e) => Err(e),
+        };
+        if watching.is_ok() {
+            let mut waited = Duration::ZERO;
+            while !seen.get() && waited < ADVERTISEMENT_TIMEOUT && keep_waiting() {
+                transport::sleep(ADVERTISEMENT_POLL).await;
+                waited += ADVERTISEMENT_POLL;
+            }
+        }
+        abort.abort();
+        remove_listener.call2(device, &event, listener.as_ref())?;
+        watching?;
+        Ok(seen.get())
+    }
+
+    fn device_id(device: &JsValue) -> Option<String> {
+        Reflect::get(device, &JsValue::from_str("id"))
+            .ok()?
+            .as_string()
+    }
+
+    fn local_storage() -> Option<web_sys::Storage> {
+        window()?.local_storage().ok().flatten()
+    }
+
+    /// The id of the device connected last, see [`LAST_DEVICE_KEY`]
+    fn last_device_id() -> Option<String> {
+        Self::local_storage()?.get_item(LAST_DEVICE_KEY).ok().flatten()
+    }
+
+    fn remember_device(device: &JsValue) {
+        if let (Some(storage), Some(id)) = (Self::local_storage(), Self::device_id(device)) {
+            let _ = storage.set_item(LAST_DEVICE_KEY, &id);
+        }
+    }
+
     pub async fn read_config_raw(&self) -> Result<Uint8Array, JsValue> {
         console::log_1(&JsValue::from_str("web_bluetooth: read_config_raw start"));
         let char = self
             .cfg_char
             .as_ref()
             .ok_or_else(|| JsValue::from_str("Not connected"))?;
+        let value = Self::read_value(char).await?;
+        console::log_1(&JsValue::from_str("web_bluetooth: read_config_raw success"));
+        Ok(value)
+    }
+
+    async fn read_value(char: &JsValue) -> Result<Uint8Array, JsValue> {
         let read_fn = Reflect::get(char, &JsValue::from_str("readValue"))?;
         let func: Function = read_fn.dyn_into()?;
         let promise: Promise = func.call0(char)?.dyn_into()?;
         let v = JsFuture::from(promise).await?;
         let buffer = Reflect::get(&v, &JsValue::from_str("buffer"))?;
-        console::log_1(&JsValue::from_str("web_bluetooth: read_config_raw success"));
         Ok(Uint8Array::new(&buffer))
     }
 
+    pub async fn read_device_info_raw(&self) -> Result<DeviceInfo, JsValue> {
+        console::log_1(&JsValue::from_str("web_bluetooth: read_device_info start"));
+        let server = self
+            .server
+            .as_ref()
+            .ok_or_else(|| JsValue::from_str("Not connected"))?;
+        let service = Self::get_service(server, DEVICE_INFO_SERVICE).await?;
+        let read_string = async |uuid: &str| -> Result<String, JsValue> {
+            let char = Self::get_characteristic(&service, uuid).await?;
+            let value = Self::read_value(&char).await?;
+            Ok(String::from_utf8_lossy(&value.to_vec()).into_owned())
+        };
+        let info = DeviceInfo {
+            manufacturer: read_string(MANUFACTURER_NAME_CHAR).await?,
+            firmware_revision: read_string(FIRMWARE_REVISION_CHAR).await?,
+            hardware_revision: read_string(HARDWARE_REVISION_CHAR).await?,
+        };
+        console::log_1(&JsValue::from_str("web_bluetooth: read_device_info success"));
+        Ok(info)
+    }
+
     pub async fn write_config_raw(&self, data: &Uint8Array) -> Result<(), JsValue> {
         console::log_1(&JsValue::from_str("web_bluetooth: write_config_raw start"));
         let char = self
             .cfg_char
             .as_ref()
             .ok_or_else(|| JsValue::from_str("Not connected"))?;
+        Self::write_value(char, data).await?;
+        console::log_1(&JsValue::from_str(
+            "web_bluetooth: write_config_raw success",
+        ));
+        Ok(())
+    }
+
+    /// Write to the control characteristic, looked up on demand as it's only needed for large
+    /// configs and older firmware doesn't have it
+    pub async fn write_config_control_raw(&self, data: &Uint8Array) -> Result<(), JsValue> {
+        console::log_1(&JsValue::from_str(
+            "web_bluetooth: write_config_control_raw start",
+        ));
+        let service = self
+            .service
+            .as_ref()
+            .ok_or_else(|| JsValue::from_str("Not connected"))?;
+        let char = Self::get_characteristic(service, CONFIG_CONTROL_CHAR_UUID).await?;
+        Self::write_value(&char, data).await?;
+        console::log_1(&JsValue::from_str(
+            "web_bluetooth: write_config_control_raw success",
+        ));
+        Ok(())
+    }
+
+    /// Read a characteristic that is looked up on demand, as older firmware doesn't have it
+    async fn read_optional_raw(&self, uuid: &str) -> Result<Vec<u8>, JsValue> {
+        let service = self
+            .service
+            .as_ref()
+            .ok_or_else(|| JsValue::from_str("Not connected"))?;
+        let char = Self::get_characteristic(service, uuid).await?;
+        Ok(Self::read_value(&char).await?.to_vec())
+    }
+
+    /// The preset service, looked up on demand as older firmware doesn't have it
+    async fn preset_characteristic(&self, uuid: &str) -> Result<JsValue, JsValue> {
+        let server = self
+            .server
+            .as_ref()
+            .ok_or_else(|| JsValue::from_str("Not connected"))?;
+        let service = Self::get_service(server, PRESET_SERVICE_UUID).await?;
+        Self::get_characteristic(&service, uuid).await
+    }
+
+    /// Write to a characteristic that is looked up on demand, as older firmware doesn't have it
+    async fn write_optional_raw(&self, uuid: &str, data: &[u8]) -> Result<(), JsValue> {
+        let service = self
+            .service
+            .as_ref()
+            .ok_or_else(|| JsValue::from_str("Not connected"))?;
+        let char = Self::get_characteristic(service, uuid).await?;
+        Self::write_value(&char, &Uint8Array::from(data)).await
+    }
+
+    async fn write_value(char: &JsValue, data: &Uint8Array) -> Result<(), JsValue> {
         let write_fn = Reflect::get(char, &JsValue::from_str("writeValue"))?;
         let func: Function = write_fn.dyn_into()?;
         let promise: Promise = func.call1(char, data)?.dyn_into()?;
         let _ = JsFuture::from(promise).await?;
-        console::log_1(&JsValue::from_str(
-            "web_bluetooth: write_config_raw success",
-        ));
         Ok(())
     }
 
@@ -223,6 +526,49 @@ impl Bluetooth {
         Ok(())
     }
 
+    /// Subscribe to a characteristic, `on_value` gets the raw payload of every notification.
+    ///
+    /// The returned listener must be kept alive for as long as the notifications are needed.
+    async fn subscribe_raw(
+        &self,
+        uuid: &str,
+        mut on_value: impl FnMut(Uint8Array) + 'static,
+    ) -> Result<Closure<dyn FnMut(JsValue)>, JsValue> {
+        console::log_1(&JsValue::from_str(&format!(
+            "web_bluetooth: subscribe {uuid} start"
+        )));
+        let service = self
+            .service
+            .as_ref()
+            .ok_or_else(|| JsValue::from_str("Not connected"))?;
+        let char = Self::get_characteristic(service, uuid).await?;
+
+        let listener = Closure::<dyn FnMut(JsValue)>::new(move |event: JsValue| {
+            let value = Reflect::get(&event, &JsValue::from_str("target"))
+                .and_then(|target| Reflect::get(&target, &JsValue::from_str("value")))
+                .and_then(|value| Reflect::get(&value, &JsValue::from_str("buffer")));
+            if let Ok(buffer) = value {
+                on_value(Uint8Array::new(&buffer));
+            }
+        });
+        let add_fn = Reflect::get(&char, &JsValue::from_str("addEventListener"))?;
+        let func: Function = add_fn.dyn_into()?;
+        func.call2(
+            &char,
+            &JsValue::from_str("characteristicvaluechanged"),
+            listener.as_ref(),
+        )?;
+
+        let start_fn = Reflect::get(&char, &JsValue::from_str("startNotifications"))?;
+        let func: Function = start_fn.dyn_into()?;
+        let promise: Promise = func.call0(&char)?.dyn_into()?;
+        let _ = JsFuture::from(promise).await?;
+        console::log_1(&JsValue::from_str(&format!(
+            "web_bluetooth: subscribe {uuid} success"
+        )));
+        Ok(listener)
+    }
+
     /// Attempt to disconnect and clear cached handles.
     pub async fn disconnect(&mut self) -> Result<(), JsValue> {
         console::log_1(&JsValue::from_str("web_bluetooth: disconnect start"));
@@ -250,9 +596,253 @@ impl Bluetooth {
 
         // clear characteristic as well
         self.cfg_char = None;
+        self.service = None;
+        self.levels_listener = None;
+        self.config_listener = None;
+        self.alive_listener = None;
+        self.rssi_listener = None;
+        self.status_listener = None;
+        self.telemetry_listener = None;
+        self.log_listener = None;
         self.server = None;
         self.device = None;
         console::log_1(&JsValue::from_str("web_bluetooth: disconnect complete"));
         Ok(())
     }
 }
+
+impl ConfigTransport for Bluetooth {
+    async fn is_available(&mut self) -> Result<(), String> {
+        Bluetooth::is_available().await
+    }
+
+    async fn scan(&mut self) -> Result<Option<Vec<DiscoveredDevice>>, String> {
+        // the browser shows its own device chooser in connect
+        Ok(None)
+    }
+
+    async fn connect(&mut self, _id: Option<&str>) -> Result<(), String> {
+        Bluetooth::connect(self).await.map_err(|e| format!("{e:?}"))
+    }
+
+    async fn reconnect(&mut self) -> Result<(), String> {
+        Bluetooth::reconnect(self).await.map_err(|e| format!("{e:?}"))
+    }
+
+    async fn try_auto_reconnect(&mut self, trying: &dyn Fn(&str) -> bool) -> Result<bool, String> {
+        Bluetooth::try_auto_reconnect(self, trying)
+            .await
+            .map_err(|e| format!("{e:?}"))
+    }
+
+    async fn read_config(&self) -> Result<Vec<u8>, String> {
+        let u8arr = self.read_config_raw().await.map_err(|e| format!("{e:?}"))?;
+        Ok(u8arr.to_vec())
+    }
+
+    async fn write_config(&self, bytes: &[u8]) -> Result<(), String> {
+        self.write_config_raw(&Uint8Array::from(bytes))
+            .await
+            .map_err(|e| format!("{e:?}"))
+    }
+
+    async fn write_config_control(&self, bytes: &[u8]) -> Result<(), String> {
+        self.write_config_control_raw(&Uint8Array::from(bytes))
+            .await
+            .map_err(|e| format!("{e:?}"))
+    }
+
+    async fn factory_reset(&self) -> Result<(), String> {
+        // the value doesn't matter, any write resets
+        self.write_optional_raw(FACTORY_RESET_CHAR_UUID, &[1])
+            .await
+            .map_err(|e| format!("{e:?}"))
+    }
+
+    async fn set_log_level(&self, level: u8) -> Result<(), String> {
+        self.write_optional_raw(LOG_LEVEL_CHAR_UUID, &[level])
+            .await
+            .map_err(|e| format!("{e:?}"))
+    }
+
+    async fn send_command(&self, command: DeviceCommand) -> Result<(), String> {
+        self.write_optional_raw(COMMAND_CHAR_UUID, &[command as u8])
+            .await
+            .map_err(|e| format!("{e:?}"))
+    }
+
+    async fn read_device_info(&self) -> Result<DeviceInfo, String> {
+        self.read_device_info_raw().await.map_err(|e| format!("{e:?}"))
+    }
+
+    async fn read_device_name(&self) -> Result<String, String> {
+        let value = self
+            .read_optional_raw(DEVICE_NAME_CHAR_UUID)
+            .await
+            .map_err(|e| format!("{e:?}"))?;
+        Ok(String::from_utf8_lossy(&value).into_owned())
+    }
+
+    async fn write_device_name(&self, name: &str) -> Result<(), String> {
+        self.write_optional_raw(DEVICE_NAME_CHAR_UUID, name.as_bytes())
+            .await
+            .map_err(|e| format!("{e:?}"))
+    }
+
+    async fn read_enabled(&self) -> Result<bool, String> {
+        let value = self
+            .read_optional_raw(ENABLED_CHAR_UUID)
+            .await
+            .map_err(|e| format!("{e:?}"))?;
+        Ok(value.first() == Some(&1))
+    }
+
+    async fn write_enabled(&self, enabled: bool) -> Result<(), String> {
+        self.write_optional_raw(ENABLED_CHAR_UUID, &[enabled as u8])
+            .await
+            .map_err(|e| format!("{e:?}"))
+    }
+
+    async fn read_config_versions(&self) -> Result<RangeInclusive<u32>, String> {
+        let value = self
+            .read_optional_raw(CONFIG_VERSIONS_CHAR_UUID)
+            .await
+            .map_err(|e| format!("{e:?}"))?;
+        config_versions_from_bytes(&value).ok_or_else(|| "Invalid config versions".to_string())
+    }
+
+    async fn read_max_config_size(&self) -> Result<usize, String> {
+        let value = self
+            .read_optional_raw(MAX_CONFIG_SIZE_CHAR_UUID)
+            .await
+            .map_err(|e| format!("{e:?}"))?;
+        max_config_size_from_bytes(&value).ok_or_else(|| "Invalid max config size".to_string())
+    }
+
+    async fn read_preset_names(&self) -> Result<Vec<String>, String> {
+        let value = async {
+            let char = self.preset_characteristic(PRESET_NAMES_CHAR_UUID).await?;
+            Self::read_value(&char).await
+        }
+        .await
+        .map_err(|e| format!("{e:?}"))?;
+        Ok(String::from_utf8_lossy(&value.to_vec())
+            .lines()
+            .map(str::to_owned)
+            .collect())
+    }
+
+    async fn apply_preset(&self, index: u8) -> Result<(), String> {
+        async {
+            let char = self.preset_characteristic(APPLY_PRESET_CHAR_UUID).await?;
+            Self::write_value(&char, &Uint8Array::from(&[index][..])).await
+        }
+        .await
+        .map_err(|e| format!("{e:?}"))
+    }
+
+    async fn read_slot_names(&self) -> Result<Vec<String>, String> {
+        let value = async {
+            let char = self.preset_characteristic(SLOT_NAMES_CHAR_UUID).await?;
+            Self::read_value(&char).await
+        }
+        .await
+        .map_err(|e| format!("{e:?}"))?;
+        // not lines(), an unused last slot is an empty last line
+        Ok(String::from_utf8_lossy(&value.to_vec())
+            .split('\n')
+            .map(str::to_owned)
+            .collect())
+    }
+
+    async fn save_slot(&self, index: u8, name: &str) -> Result<(), String> {
+        let bytes: Vec<u8> = [index].into_iter().chain(name.bytes()).collect();
+        async {
+            let char = self.preset_characteristic(SAVE_SLOT_CHAR_UUID).await?;
+            Self::write_value(&char, &Uint8Array::from(&bytes[..])).await
+        }
+        .await
+        .map_err(|e| format!("{e:?}"))
+    }
+
+    async fn select_slot(&self, index: u8) -> Result<(), String> {
+        async {
+            let char = self.preset_characteristic(SELECT_SLOT_CHAR_UUID).await?;
+            Self::write_value(&char, &Uint8Array::from(&[index][..])).await
+        }
+        .await
+        .map_err(|e| format!("{e:?}"))
+    }
+
+    async fn heartbeat(&self) -> Result<(), String> {
+        Bluetooth::heartbeat(self).await.map_err(|e| format!("{e:?}"))
+    }
+
+    async fn subscribe_levels(&mut self, on_levels: NotifyCallback) -> Result<(), String> {
+        let listener = self
+            .subscribe_raw(LEVELS_CHAR_UUID, move |data| on_levels(&data.to_vec()))
+            .await
+            .map_err(|e| format!("{e:?}"))?;
+        // replaces (and drops) the listener of the previous connection
+        self.levels_listener = Some(listener);
+        Ok(())
+    }
+
+    async fn subscribe_config(&mut self, on_config: NotifyCallback) -> Result<(), String> {
+        let listener = self
+            .subscribe_raw(CONFIG_CHAR_UUID, move |data| on_config(&data.to_vec()))
+            .await
+            .map_err(|e| format!("{e:?}"))?;
+        self.config_listener = Some(listener);
+        Ok(())
+    }
+
+    async fn subscribe_alive(&mut self, on_alive: NotifyCallback) -> Result<(), String> {
+        let listener = self
+            .subscribe_raw(ALIVE_CHAR_UUID, move |data| on_alive(&data.to_vec()))
+            .await
+            .map_err(|e| format!("{e:?}"))?;
+        self.alive_listener = Some(listener);
+        Ok(())
+    }
+
+    async fn subscribe_status(&mut self, on_status: NotifyCallback) -> Result<(), String> {
+        let listener = self
+            .subscribe_raw(STATUS_CHAR_UUID, move |data| on_status(&data.to_vec()))
+            .await
+            .map_err(|e| format!("{e:?}"))?;
+        self.status_listener = Some(listener);
+        Ok(())
+    }
+
+    async fn subscribe_rssi(&mut self, on_rssi: NotifyCallback) -> Result<(), String> {
+        let listener = self
+            .subscribe_raw(RSSI_CHAR_UUID, move |data| on_rssi(&data.to_vec()))
+            .await
+            .map_err(|e| format!("{e:?}"))?;
+        self.rssi_listener = Some(listener);
+        Ok(())
+    }
+
+    async fn subscribe_telemetry(&mut self, on_telemetry: NotifyCallback) -> Result<(), String> {
+        let listener = self
+            .subscribe_raw(TELEMETRY_CHAR_UUID, move |data| on_telemetry(&data.to_vec()))
+            .await
+            .map_err(|e| format!("{e:?}"))?;
+        self.telemetry_listener = Some(listener);
+        Ok(())
+    }
+
+    async fn subscribe_log(&mut self, on_line: NotifyCallback) -> Result<(), String> {
+        let listener = self
+            .subscribe_raw(LOG_CHAR_UUID, move |data| on_line(&data.to_vec()))
+            .await
+            .map_err(|e| format!("{e:?}"))?;
+        self.log_listener = Some(listener);
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), String> {
+        Bluetooth::disconnect(self).await.map_err(|e| format!("{e:?}"))
+    }
+}
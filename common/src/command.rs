@@ -0,0 +1,41 @@
+//! One-byte commands written to the command characteristic.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+pub enum DeviceCommand {
+    /// Blink the matrix white a few times, to find the device in a room
+    Identify = 0x01,
+    Reboot = 0x02,
+    /// Run the startup animation once
+    Demo = 0x03,
+    /// Walk a pixel along the rows, to check the wiring of a new panel
+    TestPattern = 0x04,
+    /// Measure the spectrum for a moment and subtract it from then on, run while the room is as
+    /// quiet as it gets with nothing playing
+    CalibrateNoiseFloor = 0x05,
+    /// Forget the measured noise floor
+    ClearNoiseFloor = 0x06,
+    /// Keep showing the current frame, until [`DeviceCommand::Unfreeze`]
+    Freeze = 0x07,
+    Unfreeze = 0x08,
+    /// Show a still frame for a few seconds to check the data signal with a scope, see
+    /// [`crate::dsp::render_timing_pattern`]
+    TimingPattern = 0x09,
+}
+
+impl DeviceCommand {
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0x01 => Some(Self::Identify),
+            0x02 => Some(Self::Reboot),
+            0x03 => Some(Self::Demo),
+            0x04 => Some(Self::TestPattern),
+            0x05 => Some(Self::CalibrateNoiseFloor),
+            0x06 => Some(Self::ClearNoiseFloor),
+            0x07 => Some(Self::Freeze),
+            0x08 => Some(Self::Unfreeze),
+            0x09 => Some(Self::TimingPattern),
+            _ => None,
+        }
+    }
+}
@@ -1,18 +1,73 @@
+use rgb::RGB8;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+use crate::dsp::MAX_CHANNELS;
+use crate::status::AudioInput;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum AggregationMethod {
     Sum,
     Max,
+    /// Mean of the bins after premult, noise gate and exponent were applied to each of them.
+    ///
+    /// Bins below the noise gate count as zero, so a wide channel with only a few loud bins
+    /// ends up dimmer than a narrow one.
     Average,
+    /// sqrt(mean(|X[k]|²)): the power is averaged over the bins first, then premult, noise gate and
+    /// exponent are applied once to the result.
+    ///
+    /// With exponent 1 this is the RMS magnitude of the band, which doesn't depend on the number of
+    /// bins, so a 1-bin bass channel and a 10-bin treble channel are comparable in brightness.
+    RmsNormalized,
+}
+
+impl AggregationMethod {
+    /// All variants, for UI selectors
+    pub const ALL: [AggregationMethod; 4] = [
+        AggregationMethod::Sum,
+        AggregationMethod::Max,
+        AggregationMethod::Average,
+        AggregationMethod::RmsNormalized,
+    ];
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+/// Which audio channel a [`ChannelConfig`] reacts to
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum AudioSource {
+    Left,
+    Right,
+    /// Average power of both channels
+    Mono,
+}
+
+impl AudioSource {
+    /// All variants, for UI selectors
+    pub const ALL: [AudioSource; 3] = [AudioSource::Left, AudioSource::Right, AudioSource::Mono];
+}
+
+/// How a [`ChannelConfig`] picks the color of its LEDs
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum ColorMode {
+    /// `color`, scaled by the strength of the channel
+    Solid,
+    /// The hue moves from `from_hue` (silent) to `to_hue` (full strength), in degrees.
+    /// The brightness still follows the strength, `color` is ignored.
+    HueShift { from_hue: f32, to_hue: f32 },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct ChannelConfig {
-    /// index into the FFT array, inclusive
+    /// index into the FFT array, inclusive.
+    ///
+    /// A bin covers a different frequency at each sample rate (at 44.1 kHz everything is ~8% lower
+    /// than at 48 kHz), set `start_hz` / `end_hz` for a range that doesn't move with the rate.
     pub start_index: usize,
     /// index into the FFT array, inclusive
     pub end_index: usize,
+    /// lower edge of the frequency range, takes precedence over `start_index` if set
+    pub start_hz: Option<f32>,
+    /// upper edge of the frequency range, takes precedence over `end_index` if set
+    pub end_hz: Option<f32>,
 
     pub premult: f32,
     pub noise_gate: f32,
@@ -20,32 +75,714 @@ pub struct ChannelConfig {
     /// RGB color for this channel (0.0 - 1.0)
     pub color: [f32; 3],
     pub aggregate: AggregationMethod,
+    pub source: AudioSource,
+    pub color_mode: ColorMode,
+}
+
+impl ChannelConfig {
+    /// The inclusive range of FFT bins this channel covers, preferring the Hz fields if set
+    pub fn bin_range(&self, sample_rate: u32, fft_size: usize) -> (usize, usize) {
+        let start = match self.start_hz {
+            Some(hz) => hz_to_bin(hz, sample_rate, fft_size),
+            None => self.start_index,
+        };
+        let end = match self.end_hz {
+            Some(hz) => hz_to_bin(hz, sample_rate, fft_size),
+            None => self.end_index,
+        };
+        (start, end)
+    }
+
+    /// Take over how `other` turns its bins into a level: premult, noise gate, exponent and
+    /// aggregation. The bins, colors and source stay.
+    pub fn copy_gains_from(&mut self, other: &ChannelConfig) {
+        self.premult = other.premult;
+        self.noise_gate = other.noise_gate;
+        self.exponent = other.exponent;
+        self.aggregate = other.aggregate.clone();
+    }
+
+    /// Take over everything of `other` but the bins, so a channel pasted onto another one keeps
+    /// its frequencies
+    pub fn paste_from(&mut self, other: &ChannelConfig) {
+        *self = ChannelConfig {
+            start_index: self.start_index,
+            end_index: self.end_index,
+            start_hz: self.start_hz,
+            end_hz: self.end_hz,
+            ..other.clone()
+        };
+    }
+}
+
+/// Sample rate of the audio going into the FFT, unless the USB host picked another one
+pub const SAMPLE_RATE_HZ: u32 = 48_000;
+
+/// How the two audio channels share the matrix
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum StereoMode {
+    /// The pattern covers the whole matrix, each channel picks its side through its
+    /// [`AudioSource`]
+    Mono,
+    /// The pattern is drawn once per side, squeezed to half the width: the left half from the
+    /// left channel, the right half from the right one, both mirrored to start in the middle
+    LeftRightSplit,
+    /// Like [`StereoMode::LeftRightSplit`], with the mid (L + R) on the left and the side (L - R)
+    /// on the right
+    MidSide,
+}
+
+impl StereoMode {
+    /// All variants, for UI selectors
+    pub const ALL: [StereoMode; 3] = [Self::Mono, Self::LeftRightSplit, Self::MidSide];
+}
+
+/// Rates the I2S microphone can be clocked at
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum I2sSampleRate {
+    Hz16000,
+    Hz32000,
+    Hz44100,
+    Hz48000,
+}
+
+impl I2sSampleRate {
+    /// All variants, for UI selectors
+    pub const ALL: [I2sSampleRate; 4] =
+        [Self::Hz16000, Self::Hz32000, Self::Hz44100, Self::Hz48000];
+
+    pub const fn hz(self) -> u32 {
+        match self {
+            Self::Hz16000 => 16_000,
+            Self::Hz32000 => 32_000,
+            Self::Hz44100 => 44_100,
+            Self::Hz48000 => 48_000,
+        }
+    }
+}
+
+/// How many bits of the 32-bit I2S slots the microphone fills, e.g. 24 for the INMP441
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum I2sBitDepth {
+    Bits16,
+    Bits18,
+    Bits20,
+    Bits24,
+    Bits32,
+}
+
+impl I2sBitDepth {
+    /// All variants, for UI selectors
+    pub const ALL: [I2sBitDepth; 5] = [
+        Self::Bits16,
+        Self::Bits18,
+        Self::Bits20,
+        Self::Bits24,
+        Self::Bits32,
+    ];
+
+    pub const fn bits(self) -> u32 {
+        match self {
+            Self::Bits16 => 16,
+            Self::Bits18 => 18,
+            Self::Bits20 => 20,
+            Self::Bits24 => 24,
+            Self::Bits32 => 32,
+        }
+    }
+
+    /// The sample value that stands for full scale, samples are divided by it to end up in
+    /// -1.0..1.0
+    pub fn full_scale(self) -> f32 {
+        (1u64 << (self.bits() - 1)) as f32
+    }
+}
+
+/// Which I2S slots carry audio, a single microphone only sends in one of them
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum I2sChannelSource {
+    Both,
+    /// The left slot drives both sides
+    Left,
+    /// The right slot drives both sides
+    Right,
+}
+
+impl I2sChannelSource {
+    /// All variants, for UI selectors
+    pub const ALL: [I2sChannelSource; 3] = [Self::Both, Self::Left, Self::Right];
+
+    /// The left and right sample of a frame whose slots hold `first` and `second`, after swapping
+    /// them if `swap` is set
+    pub fn split(self, swap: bool, first: i32, second: i32) -> (i32, i32) {
+        let (left, right) = if swap {
+            (second, first)
+        } else {
+            (first, second)
+        };
+        match self {
+            Self::Both => (left, right),
+            Self::Left => (left, left),
+            Self::Right => (right, right),
+        }
+    }
+}
+
+/// Convert a frequency to the index of the nearest FFT bin
+///
+/// Each bin is `sample_rate / fft_size` wide, ~94 Hz at 48 kHz and 512 samples.
+pub fn hz_to_bin(hz: f32, sample_rate: u32, fft_size: usize) -> usize {
+    // `as` saturates, so negative or NaN ends up as bin 0
+    libm::roundf(hz * fft_size as f32 / sample_rate as f32) as usize
+}
+
+/// Convert an FFT bin index to its center frequency
+pub fn bin_to_hz(bin: usize, sample_rate: u32, fft_size: usize) -> f32 {
+    bin as f32 * sample_rate as f32 / fft_size as f32
+}
+
+/// The frequencies the bins `start_bin` to `end_bin` (inclusive) cover together, from the lower
+/// edge of the first to the upper edge of the last.
+///
+/// Bin 0 (DC) starts at 0 Hz and the Nyquist bin (`fft_size / 2`) ends at half the sample rate,
+/// they are only half as wide as the others. Bins past it are clamped to it.
+pub fn bin_range_hz(
+    start_bin: usize,
+    end_bin: usize,
+    sample_rate: u32,
+    fft_size: usize,
+) -> (f32, f32) {
+    let nyquist_bin = fft_size / 2;
+    let edge = |bin: usize, offset: f32| {
+        let bin = bin.min(nyquist_bin) as f32 + offset;
+        (bin * sample_rate as f32 / fft_size as f32).clamp(0.0, sample_rate as f32 / 2.0)
+    };
+    (edge(start_bin, -0.5), edge(end_bin, 0.5))
+}
+
+/// How [`assign_log_bands`] spreads the bands over the frequency range
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum BandSpacing {
+    /// The same ratio between the edges of every band, like the octaves of a graphic EQ
+    Log,
+    /// Even steps on the mel scale, about linear below 1 kHz, so the bass gets fewer bands
+    Mel,
+}
+
+impl BandSpacing {
+    /// All variants, for UI selectors
+    pub const ALL: [BandSpacing; 2] = [Self::Log, Self::Mel];
+
+    /// Where `hz` lies on the scale the bands are even on
+    fn position(self, hz: f32) -> f32 {
+        match self {
+            Self::Log => libm::logf(hz),
+            Self::Mel => 2595.0 * libm::log10f(1.0 + hz / 700.0),
+        }
+    }
+
+    /// The inverse of [`Self::position`]
+    fn hz_at(self, position: f32) -> f32 {
+        match self {
+            Self::Log => libm::expf(position),
+            Self::Mel => 700.0 * (libm::powf(10.0, position / 2595.0) - 1.0),
+        }
+    }
+}
+
+/// The frequency range the channels of the pattern split among them, see
+/// [`DeviceSettings::auto_bands`]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct AutoBandConfig {
+    pub low_hz: f32,
+    pub high_hz: f32,
+    pub spacing: BandSpacing,
+}
+
+impl Default for AutoBandConfig {
+    fn default() -> Self {
+        Self {
+            low_hz: 40.0,
+            high_hz: 16_000.0,
+            spacing: BandSpacing::Log,
+        }
+    }
+}
+
+/// Split `f_low_hz` to `f_high_hz` into `num_channels` contiguous, inclusive bin ranges, lowest
+/// first.
+///
+/// The range is clamped to the spectrum without the DC bin, and every band gets at least one bin,
+/// so the low ones end up wider than the spacing asks for. There are fewer bands than
+/// `num_channels` if the range has fewer bins, or more than [`MAX_CHANNELS`] are asked for.
+pub fn assign_log_bands(
+    num_channels: usize,
+    f_low_hz: f32,
+    f_high_hz: f32,
+    sample_rate: u32,
+    fft_size: usize,
+    spacing: BandSpacing,
+) -> heapless::Vec<(usize, usize), MAX_CHANNELS> {
+    let mut ranges = heapless::Vec::new();
+    let last_bin = fft_size / 2 - 1;
+    let low_bin = hz_to_bin(f_low_hz, sample_rate, fft_size).clamp(1, last_bin);
+    let high_bin = hz_to_bin(f_high_hz, sample_rate, fft_size).clamp(low_bin, last_bin);
+    let count = num_channels.min(MAX_CHANNELS).min(high_bin - low_bin + 1);
+
+    let low = spacing.position(bin_to_hz(low_bin, sample_rate, fft_size));
+    let high = spacing.position(bin_to_hz(high_bin, sample_rate, fft_size));
+    let mut start = low_bin;
+    for i in 0..count {
+        // leave a bin for each of the bands above
+        let bands_above = count - 1 - i;
+        let end = if bands_above == 0 {
+            high_bin
+        } else {
+            let edge = spacing.hz_at(low + (high - low) * (i + 1) as f32 / count as f32);
+            hz_to_bin(edge, sample_rate, fft_size).clamp(start, high_bin - bands_above)
+        };
+        let _ = ranges.push((start, end));
+        start = end + 1;
+    }
+    ranges
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+// no allocator on the device, so the channels can't be boxed
+#[allow(clippy::large_enum_variant)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum NeopixelMatrixPattern {
     Stripes([ChannelConfig; 4]),
     Bars([ChannelConfig; 8]),
     Quarters([ChannelConfig; 4]),
+    /// A single meter across the whole matrix, green to red from the bottom up, with a peak marker
+    /// that falls back slowly. The channel should cover all bins that matter, its color is unused.
+    VuMeter(ChannelConfig),
+    /// Scrolls `text` across the middle of the matrix in the color of the channel, faster the
+    /// louder the channel gets. Characters outside of printable ASCII are shown as '?'.
+    Scroller {
+        text: ScrollerText,
+        channel: ChannelConfig,
+    },
+    /// The whole matrix in one color that follows the loudest bin between `min_bin` and `max_bin`
+    /// (inclusive): red for the lowest, through green and blue, to violet for the highest, and as
+    /// bright as that bin is loud. Has no channels.
+    PitchColor {
+        min_bin: usize,
+        max_bin: usize,
+    },
+    /// A graphic equalizer: [`crate::dsp::SPECTRUM_COLUMNS`] bars side by side, each as high as
+    /// one bin is loud, starting at the first bin of the channel. The color, scaling and source
+    /// of the channel apply to every bar, its end and aggregation are unused.
+    Spectrum(ChannelConfig),
+    /// A waterfall of the bins of [`NeopixelMatrixPattern::Spectrum`]: they come in as the bottom
+    /// row, blue for quiet to red for loud, and move up the matrix over time. The scaling and
+    /// source of the channel apply, its color, end and aggregation are unused.
+    Spectrogram(ChannelConfig),
+}
+
+/// Max length of the [`NeopixelMatrixPattern::Scroller`] text, in bytes
+pub const SCROLLER_TEXT_SIZE: usize = 32;
+
+pub type ScrollerText = heapless::String<SCROLLER_TEXT_SIZE>;
+
+/// As much of `text` as fits into a [`ScrollerText`], cut at a character boundary
+pub fn scroller_text(text: &str) -> ScrollerText {
+    let mut result = ScrollerText::new();
+    for c in text.chars() {
+        if result.push(c).is_err() {
+            break;
+        }
+    }
+    result
+}
+
+impl NeopixelMatrixPattern {
+    pub fn channels(&self) -> &[ChannelConfig] {
+        match self {
+            NeopixelMatrixPattern::Stripes(chs) => chs,
+            NeopixelMatrixPattern::Bars(chs) => chs,
+            NeopixelMatrixPattern::Quarters(chs) => chs,
+            NeopixelMatrixPattern::VuMeter(ch) => core::slice::from_ref(ch),
+            NeopixelMatrixPattern::Scroller { channel, .. } => core::slice::from_ref(channel),
+            NeopixelMatrixPattern::PitchColor { .. } => &[],
+            NeopixelMatrixPattern::Spectrum(ch) | NeopixelMatrixPattern::Spectrogram(ch) => {
+                core::slice::from_ref(ch)
+            }
+        }
+    }
+
+    pub fn channels_mut(&mut self) -> &mut [ChannelConfig] {
+        match self {
+            NeopixelMatrixPattern::Stripes(chs) => chs,
+            NeopixelMatrixPattern::Bars(chs) => chs,
+            NeopixelMatrixPattern::Quarters(chs) => chs,
+            NeopixelMatrixPattern::VuMeter(ch) => core::slice::from_mut(ch),
+            NeopixelMatrixPattern::Scroller { channel, .. } => core::slice::from_mut(channel),
+            NeopixelMatrixPattern::PitchColor { .. } => &mut [],
+            NeopixelMatrixPattern::Spectrum(ch) | NeopixelMatrixPattern::Spectrogram(ch) => {
+                core::slice::from_mut(ch)
+            }
+        }
+    }
+}
+
+/// Animation shown instead of the pattern while there's no audio
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum IdlePattern {
+    /// Keep rendering the pattern, which usually means the matrix goes dark
+    Off,
+    /// Red, green and blue sine waves chasing each other along the strip
+    RainbowCycle,
+    /// Slowly pulse the whole matrix in the color of the first channel
+    Breathe,
+}
+
+/// Where the device takes its audio from
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum InputSource {
+    UsbAudio,
+    /// The I2S microphone board
+    I2s,
+    /// USB audio while the host streams, the microphone after [`AUTO_INPUT_FALLBACK_MS`] without
+    Auto,
+}
+
+/// How long [`InputSource::Auto`] waits for USB audio before it falls back to the microphone
+pub const AUTO_INPUT_FALLBACK_MS: u64 = 2000;
+
+impl InputSource {
+    /// All variants, for UI selectors
+    pub const ALL: [InputSource; 3] = [Self::UsbAudio, Self::I2s, Self::Auto];
+
+    /// Whether audio from `input` drives the lights. `usb_active` is whether the host sent
+    /// anything but silence in the last [`AUTO_INPUT_FALLBACK_MS`].
+    pub fn accepts(self, input: AudioInput, usb_active: bool) -> bool {
+        match (self, input) {
+            (_, AudioInput::None) => false,
+            (Self::UsbAudio, input) => input == AudioInput::Usb,
+            (Self::I2s, input) => input == AudioInput::I2s,
+            (Self::Auto, AudioInput::Usb) => true,
+            (Self::Auto, AudioInput::I2s) => !usb_active,
+        }
+    }
+}
+
+/// The LED chips of the panel
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum LedChipset {
+    /// WS2812 and compatibles, 3 bytes per pixel
+    Ws2812,
+    /// SK6812 RGBW, 4 bytes per pixel. The white LED takes over the part of a color that all three
+    /// of red, green and blue share.
+    Sk6812Rgbw,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+impl LedChipset {
+    /// All variants, for UI selectors
+    pub const ALL: [LedChipset; 2] = [Self::Ws2812, Self::Sk6812Rgbw];
+}
+
+/// Order of the color bytes on the wire, some WS2812 clones differ from the original GRB
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum ColorOrder {
+    Grb,
+    Rgb,
+    Brg,
+}
+
+impl ColorOrder {
+    /// All variants, for UI selectors
+    pub const ALL: [ColorOrder; 3] = [Self::Grb, Self::Rgb, Self::Brg];
+
+    /// The components of `color` in the order they are sent
+    pub fn arrange(self, color: RGB8) -> [u8; 3] {
+        match self {
+            Self::Grb => [color.g, color.r, color.b],
+            Self::Rgb => [color.r, color.g, color.b],
+            Self::Brg => [color.b, color.r, color.g],
+        }
+    }
+}
+
+/// Width and height of one LED panel, in pixels
+pub const PANEL_SIZE: usize = 16;
+
+pub const PANEL_LENGTH: usize = PANEL_SIZE * PANEL_SIZE;
+
+/// Most panels the firmware is built for, they are chained: the data out of one panel goes into
+/// the data in of the next
+pub const MAX_PANELS: usize = 2;
+
+/// Most LEDs the firmware is built for, the buffers are sized for this many
+pub const MAX_LEDS: usize = MAX_PANELS * PANEL_LENGTH;
+
+/// Where the next panel of the chain sits
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum PanelArrangement {
+    /// To the right of the previous one
+    SideBySide,
+    /// Below the previous one
+    Stacked,
+}
+
+impl PanelArrangement {
+    /// All variants, for UI selectors
+    pub const ALL: [PanelArrangement; 2] = [Self::SideBySide, Self::Stacked];
+}
+
+/// How a panel is mounted, clockwise from the orientation where its strip starts in the top left
+/// corner and runs down the first column
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum PanelRotation {
+    None,
+    Cw90,
+    Cw180,
+    Cw270,
+}
+
+impl PanelRotation {
+    /// All variants, for UI selectors
+    pub const ALL: [PanelRotation; 4] = [Self::None, Self::Cw90, Self::Cw180, Self::Cw270];
+
+    /// The position on the unrotated panel of what shows up at `x`, `y`
+    fn unrotate(self, x: usize, y: usize) -> (usize, usize) {
+        const LAST: usize = PANEL_SIZE - 1;
+        match self {
+            Self::None => (x, y),
+            Self::Cw90 => (y, LAST - x),
+            Self::Cw180 => (LAST - x, LAST - y),
+            Self::Cw270 => (LAST - y, x),
+        }
+    }
+}
+
+/// The panels making up the matrix, and how they are mounted
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct PanelLayout {
+    /// Number of chained panels, at most [`MAX_PANELS`]
+    pub panels: u8,
+    pub arrangement: PanelArrangement,
+    /// Per panel, in the order of the chain. Those past `panels` are ignored.
+    pub rotations: [PanelRotation; MAX_PANELS],
+}
+
+impl PanelLayout {
+    /// A single panel the way the firmware always expected it
+    pub const fn single() -> Self {
+        Self {
+            panels: 1,
+            arrangement: PanelArrangement::SideBySide,
+            rotations: [PanelRotation::None; MAX_PANELS],
+        }
+    }
+
+    /// Number of LEDs on the chain, see [`AppConfig::validate`] for the limit
+    pub fn led_count(&self) -> usize {
+        self.panels as usize * PANEL_LENGTH
+    }
+
+    /// The panels used for drawing, a layout with too many (or none) is drawn as far as it fits
+    fn drawn_panels(&self) -> usize {
+        (self.panels as usize).clamp(1, MAX_PANELS)
+    }
+
+    /// Width and height of the whole matrix, in pixels
+    pub fn size(&self) -> (usize, usize) {
+        match self.arrangement {
+            PanelArrangement::SideBySide => (self.drawn_panels() * PANEL_SIZE, PANEL_SIZE),
+            PanelArrangement::Stacked => (PANEL_SIZE, self.drawn_panels() * PANEL_SIZE),
+        }
+    }
+
+    /// Convert from x,y coordinates to the linear NeoPixel index
+    /// The XY coordinates are 0-indexed, with (0,0) at the top-left
+    /// x goes right, y goes down
+    pub fn index(&self, x: usize, y: usize) -> usize {
+        let (panel, x, y) = match self.arrangement {
+            PanelArrangement::SideBySide => (x / PANEL_SIZE, x % PANEL_SIZE, y),
+            PanelArrangement::Stacked => (y / PANEL_SIZE, x, y % PANEL_SIZE),
+        };
+        let (x, y) = self.rotations[panel].unrotate(x, y);
+        // each strip starts at top left, goes down, then one right and up, one right and down, ...
+        // so even columns go down, odd columns go up.
+        let y = if x.is_multiple_of(2) {
+            y
+        } else {
+            PANEL_SIZE - 1 - y
+        };
+        panel * PANEL_LENGTH + x * PANEL_SIZE + y
+    }
+}
+
+impl Default for PanelLayout {
+    fn default() -> Self {
+        Self::single()
+    }
+}
+
+/// Animation layered over any pattern, see [`crate::dsp::apply_overlay`]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Overlay {
+    None,
+    /// Slowly dims the whole frame and brings it back, once every `period_ms`. 0 stands still.
+    Breathe {
+        period_ms: u16,
+    },
+}
+
+/// Settings of the hardware and of how the device behaves, rather than of the look of a pattern
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct DeviceSettings {
+    /// The panels and how they are mounted, the patterns stretch over all of them
+    pub layout: PanelLayout,
+    /// How long the LEDs keep the last frame when no new one arrives before they fade to black,
+    /// in ms. 0 keeps it forever.
+    pub fade_timeout_ms: u16,
+    /// How long the fade to black takes, in ms
+    pub fade_duration_ms: u16,
+    /// Most new frames the LEDs pick up per second, the ones in between are skipped. 0 takes every
+    /// frame the audio processing renders.
+    pub target_fps: u8,
+    /// The I2S clock, only applied when the device starts
+    pub i2s_sample_rate: I2sSampleRate,
+    pub i2s_bit_depth: I2sBitDepth,
+    /// For microphones wired to the other word select phase
+    pub i2s_swap_channels: bool,
+    pub i2s_channel_source: I2sChannelSource,
+    pub stereo_mode: StereoMode,
+    /// Cutoff of the high-pass ahead of the FFT, which takes out the DC offset of the microphone.
+    /// 0 turns it off, e.g. for line level USB audio.
+    pub high_pass_hz: u16,
+    /// Red, green and blue gain (0.0 - 1.0) applied to every frame, to calibrate LEDs whose
+    /// white is off, e.g. bluish
+    pub white_balance: [f32; 3],
+    /// Recompute the bins of the channels from this range whenever the sample rate changes, so
+    /// they keep covering the same frequencies. `None` uses the bins of the pattern as they are.
+    pub auto_bands: Option<AutoBandConfig>,
+    /// Animation layered over the pattern, see [`crate::dsp::apply_overlay`]
+    pub overlay: Overlay,
+    /// Pause advertising for 10 min after this many minutes without anyone connecting, or until
+    /// a restart. 0 advertises all the time.
+    pub advertise_pause_after_min: u8,
+}
+
+impl Default for DeviceSettings {
+    fn default() -> Self {
+        Self {
+            layout: PanelLayout::single(),
+            fade_timeout_ms: 2000,
+            fade_duration_ms: 1000,
+            target_fps: 0,
+            i2s_sample_rate: I2sSampleRate::Hz48000,
+            i2s_bit_depth: I2sBitDepth::Bits24,
+            i2s_swap_channels: false,
+            i2s_channel_source: I2sChannelSource::Both,
+            stereo_mode: StereoMode::Mono,
+            high_pass_hz: 20,
+            white_balance: [1.0; 3],
+            auto_bands: None,
+            overlay: Overlay::None,
+            advertise_pause_after_min: 0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum FFTSize {
     Size128 = 128,
     Size256 = 256,
     Size512 = 512,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+/// Window applied to the samples before the FFT
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum WindowFunction {
+    None,
+    Hann,
+    Hamming,
+    /// 4-term Blackman-Harris, lowest leakage, but also the widest main lobe
+    BlackmanHarris,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct AppConfig {
     pub config_version: u32,
     pub sample_count: usize,
     pub fft_size: FFTSize,
-    pub use_hann_window: bool,
+    pub window_function: WindowFunction,
     pub pattern: NeopixelMatrixPattern,
+    pub idle_pattern: IdlePattern,
+    pub input_source: InputSource,
+    /// Total energy of the spectrum (see [`crate::dsp::total_energy`]) below which the audio
+    /// counts as silent
+    pub idle_threshold: f32,
+    /// Budget for the current all LEDs together may draw, in mA. Frames above it are dimmed
+    /// (see [`crate::dsp::limit_power`]). 0 disables the limit.
+    pub max_milliamps: u16,
+    /// Smooth dim gradients by dithering over time instead of rounding every frame the same way
+    /// (see [`crate::dsp::Dither`])
+    pub dither: bool,
+    /// The LEDs of the panel, they take a different number of bytes per pixel
+    pub led_chipset: LedChipset,
+    /// The order the LEDs expect the color bytes in, GRB for the genuine WS2812
+    pub color_order: ColorOrder,
+    /// Average the spectra of this many consecutive blocks before rendering a frame, less noisy
+    /// but fewer frames per second (see [`crate::dsp::SpectralAverage`]). 1 renders every block.
+    pub spectral_averages: u8,
+    /// `None` is [`DeviceSettings::default`], see [`AppConfig::set_device`]
+    pub device: Option<DeviceSettings>,
 }
 
-pub const CONFIG_VERSION: u32 = 1;
+/// Version of the postcard layout of [`AppConfig`], bumped whenever the bytes of a config change
+/// meaning. 1 is the layout of the firmware before this series.
+pub const CONFIG_VERSION: u32 = 2;
+
+/// Oldest config version the device accepts, there's no migration between versions yet
+pub const MIN_CONFIG_VERSION: u32 = CONFIG_VERSION;
+
+/// ATT application error the device replies with to a config of a version it doesn't support,
+/// as opposed to Value Not Allowed for a config it can't decode
+pub const UNSUPPORTED_CONFIG_VERSION_ERROR: u8 = 0x80;
+
+/// ATT application error the device replies with to a config with more LEDs than it is built for
+pub const TOO_MANY_LEDS_ERROR: u8 = 0x81;
+
+/// The config versions the device accepts: min (u32 LE) | max (u32 LE)
+pub const CONFIG_VERSIONS_SIZE: usize = 8;
+
+pub fn config_versions_to_bytes() -> [u8; CONFIG_VERSIONS_SIZE] {
+    let mut bytes = [0; CONFIG_VERSIONS_SIZE];
+    bytes[..4].copy_from_slice(&MIN_CONFIG_VERSION.to_le_bytes());
+    bytes[4..].copy_from_slice(&CONFIG_VERSION.to_le_bytes());
+    bytes
+}
+
+pub fn config_versions_from_bytes(data: &[u8]) -> Option<core::ops::RangeInclusive<u32>> {
+    let min = u32::from_le_bytes(data.get(..4)?.try_into().ok()?);
+    let max = u32::from_le_bytes(data.get(4..CONFIG_VERSIONS_SIZE)?.try_into().ok()?);
+    Some(min..=max)
+}
+
+/// Capacity of the config characteristic on the device.
+///
+/// MTU (247) minus the 3 byte ATT header, so a whole config still fits into a single write.
+/// Firmware before the max config size characteristic only had [`LEGACY_MAX_CONFIG_SIZE`].
+pub const MAX_CONFIG_SIZE: usize = 244;
+
+/// Capacity of the config characteristic on firmware that doesn't report its max config size,
+/// see [`crate::transfer::max_config_size_from_bytes`]. It can't take chunked transfers either.
+pub const LEGACY_MAX_CONFIG_SIZE: usize = 200;
+
+/// Why a received config was rejected
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConfigError {
+    /// Outside of [`MIN_CONFIG_VERSION`]..=[`CONFIG_VERSION`]
+    UnsupportedVersion(u32),
+    /// Not a config, or truncated
+    Invalid,
+    /// The layout has this many LEDs, more than [`MAX_LEDS`] (or none at all)
+    TooManyLeds(usize),
+}
 
 impl AppConfig {
     /// Serialize config to binary data using postcard
@@ -53,8 +790,111 @@ impl AppConfig {
         postcard::to_vec::<_, B>(self)
     }
 
+    /// How many bytes [`AppConfig::to_bytes`] takes, without a buffer to put them in. Compare it
+    /// to the limit of the device before writing.
+    pub fn serialized_size(&self) -> usize {
+        postcard::serialize_with_flavor(self, postcard::ser_flavors::Size::default())
+            .unwrap_or(usize::MAX)
+    }
+
     /// Deserialize config from binary data using postcard
     pub fn from_bytes(data: &[u8]) -> Result<Self, postcard::Error> {
         postcard::from_bytes(data)
     }
+
+    /// Like [`AppConfig::from_bytes`], but tells a config of a version this build doesn't accept
+    /// apart from one it can't decode, so the sender can be told to update
+    pub fn from_bytes_checked(data: &[u8]) -> Result<Self, ConfigError> {
+        if let Some(version) = Self::peek_version(data)
+            && !(MIN_CONFIG_VERSION..=CONFIG_VERSION).contains(&version)
+        {
+            return Err(ConfigError::UnsupportedVersion(version));
+        }
+        let config = Self::from_bytes(data).map_err(|_| ConfigError::Invalid)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// The device settings of the config, the defaults if it doesn't say
+    pub fn device(&self) -> DeviceSettings {
+        self.device.unwrap_or_default()
+    }
+
+    /// Store `device`, as `None` if it's the defaults
+    pub fn set_device(&mut self, device: DeviceSettings) {
+        self.device = (device != DeviceSettings::default()).then_some(device);
+    }
+
+    pub fn layout(&self) -> PanelLayout {
+        self.device().layout
+    }
+
+    /// The rate the selected input delivers its audio at, to map the bins to Hz. USB audio is
+    /// assumed to run at [`SAMPLE_RATE_HZ`], the host might still pick another one.
+    pub fn sample_rate(&self) -> u32 {
+        match self.input_source {
+            InputSource::I2s => self.device().i2s_sample_rate.hz(),
+            InputSource::UsbAudio | InputSource::Auto => SAMPLE_RATE_HZ,
+        }
+    }
+
+    /// Give the channels of the pattern the bins of `bands`, in the order of the channels.
+    /// Clears their Hz fields. Channels past the bands the range has room for get the highest one.
+    pub fn assign_bands(&mut self, bands: AutoBandConfig, sample_rate: u32, fft_size: usize) {
+        let channels = self.pattern.channels_mut();
+        let ranges = assign_log_bands(
+            channels.len(),
+            bands.low_hz,
+            bands.high_hz,
+            sample_rate,
+            fft_size,
+            bands.spacing,
+        );
+        let Some(&highest) = ranges.last() else {
+            return;
+        };
+        let ranges = ranges.iter().chain(core::iter::repeat(&highest));
+        for (channel, &(start, end)) in channels.iter_mut().zip(ranges) {
+            channel.start_index = start;
+            channel.end_index = end;
+            channel.start_hz = None;
+            channel.end_hz = None;
+        }
+    }
+
+    /// [`Self::assign_bands`] with [`DeviceSettings::auto_bands`], if set
+    pub fn apply_auto_bands(&mut self, sample_rate: u32, fft_size: usize) {
+        if let Some(bands) = self.device().auto_bands {
+            self.assign_bands(bands, sample_rate, fft_size);
+        }
+    }
+
+    /// Check what decodes fine but the device can't run
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let leds = self.layout().led_count();
+        if leds == 0 || leds > MAX_LEDS {
+            return Err(ConfigError::TooManyLeds(leds));
+        }
+        Ok(())
+    }
+
+    /// The `config_version` of a serialized config without decoding the rest, so it also works
+    /// for versions this build doesn't know. Relies on the version staying the first field.
+    pub fn peek_version(data: &[u8]) -> Option<u32> {
+        postcard::take_from_bytes::<u32>(data)
+            .ok()
+            .map(|(version, _)| version)
+    }
+}
+
+/// JSON, for config files that can be diffed and edited by hand. The device only speaks postcard.
+#[cfg(feature = "std")]
+impl AppConfig {
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    pub fn from_json(data: &[u8]) -> serde_json::Result<Self> {
+        serde_json::from_slice(data)
+    }
 }
@@ -1,50 +1,79 @@
 use crate::config::*;
 
+/// A 5 V / 4 A supply, full white on all 256 LEDs would draw ~15 A (see
+/// [`crate::dsp::estimate_milliamps`])
+const DEFAULT_MAX_MILLIAMPS: u16 = 4000;
+
 impl AppConfig {
     pub fn stripes() -> Self {
         Self {
             config_version: CONFIG_VERSION,
             sample_count: 256,
             fft_size: FFTSize::Size512,
-            use_hann_window: true,
+            window_function: WindowFunction::Hann,
             pattern: NeopixelMatrixPattern::Stripes([
                 ChannelConfig {
                     start_index: 1,
                     end_index: 1,
+                    start_hz: None,
+                    end_hz: None,
                     premult: 3.0,
                     noise_gate: 0.01,
                     exponent: 6,
                     color: [1.0, 0.0, 0.0],
                     aggregate: AggregationMethod::Sum,
+                    source: AudioSource::Left,
+                    color_mode: ColorMode::Solid,
                 },
                 ChannelConfig {
                     start_index: 2,
                     end_index: 10,
+                    start_hz: None,
+                    end_hz: None,
                     premult: 3.0,
                     noise_gate: 0.01,
                     exponent: 6,
                     color: [0.0, 1.0, 0.0],
                     aggregate: AggregationMethod::Sum,
+                    source: AudioSource::Left,
+                    color_mode: ColorMode::Solid,
                 },
                 ChannelConfig {
                     start_index: 11,
                     end_index: 15,
+                    start_hz: None,
+                    end_hz: None,
                     premult: 3.0,
                     noise_gate: 0.01,
                     exponent: 6,
                     color: [0.0, 0.0, 1.0],
                     aggregate: AggregationMethod::Sum,
+                    source: AudioSource::Left,
+                    color_mode: ColorMode::Solid,
                 },
                 ChannelConfig {
                     start_index: 16,
                     end_index: 25,
+                    start_hz: None,
+                    end_hz: None,
                     premult: 3.0,
                     noise_gate: 0.01,
                     exponent: 6,
                     color: [1.0, 1.0, 1.0],
                     aggregate: AggregationMethod::Sum,
+                    source: AudioSource::Left,
+                    color_mode: ColorMode::Solid,
                 },
             ]),
+            idle_pattern: IdlePattern::RainbowCycle,
+            input_source: InputSource::UsbAudio,
+            idle_threshold: 0.01,
+            max_milliamps: DEFAULT_MAX_MILLIAMPS,
+            dither: false,
+            led_chipset: LedChipset::Ws2812,
+            color_order: ColorOrder::Grb,
+            spectral_averages: 1,
+            device: None,
         }
     }
 
@@ -53,81 +82,122 @@ impl AppConfig {
             config_version: CONFIG_VERSION,
             sample_count: 256,
             fft_size: FFTSize::Size512,
-            use_hann_window: true,
+            window_function: WindowFunction::Hann,
             pattern: NeopixelMatrixPattern::Bars([
                 ChannelConfig {
                     start_index: 1,
                     end_index: 2,
+                    start_hz: None,
+                    end_hz: None,
                     premult: 3.0,
                     noise_gate: 0.01,
                     exponent: 6,
                     color: [1.0, 0.0, 0.0], // Red
                     aggregate: AggregationMethod::Sum,
+                    source: AudioSource::Left,
+                    color_mode: ColorMode::Solid,
                 },
                 ChannelConfig {
                     start_index: 3,
                     end_index: 4,
+                    start_hz: None,
+                    end_hz: None,
                     premult: 3.0,
                     noise_gate: 0.01,
                     exponent: 6,
                     color: [1.0, 0.498, 0.0], // Orange
                     aggregate: AggregationMethod::Sum,
+                    source: AudioSource::Left,
+                    color_mode: ColorMode::Solid,
                 },
                 ChannelConfig {
                     start_index: 5,
                     end_index: 7,
+                    start_hz: None,
+                    end_hz: None,
                     premult: 3.0,
                     noise_gate: 0.01,
                     exponent: 6,
                     color: [1.0, 1.0, 0.0], // Yellow
                     aggregate: AggregationMethod::Sum,
+                    source: AudioSource::Left,
+                    color_mode: ColorMode::Solid,
                 },
                 ChannelConfig {
                     start_index: 8,
                     end_index: 10,
+                    start_hz: None,
+                    end_hz: None,
                     premult: 3.0,
                     noise_gate: 0.01,
                     exponent: 6,
                     color: [0.0, 1.0, 0.0], // Green
                     aggregate: AggregationMethod::Sum,
+                    source: AudioSource::Left,
+                    color_mode: ColorMode::Solid,
                 },
                 ChannelConfig {
                     start_index: 11,
                     end_index: 14,
+                    start_hz: None,
+                    end_hz: None,
                     premult: 3.0,
                     noise_gate: 0.01,
                     exponent: 6,
                     color: [0.0, 1.0, 1.0], // Cyan
                     aggregate: AggregationMethod::Sum,
+                    source: AudioSource::Left,
+                    color_mode: ColorMode::Solid,
                 },
                 ChannelConfig {
                     start_index: 15,
                     end_index: 18,
+                    start_hz: None,
+                    end_hz: None,
                     premult: 3.0,
                     noise_gate: 0.01,
                     exponent: 6,
                     color: [0.0, 0.0, 1.0], // Blue
                     aggregate: AggregationMethod::Sum,
+                    source: AudioSource::Left,
+                    color_mode: ColorMode::Solid,
                 },
                 ChannelConfig {
                     start_index: 19,
                     end_index: 22,
+                    start_hz: None,
+                    end_hz: None,
                     premult: 3.0,
                     noise_gate: 0.01,
                     exponent: 6,
                     color: [0.498, 0.0, 1.0], // Purple
                     aggregate: AggregationMethod::Sum,
+                    source: AudioSource::Left,
+                    color_mode: ColorMode::Solid,
                 },
                 ChannelConfig {
                     start_index: 23,
                     end_index: 25,
+                    start_hz: None,
+                    end_hz: None,
                     premult: 3.0,
                     noise_gate: 0.01,
                     exponent: 6,
                     color: [1.0, 0.0, 1.0], // Magenta
                     aggregate: AggregationMethod::Sum,
+                    source: AudioSource::Left,
+                    color_mode: ColorMode::Solid,
                 },
             ]),
+            idle_pattern: IdlePattern::RainbowCycle,
+            input_source: InputSource::UsbAudio,
+            idle_threshold: 0.01,
+            max_milliamps: DEFAULT_MAX_MILLIAMPS,
+            dither: false,
+            led_chipset: LedChipset::Ws2812,
+            color_order: ColorOrder::Grb,
+            spectral_averages: 1,
+            device: None,
         }
     }
 
@@ -136,47 +206,240 @@ impl AppConfig {
             config_version: CONFIG_VERSION,
             sample_count: 256,
             fft_size: FFTSize::Size512,
-            use_hann_window: true,
+            window_function: WindowFunction::Hann,
             pattern: NeopixelMatrixPattern::Quarters([
                 ChannelConfig {
                     start_index: 1,
                     end_index: 4,
+                    start_hz: None,
+                    end_hz: None,
                     premult: 3.0,
                     noise_gate: 0.01,
                     exponent: 6,
                     color: [1.0, 0.0, 0.0],
                     aggregate: AggregationMethod::Sum,
+                    source: AudioSource::Left,
+                    color_mode: ColorMode::Solid,
                 },
                 ChannelConfig {
                     start_index: 5,
                     end_index: 10,
+                    start_hz: None,
+                    end_hz: None,
                     premult: 3.0,
                     noise_gate: 0.01,
                     exponent: 6,
                     color: [0.0, 1.0, 0.0],
                     aggregate: AggregationMethod::Sum,
+                    source: AudioSource::Left,
+                    color_mode: ColorMode::Solid,
                 },
                 ChannelConfig {
                     start_index: 11,
                     end_index: 15,
+                    start_hz: None,
+                    end_hz: None,
                     premult: 3.0,
                     noise_gate: 0.01,
                     exponent: 6,
                     color: [0.0, 0.0, 1.0],
                     aggregate: AggregationMethod::Sum,
+                    source: AudioSource::Left,
+                    color_mode: ColorMode::Solid,
                 },
                 ChannelConfig {
                     start_index: 16,
                     end_index: 25,
+                    start_hz: None,
+                    end_hz: None,
                     premult: 3.0,
                     noise_gate: 0.01,
                     exponent: 6,
                     color: [1.0, 1.0, 1.0],
                     aggregate: AggregationMethod::Sum,
+                    source: AudioSource::Left,
+                    color_mode: ColorMode::Solid,
                 },
             ]),
+            idle_pattern: IdlePattern::RainbowCycle,
+            input_source: InputSource::UsbAudio,
+            idle_threshold: 0.01,
+            max_milliamps: DEFAULT_MAX_MILLIAMPS,
+            dither: false,
+            led_chipset: LedChipset::Ws2812,
+            color_order: ColorOrder::Grb,
+            spectral_averages: 1,
+            device: None,
         }
     }
+
+    /// Like [`Self::bars`] with four bands per side, the left half of the matrix shows the left
+    /// channel and the right half the right one, with the bass in the middle.
+    pub fn stereo_bars() -> Self {
+        Self {
+            config_version: CONFIG_VERSION,
+            sample_count: 256,
+            fft_size: FFTSize::Size512,
+            window_function: WindowFunction::Hann,
+            pattern: NeopixelMatrixPattern::Bars([
+                ChannelConfig {
+                    start_index: 15,
+                    end_index: 25,
+                    start_hz: None,
+                    end_hz: None,
+                    premult: 3.0,
+                    noise_gate: 0.01,
+                    exponent: 6,
+                    color: [1.0, 0.0, 1.0], // Magenta
+                    aggregate: AggregationMethod::Sum,
+                    source: AudioSource::Left,
+                    color_mode: ColorMode::Solid,
+                },
+                ChannelConfig {
+                    start_index: 8,
+                    end_index: 14,
+                    start_hz: None,
+                    end_hz: None,
+                    premult: 3.0,
+                    noise_gate: 0.01,
+                    exponent: 6,
+                    color: [0.0, 1.0, 1.0], // Cyan
+                    aggregate: AggregationMethod::Sum,
+                    source: AudioSource::Left,
+                    color_mode: ColorMode::Solid,
+                },
+                ChannelConfig {
+                    start_index: 3,
+                    end_index: 7,
+                    start_hz: None,
+                    end_hz: None,
+                    premult: 3.0,
+                    noise_gate: 0.01,
+                    exponent: 6,
+                    color: [1.0, 1.0, 0.0], // Yellow
+                    aggregate: AggregationMethod::Sum,
+                    source: AudioSource::Left,
+                    color_mode: ColorMode::Solid,
+                },
+                ChannelConfig {
+                    start_index: 1,
+                    end_index: 2,
+                    start_hz: None,
+                    end_hz: None,
+                    premult: 3.0,
+                    noise_gate: 0.01,
+                    exponent: 6,
+                    color: [1.0, 0.0, 0.0], // Red
+                    aggregate: AggregationMethod::Sum,
+                    source: AudioSource::Left,
+                    color_mode: ColorMode::Solid,
+                },
+                ChannelConfig {
+                    start_index: 1,
+                    end_index: 2,
+                    start_hz: None,
+                    end_hz: None,
+                    premult: 3.0,
+                    noise_gate: 0.01,
+                    exponent: 6,
+                    color: [1.0, 0.0, 0.0], // Red
+                    aggregate: AggregationMethod::Sum,
+                    source: AudioSource::Right,
+                    color_mode: ColorMode::Solid,
+                },
+                ChannelConfig {
+                    start_index: 3,
+                    end_index: 7,
+                    start_hz: None,
+                    end_hz: None,
+                    premult: 3.0,
+                    noise_gate: 0.01,
+                    exponent: 6,
+                    color: [1.0, 1.0, 0.0], // Yellow
+                    aggregate: AggregationMethod::Sum,
+                    source: AudioSource::Right,
+                    color_mode: ColorMode::Solid,
+                },
+                ChannelConfig {
+                    start_index: 8,
+                    end_index: 14,
+                    start_hz: None,
+                    end_hz: None,
+                    premult: 3.0,
+                    noise_gate: 0.01,
+                    exponent: 6,
+                    color: [0.0, 1.0, 1.0], // Cyan
+                    aggregate: AggregationMethod::Sum,
+                    source: AudioSource::Right,
+                    color_mode: ColorMode::Solid,
+                },
+                ChannelConfig {
+                    start_index: 15,
+                    end_index: 25,
+                    start_hz: None,
+                    end_hz: None,
+                    premult: 3.0,
+                    noise_gate: 0.01,
+                    exponent: 6,
+                    color: [1.0, 0.0, 1.0], // Magenta
+                    aggregate: AggregationMethod::Sum,
+                    source: AudioSource::Right,
+                    color_mode: ColorMode::Solid,
+                },
+            ]),
+            idle_pattern: IdlePattern::RainbowCycle,
+            input_source: InputSource::UsbAudio,
+            idle_threshold: 0.01,
+            max_milliamps: DEFAULT_MAX_MILLIAMPS,
+            dither: false,
+            led_chipset: LedChipset::Ws2812,
+            color_order: ColorOrder::Grb,
+            spectral_averages: 1,
+            device: None,
+        }
+    }
+}
+
+/// Upper edge of [`AppConfig::bars_log`], roughly the end of the audible spectrum
+pub const BARS_LOG_MAX_HZ: f32 = 20_000.0;
+
+impl AppConfig {
+    /// The colors of [`Self::bars`], with `num_bars` logarithmically spaced bands from bin 1 up to
+    /// [`BARS_LOG_MAX_HZ`], so each bar covers about an octave like on a graphic EQ.
+    ///
+    /// `num_bars` is clamped to the 8 bars of the pattern, the ones past it stay dark.
+    pub fn bars_log(num_bars: usize) -> Self {
+        let mut config = Self::bars();
+        let NeopixelMatrixPattern::Bars(channels) = &mut config.pattern else {
+            unreachable!()
+        };
+
+        let num_bars = num_bars.clamp(1, channels.len());
+        // from bin 1, the lowest one above DC
+        let ranges = assign_log_bands(
+            num_bars,
+            0.0,
+            BARS_LOG_MAX_HZ,
+            SAMPLE_RATE_HZ,
+            crate::dsp::FFT_LENGTH,
+            BandSpacing::Log,
+        );
+        for (i, channel) in channels.iter_mut().enumerate() {
+            match ranges.get(i) {
+                Some(&(start, end)) => {
+                    channel.start_index = start;
+                    channel.end_index = end;
+                }
+                None => {
+                    let (_, last) = ranges[num_bars - 1];
+                    channel.start_index = last;
+                    channel.end_index = last;
+                    channel.color = [0.0, 0.0, 0.0];
+                }
+            }
+        }
+        config
+    }
 }
 
 impl AppConfig {
@@ -185,83 +448,174 @@ impl AppConfig {
             config_version: CONFIG_VERSION,
             sample_count: 256,
             fft_size: FFTSize::Size512,
-            use_hann_window: true,
+            window_function: WindowFunction::Hann,
             pattern: NeopixelMatrixPattern::Bars([
                 ChannelConfig {
                     start_index: 1,
                     end_index: 1,
+                    start_hz: None,
+                    end_hz: None,
                     premult: 2.0,
                     noise_gate: 0.0,
                     exponent: 1,
                     color: [1.0, 0.0, 0.0],
                     aggregate: AggregationMethod::Sum,
+                    source: AudioSource::Left,
+                    color_mode: ColorMode::Solid,
                 },
                 ChannelConfig {
                     start_index: 2,
                     end_index: 3,
+                    start_hz: None,
+                    end_hz: None,
                     premult: 3.0,
                     noise_gate: 0.0,
                     exponent: 1,
                     color: [1.0, 0.498, 0.0],
                     aggregate: AggregationMethod::Sum,
+                    source: AudioSource::Left,
+                    color_mode: ColorMode::Solid,
                 },
                 ChannelConfig {
                     start_index: 4,
                     end_index: 5,
+                    start_hz: None,
+                    end_hz: None,
                     premult: 3.0,
                     noise_gate: 0.0,
                     exponent: 1,
                     color: [1.0, 1.0, 0.0],
                     aggregate: AggregationMethod::Sum,
+                    source: AudioSource::Left,
+                    color_mode: ColorMode::Solid,
                 },
                 ChannelConfig {
                     start_index: 6,
                     end_index: 10,
+                    start_hz: None,
+                    end_hz: None,
                     premult: 5.0,
                     noise_gate: 0.0,
                     exponent: 1,
                     color: [0.0, 1.0, 0.0],
                     aggregate: AggregationMethod::Sum,
+                    source: AudioSource::Left,
+                    color_mode: ColorMode::Solid,
                 },
                 ChannelConfig {
                     start_index: 11,
                     end_index: 14,
+                    start_hz: None,
+                    end_hz: None,
                     premult: 10.0,
                     noise_gate: 0.0,
                     exponent: 1,
                     color: [0.0, 1.0, 1.0],
                     aggregate: AggregationMethod::Sum,
+                    source: AudioSource::Left,
+                    color_mode: ColorMode::Solid,
                 },
                 ChannelConfig {
                     start_index: 15,
                     end_index: 18,
+                    start_hz: None,
+                    end_hz: None,
                     premult: 10.0,
                     noise_gate: 0.0,
                     exponent: 1,
                     color: [0.0, 0.0, 1.0],
                     aggregate: AggregationMethod::Sum,
+                    source: AudioSource::Left,
+                    color_mode: ColorMode::Solid,
                 },
                 ChannelConfig {
                     start_index: 19,
                     end_index: 22,
+                    start_hz: None,
+                    end_hz: None,
                     premult: 10.0,
                     noise_gate: 0.0,
                     exponent: 1,
                     color: [0.498, 0.0, 1.0],
                     aggregate: AggregationMethod::Sum,
+                    source: AudioSource::Left,
+                    color_mode: ColorMode::Solid,
                 },
                 ChannelConfig {
                     start_index: 23,
                     end_index: 100,
+                    start_hz: None,
+                    end_hz: None,
                     premult: 10.0,
                     noise_gate: 0.0,
                     exponent: 1,
                     color: [1.0, 0.0, 1.0],
                     aggregate: AggregationMethod::Sum,
+                    source: AudioSource::Left,
+                    color_mode: ColorMode::Solid,
                 },
             ]),
+            idle_pattern: IdlePattern::RainbowCycle,
+            input_source: InputSource::UsbAudio,
+            idle_threshold: 0.01,
+            max_milliamps: DEFAULT_MAX_MILLIAMPS,
+            dither: false,
+            led_chipset: LedChipset::Ws2812,
+            color_order: ColorOrder::Grb,
+            spectral_averages: 1,
+            device: None,
+        }
+    }
+}
+
+/// A built-in config, see [`PRESETS`]
+pub struct Preset {
+    /// Shown in the app
+    pub name: &'static str,
+    pub config: fn() -> AppConfig,
+}
+
+/// The built-in presets, in the order of the preset service
+pub const PRESETS: [Preset; 6] = [
+    Preset {
+        name: "Stripes",
+        config: AppConfig::stripes,
+    },
+    Preset {
+        name: "Bars",
+        config: AppConfig::bars,
+    },
+    Preset {
+        name: "Bars2",
+        config: AppConfig::bars2,
+    },
+    Preset {
+        name: "Quarters",
+        config: AppConfig::quarters,
+    },
+    Preset {
+        name: "Stereo bars",
+        config: AppConfig::stereo_bars,
+    },
+    Preset {
+        name: "Log bars",
+        config: || AppConfig::bars_log(8),
+    },
+];
+
+/// Capacity of [`preset_names`], with room for a few more presets
+pub const PRESET_NAMES_SIZE: usize = 96;
+
+/// The names of [`PRESETS`], separated by newlines
+pub fn preset_names() -> heapless::Vec<u8, PRESET_NAMES_SIZE> {
+    let mut names = heapless::Vec::new();
+    for (i, preset) in PRESETS.iter().enumerate() {
+        if i > 0 {
+            let _ = names.push(b'\n');
         }
+        let _ = names.extend_from_slice(preset.name.as_bytes());
     }
+    names
 }
 
 impl Default for AppConfig {
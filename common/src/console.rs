@@ -0,0 +1,205 @@
+//! Text commands for the serial console of the device, to tune a config on the bench without the
+//! app. One command per line, see [`CONSOLE_HELP`].
+//!
+//! The console changes the running config only, until `save` stores it.
+
+use crate::config::{AggregationMethod, AppConfig, AudioSource, ChannelConfig};
+use crate::config_presets::PRESETS;
+
+/// Longest line the console accepts, longer ones are dropped
+pub const MAX_CONSOLE_LINE: usize = 96;
+
+pub const CONSOLE_HELP: &str = "\
+help                           this text
+config                         the running config as JSON
+presets                        the names of the built-in presets
+preset <name>                  run a built-in preset
+set <channel> <field> <value>  change a channel of the pattern, counting from 0. Fields:
+    start, end                 bin index, clears the Hz value
+    start_hz, end_hz           frequency
+    premult, noise_gate        number
+    exponent                   0 - 255
+    color                      hex, e.g. ff8000
+    aggregate                  sum, max, average or rms
+    source                     left, right or mono
+save                           keep the running config over a restart";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConsoleCommand {
+    Help,
+    Config,
+    Presets,
+    /// Index into [`PRESETS`]
+    Preset(usize),
+    Set {
+        channel: usize,
+        field: ChannelField,
+    },
+    Save,
+}
+
+/// A new value for one field of a [`ChannelConfig`]
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChannelField {
+    Start(usize),
+    End(usize),
+    StartHz(f32),
+    EndHz(f32),
+    Premult(f32),
+    NoiseGate(f32),
+    Exponent(u8),
+    Color([f32; 3]),
+    Aggregate(AggregationMethod),
+    Source(AudioSource),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConsoleError {
+    UnknownCommand,
+    /// The command needs more arguments
+    MissingArgument,
+    UnknownField,
+    InvalidValue,
+    UnknownPreset,
+    /// The pattern has fewer channels
+    NoSuchChannel,
+}
+
+/// Parse one line, without its line break. Commands and names are case-insensitive.
+pub fn parse_command(line: &str) -> Result<ConsoleCommand, ConsoleError> {
+    let line = line.trim();
+    let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+    let rest = rest.trim();
+    let mut args = rest.split_whitespace();
+    let mut arg = || args.next().ok_or(ConsoleError::MissingArgument);
+
+    let is = |name: &str| command.eq_ignore_ascii_case(name);
+    if is("help") || is("?") {
+        Ok(ConsoleCommand::Help)
+    } else if is("config") {
+        Ok(ConsoleCommand::Config)
+    } else if is("presets") {
+        Ok(ConsoleCommand::Presets)
+    } else if is("preset") {
+        if rest.is_empty() {
+            return Err(ConsoleError::MissingArgument);
+        }
+        // names can have spaces
+        PRESETS
+            .iter()
+            .position(|p| p.name.eq_ignore_ascii_case(rest))
+            .map(ConsoleCommand::Preset)
+            .ok_or(ConsoleError::UnknownPreset)
+    } else if is("set") {
+        let channel = arg()?.parse().map_err(|_| ConsoleError::InvalidValue)?;
+        let name = arg()?;
+        let field = parse_field(name, arg()?)?;
+        Ok(ConsoleCommand::Set { channel, field })
+    } else if is("save") {
+        Ok(ConsoleCommand::Save)
+    } else {
+        Err(ConsoleError::UnknownCommand)
+    }
+}
+
+const AGGREGATES: [(&str, AggregationMethod); 4] = [
+    ("sum", AggregationMethod::Sum),
+    ("max", AggregationMethod::Max),
+    ("average", AggregationMethod::Average),
+    ("rms", AggregationMethod::RmsNormalized),
+];
+
+const SOURCES: [(&str, AudioSource); 3] = [
+    ("left", AudioSource::Left),
+    ("right", AudioSource::Right),
+    ("mono", AudioSource::Mono),
+];
+
+/// The value of the entry of `table` called `name`, ignoring case
+fn lookup<T: Clone>(table: &[(&str, T)], name: &str) -> Result<T, ConsoleError> {
+    table
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.clone())
+        .ok_or(ConsoleError::InvalidValue)
+}
+
+fn parse_field(name: &str, value: &str) -> Result<ChannelField, ConsoleError> {
+    fn number<T: core::str::FromStr>(value: &str) -> Result<T, ConsoleError> {
+        value.parse().map_err(|_| ConsoleError::InvalidValue)
+    }
+
+    let is = |field: &str| name.eq_ignore_ascii_case(field);
+    let field = if is("start") {
+        ChannelField::Start(number(value)?)
+    } else if is("end") {
+        ChannelField::End(number(value)?)
+    } else if is("start_hz") {
+        ChannelField::StartHz(number(value)?)
+    } else if is("end_hz") {
+        ChannelField::EndHz(number(value)?)
+    } else if is("premult") {
+        ChannelField::Premult(number(value)?)
+    } else if is("noise_gate") {
+        ChannelField::NoiseGate(number(value)?)
+    } else if is("exponent") {
+        ChannelField::Exponent(number(value)?)
+    } else if is("color") {
+        ChannelField::Color(parse_hex_color(value)?)
+    } else if is("aggregate") {
+        ChannelField::Aggregate(lookup(&AGGREGATES, value)?)
+    } else if is("source") {
+        ChannelField::Source(lookup(&SOURCES, value)?)
+    } else {
+        return Err(ConsoleError::UnknownField);
+    };
+    Ok(field)
+}
+
+/// `rrggbb`, with or without a leading `#`
+fn parse_hex_color(value: &str) -> Result<[f32; 3], ConsoleError> {
+    let hex = value.strip_prefix('#').unwrap_or(value);
+    if hex.len() != 6 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(ConsoleError::InvalidValue);
+    }
+    let rgb = u32::from_str_radix(hex, 16).map_err(|_| ConsoleError::InvalidValue)?;
+    Ok([16, 8, 0].map(|shift| ((rgb >> shift) & 0xff) as f32 / 255.0))
+}
+
+impl ChannelField {
+    pub fn apply(self, channel: &mut ChannelConfig) {
+        match self {
+            Self::Start(index) => {
+                channel.start_index = index;
+                channel.start_hz = None;
+            }
+            Self::End(index) => {
+                channel.end_index = index;
+                channel.end_hz = None;
+            }
+            Self::StartHz(hz) => channel.start_hz = Some(hz),
+            Self::EndHz(hz) => channel.end_hz = Some(hz),
+            Self::Premult(premult) => channel.premult = premult,
+            Self::NoiseGate(gate) => channel.noise_gate = gate,
+            Self::Exponent(exponent) => channel.exponent = exponent,
+            Self::Color(color) => channel.color = color,
+            Self::Aggregate(aggregate) => channel.aggregate = aggregate,
+            Self::Source(source) => channel.source = source,
+        }
+    }
+}
+
+/// Apply a [`ConsoleCommand::Set`] to `config`
+pub fn set_channel_field(
+    config: &mut AppConfig,
+    channel: usize,
+    field: ChannelField,
+) -> Result<(), ConsoleError> {
+    let channel = config
+        .pattern
+        .channels_mut()
+        .get_mut(channel)
+        .ok_or(ConsoleError::NoSuchChannel)?;
+    field.apply(channel);
+    Ok(())
+}
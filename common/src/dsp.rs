@@ -0,0 +1,1118 @@
+//! Spectrum -> LED color rendering.
+//!
+//! This is shared between the firmware, which feeds it the live FFT, and the app, which uses it
+//! to preview a config without the hardware.
+
+use rgb::{RGB, RGB8};
+
+use crate::config::*;
+use crate::font::{GLYPH_ADVANCE, GLYPH_HEIGHT, glyph};
+
+/// LEDs in a frame, enough for the largest layout. Only the first [`PanelLayout::led_count`] are
+/// used, the others stay off.
+pub const MATRIX_LENGTH: usize = MAX_LEDS;
+
+/// Number of bins of a 512-point real FFT (DC up to, but excluding, Nyquist)
+pub const SPECTRUM_LENGTH: usize = 256;
+
+/// Number of samples going into the FFT
+pub const FFT_LENGTH: usize = 2 * SPECTRUM_LENGTH;
+
+/// Copy up to [`FFT_LENGTH`] samples into the middle of a zero-padded buffer and window them,
+/// with the coefficients from `table`.
+pub fn prepare_fft_input(
+    samples: impl ExactSizeIterator<Item = f32>,
+    window: WindowFunction,
+    table: &mut WindowTable,
+) -> [f32; FFT_LENGTH] {
+    let mut fft_input = [0.0f32; FFT_LENGTH];
+    let sample_count = samples.len().min(FFT_LENGTH);
+    let left_padding = (FFT_LENGTH - sample_count) / 2;
+
+    let populated = &mut fft_input[left_padding..left_padding + sample_count];
+    for (dst, src) in populated.iter_mut().zip(samples) {
+        *dst = src;
+    }
+
+    // apply window to the populated region only
+    table.apply(populated, window);
+
+    fft_input
+}
+
+/// Multiply the samples with the window function, in place.
+///
+/// Computes every coefficient, keep a [`WindowTable`] for the blocks of a stream instead.
+pub fn apply_window(buffer: &mut [f32], window: WindowFunction) {
+    let n = buffer.len();
+    if n < 2 || window == WindowFunction::None {
+        return;
+    }
+    for (i, v) in buffer.iter_mut().enumerate() {
+        *v *= window_coefficient(window, i, n);
+    }
+}
+
+/// Coefficient `i` of `window` over `n` samples, `n` has to be at least 2
+fn window_coefficient(window: WindowFunction, i: usize, n: usize) -> f32 {
+    // x goes from 0 to 2π over the window
+    let x = 2.0 * core::f32::consts::PI * (i as f32) / (n - 1) as f32;
+    match window {
+        WindowFunction::None => 1.0,
+        // w[n] = 0.5 * (1 - cos(2π n / (N-1)))
+        WindowFunction::Hann => 0.5 * (1.0 - libm::cosf(x)),
+        // w[n] = 0.54 - 0.46 * cos(2π n / (N-1))
+        WindowFunction::Hamming => 0.54 - 0.46 * libm::cosf(x),
+        // w[n] = a0 - a1 * cos(x) + a2 * cos(2x) - a3 * cos(3x)
+        WindowFunction::BlackmanHarris => {
+            0.35875 - 0.48829 * libm::cosf(x) + 0.14128 * libm::cosf(2.0 * x)
+                - 0.01168 * libm::cosf(3.0 * x)
+        }
+    }
+}
+
+/// The coefficients of one window function over one block length, computed when either changes
+/// instead of for every block
+#[derive(Clone, Debug)]
+pub struct WindowTable {
+    window: WindowFunction,
+    len: usize,
+    coefficients: [f32; FFT_LENGTH],
+}
+
+impl WindowTable {
+    pub const fn new() -> Self {
+        Self {
+            window: WindowFunction::None,
+            len: 0,
+            coefficients: [1.0; FFT_LENGTH],
+        }
+    }
+
+    /// The coefficients of `window` over `len` samples (at most [`FFT_LENGTH`])
+    pub fn coefficients(&mut self, window: WindowFunction, len: usize) -> &[f32] {
+        let len = len.min(FFT_LENGTH);
+        if (window, len) != (self.window, self.len) {
+            self.window = window;
+            self.len = len;
+            for (i, c) in self.coefficients[..len].iter_mut().enumerate() {
+                *c = if len < 2 {
+                    1.0
+                } else {
+                    window_coefficient(window, i, len)
+                };
+            }
+        }
+        &self.coefficients[..len]
+    }
+
+    /// Multiply the samples with the window function, in place. Like [`apply_window`], but only
+    /// a multiplication per sample.
+    pub fn apply(&mut self, buffer: &mut [f32], window: WindowFunction) {
+        if window == WindowFunction::None {
+            return;
+        }
+        let coefficients = self.coefficients(window, buffer.len());
+        for (v, c) in buffer.iter_mut().zip(coefficients) {
+            *v *= c;
+        }
+    }
+}
+
+impl Default for WindowTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Ambient noise per FFT bin, in the units of the power spectrum. Subtracted from the spectrum
+/// before the noise gate, see [`calculate_channel`].
+pub type NoiseFloor = [f32; SPECTRUM_LENGTH];
+
+/// Collects the spectra of silence (or whatever counts as silence in the room) into a
+/// [`NoiseFloor`]
+#[derive(Clone, Debug)]
+pub struct NoiseFloorCalibration {
+    floor: NoiseFloor,
+    blocks: usize,
+}
+
+impl NoiseFloorCalibration {
+    pub const fn new() -> Self {
+        Self {
+            floor: [0.0; SPECTRUM_LENGTH],
+            blocks: 0,
+        }
+    }
+
+    /// Feed the power spectrum of one block
+    pub fn add(&mut self, power_spectrum: &[f32]) {
+        for (floor, power) in self.floor.iter_mut().zip(power_spectrum) {
+            *floor = floor.max(*power);
+        }
+        self.blocks += 1;
+    }
+
+    /// Number of blocks fed so far
+    pub fn blocks(&self) -> usize {
+        self.blocks
+    }
+
+    /// The loudest each bin got, so the noise ends up below the floor and not just on average
+    pub fn floor(&self) -> NoiseFloor {
+        self.floor
+    }
+}
+
+impl Default for NoiseFloorCalibration {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// One-pole high-pass for the samples ahead of the FFT, takes out the DC offset some microphones
+/// have. Keep one per channel, the filter carries over from one block to the next.
+#[derive(Clone, Debug, Default)]
+pub struct HighPass {
+    last_in: Option<f32>,
+    last_out: f32,
+}
+
+impl HighPass {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Filter `samples` in place with the cutoff at `cutoff_hz`, 0 leaves them alone
+    pub fn apply(&mut self, samples: &mut [f32], cutoff_hz: u16, sample_rate: u32) {
+        if cutoff_hz == 0 {
+            *self = Self::new();
+            return;
+        }
+        let rc = 1.0 / (2.0 * core::f32::consts::PI * cutoff_hz as f32);
+        let dt = 1.0 / sample_rate as f32;
+        let alpha = rc / (rc + dt);
+
+        // starting from the first sample, so the offset doesn't ring out over the first blocks
+        let mut last_in = self
+            .last_in
+            .unwrap_or(samples.first().copied().unwrap_or(0.0));
+        let mut last_out = self.last_out;
+        for sample in samples.iter_mut() {
+            let x = *sample;
+            last_out = alpha * (last_out + x - last_in);
+            last_in = x;
+            *sample = last_out;
+        }
+        self.last_in = Some(last_in);
+        self.last_out = last_out;
+    }
+}
+
+/// Averages the power spectra of consecutive blocks of both channels, see
+/// [`AppConfig::spectral_averages`]
+#[derive(Clone, Debug)]
+pub struct SpectralAverage {
+    left: [f32; SPECTRUM_LENGTH],
+    right: [f32; SPECTRUM_LENGTH],
+    blocks: u8,
+}
+
+impl SpectralAverage {
+    pub const fn new() -> Self {
+        Self {
+            left: [0.0; SPECTRUM_LENGTH],
+            right: [0.0; SPECTRUM_LENGTH],
+            blocks: 0,
+        }
+    }
+
+    /// Feed the power spectra of one block. Returns their averages once `averages` blocks were
+    /// fed, and starts over. 0 counts as 1, every block is passed through.
+    pub fn add(
+        &mut self,
+        left: &[f32],
+        right: &[f32],
+        averages: u8,
+    ) -> Option<([f32; SPECTRUM_LENGTH], [f32; SPECTRUM_LENGTH])> {
+        for (sum, power) in self.left.iter_mut().zip(left) {
+            *sum += power;
+        }
+        for (sum, power) in self.right.iter_mut().zip(right) {
+            *sum += power;
+        }
+        self.blocks += 1;
+        if self.blocks < averages {
+            return None;
+        }
+
+        let blocks = self.blocks as f32;
+        let result = (
+            self.left.map(|sum| sum / blocks),
+            self.right.map(|sum| sum / blocks),
+        );
+        *self = Self::new();
+        Some(result)
+    }
+}
+
+impl Default for SpectralAverage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The strength of a single bin with the power `power`, scaled by the settings of `channel_cfg`
+fn norm_one_bucket(power: f32, floor: f32, channel_cfg: &ChannelConfig) -> f32 {
+    // step 0: noise floor
+    let power = (power - floor).max(0.0);
+    // step 1: premult (the spectrum is already squared, so the factor is too)
+    let power = power * channel_cfg.premult * channel_cfg.premult;
+    // step 2: scale
+    let val = power * 0.001 / 255.0;
+
+    // step 3: noise gate
+    if val < channel_cfg.noise_gate {
+        return 0.0;
+    }
+
+    // step 4: exponent
+    if channel_cfg.exponent == 1 {
+        libm::sqrtf(val)
+    } else if channel_cfg.exponent == 2 {
+        val
+    } else if channel_cfg.exponent.is_multiple_of(2) {
+        libm::powf(val, channel_cfg.exponent as f32 / 2.0)
+    } else {
+        libm::powf(libm::sqrtf(val), channel_cfg.exponent as f32)
+    }
+}
+
+/// Calculate the (unclamped) strength of one channel.
+///
+/// `power_spectrum` contains the squared magnitude of each FFT bin, of audio sampled at
+/// `sample_rate`. `noise_floor` is subtracted from each bin first, if there is one.
+pub fn calculate_channel(
+    power_spectrum: &[f32],
+    channel_cfg: &ChannelConfig,
+    sample_rate: u32,
+    noise_floor: Option<&NoiseFloor>,
+) -> f32 {
+    // Note: the range includes the bin after `end_index`, and is clamped to the spectrum so a bad
+    // config can't crash the device.
+    let (start_index, end_index) = channel_cfg.bin_range(sample_rate, FFT_LENGTH);
+    let last = (end_index + 1).min(power_spectrum.len().saturating_sub(1));
+    if start_index > last {
+        return 0.0;
+    }
+
+    let floor = |bin: usize| noise_floor.and_then(|f| f.get(bin)).copied().unwrap_or(0.0);
+    let bins = start_index..last + 1;
+    let buckets = bins
+        .clone()
+        .map(|bin| norm_one_bucket(power_spectrum[bin], floor(bin), channel_cfg));
+
+    match channel_cfg.aggregate {
+        AggregationMethod::Sum => buckets.sum::<f32>(),
+        AggregationMethod::Max => buckets.reduce(f32::max).unwrap_or(0.0),
+        AggregationMethod::Average => {
+            let len = buckets.len() as f32;
+            if len == 0.0 {
+                0.0
+            } else {
+                buckets.sum::<f32>() / len
+            }
+        }
+        AggregationMethod::RmsNormalized => {
+            // the range is never empty here, see the check above
+            let len = bins.len() as f32;
+            let mean_power = bins
+                .map(|bin| (power_spectrum[bin] - floor(bin)).max(0.0))
+                .sum::<f32>()
+                / len;
+            norm_one_bucket(mean_power, 0.0, channel_cfg)
+        }
+    }
+}
+
+/// Sum of all bins, scaled the same way as the values the noise gate is compared against
+/// (premult 1.0).
+pub fn total_energy(power_spectrum: &[f32]) -> f32 {
+    power_spectrum.iter().sum::<f32>() * 0.001 / 255.0
+}
+
+/// One frame of the wiring test: a white pixel walks along the rows from the top left, the rows
+/// it already passed stay lit dimly in a color per row. It takes [`PanelLayout::led_count`] steps.
+///
+/// On panels that don't match the `layout` the pixel jumps around instead.
+pub fn render_test_pattern(layout: &PanelLayout, step: usize) -> [RGB8; MATRIX_LENGTH] {
+    let mut colors = [RGB8::new(0, 0, 0); MATRIX_LENGTH];
+    let (width, height) = layout.size();
+    let (pixel_x, pixel_y) = (step % width, step / width);
+
+    for y in 0..height.min(pixel_y + 1) {
+        let row_color = hsv_to_rgb8(y as f32 * 360.0 / height as f32, 1.0, 0.1);
+        let end = if y == pixel_y { pixel_x } else { width };
+        for x in 0..end {
+            *xy(&mut colors, layout, x, y) = row_color;
+        }
+    }
+    if pixel_y < height {
+        *xy(&mut colors, layout, pixel_x, pixel_y) = RGB8::new(128, 128, 128);
+    }
+    colors
+}
+
+/// A still frame to check the LED data signal with a scope: with RGB LEDs the first pixel sends
+/// only 1 bits, the second alternates 1 and 0, all others send only 0 bits. That makes the high
+/// times of both bit values easy to find and measure.
+pub fn render_timing_pattern() -> [RGB8; MATRIX_LENGTH] {
+    let mut colors = [RGB8::new(0, 0, 0); MATRIX_LENGTH];
+    colors[0] = RGB8::new(0xff, 0xff, 0xff);
+    colors[1] = RGB8::new(0xaa, 0xaa, 0xaa);
+    colors
+}
+
+/// Render the idle animation, `t` is the time in seconds.
+///
+/// `color` is used by the patterns that don't cycle through colors on their own.
+pub fn render_idle(
+    pattern: IdlePattern,
+    color: [f32; 3],
+    layout: &PanelLayout,
+    t: f32,
+) -> [RGB8; MATRIX_LENGTH] {
+    use core::f32::consts::PI;
+
+    let mut colors = [RGB8::new(0, 0, 0); MATRIX_LENGTH];
+    let leds = layout.led_count().min(MATRIX_LENGTH);
+
+    match pattern {
+        IdlePattern::Off => {}
+        IdlePattern::RainbowCycle => {
+            // Three sine waves cycling through the matrix
+            // Red starts at 0, Blue at 1/3, Green at 2/3 of the cycle
+            let time_offset = t * 3.0; // Animation speed
+
+            for (led_index, color) in colors[..leds].iter_mut().enumerate() {
+                let position = (led_index as f32) / leds as f32 * 2.0 * PI;
+
+                // negative values saturate to 0, so each color is off half of the time
+                let red = (libm::sinf(position + time_offset) * 255.0) as u8;
+                let blue = (libm::sinf(position + time_offset + 2.0 * PI / 3.0) * 255.0) as u8;
+                let green = (libm::sinf(position + time_offset + 4.0 * PI / 3.0) * 255.0) as u8;
+
+                *color = RGB8::new(red, green, blue);
+            }
+        }
+        IdlePattern::Breathe => {
+            // one breath every 4 seconds, between 5% and 50% brightness
+            const PERIOD: f32 = 4.0;
+            let strength = 0.05 + 0.45 * (0.5 - 0.5 * libm::cosf(2.0 * PI * t / PERIOD));
+            let c = RGB8::new(
+                (strength * color[0] * 255.0) as u8,
+                (strength * color[1] * 255.0) as u8,
+                (strength * color[2] * 255.0) as u8,
+            );
+            colors[..leds].fill(c);
+        }
+    }
+
+    colors
+}
+
+/// Current of one color component at full brightness, in mA
+pub const LED_COMPONENT_MA: f32 = 20.0;
+
+/// Current of one LED that is off, in mA, its controller draws it no matter the color
+pub const LED_IDLE_MA: f32 = 1.0;
+
+/// Estimate the current the LEDs draw while showing `colors`, in mA.
+///
+/// Each component draws roughly in proportion to its value, on top of the idle current.
+pub fn estimate_milliamps(colors: &[RGB8]) -> f32 {
+    let components: u32 = colors
+        .iter()
+        .map(|c| c.r as u32 + c.g as u32 + c.b as u32)
+        .sum();
+    colors.len() as f32 * LED_IDLE_MA + components as f32 * LED_COMPONENT_MA / 255.0
+}
+
+/// Dim the whole frame proportionally if it would draw more than `max_milliamps` (see
+/// [`estimate_milliamps`]), so heavy bass with every channel at full brightness can't brown out
+/// the supply. 0 disables the limit.
+///
+/// Returns whether the frame was dimmed.
+pub fn limit_power(colors: &mut [RGB8], max_milliamps: u16) -> bool {
+    if max_milliamps == 0 {
+        return false;
+    }
+    let total = estimate_milliamps(colors);
+    if total <= max_milliamps as f32 {
+        return false;
+    }
+
+    // the idle current stays, only the part the colors draw can be scaled.
+    // Truncating each component keeps the result at or below the limit.
+    let idle = colors.len() as f32 * LED_IDLE_MA;
+    let scale = ((max_milliamps as f32 - idle) / (total - idle)).max(0.0);
+    for c in colors.iter_mut() {
+        c.r = (c.r as f32 * scale) as u8;
+        c.g = (c.g as f32 * scale) as u8;
+        c.b = (c.b as f32 * scale) as u8;
+    }
+    true
+}
+
+/// Scale the red, green and blue of every LED by `balance`, see
+/// [`DeviceSettings::white_balance`]. Only ever dims, so a frame stays within the power limit.
+pub fn apply_white_balance(colors: &mut [RGB8], balance: [f32; 3]) {
+    if balance == [1.0; 3] {
+        return;
+    }
+    let [r, g, b] = balance.map(|f| f.clamp(0.0, 1.0));
+    let scale = |c: u8, f: f32| libm::roundf(c as f32 * f) as u8;
+    for c in colors.iter_mut() {
+        *c = RGB8::new(scale(c.r, r), scale(c.g, g), scale(c.b, b));
+    }
+}
+
+/// Share of the brightness [`Overlay::Breathe`] keeps at its dimmest
+pub const BREATHE_MIN_GAIN: f32 = 0.3;
+
+/// The factor `overlay` scales the brightness by at `t` seconds, 1.0 without one
+pub fn overlay_gain(overlay: Overlay, t: f32) -> f32 {
+    use core::f32::consts::PI;
+
+    match overlay {
+        Overlay::None | Overlay::Breathe { period_ms: 0 } => 1.0,
+        Overlay::Breathe { period_ms } => {
+            // starts at full brightness
+            let phase = 2.0 * PI * t * 1000.0 / period_ms as f32;
+            BREATHE_MIN_GAIN + (1.0 - BREATHE_MIN_GAIN) * (0.5 + 0.5 * libm::cosf(phase))
+        }
+    }
+}
+
+/// Scale the whole frame by [`overlay_gain`]. Only ever dims like [`apply_white_balance`], so
+/// the two can be applied in any order.
+pub fn apply_overlay(colors: &mut [RGB8], overlay: Overlay, t: f32) {
+    let gain = overlay_gain(overlay, t);
+    if gain >= 1.0 {
+        return;
+    }
+    let scale = |c: u8| libm::roundf(c as f32 * gain) as u8;
+    for c in colors.iter_mut() {
+        *c = RGB8::new(scale(c.r), scale(c.g), scale(c.b));
+    }
+}
+
+/// Linear blend between two frames, `t` = 0.0 gives `from`, 1.0 (or more) gives `to`.
+pub fn blend_frames(from: &[RGB8], to: &[RGB8], t: f32, out: &mut [RGB8]) {
+    let t = t.clamp(0.0, 1.0);
+    let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t + 0.5) as u8;
+    for ((o, a), b) in out.iter_mut().zip(from).zip(to) {
+        *o = RGB8::new(mix(a.r, b.r), mix(a.g, b.g), mix(a.b, b.b));
+    }
+}
+
+/// Most the host can boost the USB audio volume, anything above is treated as this
+pub const MAX_VOLUME_DB: f32 = 12.0;
+
+/// Convert a USB volume in dB to a linear scale factor: 10^(dB/20).
+///
+/// Boosts are capped at [`MAX_VOLUME_DB`], a NaN from the host counts as 0 dB.
+pub fn db_to_scale(db: f32) -> f32 {
+    if db.is_nan() {
+        return 1.0;
+    }
+    libm::powf(10.0, db.min(MAX_VOLUME_DB) / 20.0)
+}
+
+/// Apply the volume scale to a signed 32-bit sample, saturating instead of wrapping around when
+/// a loud sample is boosted.
+pub fn apply_volume(sample: u32, scale: f32) -> u32 {
+    let scaled = (sample as i32 as f32 * scale).clamp(i32::MIN as f32, i32::MAX as f32);
+    scaled as i32 as u32
+}
+
+/// Convert a color from HSV to RGB.
+///
+/// `hue` is in degrees and wraps around, `saturation` and `value` go from 0.0 to 1.0.
+pub fn hsv_to_rgb8(hue: f32, saturation: f32, value: f32) -> RGB8 {
+    to_rgb8(hsv_to_precise(hue, saturation, value))
+}
+
+/// Like [`hsv_to_rgb8`], without rounding
+fn hsv_to_precise(hue: f32, saturation: f32, value: f32) -> PreciseColor {
+    let hue = libm::fmodf(hue, 360.0);
+    let hue = if hue < 0.0 { hue + 360.0 } else { hue };
+    let saturation = saturation.clamp(0.0, 1.0);
+    let value = value.clamp(0.0, 1.0);
+
+    // the hue circle is split into 6 sectors, in each of them one component ramps up or down
+    let chroma = value * saturation;
+    let sector = hue / 60.0;
+    let x = chroma * (1.0 - libm::fabsf(libm::fmodf(sector, 2.0) - 1.0));
+    let (r, g, b) = match sector as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = value - chroma;
+    PreciseColor::new((r + m) * 255.0, (g + m) * 255.0, (b + m) * 255.0)
+}
+
+/// A color before it is rounded to what the LEDs can show, the components go from 0.0 to 255.0
+pub type PreciseColor = RGB<f32>;
+
+/// Round down like the patterns always did, see [`Dither`] for the alternative
+fn to_rgb8(c: PreciseColor) -> RGB8 {
+    RGB8::new(c.r as u8, c.g as u8, c.b as u8)
+}
+
+fn to_precise(c: RGB8) -> PreciseColor {
+    PreciseColor::new(c.r as f32, c.g as f32, c.b as f32)
+}
+
+/// The color of a channel at the given strength (0.0 - 1.0), see [`ColorMode`]
+fn channel_color(channel_cfg: &ChannelConfig, strength: f32) -> PreciseColor {
+    match channel_cfg.color_mode {
+        ColorMode::Solid => PreciseColor::new(
+            strength * channel_cfg.color[0] * 255.0,
+            strength * channel_cfg.color[1] * 255.0,
+            strength * channel_cfg.color[2] * 255.0,
+        ),
+        ColorMode::HueShift { from_hue, to_hue } => {
+            let hue = from_hue + (to_hue - from_hue) * strength;
+            hsv_to_precise(hue, 1.0, strength)
+        }
+    }
+}
+
+/// What a channel's LEDs show at `level` (see [`channel_levels`]), rounded like a frame without
+/// [`Dither`]. Before the power limit, the white balance and the overlay.
+pub fn channel_led_color(channel_cfg: &ChannelConfig, level: f32) -> RGB8 {
+    to_rgb8(channel_color(channel_cfg, level.clamp(0.0, 1.0)))
+}
+
+/// Temporal dithering: whatever a pixel lost to rounding is added to it in the next frame, so
+/// over a few frames it shows its exact brightness instead of the step below. Smooths the banding
+/// of dim gradients, at the cost of a faint flicker.
+pub struct Dither {
+    error: [PreciseColor; MATRIX_LENGTH],
+}
+
+impl Dither {
+    pub fn new() -> Self {
+        Self {
+            error: [PreciseColor::new(0.0, 0.0, 0.0); MATRIX_LENGTH],
+        }
+    }
+
+    /// Round a frame of [`render_levels_precise`], carrying the error into the next call
+    pub fn apply(&mut self, frame: &[PreciseColor; MATRIX_LENGTH]) -> [RGB8; MATRIX_LENGTH] {
+        core::array::from_fn(|i| {
+            let (c, e) = (frame[i], &mut self.error[i]);
+            RGB8::new(
+                dither_component(c.r, &mut e.r),
+                dither_component(c.g, &mut e.g),
+                dither_component(c.b, &mut e.b),
+            )
+        })
+    }
+}
+
+impl Default for Dither {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn dither_component(value: f32, error: &mut f32) -> u8 {
+    let wanted = value.clamp(0.0, 255.0) + *error;
+    let shown = libm::roundf(wanted).clamp(0.0, 255.0);
+    *error = wanted - shown;
+    shown as u8
+}
+
+/// Most channels any pattern has
+pub const MAX_CHANNELS: usize = 8;
+
+/// How many bars [`NeopixelMatrixPattern::Spectrum`] has, one per column of a panel
+pub const SPECTRUM_COLUMNS: usize = 16;
+
+/// Most levels any pattern has, [`NeopixelMatrixPattern::Spectrum`] has one per bar
+pub const MAX_LEVELS: usize = SPECTRUM_COLUMNS;
+
+/// The unclamped strength of each channel, in the order of [`NeopixelMatrixPattern::channels`]
+/// (see [`channel_levels`] for the patterns that differ)
+pub type ChannelLevels = heapless::Vec<f32, MAX_LEVELS>;
+
+/// Max size of [`ChannelLevels`] serialized with postcard (length + f32 per level)
+pub const LEVELS_PACKET_SIZE: usize = 1 + 4 * MAX_LEVELS;
+
+/// Serialize the levels for the diagnostics characteristic
+pub fn levels_to_bytes(levels: &[f32]) -> postcard::Result<heapless::Vec<u8, LEVELS_PACKET_SIZE>> {
+    postcard::to_vec(levels)
+}
+
+pub fn levels_from_bytes(data: &[u8]) -> postcard::Result<ChannelLevels> {
+    postcard::from_bytes(data)
+}
+
+/// Calculate the strength of every channel of the configured pattern.
+///
+/// `left` and `right` are the power spectra of the two audio channels, each channel config picks
+/// one of them (or their average) through its [`AudioSource`]. `sample_rate` is the rate of the
+/// audio they were calculated from, it decides which bins the Hz ranges of the channels cover.
+/// `noise_floor` is subtracted from whichever spectrum a channel uses.
+///
+/// [`NeopixelMatrixPattern::PitchColor`] has no channels, its levels are where the loudest bin of
+/// the mono spectrum lies in its range (0.0 - 1.0) and that bin's strength.
+/// [`NeopixelMatrixPattern::Spectrum`] and [`NeopixelMatrixPattern::Spectrogram`] have a level
+/// per column, see [`spectrum_levels`].
+pub fn channel_levels(
+    left: &[f32],
+    right: &[f32],
+    config: &AppConfig,
+    sample_rate: u32,
+    noise_floor: Option<&NoiseFloor>,
+) -> ChannelLevels {
+    let mut mono = [0.0f32; SPECTRUM_LENGTH];
+    for (m, (l, r)) in mono.iter_mut().zip(left.iter().zip(right)) {
+        *m = (l + r) / 2.0;
+    }
+    let mono = &mono[..left.len().min(right.len()).min(SPECTRUM_LENGTH)];
+
+    let spectrum_of = |source: AudioSource| match source {
+        AudioSource::Left => left,
+        AudioSource::Right => right,
+        AudioSource::Mono => mono,
+    };
+
+    match &config.pattern {
+        NeopixelMatrixPattern::PitchColor { min_bin, max_bin } => {
+            return pitch_levels(mono, *min_bin, *max_bin, noise_floor);
+        }
+        NeopixelMatrixPattern::Spectrum(channel) | NeopixelMatrixPattern::Spectrogram(channel) => {
+            let power_spectrum = spectrum_of(channel.source);
+            return spectrum_levels(power_spectrum, channel, sample_rate, noise_floor);
+        }
+        _ => {}
+    }
+
+    config
+        .pattern
+        .channels()
+        .iter()
+        .map(|ch| calculate_channel(spectrum_of(ch.source), ch, sample_rate, noise_floor))
+        .collect()
+}
+
+/// The levels of [`NeopixelMatrixPattern::Spectrum`]: the strength of each of the
+/// [`SPECTRUM_COLUMNS`] bins from the first one of `channel_cfg` on, 0.0 past the spectrum
+pub fn spectrum_levels(
+    power_spectrum: &[f32],
+    channel_cfg: &ChannelConfig,
+    sample_rate: u32,
+    noise_floor: Option<&NoiseFloor>,
+) -> ChannelLevels {
+    let (start, _) = channel_cfg.bin_range(sample_rate, FFT_LENGTH);
+    let floor = |bin: usize| noise_floor.and_then(|f| f.get(bin)).copied().unwrap_or(0.0);
+    (start..start + SPECTRUM_COLUMNS)
+        .map(|bin| match power_spectrum.get(bin) {
+            Some(&power) => norm_one_bucket(power, floor(bin), channel_cfg),
+            None => 0.0,
+        })
+        .collect()
+}
+
+/// Like [`channel_levels`], but every channel reads `spectrum` whatever its [`AudioSource`], for
+/// one side of a split matrix (see [`render_split_frame`])
+pub fn side_levels(
+    spectrum: &[f32],
+    config: &AppConfig,
+    sample_rate: u32,
+    noise_floor: Option<&NoiseFloor>,
+) -> ChannelLevels {
+    channel_levels(spectrum, spectrum, config, sample_rate, noise_floor)
+}
+
+/// Turn left and right samples into mid ((L + R) / 2) and side ((L - R) / 2), in place
+pub fn to_mid_side(left: &mut [i32], right: &mut [i32]) {
+    for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+        let (wide_l, wide_r) = (*l as i64, *r as i64);
+        *l = ((wide_l + wide_r) / 2) as i32;
+        *r = ((wide_l - wide_r) / 2) as i32;
+    }
+}
+
+/// The loudest bin between `min_bin` and `max_bin` (inclusive) and its power, `None` if the range
+/// is empty or outside of the spectrum. `noise_floor` is subtracted from each bin first.
+pub fn dominant_bin(
+    power_spectrum: &[f32],
+    min_bin: usize,
+    max_bin: usize,
+    noise_floor: Option<&NoiseFloor>,
+) -> Option<(usize, f32)> {
+    let last = max_bin.min(power_spectrum.len().checked_sub(1)?);
+    let floor = |bin: usize| noise_floor.and_then(|f| f.get(bin)).copied().unwrap_or(0.0);
+    (min_bin..=last)
+        .map(|bin| (bin, (power_spectrum[bin] - floor(bin)).max(0.0)))
+        .reduce(|loudest, bin| if bin.1 > loudest.1 { bin } else { loudest })
+}
+
+/// Hue of the highest bin of [`NeopixelMatrixPattern::PitchColor`], in degrees, the lowest is red
+/// at 0°. Stops short of going round to red again.
+pub const PITCH_HUE_RANGE: f32 = 270.0;
+
+/// The levels of [`NeopixelMatrixPattern::PitchColor`]: where the loudest bin lies in the range,
+/// from 0.0 at `min_bin` to 1.0 at `max_bin`, and its strength.
+///
+/// The position is logarithmic, so every octave of a melody moves the hue by the same amount.
+fn pitch_levels(
+    power_spectrum: &[f32],
+    min_bin: usize,
+    max_bin: usize,
+    noise_floor: Option<&NoiseFloor>,
+) -> ChannelLevels {
+    let Some((bin, power)) = dominant_bin(power_spectrum, min_bin, max_bin, noise_floor) else {
+        return ChannelLevels::new();
+    };
+    // bin 0 is DC, it counts as the bottom of the range
+    let low = min_bin.max(1) as f32;
+    let octaves = libm::log2f(max_bin as f32 / low);
+    let position = if octaves > 0.0 {
+        (libm::log2f(bin as f32 / low) / octaves).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    // scaled like a channel with premult 1.0 and exponent 1
+    let strength = libm::sqrtf(power * 0.001 / 255.0);
+    [position, strength].into_iter().collect()
+}
+
+/// How fast the VU meter peak marker falls, in matrix heights per second
+pub const VU_PEAK_DECAY: f32 = 0.5;
+
+/// How many rows [`NeopixelMatrixPattern::Spectrogram`] remembers, enough for the tallest layout
+pub const SPECTROGRAM_ROWS: usize = MATRIX_LENGTH / SPECTRUM_COLUMNS;
+
+/// How fast [`NeopixelMatrixPattern::Spectrogram`] moves up, in rows per second
+pub const SPECTROGRAM_SPEED: f32 = 20.0;
+
+/// Hue of a silent bin in [`NeopixelMatrixPattern::Spectrogram`], in degrees, the loudest are red
+/// at 0°
+pub const SPECTROGRAM_QUIET_HUE: f32 = 240.0;
+
+/// What the patterns remember from one frame to the next, the caller keeps one per output
+#[derive(Clone, Debug, Default)]
+pub struct RenderState {
+    /// Time of the last frame in seconds, see [`render_levels`]
+    pub last_t: f32,
+    /// Height of the [`NeopixelMatrixPattern::VuMeter`] peak marker, 0.0 - 1.0
+    pub vu_peak: f32,
+    /// How many columns the [`NeopixelMatrixPattern::Scroller`] text moved to the left
+    pub scroll_offset: f32,
+    /// The levels of the [`NeopixelMatrixPattern::Spectrogram`] rows, the newest (bottom) first
+    pub spectrogram: [[f32; SPECTRUM_COLUMNS]; SPECTROGRAM_ROWS],
+    /// How far the spectrogram moved up since its newest row came in, in rows
+    pub spectrogram_offset: f32,
+    /// Whether [`render_frame`] had to dim the last frame to stay within
+    /// [`AppConfig::max_milliamps`]
+    pub power_limited: bool,
+}
+
+/// Scroller speed while the channel is silent, in columns per second
+pub const SCROLL_SPEED_MIN: f32 = 4.0;
+
+/// Scroller speed at full strength, in columns per second
+pub const SCROLL_SPEED_MAX: f32 = 24.0;
+
+/// Render the configured pattern into a frame, in LED strip order.
+///
+/// See [`channel_levels`] for `left`, `right`, `sample_rate` and `noise_floor`, and
+/// [`render_levels`] for `t`.
+pub fn render_pattern(
+    left: &[f32],
+    right: &[f32],
+    config: &AppConfig,
+    sample_rate: u32,
+    noise_floor: Option<&NoiseFloor>,
+    state: &mut RenderState,
+    t: f32,
+) -> [RGB8; MATRIX_LENGTH] {
+    let levels = channel_levels(left, right, config, sample_rate, noise_floor);
+    render_levels(&levels, config, state, t)
+}
+
+/// Render what goes to the LEDs from the result of [`channel_levels`]: the pattern, dithered if the
+/// config asks for it, held to the power limit. Everything the firmware does between the FFT and
+/// the LED driver, so it can be tested without the hardware.
+///
+/// See [`render_levels`] for `t`.
+pub fn render_frame(
+    levels: &[f32],
+    config: &AppConfig,
+    state: &mut RenderState,
+    dither: &mut Dither,
+    t: f32,
+) -> [RGB8; MATRIX_LENGTH] {
+    let colors = render_levels_precise(levels, config, state, t);
+    finish_frame(colors, config, state, dither)
+}
+
+/// Like [`render_frame`], for a matrix split between the two sides (see [`StereoMode`]).
+///
+/// The pattern is rendered once from each side's levels, see [`side_levels`], and every other
+/// column of it goes into that side's half, mirrored so both start in the middle. Each side has
+/// its own `RenderState`, [`RenderState::power_limited`] ends up in `state`.
+pub fn render_split_frame(
+    left_levels: &[f32],
+    right_levels: &[f32],
+    config: &AppConfig,
+    state: &mut RenderState,
+    right_state: &mut RenderState,
+    dither: &mut Dither,
+    t: f32,
+) -> [RGB8; MATRIX_LENGTH] {
+    let left = render_levels_precise(left_levels, config, state, t);
+    let right = render_levels_precise(right_levels, config, right_state, t);
+
+    let layout = &config.layout();
+    let (width, height) = layout.size();
+    let half = width / 2;
+    let mut colors = [PreciseColor::new(0.0, 0.0, 0.0); MATRIX_LENGTH];
+    for y in 0..height {
+        for x in 0..half {
+            let from = layout.index(2 * x, y);
+            *xy(&mut colors, layout, half - 1 - x, y) = left[from];
+            *xy(&mut colors, layout, half + x, y) = right[from];
+        }
+    }
+    finish_frame(colors, config, state, dither)
+}
+
+/// Round the frame, dithered if the config asks for it, and hold it to the power limit
+fn finish_frame(
+    colors: [PreciseColor; MATRIX_LENGTH],
+    config: &AppConfig,
+    state: &mut RenderState,
+    dither: &mut Dither,
+) -> [RGB8; MATRIX_LENGTH] {
+    let mut colors = if config.dither {
+        dither.apply(&colors)
+    } else {
+        colors.map(to_rgb8)
+    };
+    let leds = config.layout().led_count().min(MATRIX_LENGTH);
+    state.power_limited = limit_power(&mut colors[..leds], config.max_milliamps);
+    colors
+}
+
+/// Color of row `y` of a VU meter `height` rows high, counted from the bottom: green, yellow in the
+/// middle, red on top
+fn vu_color(y: usize, height: usize) -> RGB8 {
+    let f = y as f32 / (height - 1) as f32;
+    if f < 0.5 {
+        RGB8::new((f * 2.0 * 255.0) as u8, 255, 0)
+    } else {
+        RGB8::new(255, ((1.0 - f) * 2.0 * 255.0) as u8, 0)
+    }
+}
+
+/// Render the configured pattern from the result of [`channel_levels`].
+///
+/// `t` is the time in seconds, the animated parts of the patterns move with it rather than with
+/// the frame rate.
+pub fn render_levels(
+    levels: &[f32],
+    config: &AppConfig,
+    state: &mut RenderState,
+    t: f32,
+) -> [RGB8; MATRIX_LENGTH] {
+    render_levels_precise(levels, config, state, t).map(to_rgb8)
+}
+
+/// Like [`render_levels`], but leaves the rounding to the caller, e.g. to a [`Dither`]
+pub fn render_levels_precise(
+    levels: &[f32],
+    config: &AppConfig,
+    state: &mut RenderState,
+    t: f32,
+) -> [PreciseColor; MATRIX_LENGTH] {
+    // a pause (or a restarted clock) shouldn't make everything jump
+    let dt = (t - state.last_t).clamp(0.0, 0.1);
+    state.last_t = t;
+
+    let level = |i: usize| levels.get(i).copied().unwrap_or(0.0).min(1.0);
+
+    // the patterns stretch over all panels, the LEDs past the last one stay off
+    let layout = &config.layout();
+    let (width, height) = layout.size();
+    let leds = layout.led_count().min(MATRIX_LENGTH);
+    let mut colors = [PreciseColor::new(0.0, 0.0, 0.0); MATRIX_LENGTH];
+
+    match &config.pattern {
+        NeopixelMatrixPattern::Stripes(channels) => {
+            let channel_colors: [PreciseColor; 4] =
+                core::array::from_fn(|i| channel_color(&channels[i], level(i)));
+
+            // create a striped pattern, with half-column stripes along the strip
+            let columns = leds / PANEL_SIZE;
+            for (i, color) in colors[..leds].iter_mut().enumerate() {
+                let row = i / PANEL_SIZE;
+                let col = i % PANEL_SIZE;
+                let (first_half, top) = (row < columns / 2, col < PANEL_SIZE / 2);
+
+                *color = if first_half && top {
+                    channel_colors[0]
+                } else if first_half {
+                    channel_colors[1]
+                } else if top {
+                    channel_colors[2]
+                } else {
+                    channel_colors[3]
+                };
+            }
+        }
+        NeopixelMatrixPattern::Bars(channels) => {
+            let channel_strengths: [f32; 8] = core::array::from_fn(level);
+
+            // create a bar pattern, 8 bars over the full width and height
+            let bar_width = width / 8;
+            for i in 0..8 {
+                let channel_cfg = &channels[i];
+                let pixels = (channel_strengths[i] * height as f32) as usize;
+                for y in 0..pixels {
+                    for x in 0..bar_width {
+                        let pixel_x = i * bar_width + x;
+                        let pixel_y = height - 1 - y; // bottom to top
+                        let pixel = xy(&mut colors, layout, pixel_x, pixel_y);
+                        *pixel = channel_color(channel_cfg, channel_strengths[i]);
+                    }
+                }
+            }
+        }
+        NeopixelMatrixPattern::Quarters(channels) => {
+            let channel_colors: [PreciseColor; 4] =
+                core::array::from_fn(|i| channel_color(&channels[i], level(i)));
+
+            // create a quartered pattern
+            let (quarter_width, quarter_height) = (width / 2, height / 2);
+            for (i, channel_color) in channel_colors.iter().enumerate() {
+                let (offset_x, offset_y) = match i {
+                    0 => (0, 0),                          // Top-left
+                    1 => (quarter_width, 0),              // Top-right
+                    2 => (0, quarter_height),             // Bottom-left
+                    _ => (quarter_width, quarter_height), // Bottom-right
+                };
+                for y in 0..quarter_height {
+                    for x in 0..quarter_width {
+                        let pixel = xy(&mut colors, layout, offset_x + x, offset_y + y);
+                        *pixel = *channel_color;
+                    }
+                }
+            }
+        }
+        NeopixelMatrixPattern::VuMeter(_) => {
+            let strength = level(0).max(0.0);
+            state.vu_peak = strength.max(state.vu_peak - VU_PEAK_DECAY * dt);
+
+            let rows = (strength * height as f32) as usize;
+            for y in 0..rows {
+                for x in 0..width {
+                    *xy(&mut colors, layout, x, height - 1 - y) = to_precise(vu_color(y, height));
+                }
+            }
+
+            // the marker sits in the row the peak reached, once it's above the bottom row
+            let peak_row = (state.vu_peak * height as f32) as usize;
+            if peak_row > 0 {
+                let y = peak_row.min(height) - 1;
+                for x in 0..width {
+                    *xy(&mut colors, layout, x, height - 1 - y) = to_precise(vu_color(y, height));
+                }
+            }
+        }
+        NeopixelMatrixPattern::Scroller { text, channel } => {
+            let strength = level(0).max(0.0);
+            let speed = SCROLL_SPEED_MIN + (SCROLL_SPEED_MAX - SCROLL_SPEED_MIN) * strength;
+
+            // the text comes in on the right and leaves completely on the left before it repeats
+            let text_width = text.chars().count() * GLYPH_ADVANCE;
+            let period = (text_width + width) as f32;
+            state.scroll_offset = libm::fmodf(state.scroll_offset + speed * dt, period);
+            let start = width as isize - state.scroll_offset as isize;
+
+            let color = channel_color(channel, 1.0);
+            let top = (height - GLYPH_HEIGHT) / 2;
+            for (i, c) in text.chars().enumerate() {
+                let left = start + (i * GLYPH_ADVANCE) as isize;
+                for (column, bits) in glyph(c).iter().enumerate() {
+                    let x = left + column as isize;
+                    if !(0..width as isize).contains(&x) {
+                        continue;
+                    }
+                    for y in 0..GLYPH_HEIGHT {
+                        if bits & (1 << y) != 0 {
+                            *xy(&mut colors, layout, x as usize, top + y) = color;
+                        }
+                    }
+                }
+            }
+        }
+        NeopixelMatrixPattern::PitchColor { .. } => {
+            let color = hsv_to_precise(level(0).max(0.0) * PITCH_HUE_RANGE, 1.0, level(1));
+            colors[..leds].fill(color);
+        }
+        NeopixelMatrixPattern::Spectrum(channel) => {
+            // like the bars, one column per bin on a single panel
+            let bar_width = width / SPECTRUM_COLUMNS;
+            for i in 0..SPECTRUM_COLUMNS {
+                let strength = level(i).max(0.0);
+                let color = channel_color(channel, strength);
+                let pixels = (strength * height as f32) as usize;
+                for y in 0..pixels {
+                    for x in 0..bar_width {
+                        *xy(&mut colors, layout, i * bar_width + x, height - 1 - y) = color;
+                    }
+                }
+            }
+        }
+        NeopixelMatrixPattern::Spectrogram(_) => {
+            // moves with the time like the scroller, a new row comes in for every row it moved
+            state.spectrogram_offset += SPECTROGRAM_SPEED * dt;
+            let new_rows = (state.spectrogram_offset as usize).min(SPECTROGRAM_ROWS);
+            state.spectrogram_offset = libm::fmodf(state.spectrogram_offset, 1.0);
+            if new_rows > 0 {
+                let row: [f32; SPECTRUM_COLUMNS] = core::array::from_fn(|i| level(i).max(0.0));
+                let history = &mut state.spectrogram;
+                history.copy_within(..SPECTROGRAM_ROWS - new_rows, new_rows);
+                history[..new_rows].fill(row);
+            }
+
+            let bar_width = width / SPECTRUM_COLUMNS;
+            for (age, row) in state.spectrogram.iter().take(height).enumerate() {
+                for (i, &strength) in row.iter().enumerate() {
+                    let hue = (1.0 - strength) * SPECTROGRAM_QUIET_HUE;
+                    let color = hsv_to_precise(hue, 1.0, strength);
+                    for x in 0..bar_width {
+                        *xy(&mut colors, layout, i * bar_width + x, height - 1 - age) = color;
+                    }
+                }
+            }
+        }
+    }
+
+    colors
+}
+
+/// The element of the pixel at `x`, `y` of the matrix, see [`PanelLayout::index`]
+pub fn xy<'a, T>(arr: &'a mut [T], layout: &PanelLayout, x: usize, y: usize) -> &'a mut T {
+    &mut arr[layout.index(x, y)]
+}
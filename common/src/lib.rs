@@ -1,4 +1,13 @@
-#![no_std]
+#![cfg_attr(not(feature = "std"), no_std)]
 
+pub mod command;
 pub mod config;
 pub mod config_presets;
+pub mod console;
+pub mod dsp;
+pub mod font;
+pub mod persist;
+pub mod serial;
+pub mod status;
+pub mod transfer;
+pub mod ws2812;
@@ -0,0 +1,259 @@
+//! Format of the config stored in flash, so it survives a reboot.
+//!
+//! ```text
+//! magic (4) | config version (u32 LE) | length (u16 LE) | crc32 (u32 LE) | postcard config
+//! ```
+//!
+//! Erased flash, a config from another firmware version or a torn write all fail to decode, and
+//! the device then boots with the default config.
+//!
+//! The device name, the noise floor and the preset slots are separate records, so they survive
+//! config changes and factory resets.
+
+use crate::config::{AppConfig, CONFIG_VERSION};
+use crate::dsp::{NoiseFloor, SPECTRUM_LENGTH};
+use crate::transfer::MAX_CHUNKED_CONFIG_SIZE;
+
+const MAGIC: [u8; 4] = *b"PLCF";
+
+/// Size of everything in front of the config
+pub const STORED_HEADER_SIZE: usize = 4 + 4 + 2 + 4;
+
+/// Largest stored config, the same limit as for configs received over BLE
+pub const MAX_STORED_CONFIG_SIZE: usize = STORED_HEADER_SIZE + MAX_CHUNKED_CONFIG_SIZE;
+
+pub fn encode_stored_config(
+    cfg: &AppConfig,
+) -> postcard::Result<heapless::Vec<u8, MAX_STORED_CONFIG_SIZE>> {
+    let data = cfg.to_bytes::<MAX_CHUNKED_CONFIG_SIZE>()?;
+
+    let mut stored = heapless::Vec::new();
+    // can't fail, the header plus MAX_CHUNKED_CONFIG_SIZE is exactly the capacity
+    let _ = stored.extend_from_slice(&MAGIC);
+    let _ = stored.extend_from_slice(&CONFIG_VERSION.to_le_bytes());
+    let _ = stored.extend_from_slice(&(data.len() as u16).to_le_bytes());
+    let _ = stored.extend_from_slice(&crc32(&data).to_le_bytes());
+    let _ = stored.extend_from_slice(&data);
+    Ok(stored)
+}
+
+/// Length of the whole record (header included) if `header` is a valid header, so the caller
+/// knows how much to read.
+pub fn stored_config_len(header: &[u8]) -> Option<usize> {
+    let header = header.get(..STORED_HEADER_SIZE)?;
+    if header[0..4] != MAGIC {
+        return None;
+    }
+    let version = u32::from_le_bytes(header[4..8].try_into().unwrap());
+    if version != CONFIG_VERSION {
+        return None;
+    }
+    let len = u16::from_le_bytes(header[8..10].try_into().unwrap()) as usize;
+    if len > MAX_CHUNKED_CONFIG_SIZE {
+        return None;
+    }
+    Some(STORED_HEADER_SIZE + len)
+}
+
+/// Decode a record written by [`encode_stored_config`], `None` if it's missing or damaged
+pub fn decode_stored_config(stored: &[u8]) -> Option<AppConfig> {
+    let len = stored_config_len(stored)?;
+    let data = stored.get(STORED_HEADER_SIZE..len)?;
+    let crc = u32::from_le_bytes(stored[10..14].try_into().unwrap());
+    if crc32(data) != crc {
+        return None;
+    }
+    AppConfig::from_bytes(data).ok()
+}
+
+/// Name the device advertises until it's renamed
+pub const DEFAULT_DEVICE_NAME: &str = "Diskomator";
+
+/// Longest device name in bytes, it has to fit into the scan response next to its header
+pub const MAX_DEVICE_NAME_SIZE: usize = 20;
+
+pub type DeviceName = heapless::String<MAX_DEVICE_NAME_SIZE>;
+
+const NAME_MAGIC: [u8; 4] = *b"PLNM";
+
+/// The stored device name: magic (4) | length (u8) | utf-8 name
+pub const MAX_STORED_NAME_SIZE: usize = 4 + 1 + MAX_DEVICE_NAME_SIZE;
+
+/// `bytes` as a device name, `None` if it's empty, too long or not utf-8
+pub fn device_name_from_bytes(bytes: &[u8]) -> Option<DeviceName> {
+    if bytes.is_empty() || bytes.len() > MAX_DEVICE_NAME_SIZE {
+        return None;
+    }
+    let mut name = DeviceName::new();
+    // can't fail, the length was checked above
+    let _ = name.push_str(core::str::from_utf8(bytes).ok()?);
+    Some(name)
+}
+
+pub fn encode_stored_name(name: &str) -> heapless::Vec<u8, MAX_STORED_NAME_SIZE> {
+    let mut stored = heapless::Vec::new();
+    let name = &name.as_bytes()[..name.len().min(MAX_DEVICE_NAME_SIZE)];
+    let _ = stored.extend_from_slice(&NAME_MAGIC);
+    let _ = stored.push(name.len() as u8);
+    let _ = stored.extend_from_slice(name);
+    stored
+}
+
+/// Decode a record written by [`encode_stored_name`], `None` if there is none
+pub fn decode_stored_name(stored: &[u8]) -> Option<DeviceName> {
+    if stored.get(0..4)? != NAME_MAGIC {
+        return None;
+    }
+    let len = *stored.get(4)? as usize;
+    device_name_from_bytes(stored.get(5..5 + len)?)
+}
+
+/// Number of slots the user can save configs into, to switch between them without sending them
+pub const SLOT_COUNT: usize = 4;
+
+/// Longest slot name in bytes
+pub const MAX_SLOT_NAME_SIZE: usize = 16;
+
+pub type SlotName = heapless::String<MAX_SLOT_NAME_SIZE>;
+
+const SLOT_MAGIC: [u8; 4] = *b"PLSL";
+
+/// A saved slot: magic (4) | name length (u8) | utf-8 name | record of [`encode_stored_config`]
+pub const MAX_STORED_SLOT_SIZE: usize = 4 + 1 + MAX_SLOT_NAME_SIZE + MAX_STORED_CONFIG_SIZE;
+
+/// `bytes` as a slot name, `None` if it's empty, too long, not utf-8 or has a line break (the
+/// names are sent one per line)
+pub fn slot_name_from_bytes(bytes: &[u8]) -> Option<SlotName> {
+    if bytes.is_empty() || bytes.len() > MAX_SLOT_NAME_SIZE || bytes.contains(&b'\n') {
+        return None;
+    }
+    let mut name = SlotName::new();
+    // can't fail, the length was checked above
+    let _ = name.push_str(core::str::from_utf8(bytes).ok()?);
+    Some(name)
+}
+
+pub fn encode_stored_slot(
+    name: &str,
+    cfg: &AppConfig,
+) -> postcard::Result<heapless::Vec<u8, MAX_STORED_SLOT_SIZE>> {
+    let config = encode_stored_config(cfg)?;
+    let name = &name.as_bytes()[..name.len().min(MAX_SLOT_NAME_SIZE)];
+
+    let mut stored = heapless::Vec::new();
+    // can't fail, everything is limited to its part of the capacity
+    let _ = stored.extend_from_slice(&SLOT_MAGIC);
+    let _ = stored.push(name.len() as u8);
+    let _ = stored.extend_from_slice(name);
+    let _ = stored.extend_from_slice(&config);
+    Ok(stored)
+}
+
+/// Decode a record written by [`encode_stored_slot`], `None` if the slot is empty or damaged
+pub fn decode_stored_slot(stored: &[u8]) -> Option<(SlotName, AppConfig)> {
+    if stored.get(0..4)? != SLOT_MAGIC {
+        return None;
+    }
+    let len = *stored.get(4)? as usize;
+    let name = slot_name_from_bytes(stored.get(5..5 + len)?)?;
+    let config = decode_stored_config(stored.get(5 + len..)?)?;
+    Some((name, config))
+}
+
+/// Capacity of the slot names characteristic
+pub const SLOT_NAMES_SIZE: usize = SLOT_COUNT * (MAX_SLOT_NAME_SIZE + 1);
+
+/// The names of all slots, one per line and an empty line for an unused slot
+pub fn slot_names_to_bytes<'a>(
+    names: impl IntoIterator<Item = Option<&'a str>>,
+) -> heapless::Vec<u8, SLOT_NAMES_SIZE> {
+    let mut bytes = heapless::Vec::new();
+    for (i, name) in names.into_iter().take(SLOT_COUNT).enumerate() {
+        if i > 0 {
+            let _ = bytes.push(b'\n');
+        }
+        // can't fail, every name fits into its share of the capacity
+        let _ = bytes.extend_from_slice(name.unwrap_or_default().as_bytes());
+    }
+    bytes
+}
+
+/// Write to the save slot characteristic: slot index (u8) | utf-8 name
+pub const SAVE_SLOT_SIZE: usize = 1 + MAX_SLOT_NAME_SIZE;
+
+/// Decode a write to the save slot characteristic, `None` if the index or the name is invalid
+pub fn parse_save_slot(bytes: &[u8]) -> Option<(usize, SlotName)> {
+    let (&index, name) = bytes.split_first()?;
+    let index = index as usize;
+    if index >= SLOT_COUNT {
+        return None;
+    }
+    Some((index, slot_name_from_bytes(name)?))
+}
+
+const ENABLED_MAGIC: [u8; 4] = *b"PLEN";
+
+/// The stored on/off switch: magic (4) | enabled (u8)
+pub const STORED_ENABLED_SIZE: usize = 4 + 1;
+
+pub fn encode_stored_enabled(enabled: bool) -> [u8; STORED_ENABLED_SIZE] {
+    let [a, b, c, d] = ENABLED_MAGIC;
+    [a, b, c, d, enabled as u8]
+}
+
+/// Decode a record written by [`encode_stored_enabled`], `None` if there is none
+pub fn decode_stored_enabled(stored: &[u8]) -> Option<bool> {
+    if stored.get(0..4)? != ENABLED_MAGIC {
+        return None;
+    }
+    match stored.get(4)? {
+        0 => Some(false),
+        1 => Some(true),
+        _ => None,
+    }
+}
+
+const NOISE_FLOOR_MAGIC: [u8; 4] = *b"PLNF";
+
+/// The stored noise floor: magic (4) | f32 LE per bin
+pub const STORED_NOISE_FLOOR_SIZE: usize = 4 + 4 * SPECTRUM_LENGTH;
+
+pub fn encode_stored_noise_floor(floor: &NoiseFloor) -> [u8; STORED_NOISE_FLOOR_SIZE] {
+    let mut stored = [0u8; STORED_NOISE_FLOOR_SIZE];
+    stored[..4].copy_from_slice(&NOISE_FLOOR_MAGIC);
+    let (values, _) = stored[4..].as_chunks_mut::<4>();
+    for (bytes, value) in values.iter_mut().zip(floor) {
+        bytes.copy_from_slice(&value.to_le_bytes());
+    }
+    stored
+}
+
+/// Decode a record written by [`encode_stored_noise_floor`], `None` if there is none or it holds
+/// values that can't be a floor
+pub fn decode_stored_noise_floor(stored: &[u8]) -> Option<NoiseFloor> {
+    if stored.get(0..4)? != NOISE_FLOOR_MAGIC {
+        return None;
+    }
+    let (values, _) = stored.get(4..STORED_NOISE_FLOOR_SIZE)?.as_chunks::<4>();
+    let mut floor = [0.0; SPECTRUM_LENGTH];
+    for (value, bytes) in floor.iter_mut().zip(values) {
+        *value = f32::from_le_bytes(*bytes);
+        if !value.is_finite() || *value < 0.0 {
+            return None;
+        }
+    }
+    Some(floor)
+}
+
+/// CRC-32 (IEEE), bitwise as this only runs on boot and on saves
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
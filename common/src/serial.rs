@@ -0,0 +1,140 @@
+//! Config over the USB serial port (CDC-ACM), for setups without Bluetooth.
+//!
+//! Every message is a [`SerialRequest`] or [`SerialResponse`] serialized with postcard, COBS
+//! encoded and terminated by a 0 byte, so a reader that joins in the middle of a message can
+//! find the start of the next one. The device answers every request with exactly one response.
+
+use serde::{Deserialize, Serialize};
+
+use crate::status::DeviceStatus;
+use crate::transfer::MAX_CHUNKED_CONFIG_SIZE;
+
+/// Largest message before encoding: a config of up to [`MAX_CHUNKED_CONFIG_SIZE`] bytes plus the
+/// variant and its length
+pub const MAX_SERIAL_MESSAGE_SIZE: usize = MAX_CHUNKED_CONFIG_SIZE + 8;
+
+/// Largest frame on the wire, COBS adds a byte every 254 bytes plus one, then the terminator
+pub const MAX_SERIAL_FRAME_SIZE: usize =
+    MAX_SERIAL_MESSAGE_SIZE + MAX_SERIAL_MESSAGE_SIZE / 254 + 2;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum SerialRequest<'a> {
+    /// Answered with [`SerialResponse::Config`]
+    ReadConfig,
+    /// A config serialized like for the config characteristic, answered with
+    /// [`SerialResponse::Ok`] once it is applied
+    WriteConfig(&'a [u8]),
+    /// Answered with [`SerialResponse::Status`]
+    GetStatus,
+    /// Same as [`crate::command::DeviceCommand::Identify`], answered with [`SerialResponse::Ok`]
+    Identify,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum SerialResponse<'a> {
+    /// The current config, serialized like for the config characteristic
+    Config(&'a [u8]),
+    Status(DeviceStatus),
+    Ok,
+    Error(SerialError),
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum SerialError {
+    /// The frame isn't a request
+    Malformed,
+    /// The frame is longer than [`MAX_SERIAL_FRAME_SIZE`]
+    TooLarge,
+    /// The written config is of a version the device doesn't accept
+    UnsupportedVersion(u32),
+    /// The written config can't be decoded
+    InvalidConfig,
+    /// Nothing to report yet, e.g. the status right after boot
+    Unavailable,
+    /// The written config has this many LEDs, more than [`crate::config::MAX_LEDS`]
+    TooManyLeds(u32),
+}
+
+impl<'a> SerialRequest<'a> {
+    /// Encode into `buf`, including the terminator
+    pub fn to_frame<'b>(&self, buf: &'b mut [u8]) -> postcard::Result<&'b mut [u8]> {
+        postcard::to_slice_cobs(self, buf)
+    }
+
+    /// Decode a frame in place, with or without its terminator
+    pub fn from_frame(frame: &'a mut [u8]) -> postcard::Result<Self> {
+        postcard::from_bytes_cobs(frame)
+    }
+}
+
+impl<'a> SerialResponse<'a> {
+    /// Encode into `buf`, including the terminator
+    pub fn to_frame<'b>(&self, buf: &'b mut [u8]) -> postcard::Result<&'b mut [u8]> {
+        postcard::to_slice_cobs(self, buf)
+    }
+
+    /// Decode a frame in place, with or without its terminator
+    pub fn from_frame(frame: &'a mut [u8]) -> postcard::Result<Self> {
+        postcard::from_bytes_cobs(frame)
+    }
+}
+
+/// Collects the bytes read from the port into frames.
+pub struct FrameReader<const N: usize> {
+    buf: heapless::Vec<u8, N>,
+    /// The frame in `buf` was handed out and is dropped on the next call
+    complete: bool,
+    /// The current frame didn't fit, its bytes are skipped until the terminator
+    overflowed: bool,
+}
+
+impl<const N: usize> FrameReader<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: heapless::Vec::new(),
+            complete: false,
+            overflowed: false,
+        }
+    }
+
+    /// The next complete frame in `data`, without its terminator. `data` is advanced past the
+    /// consumed bytes, so the caller can call again for the frames that follow. The returned frame
+    /// is only valid until the next call.
+    pub fn feed(&mut self, data: &mut &[u8]) -> Option<Result<&mut [u8], SerialError>> {
+        if core::mem::take(&mut self.complete) {
+            self.buf.clear();
+        }
+        while let Some((&byte, rest)) = data.split_first() {
+            *data = rest;
+            if byte == 0 {
+                if core::mem::take(&mut self.overflowed) {
+                    return Some(Err(SerialError::TooLarge));
+                }
+                if self.buf.is_empty() {
+                    // e.g. a host flushing the line with a few terminators
+                    continue;
+                }
+                self.complete = true;
+                return Some(Ok(&mut self.buf));
+            }
+            if !self.overflowed && self.buf.push(byte).is_err() {
+                self.buf.clear();
+                self.overflowed = true;
+            }
+        }
+        None
+    }
+
+    /// Drop a partially received frame, e.g. after the host disconnected
+    pub fn clear(&mut self) {
+        self.buf.clear();
+        self.complete = false;
+        self.overflowed = false;
+    }
+}
+
+impl<const N: usize> Default for FrameReader<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,83 @@
+//! Runtime status the device notifies once per second, so the app can show whether audio is
+//! arriving and how busy the device is.
+
+use serde::{Deserialize, Serialize};
+
+/// Where the processed audio came from
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum AudioInput {
+    /// Nothing was processed since the last status
+    None,
+    Usb,
+    I2s,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DeviceStatus {
+    pub uptime_s: u32,
+    /// Free bytes on the heap
+    pub free_heap: u32,
+    /// Frames written to the LEDs per second
+    pub led_fps: f32,
+    /// Blocks of audio run through the FFT per second
+    pub audio_fps: f32,
+    pub audio_input: AudioInput,
+    /// USB audio packets dropped since the last status, because the audio processing fell behind
+    pub usb_overflows: u32,
+    /// USB frames since the last status in which the host sent no audio packet
+    pub usb_underruns: u32,
+    /// USB audio packets received since the last status, about 1000 per second while streaming
+    pub usb_packets: u32,
+    /// Audio blocks skipped since the last status because the FFT was still busy with the previous
+    /// ones
+    pub dropped_blocks: u32,
+    /// Whether any frame since the last status was dimmed to stay within
+    /// [`crate::config::AppConfig::max_milliamps`]
+    pub power_limited: bool,
+    /// New frames the LEDs picked up per second, at most
+    /// [`crate::config::DeviceSettings::target_fps`]. `led_fps` also counts the blended frames in
+    /// between.
+    pub frame_fps: f32,
+    /// Frames since the last status that were replaced by a newer one before the LEDs picked them
+    /// up
+    pub dropped_frames: u32,
+}
+
+/// Max size of [`DeviceStatus`] serialized with postcard
+/// (varints + 2 f32 + enum + varints + bool + f32 + varint)
+pub const STATUS_PACKET_SIZE: usize = 5 + 5 + 4 + 4 + 1 + 5 + 5 + 5 + 5 + 1 + 4 + 5;
+
+impl DeviceStatus {
+    pub fn to_bytes(&self) -> postcard::Result<heapless::Vec<u8, STATUS_PACKET_SIZE>> {
+        postcard::to_vec(self)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> postcard::Result<Self> {
+        postcard::from_bytes(data)
+    }
+}
+
+/// How long the audio processing takes, notified once per second so the firmware can be tuned
+/// (e.g. whether a larger FFT would still keep up)
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Telemetry {
+    /// Blocks run through the FFT and the pattern per second
+    pub fft_fps: f32,
+    /// Average time of one block, in microseconds
+    pub fft_avg_us: u32,
+    /// Slowest block since the last telemetry, in microseconds
+    pub fft_max_us: u32,
+}
+
+/// Max size of [`Telemetry`] serialized with postcard (f32 + varints)
+pub const TELEMETRY_PACKET_SIZE: usize = 4 + 5 + 5;
+
+impl Telemetry {
+    pub fn to_bytes(&self) -> postcard::Result<heapless::Vec<u8, TELEMETRY_PACKET_SIZE>> {
+        postcard::to_vec(self)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> postcard::Result<Self> {
+        postcard::from_bytes(data)
+    }
+}
@@ -0,0 +1,165 @@
+//! Chunked config transfer, for configs that don't fit into a single write.
+//!
+//! Configs up to [`MAX_CONFIG_SIZE`] are still written to the config characteristic in one go.
+//! Larger ones are sent as:
+//!
+//! 1. [`ConfigControl::Begin`] on the control characteristic
+//! 2. the chunks from [`chunks`] on the config characteristic, in order
+//! 3. [`ConfigControl::Commit`] on the control characteristic
+//!
+//! The device collects the chunks in a [`ConfigAssembler`] and only applies the config on commit.
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::MAX_CONFIG_SIZE;
+
+/// Largest config that can be sent in chunks
+pub const MAX_CHUNKED_CONFIG_SIZE: usize = 2048;
+
+/// Size of the max config size characteristic, the largest config the device takes as little
+/// endian u16. Firmware without it only takes [`LEGACY_MAX_CONFIG_SIZE`].
+///
+/// [`LEGACY_MAX_CONFIG_SIZE`]: crate::config::LEGACY_MAX_CONFIG_SIZE
+pub const MAX_CONFIG_SIZE_SIZE: usize = 2;
+
+pub fn max_config_size_from_bytes(data: &[u8]) -> Option<usize> {
+    Some(u16::from_le_bytes(data.get(..MAX_CONFIG_SIZE_SIZE)?.try_into().ok()?) as usize)
+}
+
+/// Each chunk starts with its sequence number as little endian u16
+const CHUNK_HEADER_SIZE: usize = 2;
+
+/// Config bytes per chunk, so a whole chunk fits into one write
+pub const CHUNK_DATA_SIZE: usize = MAX_CONFIG_SIZE - CHUNK_HEADER_SIZE;
+
+/// Capacity of the control characteristic
+pub const CONFIG_CONTROL_SIZE: usize = 8;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum ConfigControl {
+    /// Start a new transfer, dropping any unfinished one
+    Begin { total_len: u32 },
+    /// All chunks were sent, apply the config
+    Commit,
+    /// Drop the unfinished transfer
+    Abort,
+}
+
+impl ConfigControl {
+    pub fn to_bytes(&self) -> postcard::Result<heapless::Vec<u8, CONFIG_CONTROL_SIZE>> {
+        postcard::to_vec(self)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> postcard::Result<Self> {
+        postcard::from_bytes(data)
+    }
+}
+
+/// Split `bytes` into the chunks written to the config characteristic
+pub fn chunks(bytes: &[u8]) -> impl Iterator<Item = heapless::Vec<u8, MAX_CONFIG_SIZE>> + '_ {
+    bytes
+        .chunks(CHUNK_DATA_SIZE)
+        .enumerate()
+        .map(|(seq, data)| {
+            let mut chunk = heapless::Vec::new();
+            // can't fail, the header plus CHUNK_DATA_SIZE is exactly the capacity
+            let _ = chunk.extend_from_slice(&(seq as u16).to_le_bytes());
+            let _ = chunk.extend_from_slice(data);
+            chunk
+        })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChunkError {
+    /// A chunk or commit arrived without a transfer being started
+    NotStarted,
+    /// The announced length is larger than [`MAX_CHUNKED_CONFIG_SIZE`], or more bytes arrived than
+    /// were announced
+    TooLarge,
+    /// A chunk was lost or sent twice
+    OutOfOrder,
+    /// Commit arrived before all announced bytes
+    Incomplete,
+    /// A chunk without the sequence number
+    Malformed,
+}
+
+/// Collects the chunks of a transfer on the device
+pub struct ConfigAssembler {
+    buffer: heapless::Vec<u8, MAX_CHUNKED_CONFIG_SIZE>,
+    total_len: usize,
+    next_seq: u16,
+    active: bool,
+}
+
+impl ConfigAssembler {
+    pub const fn new() -> Self {
+        Self {
+            buffer: heapless::Vec::new(),
+            total_len: 0,
+            next_seq: 0,
+            active: false,
+        }
+    }
+
+    /// True between begin and commit/abort, the config characteristic then only takes chunks
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn begin(&mut self, total_len: usize) -> Result<(), ChunkError> {
+        self.abort();
+        if total_len > MAX_CHUNKED_CONFIG_SIZE {
+            return Err(ChunkError::TooLarge);
+        }
+        self.total_len = total_len;
+        self.active = true;
+        Ok(())
+    }
+
+    pub fn push(&mut self, chunk: &[u8]) -> Result<(), ChunkError> {
+        if !self.active {
+            return Err(ChunkError::NotStarted);
+        }
+        if chunk.len() < CHUNK_HEADER_SIZE {
+            return Err(ChunkError::Malformed);
+        }
+        let (header, data) = chunk.split_at(CHUNK_HEADER_SIZE);
+        let seq = u16::from_le_bytes([header[0], header[1]]);
+        if seq != self.next_seq {
+            return Err(ChunkError::OutOfOrder);
+        }
+        if self.buffer.len() + data.len() > self.total_len {
+            return Err(ChunkError::TooLarge);
+        }
+        // can't fail, total_len was checked against the capacity in begin
+        let _ = self.buffer.extend_from_slice(data);
+        self.next_seq = self.next_seq.wrapping_add(1);
+        Ok(())
+    }
+
+    /// Finish the transfer and return the reassembled config bytes
+    pub fn commit(&mut self) -> Result<&[u8], ChunkError> {
+        if !self.active {
+            return Err(ChunkError::NotStarted);
+        }
+        self.active = false;
+        if self.buffer.len() != self.total_len {
+            return Err(ChunkError::Incomplete);
+        }
+        Ok(&self.buffer)
+    }
+
+    pub fn abort(&mut self) {
+        self.buffer.clear();
+        self.total_len = 0;
+        self.next_seq = 0;
+        self.active = false;
+    }
+}
+
+impl Default for ConfigAssembler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,145 @@
+//! Encodes pixels into the SPI bit stream of WS2812 style LEDs, the firmware's driver sends it.
+//! [`wire_bytes`] is shared with the RMT driver, which encodes the bits itself.
+//!
+//! Based on https://github.com/smart-leds-rs/ws2812-spi-rs
+
+use rgb::RGB8;
+
+use crate::config::{ColorOrder, LedChipset};
+
+pub const WS2812_RESET_BYTES: usize = 140;
+
+/// SPI clock, bit patterns and reset length, these differ between LED generations
+#[derive(Clone, Copy, Debug)]
+pub struct Ws2812Timing {
+    /// The SPI clock, one SPI bit is 1/f long
+    pub spi_khz: u32,
+    /// SPI byte sent for each pair of data bits (00, 01, 10, 11).
+    /// Each nibble is one data bit, high time first, then the low time.
+    pub patterns: [u8; 4],
+    /// Number of zero bytes sent after the pixels, so the LEDs latch the new colors
+    pub reset_bytes: usize,
+}
+
+impl Ws2812Timing {
+    /// Classic WS2812/WS2812B.
+    ///
+    /// The maximum for T0H is 500ns, the minimum for one bit 1063 ns.
+    /// These result in the upper and lower spi frequency limits
+    pub const WS2812: Self = Self {
+        spi_khz: 4_500,
+        patterns: [0b1000_1000, 0b1000_1110, 0b11101000, 0b11101110],
+        // ~250 µs at 4.5 MHz
+        reset_bytes: WS2812_RESET_BYTES,
+    };
+
+    /// WS2812B-V5, WS2815 and other newer chips, which need a reset of more than 280 µs
+    pub const WS2812B_V5: Self = Self {
+        // ~320 µs at 4.5 MHz
+        reset_bytes: 180,
+        ..Self::WS2812
+    };
+
+    /// Size of the SPI buffer for `n` pixels of `chipset`
+    pub const fn buffer_size(&self, n: usize, chipset: LedChipset) -> usize {
+        spi_bytes_per_pixel(chipset) * n + self.reset_bytes
+    }
+}
+
+impl Default for Ws2812Timing {
+    fn default() -> Self {
+        Self::WS2812
+    }
+}
+
+/// SPI bytes for one pixel, each color byte takes 4
+pub const fn spi_bytes_per_pixel(chipset: LedChipset) -> usize {
+    match chipset {
+        LedChipset::Ws2812 => 12,
+        LedChipset::Sk6812Rgbw => 16,
+    }
+}
+
+/// A pixel of an RGBW strip, `w` drives the separate white LED
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RGBW8 {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub w: u8,
+}
+
+impl From<RGB8> for RGBW8 {
+    /// The white LED takes over the part of the color all three components share
+    fn from(pixel: RGB8) -> Self {
+        let w = pixel.r.min(pixel.g).min(pixel.b);
+        Self {
+            r: pixel.r - w,
+            g: pixel.g - w,
+            b: pixel.b - w,
+            w,
+        }
+    }
+}
+
+// ----------------------------------------------------------------
+
+fn encode_reset(buffer: &mut [u8]) {
+    buffer.fill(0);
+}
+
+fn encode_byte(buffer: &mut [u8; 4], mut data: u8, patterns: &[u8; 4]) {
+    // Send two bits in one spi byte
+    for spi_byte in buffer {
+        let bits = (data & 0b1100_0000) >> 6;
+        *spi_byte = patterns[bits as usize];
+        data <<= 2;
+    }
+}
+
+/// The color bytes of `pixels` in the order they go out on the wire, with the white byte last
+/// for RGBW (SK6812)
+pub fn wire_bytes(
+    pixels: &[RGB8],
+    chipset: LedChipset,
+    order: ColorOrder,
+) -> impl Iterator<Item = u8> + '_ {
+    pixels.iter().flat_map(move |&pixel| {
+        let (bytes, len) = match chipset {
+            LedChipset::Ws2812 => {
+                let [a, b, c] = order.arrange(pixel);
+                ([a, b, c, 0], 3)
+            }
+            LedChipset::Sk6812Rgbw => {
+                let pixel = RGBW8::from(pixel);
+                let [a, b, c] = order.arrange(RGB8::new(pixel.r, pixel.g, pixel.b));
+                ([a, b, c, pixel.w], 4)
+            }
+        };
+        bytes.into_iter().take(len)
+    })
+}
+
+/// Encode the pixels followed by the reset, returns the number of bytes used
+pub fn encode_sequence<const B: usize>(
+    buffer: &mut [u8; B],
+    pixels: &[RGB8],
+    timing: &Ws2812Timing,
+    chipset: LedChipset,
+    order: ColorOrder,
+) -> usize {
+    let len = timing.buffer_size(pixels.len(), chipset);
+    assert!(B >= len);
+
+    let (pixel_bytes, reset) =
+        buffer[..len].split_at_mut(pixels.len() * spi_bytes_per_pixel(chipset));
+    // 4 SPI bytes per color byte
+    let chunks = pixel_bytes.as_chunks_mut::<4>().0.iter_mut();
+    for (chunk, byte) in chunks.zip(wire_bytes(pixels, chipset, order)) {
+        encode_byte(chunk, byte, &timing.patterns);
+    }
+    encode_reset(reset);
+
+    len
+}
@@ -0,0 +1,104 @@
+//! The bands spread over a frequency range have to cover it without gaps or overlaps, whatever the
+//! FFT and the rate.
+
+use common::config::{
+    AppConfig, AutoBandConfig, BandSpacing, DeviceSettings, SAMPLE_RATE_HZ, assign_log_bands,
+    hz_to_bin,
+};
+use common::dsp::FFT_LENGTH;
+
+#[test]
+fn bands_are_contiguous_and_within_the_spectrum() {
+    for spacing in BandSpacing::ALL {
+        for fft_size in [128, 256, 512] {
+            for sample_rate in [16_000, 44_100, 48_000] {
+                for num_channels in 1..=8 {
+                    let ranges = assign_log_bands(
+                        num_channels,
+                        40.0,
+                        16_000.0,
+                        sample_rate,
+                        fft_size,
+                        spacing,
+                    );
+                    let context = format!("{spacing:?}, {fft_size}, {sample_rate} Hz: {ranges:?}");
+
+                    assert!(!ranges.is_empty(), "{context}");
+                    assert!(ranges.len() <= num_channels, "{context}");
+                    assert!(ranges[0].0 >= 1, "DC bin used, {context}");
+                    assert!(ranges.last().unwrap().1 < fft_size / 2, "{context}");
+                    for &(start, end) in &ranges {
+                        assert!(start <= end, "{context}");
+                    }
+                    for pair in ranges.windows(2) {
+                        assert_eq!(pair[0].1 + 1, pair[1].0, "gap or overlap, {context}");
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn bands_cover_the_requested_range() {
+    let ranges = assign_log_bands(
+        8,
+        100.0,
+        10_000.0,
+        SAMPLE_RATE_HZ,
+        FFT_LENGTH,
+        BandSpacing::Log,
+    );
+    assert_eq!(ranges.len(), 8);
+    assert_eq!(ranges[0].0, hz_to_bin(100.0, SAMPLE_RATE_HZ, FFT_LENGTH));
+    assert_eq!(ranges[7].1, hz_to_bin(10_000.0, SAMPLE_RATE_HZ, FFT_LENGTH));
+}
+
+#[test]
+fn mel_bands_give_the_bass_fewer_bins_to_itself_than_log() {
+    let low_band_width = |spacing| {
+        let ranges = assign_log_bands(8, 40.0, 16_000.0, SAMPLE_RATE_HZ, FFT_LENGTH, spacing);
+        ranges[0].1 - ranges[0].0 + 1
+    };
+    assert!(low_band_width(BandSpacing::Mel) > low_band_width(BandSpacing::Log));
+}
+
+#[test]
+fn a_narrow_range_gets_one_bin_per_band() {
+    // bins 2 - 4 at 48 kHz and 512 samples
+    let ranges = assign_log_bands(
+        8,
+        200.0,
+        400.0,
+        SAMPLE_RATE_HZ,
+        FFT_LENGTH,
+        BandSpacing::Log,
+    );
+    assert_eq!(ranges.as_slice(), &[(2, 2), (3, 3), (4, 4)]);
+}
+
+#[test]
+fn the_device_keeps_the_bands_on_the_same_frequencies() {
+    let mut config = AppConfig::bars();
+    config.set_device(DeviceSettings {
+        auto_bands: Some(AutoBandConfig::default()),
+        ..DeviceSettings::default()
+    });
+
+    let highest_bin = |config: &AppConfig| config.pattern.channels()[7].end_index;
+    config.apply_auto_bands(48_000, FFT_LENGTH);
+    assert_eq!(
+        highest_bin(&config),
+        hz_to_bin(16_000.0, 48_000, FFT_LENGTH)
+    );
+    config.apply_auto_bands(16_000, FFT_LENGTH);
+    // 16 kHz is past the Nyquist frequency of 8 kHz
+    assert_eq!(highest_bin(&config), FFT_LENGTH / 2 - 1);
+    assert!(
+        config
+            .pattern
+            .channels()
+            .iter()
+            .all(|c| c.start_hz.is_none())
+    );
+}
@@ -0,0 +1,43 @@
+//! The generated bars cover the spectrum without gaps or overlaps.
+
+use common::config::{AppConfig, NeopixelMatrixPattern, SAMPLE_RATE_HZ, hz_to_bin};
+use common::config_presets::BARS_LOG_MAX_HZ;
+use common::dsp::FFT_LENGTH;
+
+#[test]
+fn ranges_are_contiguous_and_cover_the_spectrum() {
+    let last_bin = hz_to_bin(BARS_LOG_MAX_HZ, SAMPLE_RATE_HZ, FFT_LENGTH);
+
+    for num_bars in 1..=8 {
+        let NeopixelMatrixPattern::Bars(channels) = AppConfig::bars_log(num_bars).pattern else {
+            panic!("not a bars pattern");
+        };
+
+        let mut next = 1;
+        for channel in &channels[..num_bars] {
+            assert_eq!(
+                channel.start_index, next,
+                "gap or overlap with {num_bars} bars"
+            );
+            assert!(channel.end_index >= channel.start_index);
+            next = channel.end_index + 1;
+        }
+        assert_eq!(
+            next - 1,
+            last_bin,
+            "{num_bars} bars don't end at bin {last_bin}"
+        );
+    }
+}
+
+#[test]
+fn bands_get_wider_towards_the_top() {
+    let NeopixelMatrixPattern::Bars(channels) = AppConfig::bars_log(8).pattern else {
+        panic!("not a bars pattern");
+    };
+    let widths: Vec<usize> = channels
+        .iter()
+        .map(|c| c.end_index - c.start_index + 1)
+        .collect();
+    assert!(widths.windows(2).all(|w| w[0] <= w[1]), "{widths:?}");
+}
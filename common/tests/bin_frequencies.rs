@@ -0,0 +1,29 @@
+//! The frequencies shown next to the bins in the app.
+
+use common::config::{SAMPLE_RATE_HZ, bin_range_hz};
+use common::dsp::FFT_LENGTH;
+
+/// 93.75 Hz at 48 kHz and 512 samples
+const BIN_WIDTH: f32 = SAMPLE_RATE_HZ as f32 / FFT_LENGTH as f32;
+
+#[test]
+fn a_range_reaches_from_edge_to_edge() {
+    let (low, high) = bin_range_hz(2, 10, SAMPLE_RATE_HZ, FFT_LENGTH);
+    assert_eq!(low, 1.5 * BIN_WIDTH);
+    assert_eq!(high, 10.5 * BIN_WIDTH);
+}
+
+#[test]
+fn dc_and_nyquist_stay_within_the_spectrum() {
+    assert_eq!(
+        bin_range_hz(0, 0, SAMPLE_RATE_HZ, FFT_LENGTH),
+        (0.0, BIN_WIDTH / 2.0)
+    );
+    let nyquist = SAMPLE_RATE_HZ as f32 / 2.0;
+    for end in [FFT_LENGTH / 2, FFT_LENGTH / 2 + 1, usize::MAX] {
+        assert_eq!(
+            bin_range_hz(FFT_LENGTH / 2, end, SAMPLE_RATE_HZ, FFT_LENGTH),
+            (nyquist - BIN_WIDTH / 2.0, nyquist)
+        );
+    }
+}
@@ -0,0 +1,56 @@
+//! Copying the tuning of one channel onto the others has to leave their frequencies alone.
+
+use common::config::{AggregationMethod, AppConfig, AudioSource};
+
+#[test]
+fn gains_are_copied_without_the_colors_and_bins() {
+    let mut config = AppConfig::bars();
+    let channels = config.pattern.channels_mut();
+    channels[0].premult = 12.0;
+    channels[0].noise_gate = 3.0;
+    channels[0].exponent = 3;
+    channels[0].aggregate = AggregationMethod::Max;
+    channels[0].source = AudioSource::Left;
+    let source = channels[0].clone();
+    let before = channels[5].clone();
+
+    channels[5].copy_gains_from(&source);
+
+    let after = &channels[5];
+    assert_eq!(
+        (after.premult, after.noise_gate, after.exponent),
+        (12.0, 3.0, 3)
+    );
+    assert_eq!(after.aggregate, AggregationMethod::Max);
+    assert_eq!(after.color, before.color);
+    assert_eq!(after.source, before.source);
+    assert_eq!(
+        (
+            after.start_index,
+            after.end_index,
+            after.start_hz,
+            after.end_hz
+        ),
+        (
+            before.start_index,
+            before.end_index,
+            before.start_hz,
+            before.end_hz
+        )
+    );
+}
+
+#[test]
+fn pasting_keeps_the_bins_of_the_channel() {
+    let source = AppConfig::bars().pattern.channels()[0].clone();
+    // quarters take a channel of the bars
+    let mut config = AppConfig::quarters();
+    let target = &mut config.pattern.channels_mut()[3];
+    let (start, end) = (target.start_index, target.end_index);
+
+    target.paste_from(&source);
+
+    assert_eq!((target.start_index, target.end_index), (start, end));
+    assert_eq!(target.color, source.color);
+    assert_eq!(target.premult, source.premult);
+}
@@ -0,0 +1,141 @@
+//! The app sends configs to the device with postcard, these make sure nothing gets lost on the way.
+
+use common::config::{
+    AggregationMethod, AppConfig, CONFIG_VERSION, ConfigError, DeviceSettings, MAX_CONFIG_SIZE,
+    NeopixelMatrixPattern,
+};
+use common::config_presets::PRESETS;
+
+#[test]
+fn every_aggregation_method_round_trips() {
+    for method in AggregationMethod::ALL {
+        let mut cfg = AppConfig::bars();
+        let NeopixelMatrixPattern::Bars(channels) = &mut cfg.pattern else {
+            panic!("bars preset is not a bar pattern");
+        };
+        channels[3].aggregate = method.clone();
+
+        let bytes = cfg.to_bytes::<MAX_CONFIG_SIZE>().unwrap();
+        let parsed = AppConfig::from_bytes(&bytes).unwrap();
+
+        let NeopixelMatrixPattern::Bars(channels) = &parsed.pattern else {
+            panic!("pattern changed after the round trip");
+        };
+        assert_eq!(channels[3].aggregate, method);
+    }
+}
+
+#[test]
+fn every_preset_fits_a_single_write() {
+    // the preset service relies on this to apply them without a chunked transfer
+    for preset in &PRESETS {
+        let cfg = (preset.config)();
+        let bytes = cfg.to_bytes::<MAX_CONFIG_SIZE>();
+        assert!(bytes.is_ok(), "{} doesn't fit", preset.name);
+        assert_eq!(AppConfig::from_bytes(&bytes.unwrap()).unwrap(), cfg);
+    }
+}
+
+#[test]
+fn the_serialized_size_is_known_before_writing() {
+    // the app disables Write with it, so it has to be exact
+    for preset in &PRESETS {
+        let cfg = (preset.config)();
+        let size = cfg.serialized_size();
+        assert!(size <= MAX_CONFIG_SIZE, "{} is {size} bytes", preset.name);
+        assert_eq!(size, cfg.to_bytes::<MAX_CONFIG_SIZE>().unwrap().len());
+    }
+}
+
+#[test]
+fn the_version_can_be_read_from_any_config() {
+    let bytes = AppConfig::bars().to_bytes::<MAX_CONFIG_SIZE>().unwrap();
+    assert_eq!(AppConfig::peek_version(&bytes), Some(CONFIG_VERSION));
+
+    // a future version with fields this build can't decode
+    let future = [CONFIG_VERSION as u8 + 1, 0xff, 0xff];
+    assert!(AppConfig::from_bytes(&future).is_err());
+    assert_eq!(AppConfig::peek_version(&future), Some(CONFIG_VERSION + 1));
+
+    assert_eq!(AppConfig::peek_version(&[]), None);
+}
+
+/// The layout of version 1, as the firmware before the channels got their ranges in Hz sends it
+mod v1 {
+    use serde::Serialize;
+
+    #[derive(Serialize)]
+    pub struct ChannelConfig {
+        pub start_index: usize,
+        pub end_index: usize,
+        pub premult: f32,
+        pub noise_gate: f32,
+        pub exponent: u8,
+        pub color: [f32; 3],
+        /// Sum
+        pub aggregate: u8,
+    }
+
+    #[derive(Serialize)]
+    pub enum NeopixelMatrixPattern {
+        Stripes([ChannelConfig; 4]),
+    }
+
+    #[derive(Serialize)]
+    pub struct AppConfig {
+        pub config_version: u32,
+        pub sample_count: usize,
+        /// Size512
+        pub fft_size: u8,
+        pub use_hann_window: bool,
+        pub pattern: NeopixelMatrixPattern,
+    }
+}
+
+#[test]
+fn a_version_1_config_is_unsupported_rather_than_broken() {
+    let channel = |start_index, end_index| v1::ChannelConfig {
+        start_index,
+        end_index,
+        premult: 3.0,
+        noise_gate: 0.01,
+        exponent: 6,
+        color: [1.0, 0.0, 0.0],
+        aggregate: 0,
+    };
+    let old = v1::AppConfig {
+        config_version: 1,
+        sample_count: 256,
+        fft_size: 2,
+        use_hann_window: true,
+        pattern: v1::NeopixelMatrixPattern::Stripes([
+            channel(1, 1),
+            channel(2, 10),
+            channel(11, 15),
+            channel(16, 25),
+        ]),
+    };
+    let bytes = postcard::to_vec::<_, MAX_CONFIG_SIZE>(&old).unwrap();
+
+    assert_eq!(
+        AppConfig::from_bytes_checked(&bytes),
+        Err(ConfigError::UnsupportedVersion(1))
+    );
+}
+
+#[test]
+fn default_device_settings_are_left_out() {
+    // the presets are close to the single write limit, the defaults mustn't take any room
+    let mut cfg = AppConfig::quarters();
+    cfg.set_device(DeviceSettings::default());
+    assert_eq!(cfg.device, None);
+
+    let never_fade = DeviceSettings {
+        fade_timeout_ms: 0,
+        ..DeviceSettings::default()
+    };
+    cfg.set_device(never_fade);
+    assert_eq!(cfg.device, Some(never_fade));
+    let bytes = cfg.to_bytes::<MAX_CONFIG_SIZE>().unwrap();
+    assert_eq!(AppConfig::from_bytes(&bytes).unwrap().device(), never_fade);
+}
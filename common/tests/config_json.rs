@@ -0,0 +1,52 @@
+//! The app saves configs as JSON files, these make sure they survive the round trip.
+
+use common::config::{AppConfig, MAX_CONFIG_SIZE};
+
+fn presets() -> [(&'static str, AppConfig); 5] {
+    [
+        ("stripes", AppConfig::stripes()),
+        ("bars", AppConfig::bars()),
+        ("bars2", AppConfig::bars2()),
+        ("quarters", AppConfig::quarters()),
+        ("stereo_bars", AppConfig::stereo_bars()),
+    ]
+}
+
+#[test]
+fn presets_round_trip_through_json() {
+    for (name, cfg) in presets() {
+        let json = serde_json::to_string_pretty(&cfg).unwrap();
+        let parsed: AppConfig = serde_json::from_str(&json).unwrap();
+
+        // compare the wire format, that's what ends up on the device
+        assert_eq!(
+            cfg.to_bytes::<MAX_CONFIG_SIZE>().unwrap(),
+            parsed.to_bytes::<MAX_CONFIG_SIZE>().unwrap(),
+            "{name} changed after the round trip"
+        );
+    }
+}
+
+#[test]
+fn malformed_json_is_an_error() {
+    let json = serde_json::to_string(&AppConfig::bars()).unwrap();
+
+    assert!(serde_json::from_str::<AppConfig>("").is_err());
+    assert!(serde_json::from_str::<AppConfig>("{}").is_err());
+    assert!(serde_json::from_str::<AppConfig>(&json[..json.len() / 2]).is_err());
+    assert!(serde_json::from_str::<AppConfig>(&json.replace("Bars", "Triangles")).is_err());
+}
+
+#[test]
+fn json_and_postcard_describe_the_same_config() {
+    for (name, cfg) in presets() {
+        let json = cfg.to_json().unwrap();
+        let from_json = AppConfig::from_json(json.as_bytes()).unwrap();
+
+        let bytes = from_json.to_bytes::<MAX_CONFIG_SIZE>().unwrap();
+        let from_postcard = AppConfig::from_bytes(&bytes).unwrap();
+
+        assert_eq!(from_postcard, cfg, "{name} changed between the formats");
+        assert_eq!(from_postcard.to_json().unwrap(), json);
+    }
+}
@@ -0,0 +1,94 @@
+//! Configs larger than one write go to the device in chunks, these make sure the device gets back
+//! exactly what the app sent.
+
+use common::config::{AppConfig, ColorMode, MAX_CONFIG_SIZE, NeopixelMatrixPattern};
+use common::transfer::{self, ChunkError, ConfigAssembler, ConfigControl, MAX_CHUNKED_CONFIG_SIZE};
+
+/// A bars config with every optional field set and every varint at its widest
+fn max_size_config() -> AppConfig {
+    let mut cfg = AppConfig::bars();
+    cfg.sample_count = u32::MAX as usize;
+    cfg.max_milliamps = u16::MAX;
+    let NeopixelMatrixPattern::Bars(channels) = &mut cfg.pattern else {
+        panic!("bars preset is not a bar pattern");
+    };
+    for ch in channels.iter_mut() {
+        ch.start_index = u32::MAX as usize;
+        ch.end_index = u32::MAX as usize;
+        ch.start_hz = Some(20.0);
+        ch.end_hz = Some(20_000.0);
+        ch.color_mode = ColorMode::HueShift {
+            from_hue: 240.0,
+            to_hue: 0.0,
+        };
+    }
+    cfg
+}
+
+fn send(assembler: &mut ConfigAssembler, bytes: &[u8]) -> Result<Vec<u8>, ChunkError> {
+    assembler.begin(bytes.len())?;
+    for chunk in transfer::chunks(bytes) {
+        assert!(chunk.len() <= MAX_CONFIG_SIZE);
+        assembler.push(&chunk)?;
+    }
+    assembler.commit().map(|data| data.to_vec())
+}
+
+#[test]
+fn a_max_size_config_is_too_large_for_a_single_write() {
+    let cfg = max_size_config();
+    assert!(cfg.to_bytes::<MAX_CONFIG_SIZE>().is_err());
+    assert!(cfg.to_bytes::<MAX_CHUNKED_CONFIG_SIZE>().is_ok());
+}
+
+#[test]
+fn a_max_size_config_survives_the_chunked_transfer() {
+    let cfg = max_size_config();
+    let bytes = cfg.to_bytes::<MAX_CHUNKED_CONFIG_SIZE>().unwrap();
+
+    let mut assembler = ConfigAssembler::new();
+    let received = send(&mut assembler, &bytes).unwrap();
+    assert!(!assembler.is_active());
+    assert_eq!(AppConfig::from_bytes(&received).unwrap(), cfg);
+}
+
+#[test]
+fn configs_over_the_chunked_limit_are_rejected_up_front() {
+    let mut assembler = ConfigAssembler::new();
+    assert_eq!(
+        assembler.begin(MAX_CHUNKED_CONFIG_SIZE + 1),
+        Err(ChunkError::TooLarge)
+    );
+    assert!(!assembler.is_active());
+}
+
+#[test]
+fn lost_chunks_are_detected() {
+    let bytes = max_size_config()
+        .to_bytes::<MAX_CHUNKED_CONFIG_SIZE>()
+        .unwrap();
+    let chunks: Vec<_> = transfer::chunks(&bytes).collect();
+    assert!(chunks.len() > 1);
+
+    let mut assembler = ConfigAssembler::new();
+    assembler.begin(bytes.len()).unwrap();
+    assert_eq!(assembler.push(&chunks[1]), Err(ChunkError::OutOfOrder));
+
+    assembler.begin(bytes.len()).unwrap();
+    assembler.push(&chunks[0]).unwrap();
+    assert_eq!(assembler.commit(), Err(ChunkError::Incomplete));
+}
+
+#[test]
+fn control_messages_fit_their_characteristic() {
+    for msg in [
+        ConfigControl::Begin {
+            total_len: u32::MAX,
+        },
+        ConfigControl::Commit,
+        ConfigControl::Abort,
+    ] {
+        let bytes = msg.to_bytes().unwrap();
+        assert_eq!(ConfigControl::from_bytes(&bytes).unwrap(), msg);
+    }
+}
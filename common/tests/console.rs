@@ -0,0 +1,81 @@
+//! The serial console has to understand the commands in its help, and refuse anything else
+//! before the running config is touched.
+
+use common::config::{AggregationMethod, AppConfig, AudioSource};
+use common::config_presets::PRESETS;
+use common::console::{
+    ChannelField, ConsoleCommand, ConsoleError, parse_command, set_channel_field,
+};
+
+#[test]
+fn commands_are_parsed() {
+    assert_eq!(parse_command("help"), Ok(ConsoleCommand::Help));
+    assert_eq!(parse_command("  CONFIG \r"), Ok(ConsoleCommand::Config));
+    assert_eq!(parse_command("presets"), Ok(ConsoleCommand::Presets));
+    assert_eq!(parse_command("save"), Ok(ConsoleCommand::Save));
+
+    // preset names can have spaces
+    let stereo = PRESETS
+        .iter()
+        .position(|p| p.name == "Stereo bars")
+        .unwrap();
+    assert_eq!(
+        parse_command("preset stereo bars"),
+        Ok(ConsoleCommand::Preset(stereo))
+    );
+    assert_eq!(
+        parse_command("preset disco"),
+        Err(ConsoleError::UnknownPreset)
+    );
+    assert_eq!(parse_command("dance"), Err(ConsoleError::UnknownCommand));
+}
+
+#[test]
+fn channel_fields_are_parsed() {
+    let set = |line| match parse_command(line) {
+        Ok(ConsoleCommand::Set { channel, field }) => Ok((channel, field)),
+        Ok(other) => panic!("{line} parsed as {other:?}"),
+        Err(e) => Err(e),
+    };
+    assert_eq!(
+        set("set 2 premult 1.5"),
+        Ok((2, ChannelField::Premult(1.5)))
+    );
+    assert_eq!(
+        set("set 0 color #ff8000"),
+        Ok((0, ChannelField::Color([1.0, 128.0 / 255.0, 0.0])))
+    );
+    assert_eq!(
+        set("set 1 aggregate RMS"),
+        Ok((1, ChannelField::Aggregate(AggregationMethod::RmsNormalized)))
+    );
+    assert_eq!(
+        set("set 1 source right"),
+        Ok((1, ChannelField::Source(AudioSource::Right)))
+    );
+
+    assert_eq!(set("set 1 exponent 300"), Err(ConsoleError::InvalidValue));
+    assert_eq!(set("set 1 color +12345"), Err(ConsoleError::InvalidValue));
+    assert_eq!(set("set 1 brightness 3"), Err(ConsoleError::UnknownField));
+    assert_eq!(set("set 1 premult"), Err(ConsoleError::MissingArgument));
+}
+
+#[test]
+fn setting_a_bin_drops_the_frequency() {
+    let mut config = AppConfig::bars();
+    let ConsoleCommand::Set { channel, field } = parse_command("set 3 start_hz 440").unwrap()
+    else {
+        panic!("not a set");
+    };
+    set_channel_field(&mut config, channel, field).unwrap();
+    assert_eq!(config.pattern.channels()[3].start_hz, Some(440.0));
+
+    set_channel_field(&mut config, 3, ChannelField::Start(7)).unwrap();
+    assert_eq!(config.pattern.channels()[3].start_index, 7);
+    assert_eq!(config.pattern.channels()[3].start_hz, None);
+
+    assert_eq!(
+        set_channel_field(&mut config, 8, ChannelField::Start(7)),
+        Err(ConsoleError::NoSuchChannel)
+    );
+}
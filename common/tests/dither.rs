@@ -0,0 +1,45 @@
+//! Dithered frames have to average out to the exact color, and must not flicker where there's
+//! nothing to smooth.
+
+use common::dsp::{Dither, MATRIX_LENGTH, PreciseColor};
+
+fn frame(value: f32) -> [PreciseColor; MATRIX_LENGTH] {
+    [PreciseColor::new(value, value / 2.0, 0.0); MATRIX_LENGTH]
+}
+
+#[test]
+fn a_dim_color_averages_out_to_its_exact_value() {
+    let mut dither = Dither::new();
+    let frame = frame(3.3);
+    let frames = 100;
+    let (mut r, mut g) = (0u32, 0u32);
+    for _ in 0..frames {
+        let out = dither.apply(&frame);
+        r += out[0].r as u32;
+        g += out[0].g as u32;
+        // every step is one of the two closest the LEDs can show
+        assert!((3..=4).contains(&out[0].r));
+        assert!((1..=2).contains(&out[0].g));
+    }
+    assert!((r as f32 / frames as f32 - 3.3).abs() < 0.02);
+    assert!((g as f32 / frames as f32 - 1.65).abs() < 0.02);
+}
+
+#[test]
+fn whole_values_do_not_flicker() {
+    let mut dither = Dither::new();
+    let frame = frame(42.0);
+    for _ in 0..10 {
+        let out = dither.apply(&frame);
+        assert!(out.iter().all(|c| c.r == 42 && c.g == 21 && c.b == 0));
+    }
+}
+
+#[test]
+fn out_of_range_values_are_clamped() {
+    let mut dither = Dither::new();
+    for value in [300.0, -20.0, 255.0] {
+        let out = dither.apply(&frame(value));
+        assert_eq!(out[0].r, value.clamp(0.0, 255.0) as u8);
+    }
+}
@@ -0,0 +1,51 @@
+//! The high-pass ahead of the FFT has to take out a DC offset without touching the lowest bins
+//! that carry actual audio.
+
+use common::config::SAMPLE_RATE_HZ;
+use common::dsp::{FFT_LENGTH, HighPass};
+
+/// Power of DFT bin `bin` of `samples`
+fn bin_power(samples: &[f32], bin: usize) -> f32 {
+    let (mut re, mut im) = (0.0f32, 0.0f32);
+    for (n, s) in samples.iter().enumerate() {
+        let phase = 2.0 * core::f32::consts::PI * (bin * n) as f32 / samples.len() as f32;
+        re += s * phase.cos();
+        im -= s * phase.sin();
+    }
+    re * re + im * im
+}
+
+/// A sine right on bin 1 (~94 Hz) on top of an offset twice its amplitude
+fn offset_sine() -> Vec<f32> {
+    (0..FFT_LENGTH)
+        .map(|n| 0.4 + 0.2 * (2.0 * core::f32::consts::PI * n as f32 / FFT_LENGTH as f32).sin())
+        .collect()
+}
+
+#[test]
+fn the_offset_goes_and_the_bass_stays() {
+    let input = offset_sine();
+    let mut filter = HighPass::new();
+    // the device filters without a break, so measure once the filter settled
+    let mut output = input.clone();
+    for _ in 0..4 {
+        output.copy_from_slice(&input);
+        filter.apply(&mut output, 20, SAMPLE_RATE_HZ);
+    }
+
+    let (dc_before, dc_after) = (bin_power(&input, 0), bin_power(&output, 0));
+    assert!(dc_after < dc_before * 1e-4, "DC {dc_before} -> {dc_after}");
+
+    // 20 Hz is far enough below bin 1 to cost it only a few percent
+    let (bass_before, bass_after) = (bin_power(&input, 1), bin_power(&output, 1));
+    let ratio = bass_after / bass_before;
+    assert!((0.9..=1.01).contains(&ratio), "bin 1 scaled by {ratio}");
+}
+
+#[test]
+fn zero_turns_it_off() {
+    let input = offset_sine();
+    let mut output = input.clone();
+    HighPass::new().apply(&mut output, 0, SAMPLE_RATE_HZ);
+    assert_eq!(output, input);
+}
@@ -0,0 +1,53 @@
+//! Which input drives the lights, for each selectable source, and the format it delivers.
+
+use common::config::{
+    AppConfig, I2sBitDepth, I2sChannelSource, I2sSampleRate, InputSource, SAMPLE_RATE_HZ,
+};
+use common::status::AudioInput;
+
+#[test]
+fn fixed_sources_ignore_the_other_input() {
+    for usb_active in [false, true] {
+        assert!(InputSource::UsbAudio.accepts(AudioInput::Usb, usb_active));
+        assert!(!InputSource::UsbAudio.accepts(AudioInput::I2s, usb_active));
+        assert!(InputSource::I2s.accepts(AudioInput::I2s, usb_active));
+        assert!(!InputSource::I2s.accepts(AudioInput::Usb, usb_active));
+    }
+}
+
+#[test]
+fn auto_prefers_usb_while_it_streams() {
+    assert!(InputSource::Auto.accepts(AudioInput::Usb, true));
+    assert!(!InputSource::Auto.accepts(AudioInput::I2s, true));
+    assert!(InputSource::Auto.accepts(AudioInput::I2s, false));
+}
+
+#[test]
+fn the_bins_follow_the_rate_of_the_microphone() {
+    let mut config = AppConfig::default();
+    let mut device = config.device();
+    device.i2s_sample_rate = I2sSampleRate::Hz16000;
+    config.set_device(device);
+
+    config.input_source = InputSource::I2s;
+    assert_eq!(config.sample_rate(), 16_000);
+    config.input_source = InputSource::UsbAudio;
+    assert_eq!(config.sample_rate(), SAMPLE_RATE_HZ);
+}
+
+#[test]
+fn full_scale_matches_the_bit_depth() {
+    assert_eq!(I2sBitDepth::Bits24.full_scale(), (1 << 23) as f32);
+    assert_eq!(I2sBitDepth::Bits32.full_scale(), 2147483648.0);
+    assert_eq!(I2sBitDepth::Bits16.full_scale(), i16::MAX as f32 + 1.0);
+}
+
+#[test]
+fn the_i2s_slots_can_be_swapped_or_shared() {
+    assert_eq!(I2sChannelSource::Both.split(false, 1, 2), (1, 2));
+    assert_eq!(I2sChannelSource::Both.split(true, 1, 2), (2, 1));
+    assert_eq!(I2sChannelSource::Left.split(false, 1, 2), (1, 1));
+    assert_eq!(I2sChannelSource::Right.split(false, 1, 2), (2, 2));
+    // the swap comes first, so a mic on the other phase still shows up as left
+    assert_eq!(I2sChannelSource::Left.split(true, 0, 2), (2, 2));
+}
@@ -0,0 +1,55 @@
+//! The calibrated noise floor is subtracted before the noise gate, so steady room noise doesn't
+//! light up the matrix.
+
+use common::config::{AppConfig, NeopixelMatrixPattern, SAMPLE_RATE_HZ};
+use common::dsp::{NoiseFloorCalibration, SPECTRUM_LENGTH, calculate_channel, channel_levels};
+use common::persist::{decode_stored_noise_floor, encode_stored_noise_floor};
+
+fn noise(level: f32) -> [f32; SPECTRUM_LENGTH] {
+    core::array::from_fn(|bin| level * (1.0 + (bin % 3) as f32 * 0.1))
+}
+
+fn calibrated(level: f32) -> NoiseFloorCalibration {
+    let mut calibration = NoiseFloorCalibration::new();
+    // the floor keeps the loudest block, not the last one
+    calibration.add(&noise(level));
+    calibration.add(&noise(level / 2.0));
+    calibration
+}
+
+#[test]
+fn calibrated_noise_is_silent() {
+    let cfg = AppConfig::bars();
+    let floor = calibrated(1.0e6).floor();
+    let spectrum = noise(1.0e6);
+
+    let without = channel_levels(&spectrum, &spectrum, &cfg, SAMPLE_RATE_HZ, None);
+    let with = channel_levels(&spectrum, &spectrum, &cfg, SAMPLE_RATE_HZ, Some(&floor));
+    assert!(without.iter().any(|level| *level > 0.0));
+    assert!(with.iter().all(|level| *level == 0.0));
+}
+
+#[test]
+fn signals_above_the_floor_still_get_through() {
+    let cfg = AppConfig::bars();
+    let NeopixelMatrixPattern::Bars(channels) = &cfg.pattern else {
+        panic!("bars preset is not a bar pattern");
+    };
+    let floor = calibrated(1.0e6).floor();
+    let mut spectrum = noise(1.0e6);
+    let (start, _) = channels[0].bin_range(SAMPLE_RATE_HZ, 2 * SPECTRUM_LENGTH);
+    spectrum[start] += 1.0e9;
+
+    let level = calculate_channel(&spectrum, &channels[0], SAMPLE_RATE_HZ, Some(&floor));
+    assert!(level > 0.0);
+}
+
+#[test]
+fn the_floor_survives_a_reboot() {
+    let floor = calibrated(1234.5).floor();
+    let stored = encode_stored_noise_floor(&floor);
+    assert_eq!(decode_stored_noise_floor(&stored), Some(floor));
+
+    // blank flash
+    assert_eq!(decode_stored_noise_floor(&[0xff; 16]), None);
+}
@@ -0,0 +1,124 @@
+//! Two chained panels have to be drawn as one matrix whatever way they are mounted, and a layout
+//! with more LEDs than the firmware is built for has to be rejected.
+
+use std::collections::HashSet;
+
+use common::config::{
+    AppConfig, ConfigError, DeviceSettings, MAX_CONFIG_SIZE, MAX_LEDS, PANEL_LENGTH,
+    PanelArrangement, PanelLayout, PanelRotation, SAMPLE_RATE_HZ,
+};
+use common::dsp::{Dither, RenderState, SPECTRUM_LENGTH, channel_levels, render_frame};
+use rgb::RGB8;
+
+fn two_panels(arrangement: PanelArrangement, rotations: [PanelRotation; 2]) -> PanelLayout {
+    PanelLayout {
+        panels: 2,
+        arrangement,
+        rotations,
+    }
+}
+
+fn with_layout(mut config: AppConfig, layout: PanelLayout) -> AppConfig {
+    config.set_device(DeviceSettings {
+        layout,
+        ..DeviceSettings::default()
+    });
+    config
+}
+
+#[test]
+fn a_single_panel_runs_down_the_even_columns_and_up_the_odd_ones() {
+    let layout = PanelLayout::single();
+    assert_eq!(layout.size(), (16, 16));
+    assert_eq!(layout.index(0, 0), 0);
+    assert_eq!(layout.index(0, 15), 15);
+    assert_eq!(layout.index(1, 15), 16);
+    assert_eq!(layout.index(1, 0), 31);
+}
+
+#[test]
+fn the_second_panel_continues_the_chain() {
+    let side_by_side = two_panels(PanelArrangement::SideBySide, [PanelRotation::None; 2]);
+    assert_eq!(side_by_side.size(), (32, 16));
+    assert_eq!(side_by_side.index(16, 0), PANEL_LENGTH);
+
+    let stacked = two_panels(PanelArrangement::Stacked, [PanelRotation::None; 2]);
+    assert_eq!(stacked.size(), (16, 32));
+    assert_eq!(stacked.index(0, 16), PANEL_LENGTH);
+}
+
+#[test]
+fn rotated_panels_start_in_another_corner() {
+    use PanelRotation::*;
+    let layout = two_panels(PanelArrangement::SideBySide, [None, Cw180]);
+    // the second panel is upside down, its strip starts in the bottom right and runs up
+    assert_eq!(layout.index(31, 15), PANEL_LENGTH);
+    assert_eq!(layout.index(31, 14), PANEL_LENGTH + 1);
+
+    // a quarter turn clockwise moves the start to the top right, running left along the top row
+    let layout = two_panels(PanelArrangement::Stacked, [Cw90, Cw270]);
+    assert_eq!(layout.index(15, 0), 0);
+    assert_eq!(layout.index(14, 0), 1);
+    // and the other way round to the bottom left, running right
+    assert_eq!(layout.index(0, 31), PANEL_LENGTH);
+    assert_eq!(layout.index(1, 31), PANEL_LENGTH + 1);
+}
+
+#[test]
+fn every_pixel_has_its_own_led() {
+    for arrangement in PanelArrangement::ALL {
+        for first in PanelRotation::ALL {
+            for second in PanelRotation::ALL {
+                let layout = two_panels(arrangement, [first, second]);
+                let (width, height) = layout.size();
+                let leds: HashSet<usize> = (0..height)
+                    .flat_map(|y| (0..width).map(move |x| layout.index(x, y)))
+                    .collect();
+                assert_eq!(leds, (0..MAX_LEDS).collect(), "{layout:?}");
+            }
+        }
+    }
+}
+
+#[test]
+fn bars_stretch_over_both_panels() {
+    let config = with_layout(
+        AppConfig::bars(),
+        two_panels(PanelArrangement::SideBySide, [PanelRotation::None; 2]),
+    );
+    // the third bar covers bins 5 - 8
+    let mut spectrum = [0.0; SPECTRUM_LENGTH];
+    spectrum[6] = 1e9;
+    let levels = channel_levels(&spectrum, &spectrum, &config, SAMPLE_RATE_HZ, None);
+    let frame = render_frame(
+        &levels,
+        &config,
+        &mut RenderState::default(),
+        &mut Dither::new(),
+        0.0,
+    );
+
+    let (width, height) = config.layout().size();
+    for y in 0..height {
+        for x in 0..width {
+            let lit = frame[config.layout().index(x, y)] != RGB8::default();
+            assert_eq!(lit, x / 4 == 2, "pixel ({x}, {y})");
+        }
+    }
+}
+
+#[test]
+fn too_many_leds_are_rejected() {
+    for panels in [0, 3] {
+        let layout = PanelLayout {
+            panels,
+            ..PanelLayout::single()
+        };
+        let config = with_layout(AppConfig::quarters(), layout);
+        let bytes = config.to_bytes::<MAX_CONFIG_SIZE>().unwrap();
+        assert_eq!(
+            AppConfig::from_bytes_checked(&bytes),
+            Err(ConfigError::TooManyLeds(panels as usize * PANEL_LENGTH))
+        );
+    }
+}
@@ -0,0 +1,77 @@
+//! The pitch pattern has to pick the loudest bin of its range and move from red to violet as the
+//! pitch goes up.
+
+use common::config::{AppConfig, NeopixelMatrixPattern, SAMPLE_RATE_HZ};
+use common::dsp::{RenderState, SPECTRUM_LENGTH, dominant_bin, render_pattern};
+
+fn spectrum_with_peak(bin: usize) -> [f32; SPECTRUM_LENGTH] {
+    let mut spectrum = [1000.0; SPECTRUM_LENGTH];
+    spectrum[bin] = 1e9;
+    spectrum
+}
+
+fn pitch_config() -> AppConfig {
+    AppConfig {
+        pattern: NeopixelMatrixPattern::PitchColor {
+            min_bin: 2,
+            max_bin: 64,
+        },
+        ..AppConfig::default()
+    }
+}
+
+#[test]
+fn the_loudest_bin_inside_the_range_wins() {
+    let mut spectrum = spectrum_with_peak(10);
+    spectrum[100] = 1e12;
+    assert_eq!(dominant_bin(&spectrum, 2, 64, None), Some((10, 1e9)));
+    assert_eq!(dominant_bin(&spectrum, 2, 1000, None), Some((100, 1e12)));
+    assert_eq!(dominant_bin(&spectrum, 300, 400, None), None);
+}
+
+#[test]
+fn the_hue_follows_the_pitch() {
+    let config = pitch_config();
+    let render = |bin: usize| {
+        let spectrum = spectrum_with_peak(bin);
+        let mut state = RenderState::default();
+        render_pattern(
+            &spectrum,
+            &spectrum,
+            &config,
+            SAMPLE_RATE_HZ,
+            None,
+            &mut state,
+            0.0,
+        )
+    };
+
+    // the bottom of the range is red, the top violet, all over the matrix
+    let low = render(2);
+    let leds = config.layout().led_count();
+    assert!(low[..leds].iter().all(|&c| c == low[0]));
+    assert!(low[0].r > 200 && low[0].g < 10 && low[0].b < 10);
+    let high = render(64);
+    assert!(high[0].b > 200 && high[0].r > 100 && high[0].g < 10);
+
+    // every octave moves the hue by the same amount, the middle of 5 octaves is in the greens
+    let middle = render(11);
+    assert!(middle[0].g > middle[0].r && middle[0].g > 100);
+}
+
+#[test]
+fn silence_is_dark() {
+    let config = pitch_config();
+    let spectrum = [0.0; SPECTRUM_LENGTH];
+    let mut state = RenderState::default();
+    let frame = render_pattern(
+        &spectrum,
+        &spectrum,
+        &config,
+        SAMPLE_RATE_HZ,
+        None,
+        &mut state,
+        0.0,
+    );
+    assert!(frame.iter().all(|c| c.r == 0 && c.g == 0 && c.b == 0));
+}
@@ -0,0 +1,89 @@
+//! Frames above the power limit get dimmed, everything else stays untouched. The white balance
+//! and the overlay only ever dim too.
+
+use common::config::Overlay;
+use common::dsp::{
+    BREATHE_MIN_GAIN, LED_COMPONENT_MA, LED_IDLE_MA, MATRIX_LENGTH, apply_overlay,
+    apply_white_balance, estimate_milliamps, limit_power, overlay_gain,
+};
+use rgb::RGB8;
+
+#[test]
+fn full_white_is_scaled_to_the_limit() {
+    let limit = 4000;
+    let mut colors = [RGB8::new(255, 255, 255); MATRIX_LENGTH];
+    assert!(limit_power(&mut colors, limit));
+
+    let total = estimate_milliamps(&colors);
+    assert!(total <= limit as f32, "{total} is above the limit");
+    // each component is rounded down, so it can end up at most 1 below per component
+    let rounding = 3.0 * MATRIX_LENGTH as f32 * LED_COMPONENT_MA / 255.0;
+    assert!(
+        total > limit as f32 - rounding,
+        "{total} was dimmed too much"
+    );
+    // scaled proportionally, so white stays white
+    assert!(colors.iter().all(|c| c.r == c.g && c.g == c.b));
+}
+
+#[test]
+fn frames_below_the_limit_are_unchanged() {
+    let mut colors = [RGB8::new(10, 20, 30); MATRIX_LENGTH];
+    let original = colors;
+    let limit = estimate_milliamps(&original).ceil() as u16;
+    assert!(!limit_power(&mut colors, limit));
+    assert_eq!(colors, original);
+}
+
+#[test]
+fn the_idle_current_counts_towards_the_limit() {
+    let idle = MATRIX_LENGTH as f32 * LED_IDLE_MA;
+    assert_eq!(estimate_milliamps(&[RGB8::default(); MATRIX_LENGTH]), idle);
+
+    // a budget that only covers the idle current leaves nothing for the colors
+    let mut colors = [RGB8::new(255, 0, 0); MATRIX_LENGTH];
+    assert!(limit_power(&mut colors, idle as u16));
+    assert_eq!(colors, [RGB8::default(); MATRIX_LENGTH]);
+}
+
+#[test]
+fn zero_disables_the_limit() {
+    let mut colors = [RGB8::new(255, 255, 255); MATRIX_LENGTH];
+    assert!(!limit_power(&mut colors, 0));
+    assert_eq!(colors, [RGB8::new(255, 255, 255); MATRIX_LENGTH]);
+}
+
+#[test]
+fn white_balance_scales_each_component() {
+    let mut colors = [RGB8::new(255, 255, 255), RGB8::new(100, 50, 0)];
+    apply_white_balance(&mut colors, [1.0, 0.8, 0.6]);
+    assert_eq!(colors, [RGB8::new(255, 204, 153), RGB8::new(100, 40, 0)]);
+
+    // gains above 1 would break the power limit
+    let mut colors = [RGB8::new(200, 200, 200)];
+    apply_white_balance(&mut colors, [2.0, 1.0, -1.0]);
+    assert_eq!(colors, [RGB8::new(200, 200, 0)]);
+}
+
+#[test]
+fn breathing_dims_the_frame_and_brings_it_back() {
+    let breathe = Overlay::Breathe { period_ms: 4000 };
+    assert_eq!(overlay_gain(breathe, 0.0), 1.0);
+    assert!((overlay_gain(breathe, 2.0) - BREATHE_MIN_GAIN).abs() < 1e-6);
+    assert!((overlay_gain(breathe, 4.0) - 1.0).abs() < 1e-6);
+    for t in 0..100 {
+        let gain = overlay_gain(breathe, t as f32 * 0.1);
+        assert!((BREATHE_MIN_GAIN..=1.0).contains(&gain), "{gain} at {t}");
+    }
+
+    let mut colors = [RGB8::new(200, 100, 0)];
+    apply_overlay(&mut colors, breathe, 2.0);
+    assert_eq!(colors, [RGB8::new(60, 30, 0)]);
+
+    // a period of 0 would divide by zero
+    for overlay in [Overlay::None, Overlay::Breathe { period_ms: 0 }] {
+        let mut colors = [RGB8::new(200, 100, 0)];
+        apply_overlay(&mut colors, overlay, 2.0);
+        assert_eq!(colors, [RGB8::new(200, 100, 0)]);
+    }
+}
@@ -0,0 +1,50 @@
+//! The preset slots have to come back from flash with their name and config, and the BLE writes
+//! naming them have to be checked before anything is stored.
+
+use common::config::AppConfig;
+use common::persist::{
+    MAX_SLOT_NAME_SIZE, SLOT_COUNT, decode_stored_slot, encode_stored_slot, parse_save_slot,
+    slot_names_to_bytes,
+};
+
+#[test]
+fn a_slot_survives_the_round_trip() {
+    let config = AppConfig::quarters();
+    let stored = encode_stored_slot("Party", &config).unwrap();
+    let (name, decoded) = decode_stored_slot(&stored).unwrap();
+    assert_eq!(name.as_str(), "Party");
+    assert_eq!(decoded, config);
+
+    // erased flash and a damaged config are both an empty slot
+    assert_eq!(decode_stored_slot(&[0xff; 64]), None);
+    let mut damaged = stored.clone();
+    let last = damaged.len() - 1;
+    damaged[last] ^= 0xff;
+    assert_eq!(decode_stored_slot(&damaged), None);
+}
+
+#[test]
+fn the_names_are_sent_one_per_line() {
+    let names = [Some("Bars"), None, Some("Chill"), None];
+    assert_eq!(slot_names_to_bytes(names).as_slice(), b"Bars\n\nChill\n");
+    assert_eq!(
+        String::from_utf8_lossy(&slot_names_to_bytes(names))
+            .split('\n')
+            .count(),
+        SLOT_COUNT
+    );
+}
+
+#[test]
+fn save_requests_are_checked() {
+    let (index, name) = parse_save_slot(b"\x02Chill").unwrap();
+    assert_eq!((index, name.as_str()), (2, "Chill"));
+
+    // slot out of range, no name, a line break or a name that's too long
+    assert_eq!(parse_save_slot(&[SLOT_COUNT as u8, b'a']), None);
+    assert_eq!(parse_save_slot(b"\x00"), None);
+    assert_eq!(parse_save_slot(b"\x00a\nb"), None);
+    let mut long = vec![0];
+    long.extend([b'a'; MAX_SLOT_NAME_SIZE + 1]);
+    assert_eq!(parse_save_slot(&long), None);
+}
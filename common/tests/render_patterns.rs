@@ -0,0 +1,217 @@
+//! A single loud bin has to light up exactly the part of the matrix of the channel covering it,
+//! whatever the pattern.
+
+use common::config::{AppConfig, NeopixelMatrixPattern, PANEL_LENGTH, SAMPLE_RATE_HZ};
+use common::dsp::{
+    Dither, MATRIX_LENGTH, RenderState, SPECTRUM_LENGTH, channel_levels, render_frame,
+    render_split_frame, side_levels, to_mid_side,
+};
+use rgb::RGB8;
+
+type Spectrum = [f32; SPECTRUM_LENGTH];
+
+fn single_bin(bin: usize) -> Spectrum {
+    let mut spectrum = [0.0; SPECTRUM_LENGTH];
+    spectrum[bin] = 1e9;
+    spectrum
+}
+
+fn render(config: &AppConfig, left: &Spectrum, right: &Spectrum) -> [RGB8; MATRIX_LENGTH] {
+    let levels = channel_levels(left, right, config, SAMPLE_RATE_HZ, None);
+    let mut state = RenderState::default();
+    render_frame(&levels, config, &mut state, &mut Dither::new(), 0.0)
+}
+
+fn is_lit(c: &RGB8) -> bool {
+    c.r > 0 || c.g > 0 || c.b > 0
+}
+
+/// The color of channel `index` at full strength
+fn full_color(config: &AppConfig, index: usize) -> RGB8 {
+    let [r, g, b] = config.pattern.channels()[index]
+        .color
+        .map(|c| (c * 255.0) as u8);
+    RGB8::new(r, g, b)
+}
+
+/// Asserts that the pixels for which `lit(x, y)` is true show `color`, and all others are off
+fn assert_lit(frame: &[RGB8; MATRIX_LENGTH], color: RGB8, lit: impl Fn(usize, usize) -> bool) {
+    let layout = AppConfig::default().layout();
+    let (width, height) = layout.size();
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = frame[layout.index(x, y)];
+            if lit(x, y) {
+                assert_eq!(pixel, color, "pixel ({x}, {y}) should be lit");
+            } else {
+                assert!(!is_lit(&pixel), "pixel ({x}, {y}) should be off: {pixel:?}");
+            }
+        }
+    }
+}
+
+#[test]
+fn stripes_light_the_block_of_the_channel() {
+    let config = AppConfig::stripes();
+    // the second channel covers bins 2 - 11
+    let frame = render(&config, &single_bin(5), &single_bin(5));
+    // the stripes are blocks of the strip, not of the matrix
+    for (i, pixel) in frame.iter().enumerate() {
+        let in_block = i / 16 < 8 && i % 16 >= 8;
+        assert_eq!(is_lit(pixel), in_block, "LED {i}");
+        if in_block {
+            assert_eq!(*pixel, full_color(&config, 1));
+        }
+    }
+}
+
+#[test]
+fn bars_light_the_column_of_the_channel() {
+    let config = AppConfig::bars();
+    // the third bar covers bins 5 - 8
+    let frame = render(&config, &single_bin(6), &single_bin(6));
+    assert_lit(&frame, full_color(&config, 2), |x, _| x / 2 == 2);
+}
+
+#[test]
+fn quarters_light_the_quarter_of_the_channel() {
+    let config = AppConfig::quarters();
+    // the second quarter covers bins 5 - 11, it's the top right one
+    let frame = render(&config, &single_bin(7), &single_bin(7));
+    assert_lit(&frame, full_color(&config, 1), |x, y| x >= 8 && y < 8);
+}
+
+#[test]
+fn stereo_bars_keep_the_sides_apart() {
+    let config = AppConfig::stereo_bars();
+    let silent = [0.0; SPECTRUM_LENGTH];
+
+    // the outer bars are the highs, left on the left and right on the right
+    let frame = render(&config, &single_bin(20), &silent);
+    assert_lit(&frame, full_color(&config, 0), |x, _| x / 2 == 0);
+
+    let frame = render(&config, &silent, &single_bin(20));
+    assert_lit(&frame, full_color(&config, 7), |x, _| x / 2 == 7);
+}
+
+#[test]
+fn the_vu_meter_fills_up_with_a_loud_bin() {
+    let config = AppConfig {
+        pattern: NeopixelMatrixPattern::VuMeter(AppConfig::bars().pattern.channels()[2].clone()),
+        ..AppConfig::default()
+    };
+    let frame = render(&config, &single_bin(6), &single_bin(6));
+    assert!(frame[..PANEL_LENGTH].iter().all(is_lit));
+    assert!(!frame[PANEL_LENGTH..].iter().any(is_lit));
+
+    // a bin outside of the channel doesn't count
+    let frame = render(&config, &single_bin(100), &single_bin(100));
+    assert!(!frame.iter().any(is_lit));
+}
+
+#[test]
+fn the_spectrum_shows_one_bin_per_column() {
+    let mut channel = AppConfig::bars().pattern.channels()[0].clone();
+    channel.start_index = 1;
+    channel.start_hz = None;
+    let config = AppConfig {
+        pattern: NeopixelMatrixPattern::Spectrum(channel),
+        ..AppConfig::default()
+    };
+    // bin 1 is the first column
+    let frame = render(&config, &single_bin(5), &single_bin(5));
+    assert_lit(&frame, full_color(&config, 0), |x, _| x == 4);
+
+    // the last column is bin 16, the bins above it don't show
+    let frame = render(&config, &single_bin(16), &single_bin(16));
+    assert_lit(&frame, full_color(&config, 0), |x, _| x == 15);
+    let frame = render(&config, &single_bin(17), &single_bin(17));
+    assert!(!frame.iter().any(is_lit));
+}
+
+#[test]
+fn the_spectrogram_moves_up() {
+    let mut channel = AppConfig::bars().pattern.channels()[0].clone();
+    channel.start_index = 1;
+    channel.start_hz = None;
+    let config = AppConfig {
+        pattern: NeopixelMatrixPattern::Spectrogram(channel),
+        ..AppConfig::default()
+    };
+    let mut state = RenderState::default();
+    let mut render_at = |spectrum: &Spectrum, t: f32| {
+        let levels = channel_levels(spectrum, spectrum, &config, SAMPLE_RATE_HZ, None);
+        render_frame(&levels, &config, &mut state, &mut Dither::new(), t)
+    };
+    let red = RGB8::new(255, 0, 0);
+
+    // 0.1 s at 20 rows per second, a loud bin 5 comes in as the two bottom rows of column 4
+    let frame = render_at(&single_bin(5), 0.1);
+    assert_lit(&frame, red, |x, y| x == 4 && y >= 14);
+
+    // and moves up, while the silence after it stays dark
+    let frame = render_at(&[0.0; SPECTRUM_LENGTH], 0.2);
+    assert_lit(&frame, red, |x, y| x == 4 && (12..14).contains(&y));
+}
+
+#[test]
+fn channels_reaching_past_the_spectrum_are_cut_off() {
+    // the last bar ends at bin 100 in the preset, try it beyond the spectrum too
+    for end_index in [100, SPECTRUM_LENGTH - 1, SPECTRUM_LENGTH, 1000] {
+        let mut config = AppConfig::bars2();
+        let NeopixelMatrixPattern::Bars(channels) = &mut config.pattern else {
+            panic!("bars2 should be bars");
+        };
+        channels[7].end_index = end_index;
+        let frame = render(&config, &single_bin(50), &single_bin(50));
+        if end_index >= 50 {
+            assert_lit(&frame, full_color(&config, 7), |x, _| x / 2 == 7);
+        }
+        let frame = render(
+            &config,
+            &single_bin(SPECTRUM_LENGTH - 1),
+            &single_bin(SPECTRUM_LENGTH - 1),
+        );
+        let last_bar_lit = frame[config.layout().index(15, 15)] != RGB8::default();
+        assert_eq!(
+            last_bar_lit,
+            end_index + 1 >= SPECTRUM_LENGTH - 1,
+            "end {end_index}"
+        );
+    }
+}
+
+#[test]
+fn a_split_matrix_shows_each_side_in_its_half() {
+    let config = AppConfig::bars();
+    let render_split = |left: &Spectrum, right: &Spectrum| {
+        let left = side_levels(left, &config, SAMPLE_RATE_HZ, None);
+        let right = side_levels(right, &config, SAMPLE_RATE_HZ, None);
+        let (mut state, mut right_state) = (RenderState::default(), RenderState::default());
+        render_split_frame(
+            &left,
+            &right,
+            &config,
+            &mut state,
+            &mut right_state,
+            &mut Dither::new(),
+            0.0,
+        )
+    };
+    let silent = [0.0; SPECTRUM_LENGTH];
+
+    // the third bar is one column wide now, counted outwards from the middle
+    let frame = render_split(&single_bin(6), &silent);
+    assert_lit(&frame, full_color(&config, 2), |x, _| x == 5);
+    let frame = render_split(&silent, &single_bin(6));
+    assert_lit(&frame, full_color(&config, 2), |x, _| x == 10);
+}
+
+#[test]
+fn mid_side_separates_what_both_sides_share() {
+    let mut left = [1000, -2000, i32::MAX];
+    let mut right = [1000, 2000, i32::MAX];
+    to_mid_side(&mut left, &mut right);
+    assert_eq!(left, [1000, 0, i32::MAX]);
+    assert_eq!(right, [0, -2000, 0]);
+}
@@ -0,0 +1,107 @@
+//! The serial port hands out whatever bytes arrived, frames must come out whole no matter how
+//! they were split up.
+
+use common::config::{AppConfig, CONFIG_VERSION, ConfigError};
+use common::serial::{
+    FrameReader, MAX_SERIAL_FRAME_SIZE, SerialError, SerialRequest, SerialResponse,
+};
+use common::transfer::MAX_CHUNKED_CONFIG_SIZE;
+
+fn frame(request: &SerialRequest) -> Vec<u8> {
+    let mut buf = [0; MAX_SERIAL_FRAME_SIZE];
+    request.to_frame(&mut buf).unwrap().to_vec()
+}
+
+#[test]
+fn a_write_config_request_round_trips() {
+    let cfg = AppConfig::bars();
+    let bytes = cfg.to_bytes::<MAX_CHUNKED_CONFIG_SIZE>().unwrap();
+    let mut encoded = frame(&SerialRequest::WriteConfig(&bytes));
+    assert_eq!(encoded.last(), Some(&0));
+    assert!(!encoded[..encoded.len() - 1].contains(&0));
+
+    let SerialRequest::WriteConfig(decoded) = SerialRequest::from_frame(&mut encoded).unwrap()
+    else {
+        panic!("decoded into another request");
+    };
+    assert_eq!(AppConfig::from_bytes_checked(decoded), Ok(cfg));
+}
+
+#[test]
+fn a_response_round_trips() {
+    let mut buf = [0; MAX_SERIAL_FRAME_SIZE];
+    let encoded = SerialResponse::Error(SerialError::UnsupportedVersion(7))
+        .to_frame(&mut buf)
+        .unwrap();
+    assert_eq!(
+        SerialResponse::from_frame(encoded),
+        Ok(SerialResponse::Error(SerialError::UnsupportedVersion(7)))
+    );
+}
+
+#[test]
+fn frames_split_across_reads_come_out_whole() {
+    let cfg = AppConfig::default()
+        .to_bytes::<MAX_CHUNKED_CONFIG_SIZE>()
+        .unwrap();
+    let mut stream = frame(&SerialRequest::ReadConfig);
+    stream.extend(frame(&SerialRequest::WriteConfig(&cfg)));
+    stream.extend(frame(&SerialRequest::Identify));
+
+    for read_size in [1, 3, 64, stream.len()] {
+        let mut reader = FrameReader::<MAX_SERIAL_FRAME_SIZE>::new();
+        let mut received = Vec::new();
+        for mut read in stream.chunks(read_size) {
+            while let Some(frame) = reader.feed(&mut read) {
+                let request = SerialRequest::from_frame(frame.unwrap()).unwrap();
+                received.push(format!("{request:?}"));
+            }
+        }
+        assert_eq!(
+            received,
+            [
+                format!("{:?}", SerialRequest::ReadConfig),
+                format!("{:?}", SerialRequest::WriteConfig(&cfg)),
+                format!("{:?}", SerialRequest::Identify),
+            ]
+        );
+    }
+}
+
+#[test]
+fn an_oversized_frame_is_skipped() {
+    let mut reader = FrameReader::<16>::new();
+    let mut stream = vec![1; 40];
+    stream.push(0);
+    stream.extend(frame(&SerialRequest::GetStatus));
+
+    let mut data = stream.as_slice();
+    assert_eq!(reader.feed(&mut data), Some(Err(SerialError::TooLarge)));
+    let next = reader.feed(&mut data).unwrap().unwrap();
+    assert_eq!(
+        SerialRequest::from_frame(next),
+        Ok(SerialRequest::GetStatus)
+    );
+    assert_eq!(reader.feed(&mut data), None);
+}
+
+#[test]
+fn a_config_of_another_version_is_told_apart_from_garbage() {
+    let cfg = AppConfig {
+        config_version: CONFIG_VERSION + 1,
+        ..AppConfig::default()
+    };
+    let bytes = cfg.to_bytes::<MAX_CHUNKED_CONFIG_SIZE>().unwrap();
+    assert_eq!(
+        AppConfig::from_bytes_checked(&bytes),
+        Err(ConfigError::UnsupportedVersion(CONFIG_VERSION + 1))
+    );
+
+    let bytes = AppConfig::default()
+        .to_bytes::<MAX_CHUNKED_CONFIG_SIZE>()
+        .unwrap();
+    assert_eq!(
+        AppConfig::from_bytes_checked(&bytes[..bytes.len() / 2]),
+        Err(ConfigError::Invalid)
+    );
+}
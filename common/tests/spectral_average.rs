@@ -0,0 +1,31 @@
+//! Averaged spectra come out once per group of blocks, and a single block passes straight through.
+
+use common::dsp::{SPECTRUM_LENGTH, SpectralAverage};
+
+#[test]
+fn blocks_are_averaged_in_groups() {
+    let mut average = SpectralAverage::new();
+    let quiet = [1.0; SPECTRUM_LENGTH];
+    let loud = [5.0; SPECTRUM_LENGTH];
+
+    assert_eq!(average.add(&quiet, &loud, 3), None);
+    assert_eq!(average.add(&loud, &loud, 3), None);
+    let (left, right) = average.add(&quiet, &loud, 3).unwrap();
+    assert!(left.iter().all(|&p| (p - 7.0 / 3.0).abs() < 1e-6));
+    assert!(right.iter().all(|&p| p == 5.0));
+
+    // the next group starts from scratch
+    assert_eq!(average.add(&loud, &quiet, 3), None);
+}
+
+#[test]
+fn one_or_zero_averages_pass_every_block() {
+    let mut average = SpectralAverage::new();
+    let mut spectrum = [0.0; SPECTRUM_LENGTH];
+    spectrum[7] = 42.0;
+    for averages in [1, 0] {
+        let (left, right) = average.add(&spectrum, &spectrum, averages).unwrap();
+        assert_eq!(left, spectrum);
+        assert_eq!(right, spectrum);
+    }
+}
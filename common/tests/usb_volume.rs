@@ -0,0 +1,37 @@
+//! Volume scaling of the USB audio samples saturates instead of wrapping around.
+
+use common::dsp::{MAX_VOLUME_DB, apply_volume, db_to_scale};
+
+fn sample(value: i32) -> u32 {
+    value as u32
+}
+
+#[test]
+fn boosted_extreme_samples_saturate() {
+    assert_eq!(apply_volume(sample(i32::MAX), 2.0), sample(i32::MAX));
+    assert_eq!(apply_volume(sample(i32::MIN), 2.0), sample(i32::MIN));
+    assert_eq!(
+        apply_volume(sample(i32::MAX / 2 + 1000), 4.0),
+        sample(i32::MAX)
+    );
+    assert_eq!(
+        apply_volume(sample(i32::MIN / 2 - 1000), 4.0),
+        sample(i32::MIN)
+    );
+}
+
+#[test]
+fn quiet_samples_are_scaled() {
+    assert_eq!(apply_volume(sample(1000), 1.0), sample(1000));
+    assert_eq!(apply_volume(sample(-1000), 0.5), sample(-500));
+    assert_eq!(apply_volume(sample(1000), 0.0), sample(0));
+}
+
+#[test]
+fn volume_boost_is_capped() {
+    assert!((db_to_scale(0.0) - 1.0).abs() < 1e-6);
+    assert!((db_to_scale(-20.0) - 0.1).abs() < 1e-6);
+    assert_eq!(db_to_scale(100.0), db_to_scale(MAX_VOLUME_DB));
+    assert_eq!(db_to_scale(f32::INFINITY), db_to_scale(MAX_VOLUME_DB));
+    assert_eq!(db_to_scale(f32::NAN), 1.0);
+}
@@ -0,0 +1,65 @@
+//! The precomputed window tables have to match the closed-form window functions.
+
+use common::config::WindowFunction;
+use common::dsp::{FFT_LENGTH, WindowTable, apply_window, prepare_fft_input};
+
+/// The textbook formula, in f64
+fn closed_form(window: WindowFunction, i: usize, n: usize) -> f64 {
+    let x = 2.0 * std::f64::consts::PI * i as f64 / (n - 1) as f64;
+    match window {
+        WindowFunction::None => 1.0,
+        WindowFunction::Hann => 0.5 - 0.5 * x.cos(),
+        WindowFunction::Hamming => 0.54 - 0.46 * x.cos(),
+        WindowFunction::BlackmanHarris => {
+            0.35875 - 0.48829 * x.cos() + 0.14128 * (2.0 * x).cos() - 0.01168 * (3.0 * x).cos()
+        }
+    }
+}
+
+const WINDOWS: [WindowFunction; 4] = [
+    WindowFunction::None,
+    WindowFunction::Hann,
+    WindowFunction::Hamming,
+    WindowFunction::BlackmanHarris,
+];
+
+#[test]
+fn the_tables_match_the_formulas() {
+    let mut table = WindowTable::new();
+    for len in [128, 256, FFT_LENGTH] {
+        for window in WINDOWS {
+            let coefficients = table.coefficients(window, len);
+            assert_eq!(coefficients.len(), len);
+            for (i, &c) in coefficients.iter().enumerate() {
+                let expected = closed_form(window, i, len);
+                assert!(
+                    (c as f64 - expected).abs() < 1e-6,
+                    "{window:?} over {len}, coefficient {i}: {c} instead of {expected}"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn a_table_windows_like_the_direct_calculation() {
+    let samples: Vec<f32> = (0..256).map(|i| (i as f32 * 0.37).sin()).collect();
+    let mut table = WindowTable::new();
+    for window in WINDOWS {
+        let mut direct = samples.clone();
+        apply_window(&mut direct, window);
+        let mut tabled = samples.clone();
+        table.apply(&mut tabled, window);
+        assert_eq!(direct, tabled, "{window:?}");
+
+        // zero-padded on both sides
+        let padded = prepare_fft_input(samples.iter().copied(), window, &mut table);
+        assert_eq!(&padded[128..384], &direct[..]);
+        assert!(
+            padded[..128]
+                .iter()
+                .chain(&padded[384..])
+                .all(|&s| s == 0.0)
+        );
+    }
+}
@@ -0,0 +1,65 @@
+//! The SPI bytes have to carry the color bytes in the order the LEDs expect them, 2 bits each.
+
+use common::config::{ColorOrder, LedChipset};
+use common::ws2812::{Ws2812Timing, encode_sequence};
+use rgb::RGB8;
+
+const TIMING: Ws2812Timing = Ws2812Timing::WS2812;
+
+/// Red full, green off, blue 0b00_01_10_11 so every bit pattern shows up once
+const PIXEL: RGB8 = RGB8::new(0xff, 0x00, 0x1b);
+const RED: [u8; 4] = [0xee; 4];
+const GREEN: [u8; 4] = [0x88; 4];
+const BLUE: [u8; 4] = [0b1000_1000, 0b1000_1110, 0b1110_1000, 0b1110_1110];
+
+fn encode(chipset: LedChipset, order: ColorOrder) -> Vec<u8> {
+    let mut buffer = [0x55; 512];
+    let len = encode_sequence(&mut buffer, &[PIXEL, PIXEL], &TIMING, chipset, order);
+    assert_eq!(len, TIMING.buffer_size(2, chipset));
+    buffer[..len].to_vec()
+}
+
+fn expected(components: [[u8; 4]; 3], white: Option<[u8; 4]>) -> Vec<u8> {
+    let pixel: Vec<u8> = components.into_iter().chain(white).flatten().collect();
+    let mut expected = pixel.repeat(2);
+    expected.extend([0; Ws2812Timing::WS2812.reset_bytes]);
+    expected
+}
+
+#[test]
+fn grb_is_the_wire_order_of_the_original() {
+    assert_eq!(
+        encode(LedChipset::Ws2812, ColorOrder::Grb),
+        expected([GREEN, RED, BLUE], None)
+    );
+}
+
+#[test]
+fn rgb_clones_get_red_first() {
+    assert_eq!(
+        encode(LedChipset::Ws2812, ColorOrder::Rgb),
+        expected([RED, GREEN, BLUE], None)
+    );
+}
+
+#[test]
+fn brg_clones_get_blue_first() {
+    assert_eq!(
+        encode(LedChipset::Ws2812, ColorOrder::Brg),
+        expected([BLUE, RED, GREEN], None)
+    );
+}
+
+#[test]
+fn rgbw_sends_white_last_in_any_order() {
+    // green is off, so there's no white to take over
+    let white_off = [0x88; 4];
+    assert_eq!(
+        encode(LedChipset::Sk6812Rgbw, ColorOrder::Grb),
+        expected([GREEN, RED, BLUE], Some(white_off))
+    );
+    assert_eq!(
+        encode(LedChipset::Sk6812Rgbw, ColorOrder::Brg),
+        expected([BLUE, RED, GREEN], Some(white_off))
+    );
+}
@@ -1,10 +1,29 @@
 // https://github.com/embassy-rs/trouble/blob/main/examples/esp32/src/bin/ble_bas_peripheral_sec.rs
 
-use common::config::AppConfig;
+use core::cell::RefCell;
+use core::sync::atomic::Ordering;
+
+use common::command::DeviceCommand;
+use common::config::{
+    AppConfig, CONFIG_VERSIONS_SIZE, ConfigError, MAX_CONFIG_SIZE, MAX_LEDS, TOO_MANY_LEDS_ERROR,
+    UNSUPPORTED_CONFIG_VERSION_ERROR, config_versions_to_bytes,
+};
+use common::config_presets::{PRESET_NAMES_SIZE, PRESETS, preset_names};
+use common::dsp::{ChannelLevels, LEVELS_PACKET_SIZE, levels_to_bytes};
+use common::persist::{
+    DEFAULT_DEVICE_NAME, DeviceName, MAX_DEVICE_NAME_SIZE, SAVE_SLOT_SIZE, SLOT_NAMES_SIZE,
+    device_name_from_bytes, parse_save_slot,
+};
+use common::status::{STATUS_PACKET_SIZE, TELEMETRY_PACKET_SIZE, Telemetry};
+use common::transfer::{
+    CONFIG_CONTROL_SIZE, ConfigAssembler, ConfigControl, MAX_CHUNKED_CONFIG_SIZE,
+};
 use embassy_executor::Spawner;
-use embassy_futures::join::join;
-use embassy_futures::select::select;
-use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use common::status::DeviceStatus;
+use embassy_futures::join::{join, join3, join_array};
+use embassy_futures::select::{Either, select, select3, select4};
+use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, NoopRawMutex};
+use embassy_sync::{channel::Channel, mutex::Mutex, signal::Signal, watch::Watch};
 use embassy_time::Timer;
 use esp_hal::peripherals::BT;
 use esp_radio::ble::controller::BleConnector;
@@ -12,18 +31,75 @@ use log::{error, info, warn};
 use rand_core::{CryptoRng, RngCore};
 use trouble_host::prelude::*;
 
+use crate::lights::{
+    FREEZE, LED_OVERRIDE, LEDS_ENABLED, LedOverride, NOISE_FLOOR_COMMAND, NoiseFloorCommand,
+    TELEMETRY, set_leds_enabled,
+};
+use crate::device_config;
 use crate::static_cell_init;
+use crate::stats::StatusSampler;
+use crate::storage::{StorageCommand, StorageQueue};
+use crate::util::{LOG_AVAILABLE, LOG_LINE_SIZE, pop_log_line};
 
-/// Max number of connections
-const CONNECTIONS_MAX: usize = 1;
+/// Max number of connections, so e.g. a phone and a laptop can be connected at the same time
+const CONNECTIONS_MAX: usize = 2;
 
 /// Max number of L2CAP channels.
-const L2CAP_CHANNELS_MAX: usize = 2; // Signal + att
+const L2CAP_CHANNELS_MAX: usize = 2 * CONNECTIONS_MAX; // Signal + att, per connection
 
 // GATT Server definition
-#[gatt_server]
+#[gatt_server(connections_max = CONNECTIONS_MAX)]
 struct Server {
     config_service: ConfigService,
+    preset_service: PresetService,
+    device_info: DeviceInformationService,
+}
+
+/// Capacity of the device information strings
+const DEVICE_INFO_SIZE: usize = 32;
+
+/// Standard Device Information Service (0x180A), filled in by [`run`]
+#[gatt_service(uuid = service::DEVICE_INFORMATION)]
+struct DeviceInformationService {
+    #[characteristic(uuid = characteristic::MANUFACTURER_NAME_STRING, read)]
+    manufacturer_name: heapless::Vec<u8, DEVICE_INFO_SIZE>,
+
+    #[characteristic(uuid = characteristic::FIRMWARE_REVISION_STRING, read)]
+    firmware_revision: heapless::Vec<u8, DEVICE_INFO_SIZE>,
+
+    #[characteristic(uuid = characteristic::HARDWARE_REVISION_STRING, read)]
+    hardware_revision: heapless::Vec<u8, DEVICE_INFO_SIZE>,
+}
+
+/// Switch between the built-in presets and the slots saved by the user without sending a whole
+/// config
+#[gatt_service(uuid = "71c4e9a2-3f58-4b16-a0d7-9e2b5c8f1d64")]
+struct PresetService {
+    /// The preset names separated by newlines, see [`preset_names`]
+    #[descriptor(uuid = descriptors::CHARACTERISTIC_USER_DESCRIPTION, name = "preset_names", read, value = "Preset Names")]
+    #[characteristic(uuid = "a8f3d2c5-6e19-4b74-9c0a-2d5e7b1f4c83", read)]
+    preset_names: heapless::Vec<u8, PRESET_NAMES_SIZE>,
+
+    /// Index into the names, applies and stores that preset
+    #[descriptor(uuid = descriptors::CHARACTERISTIC_USER_DESCRIPTION, name = "apply_preset", read, value = "Apply Preset")]
+    #[characteristic(uuid = "f2b6a9e1-4d73-4c08-8e5b-1a9c3f7d2e46", write)]
+    apply_preset: u8,
+
+    /// The names of the slots separated by newlines, empty for an unused slot, see
+    /// [`common::persist::slot_names_to_bytes`]
+    #[descriptor(uuid = descriptors::CHARACTERISTIC_USER_DESCRIPTION, name = "slot_names", read, value = "Slot Names")]
+    #[characteristic(uuid = "08b3a8f3-5a43-40fc-a471-69d120062154", read)]
+    slot_names: heapless::Vec<u8, SLOT_NAMES_SIZE>,
+
+    /// Slot index followed by a name, saves the running config into that slot
+    #[descriptor(uuid = descriptors::CHARACTERISTIC_USER_DESCRIPTION, name = "save_slot", read, value = "Save Slot")]
+    #[characteristic(uuid = "57396568-b6ad-4184-bdfa-dde3e9f62c13", write)]
+    save_slot: heapless::Vec<u8, SAVE_SLOT_SIZE>,
+
+    /// Slot index, applies and stores the config saved in that slot
+    #[descriptor(uuid = descriptors::CHARACTERISTIC_USER_DESCRIPTION, name = "select_slot", read, value = "Select Slot")]
+    #[characteristic(uuid = "4c9bd057-5685-488a-acca-b3807a9ddba5", write)]
+    select_slot: u8,
 }
 
 ///
@@ -34,9 +110,84 @@ struct ConfigService {
     #[characteristic(uuid = "ae1f519c-5884-489d-9cd4-4e3a0bf3d979", read, value = common::config::CONFIG_VERSION)]
     config_version: u32,
 
+    /// The config versions a write may have, see [`config_versions_to_bytes`]
+    #[descriptor(uuid = descriptors::CHARACTERISTIC_USER_DESCRIPTION, name = "config_versions", read, value = "Supported Configuration Versions")]
+    #[characteristic(uuid = "4e7a1c93-b2d5-4f68-8a0e-c3f9d6b2174a", read, value = config_versions_to_bytes())]
+    config_versions: [u8; CONFIG_VERSIONS_SIZE],
+
     #[descriptor(uuid = descriptors::CHARACTERISTIC_USER_DESCRIPTION, name = "config_data", read, value = "Configuration Data")]
-    #[characteristic(uuid = "fa57339a-e7e0-434e-9c98-93a15061e1ff", write, read)]
-    config_data: heapless::Vec<u8, 200>,
+    #[characteristic(uuid = "fa57339a-e7e0-434e-9c98-93a15061e1ff", write, read, notify)]
+    config_data: heapless::Vec<u8, MAX_CONFIG_SIZE>,
+
+    /// Begin/commit/abort of a chunked config transfer, see [`common::transfer`]
+    #[descriptor(uuid = descriptors::CHARACTERISTIC_USER_DESCRIPTION, name = "config_control", read, value = "Configuration Control")]
+    #[characteristic(uuid = "8b3f6d20-1c5e-4a79-b2d4-f07a9e31c6b8", write)]
+    config_control: heapless::Vec<u8, CONFIG_CONTROL_SIZE>,
+
+    /// The largest config a write may have, chunked or not, see
+    /// [`common::transfer::max_config_size_from_bytes`]
+    #[descriptor(uuid = descriptors::CHARACTERISTIC_USER_DESCRIPTION, name = "max_config_size", read, value = "Max Configuration Size")]
+    #[characteristic(uuid = "7d2e9b41-c6a3-4f85-9e10-b4a8d3f5c762", read, value = MAX_CHUNKED_CONFIG_SIZE as u16)]
+    max_config_size: u16,
+
+    /// Any write erases the stored config and switches back to the default one
+    #[descriptor(uuid = descriptors::CHARACTERISTIC_USER_DESCRIPTION, name = "factory_reset", read, value = "Factory Reset")]
+    #[characteristic(uuid = "d4a1f7c3-6e82-4b09-9a3d-51c8e2f06b7e", write)]
+    factory_reset: u8,
+
+    /// Per-channel levels as measured by the device, see [`common::dsp::channel_levels`]
+    #[descriptor(uuid = descriptors::CHARACTERISTIC_USER_DESCRIPTION, name = "channel_levels", read, value = "Channel Levels")]
+    #[characteristic(uuid = "3c9d7e12-4b6a-4f0e-a8d5-6e21f0b4c7a9", notify)]
+    channel_levels: heapless::Vec<u8, LEVELS_PACKET_SIZE>,
+
+    /// Counter notified every [`ALIVE_PERIOD`], so the app notices a dropped connection without polling
+    #[descriptor(uuid = descriptors::CHARACTERISTIC_USER_DESCRIPTION, name = "alive", read, value = "Alive Counter")]
+    #[characteristic(uuid = "5e0c2a41-9b7d-4c3e-8f16-2d4b7a9c0e53", notify)]
+    alive: u32,
+
+    /// Signal strength of this connection in dBm, as received by the device. Notified every
+    /// [`ALIVE_PERIOD`].
+    #[descriptor(uuid = descriptors::CHARACTERISTIC_USER_DESCRIPTION, name = "rssi", read, value = "RSSI")]
+    #[characteristic(uuid = "b5d82f3e-7c41-4e96-a0f8-3e1c6d9b2a57", notify)]
+    rssi: i8,
+
+    /// [`common::status::DeviceStatus`], notified every [`STATUS_PERIOD`]
+    #[descriptor(uuid = descriptors::CHARACTERISTIC_USER_DESCRIPTION, name = "status", read, value = "Device Status")]
+    #[characteristic(uuid = "9a6b3e07-2d14-4c85-b7f9-0e38d5a1c264", notify)]
+    status: heapless::Vec<u8, STATUS_PACKET_SIZE>,
+
+    /// [`common::status::Telemetry`] of the audio processing, notified once per second while audio
+    /// is processed
+    #[descriptor(uuid = descriptors::CHARACTERISTIC_USER_DESCRIPTION, name = "telemetry", read, value = "Telemetry")]
+    #[characteristic(uuid = "9d3f6b28-e4a1-4c57-b8d2-5a0e7c1f9b36", notify)]
+    telemetry: heapless::Vec<u8, TELEMETRY_PACKET_SIZE>,
+
+    /// Log lines as UTF-8 text, one per notification
+    #[descriptor(uuid = descriptors::CHARACTERISTIC_USER_DESCRIPTION, name = "log", read, value = "Log")]
+    #[characteristic(uuid = "2f8e6c14-7a3b-4d90-b5e2-c1d07f9a8e36", notify)]
+    log: heapless::Vec<u8, LOG_LINE_SIZE>,
+
+    /// Max log level as [`log::LevelFilter`]: 0 = off, 1 = error ... 5 = trace
+    #[descriptor(uuid = descriptors::CHARACTERISTIC_USER_DESCRIPTION, name = "log_level", read, value = "Log Level")]
+    #[characteristic(uuid = "6b1d9e45-3c27-4f8a-a0b6-8e5f2d7c1a93", write, read)]
+    log_level: u8,
+
+    /// A [`DeviceCommand`] opcode
+    #[descriptor(uuid = descriptors::CHARACTERISTIC_USER_DESCRIPTION, name = "command", read, value = "Command")]
+    #[characteristic(uuid = "c7e2a9f4-5b18-4d36-9e0c-3f6a8b1d5e27", write)]
+    command: u8,
+
+    /// The name advertised and shown in the GAP service, utf-8. A new name is advertised after
+    /// the next disconnect, the GAP service only picks it up after a reboot.
+    #[descriptor(uuid = descriptors::CHARACTERISTIC_USER_DESCRIPTION, name = "device_name", read, value = "Device Name")]
+    #[characteristic(uuid = "e3b8c1d6-92f4-4a7e-8d05-b6f1a4c9e273", write, read)]
+    device_name: heapless::Vec<u8, MAX_DEVICE_NAME_SIZE>,
+
+    /// The master switch, 0 keeps the LEDs black while the device stays connected and
+    /// configurable, 1 shows the pattern again. Survives a reboot.
+    #[descriptor(uuid = descriptors::CHARACTERISTIC_USER_DESCRIPTION, name = "enabled", read, value = "LEDs Enabled")]
+    #[characteristic(uuid = "fc578d88-ecca-4d3c-8507-be7c8c9ea8f0", write, read)]
+    enabled: bool,
 }
 
 /// Run the BLE stack.
@@ -44,7 +195,10 @@ pub async fn run<C, RNG>(
     controller: C,
     random_generator: &mut RNG,
     config_signal: &Signal<CriticalSectionRawMutex, common::config::AppConfig>,
+    levels_signal: &Signal<CriticalSectionRawMutex, ChannelLevels>,
+    storage_queue: &StorageQueue,
     initial_config: AppConfig,
+    device_name: DeviceName,
 ) where
     C: Controller,
     RNG: RngCore + CryptoRng,
@@ -67,42 +221,239 @@ pub async fn run<C, RNG>(
 
     info!("Starting advertising and GATT service");
     let server = Server::new_with_config(GapConfig::Peripheral(PeripheralConfig {
-        name: "Diskomator",
+        name: device_name.as_str(),
         appearance: &appearance::human_interface_device::GENERIC_HUMAN_INTERFACE_DEVICE,
     }))
     .unwrap();
 
+    // a config written in chunks, e.g. one for two panels, might not fit
+    let initial_bytes = initial_config.to_bytes::<MAX_CONFIG_SIZE>().unwrap_or_else(|_| {
+        warn!("Config is too large to be read back");
+        heapless::Vec::new()
+    });
+    server
+        .set(&server.config_service.config_data, &initial_bytes)
+        .unwrap();
+
+    server
+        .set(&server.config_service.log_level, &(log::max_level() as u8))
+        .unwrap();
+
+    server
+        .set(&server.config_service.enabled, &LEDS_ENABLED.load(Ordering::Relaxed))
+        .unwrap();
+
     server
         .set(
-            &server.config_service.config_data,
-            &heapless::Vec::from_slice(initial_config.to_bytes::<200>().unwrap().as_slice())
-                .unwrap(),
+            &server.preset_service.preset_names,
+            &heapless::Vec::from_slice(&preset_names()).unwrap(),
         )
         .unwrap();
 
-    let _ = join(ble_task(runner), async {
+    server
+        .set(&server.preset_service.slot_names, &device_config::slot_names())
+        .unwrap();
+
+    server
+        .set(
+            &server.config_service.device_name,
+            &heapless::Vec::from_slice(device_name.as_bytes()).unwrap(),
+        )
+        .unwrap();
+
+    let device_info = &server.device_info;
+    for (characteristic, value) in [
+        (&device_info.manufacturer_name, "Rieger Industries"),
+        (&device_info.firmware_revision, env!("CARGO_PKG_VERSION")),
+        (&device_info.hardware_revision, "ESP32-S3"),
+    ] {
+        server
+            .set(characteristic, &heapless::Vec::from_slice(value.as_bytes()).unwrap())
+            .unwrap();
+    }
+
+    let broadcast = Broadcast {
+        levels: Watch::new(),
+        status: Watch::new(),
+        telemetry: Watch::new(),
+        config_changed: Watch::new(),
+        log: Mutex::new(()),
+    };
+    // one token per free connection slot, advertising stops while there are none
+    let free_slots: Channel<NoopRawMutex, (), CONNECTIONS_MAX> = Channel::new();
+    for _ in 0..CONNECTIONS_MAX {
+        let _ = free_slots.try_send(());
+    }
+    let accepted = Channel::<NoopRawMutex, _, 1>::new();
+    let deferred = DeferredConfig::new();
+    let (server, stack, broadcast, deferred) = (&server, &stack, &broadcast, &deferred);
+    let (free_slots, accepted) = (&free_slots, &accepted);
+
+    let advertise_loop = async {
         loop {
-            match advertise("Diskomator", &mut peripheral, &server).await {
-                Ok(conn) => {
-                    // set up tasks when the connection is established to a central, so they don't run when no one is connected.
-                    let a = gatt_events_task(&server, &conn, config_signal);
-                    let b = custom_task(&server, &conn, &stack);
-                    // run until any task ends (usually because the connection has been closed),
-                    // then return to advertising state.
-                    select(a, b).await;
-                }
-                Err(e) => {
+            free_slots.receive().await;
+            // written by the app since the last connection, if it was renamed
+            let name = server.get(&server.config_service.device_name).unwrap_or_default();
+            let name = core::str::from_utf8(&name).unwrap_or(DEFAULT_DEVICE_NAME);
+            let pause_after_min = device_config::current()
+                .map_or(0, |config| config.device().advertise_pause_after_min);
+            let idle = advertise_idle(pause_after_min, free_slots);
+            match select(advertise(name, &mut peripheral, server), idle).await {
+                Either::First(Ok(conn)) => accepted.send(conn).await,
+                Either::First(Err(e)) => {
                     error!("[adv] error: {e:?}");
                     panic!("[adv] error: {:?}", e);
                 }
+                // dropping the advertiser stopped it, the radio idles until the pause is over
+                Either::Second(()) => {
+                    info!("[adv] nobody connected for {pause_after_min} min, pausing");
+                    Timer::after(ADVERTISE_PAUSE).await;
+                    let _ = free_slots.try_send(());
+                }
             }
 
             embassy_futures::yield_now().await;
         }
-    })
+    };
+
+    let slots = join_array(core::array::from_fn::<_, CONNECTIONS_MAX, _>(|slot| async move {
+        loop {
+            let conn = accepted.receive().await;
+            info!("[conn {slot}] started");
+            // set up tasks when the connection is established to a central, so they don't run when no one is connected.
+            let a = gatt_events_task(
+                server,
+                &conn,
+                config_signal,
+                storage_queue,
+                broadcast,
+                deferred,
+            );
+            let b = custom_task(server, &conn, stack);
+            let c = levels_task(server, &conn, &broadcast.levels);
+            let d = status_task(server, &conn, &broadcast.status);
+            let e = log_task(server, &conn, &broadcast.log);
+            let f = config_notify_task(server, &conn, &broadcast.config_changed);
+            let g = telemetry_task(server, &conn, &broadcast.telemetry);
+            // run until any task ends (usually because the connection has been closed),
+            // then hand the slot back to advertising.
+            select(select4(a, b, c, d), select3(e, f, g)).await;
+            info!("[conn {slot}] closed");
+            drop(conn);
+            free_slots.send(()).await;
+        }
+    }));
+
+    let _ = join(
+        ble_task(runner),
+        join3(
+            advertise_loop,
+            slots,
+            join(
+                broadcast.run(levels_signal),
+                deferred.run(config_signal, storage_queue),
+            ),
+        ),
+    )
     .await;
 }
 
+/// Writes to config_data this close together are applied as one, the latest. Dragging a slider
+/// in the app writes a config every few ms, and every config restarts the audio processing.
+/// The flash has a longer delay of its own, see `storage::SAVE_DELAY`.
+const CONFIG_APPLY_DELAY: embassy_time::Duration = embassy_time::Duration::from_millis(200);
+
+/// The configs written by the centrals, applied [`CONFIG_APPLY_DELAY`] after the first one of a
+/// burst
+struct DeferredConfig {
+    latest: RefCell<Option<AppConfig>>,
+    written: Signal<NoopRawMutex, ()>,
+}
+
+impl DeferredConfig {
+    fn new() -> Self {
+        Self {
+            latest: RefCell::new(None),
+            written: Signal::new(),
+        }
+    }
+
+    /// Apply and save `config` with the next batch, replacing the one waiting if any
+    fn defer(&self, config: AppConfig) {
+        *self.latest.borrow_mut() = Some(config);
+        self.written.signal(());
+    }
+
+    /// Forget the config waiting, before a change it mustn't overwrite later
+    fn cancel(&self) {
+        self.latest.borrow_mut().take();
+    }
+
+    /// Apply and save the config waiting right away, before something that reads the running one
+    fn flush(
+        &self,
+        config_signal: &Signal<CriticalSectionRawMutex, AppConfig>,
+        storage_queue: &StorageQueue,
+    ) {
+        let config = self.latest.borrow_mut().take();
+        if let Some(config) = config {
+            info!("[gatt] Applied new config");
+            device_config::run_and_save(config, config_signal, storage_queue);
+        }
+    }
+
+    async fn run(
+        &self,
+        config_signal: &Signal<CriticalSectionRawMutex, AppConfig>,
+        storage_queue: &StorageQueue,
+    ) {
+        loop {
+            self.written.wait().await;
+            Timer::after(CONFIG_APPLY_DELAY).await;
+            self.flush(config_signal, storage_queue);
+        }
+    }
+}
+
+/// Everything notified to all connections, so every central sees the same
+struct Broadcast {
+    levels: Watch<NoopRawMutex, ChannelLevels, CONNECTIONS_MAX>,
+    status: Watch<NoopRawMutex, DeviceStatus, CONNECTIONS_MAX>,
+    telemetry: Watch<NoopRawMutex, Telemetry, CONNECTIONS_MAX>,
+    /// Sent whenever config_data holds a new config, no matter which central wrote it
+    config_changed: Watch<NoopRawMutex, (), CONNECTIONS_MAX>,
+    /// The log buffer can only be drained once, so the log goes to one connection at a time
+    log: Mutex<NoopRawMutex, ()>,
+}
+
+impl Broadcast {
+    /// Feed the levels, status and telemetry to the connections
+    async fn run(&self, levels_signal: &Signal<CriticalSectionRawMutex, ChannelLevels>) {
+        let levels = async {
+            let sender = self.levels.sender();
+            loop {
+                sender.send(levels_signal.wait().await);
+            }
+        };
+        let status = async {
+            let sender = self.status.sender();
+            // a single sampler, it resets the counters on every sample
+            let mut sampler = StatusSampler::new();
+            loop {
+                Timer::after(STATUS_PERIOD).await;
+                sender.send(sampler.sample());
+            }
+        };
+        let telemetry = async {
+            let sender = self.telemetry.sender();
+            loop {
+                sender.send(TELEMETRY.wait().await);
+            }
+        };
+        join3(levels, status, telemetry).await;
+    }
+}
+
 /// This is a background task that is required to run forever alongside any other BLE tasks.
 ///
 /// ## Alternative
@@ -136,9 +487,23 @@ async fn gatt_events_task(
     server: &Server<'_>,
     conn: &GattConnection<'_, '_, DefaultPacketPool>,
     config_signal: &Signal<CriticalSectionRawMutex, common::config::AppConfig>,
+    storage_queue: &StorageQueue,
+    broadcast: &Broadcast,
+    deferred: &DeferredConfig,
 ) -> Result<(), Error> {
     let config_version = &server.config_service.config_version;
     let config_data = &server.config_service.config_data;
+    let config_control = &server.config_service.config_control;
+    let factory_reset = &server.config_service.factory_reset;
+    let log_level = &server.config_service.log_level;
+    let command = &server.config_service.command;
+    let device_name = &server.config_service.device_name;
+    let enabled = &server.config_service.enabled;
+    let apply_preset = &server.preset_service.apply_preset;
+    let slot_names = &server.preset_service.slot_names;
+    let save_slot = &server.preset_service.save_slot;
+    let select_slot = &server.preset_service.select_slot;
+    let mut assembler = ConfigAssembler::new();
     let reason = loop {
         match conn.next().await {
             GattConnectionEvent::Disconnected { reason } => break reason,
@@ -149,6 +514,11 @@ async fn gatt_events_task(
             //     error!("[gatt] pairing error: {:?}", err);
             // }
             GattConnectionEvent::Gatt { event } => {
+                // set when config_data holds a new config, which is then notified to every central
+                // after the reply
+                let mut config_updated = false;
+                // the reboot waits for the reply, so the central doesn't see a failed write
+                let mut reboot = false;
                 let result = match &event {
                     GattEvent::Read(event) => {
                         if event.handle() == config_version.handle {
@@ -162,32 +532,223 @@ async fn gatt_events_task(
                     }
                     GattEvent::Write(event) => {
                         info!("[gatt] Write event: {:?}", event.handle());
-                        if event.handle() == config_data.handle {
+                        if event.handle() == config_data.handle && assembler.is_active() {
+                            // part of a chunked transfer, applied on commit
+                            match assembler.push(event.data()) {
+                                Ok(()) => None,
+                                Err(e) => {
+                                    warn!("[gatt] Invalid config chunk: {e:?}");
+                                    assembler.abort();
+                                    Some(AttErrorCode::VALUE_NOT_ALLOWED)
+                                }
+                            }
+                        } else if event.handle() == config_data.handle {
                             let byte_data = event.data();
                             info!(
                                 "[gatt] Write to config_data with length {}",
                                 byte_data.len()
                             );
-                            if let Ok(new_config) = AppConfig::from_bytes(byte_data) {
-                                info!("[gatt] Valid Data in config data");
-
-                                // Signal the config update to other tasks
-                                info!("[gatt] Signaling config update");
-                                config_signal.signal(new_config);
-
-                                // Update the characteristic value
-                                server
-                                    .set(
-                                        config_data,
-                                        &heapless::Vec::from_slice(byte_data).unwrap(),
-                                    )
-                                    .unwrap();
-
-                                info!("[gatt] Updated config_data characteristic");
-                                None
-                            } else {
-                                warn!("[gatt] Invalid Data in config data");
-                                Some(AttErrorCode::VALUE_NOT_ALLOWED)
+                            match apply_config(server, deferred, byte_data) {
+                                Ok(updated) => {
+                                    config_updated = updated;
+                                    None
+                                }
+                                Err(code) => Some(code),
+                            }
+                        } else if event.handle() == config_control.handle {
+                            match ConfigControl::from_bytes(event.data()) {
+                                Ok(ConfigControl::Begin { total_len }) => {
+                                    info!("[gatt] Begin chunked config transfer of {total_len} bytes");
+                                    match assembler.begin(total_len as usize) {
+                                        Ok(()) => None,
+                                        Err(e) => {
+                                            warn!("[gatt] Can't begin config transfer: {e:?}");
+                                            Some(AttErrorCode::VALUE_NOT_ALLOWED)
+                                        }
+                                    }
+                                }
+                                Ok(ConfigControl::Commit) => match assembler.commit() {
+                                    Ok(byte_data) => match apply_config(server, deferred, byte_data) {
+                                        Ok(updated) => {
+                                            config_updated = updated;
+                                            None
+                                        }
+                                        Err(code) => Some(code),
+                                    },
+                                    Err(e) => {
+                                        warn!("[gatt] Can't commit config transfer: {e:?}");
+                                        Some(AttErrorCode::VALUE_NOT_ALLOWED)
+                                    }
+                                },
+                                Ok(ConfigControl::Abort) => {
+                                    info!("[gatt] Config transfer aborted");
+                                    assembler.abort();
+                                    None
+                                }
+                                Err(_) => {
+                                    warn!("[gatt] Invalid Data in config control");
+                                    Some(AttErrorCode::VALUE_NOT_ALLOWED)
+                                }
+                            }
+                        } else if event.handle() == factory_reset.handle {
+                            info!("[gatt] Factory reset");
+                            assembler.abort();
+                            deferred.cancel();
+                            storage_queue.send(StorageCommand::Erase);
+                            let config = AppConfig::default();
+                            server
+                                .set(
+                                    config_data,
+                                    &heapless::Vec::from_slice(&config.to_bytes::<MAX_CONFIG_SIZE>().unwrap())
+                                        .unwrap(),
+                                )
+                                .unwrap();
+                            device_config::set(config, config_signal);
+                            config_updated = true;
+                            None
+                        } else if event.handle() == log_level.handle {
+                            let level = event
+                                .data()
+                                .first()
+                                .and_then(|&level| log::LevelFilter::iter().nth(level as usize));
+                            match level {
+                                Some(level) => {
+                                    info!("[gatt] Log level set to {level}");
+                                    log::set_max_level(level);
+                                    None
+                                }
+                                None => {
+                                    warn!("[gatt] Invalid log level");
+                                    Some(AttErrorCode::VALUE_NOT_ALLOWED)
+                                }
+                            }
+                        } else if event.handle() == command.handle {
+                            let cmd = event.data().first().copied().and_then(DeviceCommand::from_byte);
+                            info!("[gatt] Command: {cmd:?}");
+                            match cmd {
+                                Some(DeviceCommand::Identify) => {
+                                    LED_OVERRIDE.signal(LedOverride::Identify);
+                                    None
+                                }
+                                Some(DeviceCommand::Reboot) => {
+                                    reboot = true;
+                                    None
+                                }
+                                Some(DeviceCommand::Demo) => {
+                                    LED_OVERRIDE.signal(LedOverride::Demo);
+                                    None
+                                }
+                                Some(DeviceCommand::TestPattern) => {
+                                    LED_OVERRIDE.signal(LedOverride::TestPattern);
+                                    None
+                                }
+                                Some(DeviceCommand::TimingPattern) => {
+                                    LED_OVERRIDE.signal(LedOverride::TimingPattern);
+                                    None
+                                }
+                                Some(DeviceCommand::CalibrateNoiseFloor) => {
+                                    NOISE_FLOOR_COMMAND.signal(NoiseFloorCommand::Calibrate);
+                                    None
+                                }
+                                Some(DeviceCommand::ClearNoiseFloor) => {
+                                    NOISE_FLOOR_COMMAND.signal(NoiseFloorCommand::Clear);
+                                    None
+                                }
+                                Some(DeviceCommand::Freeze) => {
+                                    FREEZE.store(true, Ordering::Relaxed);
+                                    None
+                                }
+                                Some(DeviceCommand::Unfreeze) => {
+                                    FREEZE.store(false, Ordering::Relaxed);
+                                    None
+                                }
+                                None => Some(AttErrorCode::VALUE_NOT_ALLOWED),
+                            }
+                        } else if event.handle() == apply_preset.handle {
+                            match event.data().first().and_then(|&i| PRESETS.get(i as usize)) {
+                                Some(preset) => {
+                                    info!("[gatt] Applying preset {}", preset.name);
+                                    assembler.abort();
+                                    // all presets fit into a single write, see the config_bytes test
+                                    let bytes = (preset.config)().to_bytes::<MAX_CONFIG_SIZE>().unwrap();
+                                    match apply_config(server, deferred, &bytes) {
+                                        Ok(updated) => {
+                                            config_updated = updated;
+                                            None
+                                        }
+                                        Err(code) => Some(code),
+                                    }
+                                }
+                                None => {
+                                    warn!("[gatt] Invalid preset index");
+                                    Some(AttErrorCode::VALUE_NOT_ALLOWED)
+                                }
+                            }
+                        } else if event.handle() == save_slot.handle {
+                            match parse_save_slot(event.data()) {
+                                Some((index, name)) => {
+                                    info!("[gatt] Saving the config into slot {index} as {name:?}");
+                                    // the config the app wrote just before. Its Save is queued
+                                    // ahead of the SaveSlot, the storage task keeps both.
+                                    deferred.flush(config_signal, storage_queue);
+                                    if device_config::save_slot(index, name, storage_queue) {
+                                        server.set(slot_names, &device_config::slot_names()).unwrap();
+                                        None
+                                    } else {
+                                        Some(AttErrorCode::UNLIKELY_ERROR)
+                                    }
+                                }
+                                None => {
+                                    warn!("[gatt] Invalid slot or slot name");
+                                    Some(AttErrorCode::VALUE_NOT_ALLOWED)
+                                }
+                            }
+                        } else if event.handle() == select_slot.handle {
+                            let index = event.data().first().map(|&i| i as usize);
+                            match index.and_then(|i| device_config::slot(i).map(|config| (i, config))) {
+                                Some((index, config)) => {
+                                    info!("[gatt] Selecting slot {index}");
+                                    assembler.abort();
+                                    // every config the device ran fits, it came in the same way
+                                    let bytes = config.to_bytes::<MAX_CHUNKED_CONFIG_SIZE>().unwrap();
+                                    match apply_config(server, deferred, &bytes) {
+                                        Ok(updated) => {
+                                            config_updated = updated;
+                                            None
+                                        }
+                                        Err(code) => Some(code),
+                                    }
+                                }
+                                None => {
+                                    warn!("[gatt] Invalid or unused slot");
+                                    Some(AttErrorCode::VALUE_NOT_ALLOWED)
+                                }
+                            }
+                        } else if event.handle() == device_name.handle {
+                            match device_name_from_bytes(event.data()) {
+                                Some(name) => {
+                                    info!("[gatt] Renamed to {name:?}, advertised after the next disconnect");
+                                    storage_queue.send(StorageCommand::SaveName(name));
+                                    None
+                                }
+                                None => {
+                                    warn!("[gatt] Invalid device name");
+                                    Some(AttErrorCode::VALUE_NOT_ALLOWED)
+                                }
+                            }
+                        } else if event.handle() == enabled.handle {
+                            match event.data() {
+                                [value @ (0 | 1)] => {
+                                    let value = *value == 1;
+                                    info!("[gatt] LEDs {}", if value { "on" } else { "off" });
+                                    set_leds_enabled(value);
+                                    storage_queue.send(StorageCommand::SaveEnabled(value));
+                                    None
+                                }
+                                _ => {
+                                    warn!("[gatt] Invalid on/off value");
+                                    Some(AttErrorCode::VALUE_NOT_ALLOWED)
+                                }
                             }
                         } else {
                             info!("[gatt] Write to unknown handle");
@@ -208,6 +769,17 @@ async fn gatt_events_task(
                     Ok(reply) => reply.send().await,
                     Err(e) => warn!("[gatt] error sending response: {e:?}"),
                 }
+
+                if reboot {
+                    info!("[gatt] Rebooting");
+                    // give the reply a moment to go out
+                    Timer::after(REBOOT_DELAY).await;
+                    esp_hal::system::software_reset();
+                }
+
+                if config_updated {
+                    broadcast.config_changed.sender().send(());
+                }
             }
             _ => {} // ignore other Gatt Connection Events
         }
@@ -217,9 +789,81 @@ async fn gatt_events_task(
     Ok(())
 }
 
+/// Delay between accepting the reboot command and rebooting
+const REBOOT_DELAY: embassy_time::Duration = embassy_time::Duration::from_millis(200);
+
+/// Decode a complete config, hand it to the other tasks (see [`DeferredConfig`]) and update the
+/// characteristic.
+///
+/// Returns whether the characteristic now holds the new config, or the error to reply with if the
+/// config is invalid or of a version the device doesn't support.
+fn apply_config(
+    server: &Server<'_>,
+    deferred: &DeferredConfig,
+    byte_data: &[u8],
+) -> Result<bool, AttErrorCode> {
+    match AppConfig::from_bytes_checked(byte_data) {
+        Ok(config) => deferred.defer(config),
+        // told apart from garbage, so the app can tell the user to update
+        Err(ConfigError::UnsupportedVersion(version)) => {
+            warn!("[gatt] Unsupported config version {version}");
+            return Err(AttErrorCode::from(UNSUPPORTED_CONFIG_VERSION_ERROR));
+        }
+        Err(ConfigError::Invalid) => {
+            warn!("[gatt] Invalid Data in config data");
+            return Err(AttErrorCode::VALUE_NOT_ALLOWED);
+        }
+        Err(ConfigError::TooManyLeds(leds)) => {
+            warn!("[gatt] Config has {leds} LEDs, the firmware is built for at most {MAX_LEDS}");
+            return Err(AttErrorCode::from(TOO_MANY_LEDS_ERROR));
+        }
+    }
+
+    // Update the characteristic value, a config sent in chunks might not fit
+    match heapless::Vec::from_slice(byte_data) {
+        Ok(value) => {
+            server.set(&server.config_service.config_data, &value).unwrap();
+            info!("[gatt] Updated config_data characteristic");
+            Ok(true)
+        }
+        Err(_) => {
+            warn!("[gatt] Config is too large to be read back");
+            Ok(false)
+        }
+    }
+}
+
+/// How long advertising pauses after [`DeviceSettings::advertise_pause_after_min`] passed
+/// without a connection
+///
+/// [`DeviceSettings::advertise_pause_after_min`]: common::config::DeviceSettings::advertise_pause_after_min
+const ADVERTISE_PAUSE: embassy_time::Duration = embassy_time::Duration::from_secs(10 * 60);
+
+/// How often [`advertise_idle`] checks whether a central is connected
+const ADVERTISE_IDLE_CHECK: embassy_time::Duration = embassy_time::Duration::from_secs(1);
+
+/// Ends once no central was connected for `minutes` while advertising, never for 0. A central
+/// connected on another slot starts the count over.
+async fn advertise_idle(minutes: u8, free_slots: &Channel<NoopRawMutex, (), CONNECTIONS_MAX>) {
+    if minutes == 0 {
+        return core::future::pending().await;
+    }
+    let pause_after = embassy_time::Duration::from_secs(u64::from(minutes) * 60);
+    let mut idle_since = embassy_time::Instant::now();
+    loop {
+        Timer::after(ADVERTISE_IDLE_CHECK).await;
+        // the advertising took one token, any other one missing is a connection
+        if free_slots.len() < CONNECTIONS_MAX - 1 {
+            idle_since = embassy_time::Instant::now();
+        } else if idle_since.elapsed() >= pause_after {
+            return;
+        }
+    }
+}
+
 /// Create an advertiser to use to connect to a BLE Central, and wait for it to connect.
 async fn advertise<'values, 'server, C: Controller>(
-    name: &'values str,
+    name: &str,
     peripheral: &mut Peripheral<'values, C, DefaultPacketPool>,
     server: &'server Server<'values>,
 ) -> Result<GattConnection<'values, 'server, DefaultPacketPool>, BleHostError<C::Error>> {
@@ -262,24 +906,169 @@ async fn advertise<'values, 'server, C: Controller>(
     Ok(conn)
 }
 
-/// Example task to use the BLE notifier interface.
-/// This task will notify the connected central of a counter value every 2 seconds.
-/// It will also read the RSSI value every 2 seconds.
+/// How often the alive counter is notified, the app expects the same period
+const ALIVE_PERIOD: embassy_time::Duration = embassy_time::Duration::from_secs(2);
+
+/// This task will notify the connected central of an incrementing counter every [`ALIVE_PERIOD`].
+/// It will also read the RSSI value at the same rate and notify it,
 /// and will stop when the connection is closed by the central or an error occurs.
 async fn custom_task<C: Controller, P: PacketPool>(
-    _server: &Server<'_>,
+    server: &Server<'_>,
     conn: &GattConnection<'_, '_, P>,
     stack: &Stack<'_, C, P>,
 ) {
+    let alive = &server.config_service.alive;
+    let rssi_characteristic = &server.config_service.rssi;
+    let mut counter: u32 = 0;
     loop {
+        // only sent if the central subscribed
+        if let Err(e) = alive.notify(conn, &counter).await {
+            info!("[custom_task] error notifying connection: {e:?}");
+            break;
+        }
+        counter = counter.wrapping_add(1);
+
         // read RSSI (Received Signal Strength Indicator) of the connection.
         if let Ok(rssi) = conn.raw().rssi(stack).await {
-            info!("[custom_task] RSSI: {rssi:?}");
+            log::debug!("[custom_task] RSSI: {rssi:?}");
+            if let Err(e) = rssi_characteristic.notify(conn, &rssi).await {
+                info!("[custom_task] error notifying RSSI: {e:?}");
+                break;
+            }
         } else {
             info!("[custom_task] error getting RSSI");
             break;
         };
-        Timer::after_secs(2).await;
+        Timer::after(ALIVE_PERIOD).await;
+    }
+}
+
+/// How often the channel levels are notified, the audio is processed a lot more often than that
+const LEVELS_PERIOD: embassy_time::Duration = embassy_time::Duration::from_millis(100);
+
+/// Notify the subscribed central of the latest channel levels, for debugging presets.
+async fn levels_task<P: PacketPool>(
+    server: &Server<'_>,
+    conn: &GattConnection<'_, '_, P>,
+    levels: &Watch<NoopRawMutex, ChannelLevels, CONNECTIONS_MAX>,
+) {
+    let channel_levels = &server.config_service.channel_levels;
+    // there's a receiver for every connection
+    let Some(mut receiver) = levels.receiver() else {
+        return;
+    };
+    loop {
+        // the watch only keeps the latest value, so everything in between is skipped
+        let levels = receiver.changed().await;
+        if let Ok(bytes) = levels_to_bytes(&levels) {
+            let value = heapless::Vec::from_slice(&bytes).unwrap();
+            // only sent if the central subscribed
+            if let Err(e) = channel_levels.notify(conn, &value).await {
+                info!("[levels_task] error notifying connection: {e:?}");
+                break;
+            }
+        }
+        Timer::after(LEVELS_PERIOD).await;
+    }
+}
+
+/// How often the status is notified, the rates in it are per second
+const STATUS_PERIOD: embassy_time::Duration = embassy_time::Duration::from_secs(1);
+
+/// Notify the subscribed central of the uptime, free heap and how busy the tasks are.
+async fn status_task<P: PacketPool>(
+    server: &Server<'_>,
+    conn: &GattConnection<'_, '_, P>,
+    samples: &Watch<NoopRawMutex, DeviceStatus, CONNECTIONS_MAX>,
+) {
+    let status = &server.config_service.status;
+    let Some(mut receiver) = samples.receiver() else {
+        return;
+    };
+    loop {
+        let sample = receiver.changed().await;
+        if let Ok(bytes) = sample.to_bytes() {
+            let value = heapless::Vec::from_slice(&bytes).unwrap();
+            // only sent if the central subscribed
+            if let Err(e) = status.notify(conn, &value).await {
+                info!("[status_task] error notifying connection: {e:?}");
+                break;
+            }
+        }
+    }
+}
+
+/// Notify the subscribed central of how long the audio processing takes.
+async fn telemetry_task<P: PacketPool>(
+    server: &Server<'_>,
+    conn: &GattConnection<'_, '_, P>,
+    samples: &Watch<NoopRawMutex, Telemetry, CONNECTIONS_MAX>,
+) {
+    let telemetry = &server.config_service.telemetry;
+    let Some(mut receiver) = samples.receiver() else {
+        return;
+    };
+    loop {
+        let sample = receiver.changed().await;
+        if let Ok(bytes) = sample.to_bytes() {
+            let value = heapless::Vec::from_slice(&bytes).unwrap();
+            // only sent if the central subscribed
+            if let Err(e) = telemetry.notify(conn, &value).await {
+                info!("[telemetry_task] error notifying connection: {e:?}");
+                break;
+            }
+        }
+    }
+}
+
+/// Notify the subscribed central of every new config, including its own writes so it sees what
+/// was actually stored.
+async fn config_notify_task<P: PacketPool>(
+    server: &Server<'_>,
+    conn: &GattConnection<'_, '_, P>,
+    config_changed: &Watch<NoopRawMutex, (), CONNECTIONS_MAX>,
+) {
+    let config_data = &server.config_service.config_data;
+    let Some(mut receiver) = config_changed.receiver() else {
+        return;
+    };
+    // only the changes from now on, the central reads the current config itself
+    let _ = receiver.try_changed();
+    loop {
+        receiver.changed().await;
+        // only sent if the central subscribed
+        if let Ok(value) = server.get(config_data)
+            && let Err(e) = config_data.notify(conn, &value).await
+        {
+            info!("[config_notify_task] error notifying connection: {e:?}");
+            break;
+        }
+    }
+}
+
+/// Time the central gets to subscribe to the log, so it also gets the lines from before it connected
+const LOG_SUBSCRIBE_DELAY: embassy_time::Duration = embassy_time::Duration::from_secs(2);
+
+/// Send the buffered log lines (see [`crate::util::MultiLogger`]) to the subscribed central.
+async fn log_task<P: PacketPool>(
+    server: &Server<'_>,
+    conn: &GattConnection<'_, '_, P>,
+    log_lock: &Mutex<NoopRawMutex, ()>,
+) {
+    let log = &server.config_service.log;
+    // held until the connection closes, then the next connection takes over
+    let _lock = log_lock.lock().await;
+    Timer::after(LOG_SUBSCRIBE_DELAY).await;
+    loop {
+        while let Some(line) = pop_log_line() {
+            let value = heapless::Vec::from_slice(line.as_bytes()).unwrap();
+            // only sent if the central subscribed
+            if let Err(e) = log.notify(conn, &value).await {
+                info!("[log_task] error notifying connection: {e:?}");
+                return;
+            }
+        }
+        LOG_AVAILABLE.wait().await;
     }
 }
 
@@ -287,7 +1076,10 @@ async fn custom_task<C: Controller, P: PacketPool>(
 async fn bluetooth_task(
     bt: BT<'static>,
     config_signal: &'static Signal<CriticalSectionRawMutex, common::config::AppConfig>,
+    levels_signal: &'static Signal<CriticalSectionRawMutex, ChannelLevels>,
+    storage_queue: &'static StorageQueue,
     initial_config: AppConfig,
+    device_name: DeviceName,
 ) {
     info!("Bluetooth Task started");
 
@@ -298,14 +1090,33 @@ async fn bluetooth_task(
     let connector = BleConnector::new(radio, bt);
     let controller: ExternalController<_, 20> = ExternalController::new(connector);
 
-    run(controller, &mut rng, config_signal, initial_config).await;
+    run(
+        controller,
+        &mut rng,
+        config_signal,
+        levels_signal,
+        storage_queue,
+        initial_config,
+        device_name,
+    )
+    .await;
 }
 
 pub fn init_bluetooth(
     spawner: &Spawner,
     bt: BT<'static>,
     config_signal: &'static Signal<CriticalSectionRawMutex, common::config::AppConfig>,
+    levels_signal: &'static Signal<CriticalSectionRawMutex, ChannelLevels>,
+    storage_queue: &'static StorageQueue,
     initial_config: AppConfig,
+    device_name: DeviceName,
 ) -> Result<(), embassy_executor::SpawnError> {
-    spawner.spawn(bluetooth_task(bt, config_signal, initial_config))
+    spawner.spawn(bluetooth_task(
+        bt,
+        config_signal,
+        levels_signal,
+        storage_queue,
+        initial_config,
+        device_name,
+    ))
 }
@@ -0,0 +1,86 @@
+//! The config the device currently runs, shared by everything that can change it (BLE, USB serial).
+
+use core::cell::RefCell;
+
+use common::config::AppConfig;
+use common::persist::{SLOT_COUNT, SLOT_NAMES_SIZE, SlotName, slot_names_to_bytes};
+use critical_section::Mutex;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+
+use crate::storage::{StorageCommand, StorageQueue};
+
+static CURRENT: Mutex<RefCell<Option<AppConfig>>> = Mutex::new(RefCell::new(None));
+
+/// The preset slots, loaded from flash by main and kept here so selecting one is instant
+static SLOTS: Mutex<RefCell<[Option<(SlotName, AppConfig)>; SLOT_COUNT]>> =
+    Mutex::new(RefCell::new([const { None }; SLOT_COUNT]));
+
+/// The last config passed to [`set`], `None` only before main loaded one
+pub fn current() -> Option<AppConfig> {
+    critical_section::with(|cs| CURRENT.borrow_ref(cs).clone())
+}
+
+/// Run `config` from now on, without saving it
+pub fn set(config: AppConfig, config_signal: &Signal<CriticalSectionRawMutex, AppConfig>) {
+    critical_section::with(|cs| *CURRENT.borrow_ref_mut(cs) = Some(config.clone()));
+    crate::lights::set_led_format(&config);
+    config_signal.signal(config);
+}
+
+/// Set the slots loaded from flash
+pub fn init_slots(slots: [Option<(SlotName, AppConfig)>; SLOT_COUNT]) {
+    critical_section::with(|cs| *SLOTS.borrow_ref_mut(cs) = slots);
+}
+
+/// The config saved in slot `index`, `None` if the slot is unused
+pub fn slot(index: usize) -> Option<AppConfig> {
+    critical_section::with(|cs| {
+        let slots = SLOTS.borrow_ref(cs);
+        slots.get(index)?.as_ref().map(|(_, config)| config.clone())
+    })
+}
+
+/// The names of the slots, as sent to clients
+pub fn slot_names() -> heapless::Vec<u8, SLOT_NAMES_SIZE> {
+    critical_section::with(|cs| {
+        let slots = SLOTS.borrow_ref(cs);
+        slot_names_to_bytes(slots.iter().map(|slot| slot.as_ref().map(|(name, _)| name.as_str())))
+    })
+}
+
+/// Save the running config into slot `index` under `name`, `false` if there is none yet
+pub fn save_slot(
+    index: usize,
+    name: SlotName,
+    storage_queue: &StorageQueue,
+) -> bool {
+    let Some(config) = current() else {
+        return false;
+    };
+    storage_queue.send(StorageCommand::SaveSlot(index, name.clone(), config.clone()));
+    critical_section::with(|cs| SLOTS.borrow_ref_mut(cs)[index] = Some((name, config)));
+    true
+}
+
+/// Run `config` from now on and save it
+pub fn run_and_save(
+    config: AppConfig,
+    config_signal: &Signal<CriticalSectionRawMutex, AppConfig>,
+    storage_queue: &StorageQueue,
+) {
+    storage_queue.send(StorageCommand::Save(config.clone()));
+    set(config, config_signal);
+}
+
+/// Decode a config sent by a client, then run and save it. BLE defers this instead, see
+/// `DeferredConfig` in bluetooth.rs.
+#[cfg(feature = "usb_serial")]
+pub fn apply(
+    bytes: &[u8],
+    config_signal: &Signal<CriticalSectionRawMutex, AppConfig>,
+    storage_queue: &StorageQueue,
+) -> Result<(), common::config::ConfigError> {
+    let config = AppConfig::from_bytes_checked(bytes)?;
+    run_and_save(config, config_signal, storage_queue);
+    Ok(())
+}
@@ -1,60 +1,416 @@
 use alloc::{boxed::Box, format};
-use common::config::AppConfig;
-use common::config::ChannelConfig;
+use core::cell::{Cell, RefCell};
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+use common::config::{
+    AUTO_INPUT_FALLBACK_MS, AppConfig, ColorOrder, DeviceSettings, FFTSize, I2sChannelSource,
+    IdlePattern, InputSource, LedChipset, PanelLayout, SAMPLE_RATE_HZ, StereoMode,
+};
+use common::dsp::{
+    ChannelLevels, Dither, FFT_LENGTH, HighPass, MATRIX_LENGTH, NoiseFloor, NoiseFloorCalibration,
+    RenderState, SPECTRUM_LENGTH, SpectralAverage, WindowTable, apply_overlay, apply_white_balance,
+    blend_frames, channel_levels, limit_power, prepare_fft_input, render_frame, render_idle,
+    render_split_frame, render_test_pattern, render_timing_pattern, side_levels, to_mid_side,
+    total_energy,
+};
+use common::status::{AudioInput, Telemetry};
+use critical_section::Mutex;
+use embassy_futures::select::{Either3, Either4, select3, select4};
 use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_sync::zerocopy_channel;
+use embassy_time::Timer;
 
-use esp_hal::Async;
 use esp_hal::{dma_buffers, i2s::master::DataFormat, time::Rate};
 
 use anyhow::{Result};
 
-use microfft::{Complex32, real::rfft_512};
+use microfft::real::rfft_512;
 use smart_leds::RGB8;
 
 use crate::error_with_location;
 use crate::static_buf;
-use crate::ws2812::WS2812_RESET_BYTES;
-use crate::ws2812::WS2812_Spi;
+use crate::stats;
+use crate::storage::{StorageCommand, StorageQueue};
+use crate::ws2812::LedDriver;
+#[cfg(not(feature = "rmt"))]
+use crate::ws2812::{WS2812_Spi, Ws2812Timing};
 
 #[cfg(feature = "fake-i2s")]
 static FAKE_AUDIO_DATA: &[u8] = include_bytes!("../../test_audio_adpcm.wav");
 
-const MATRIX_LENGTH: usize = 16 * 16;
-const MATRIX_WIDTH: usize = 16;
+/// Most LEDs the firmware drives, the buffers are sized for this many. How many are connected
+/// comes with the config, see [`PanelLayout`].
 pub const TOTAL_NEOPIXEL_LENGTH: usize = MATRIX_LENGTH;
 
-const NEOPIXEL_MATRIX_BUFFER_SIZE: usize = 12 * TOTAL_NEOPIXEL_LENGTH + WS2812_RESET_BYTES;
+/// Change this to match the LEDs, see [`Ws2812Timing`]. The RMT driver has its own timing, see
+/// [`crate::ws2812_rmt`].
+#[cfg(not(feature = "rmt"))]
+pub const NEOPIXEL_TIMING: Ws2812Timing = Ws2812Timing::WS2812;
+
+/// Large enough for RGBW, the chipset comes with the config and can change at runtime
+#[cfg(not(feature = "rmt"))]
+const NEOPIXEL_MATRIX_BUFFER_SIZE: usize =
+    NEOPIXEL_TIMING.buffer_size(TOTAL_NEOPIXEL_LENGTH, LedChipset::Sk6812Rgbw);
+
+/// The [`LedChipset`] and [`ColorOrder`] of the current config, written with the next frame
+static LED_CHIPSET: AtomicU8 = AtomicU8::new(LedChipset::Ws2812 as u8);
+static LED_COLOR_ORDER: AtomicU8 = AtomicU8::new(ColorOrder::Grb as u8);
+
+/// The [`DeviceSettings`] of the current config, the layout decides how many LEDs are written.
+/// `None` until main loaded the config.
+static DEVICE_SETTINGS: Mutex<Cell<Option<DeviceSettings>>> = Mutex::new(Cell::new(None));
+
+/// Called whenever the config changes, see [`crate::device_config::set`]
+pub fn set_led_format(config: &AppConfig) {
+    LED_CHIPSET.store(config.led_chipset as u8, Ordering::Relaxed);
+    LED_COLOR_ORDER.store(config.color_order as u8, Ordering::Relaxed);
+    critical_section::with(|cs| DEVICE_SETTINGS.borrow(cs).set(Some(config.device())));
+}
 
-#[embassy_executor::task]
-pub async fn neopixel_task(
-    spi: esp_hal::spi::master::SpiDmaBus<'static, esp_hal::Blocking>,
-    pixel_signal: &'static Signal<CriticalSectionRawMutex, Box<[RGB8; TOTAL_NEOPIXEL_LENGTH]>>,
-) -> ! {
-    log::info!("Neopixel task started");
+fn device_settings() -> DeviceSettings {
+    critical_section::with(|cs| DEVICE_SETTINGS.borrow(cs).get()).unwrap_or_default()
+}
+
+fn led_layout() -> PanelLayout {
+    device_settings().layout
+}
 
+/// Number of LEDs to write, configs with more are rejected before they get here
+fn led_count() -> usize {
+    led_layout().led_count().min(TOTAL_NEOPIXEL_LENGTH)
+}
+
+/// Apply the latest [`set_led_format`]
+fn update_led_format(neopixel: &mut impl LedDriver) {
+    let chipset = match LED_CHIPSET.load(Ordering::Relaxed) {
+        x if x == LedChipset::Sk6812Rgbw as u8 => LedChipset::Sk6812Rgbw,
+        _ => LedChipset::Ws2812,
+    };
+    let color_order = match LED_COLOR_ORDER.load(Ordering::Relaxed) {
+        x if x == ColorOrder::Rgb as u8 => ColorOrder::Rgb,
+        x if x == ColorOrder::Brg as u8 => ColorOrder::Brg,
+        _ => ColorOrder::Grb,
+    };
+    neopixel.set_format(chipset, color_order);
+}
+
+/// What main hands to the neopixel task, which turns it into the [`LedDriver`] on its own core
+#[cfg(not(feature = "rmt"))]
+pub type NeopixelOutput = esp_hal::spi::master::SpiDmaBus<'static, esp_hal::Blocking>;
+#[cfg(feature = "rmt")]
+pub type NeopixelOutput = crate::ws2812_rmt::RmtOutput;
+
+#[cfg(not(feature = "rmt"))]
+fn neopixel_driver(spi: NeopixelOutput) -> Result<impl LedDriver> {
     // Note: this moves the buffer from the stack to a static location
     let neopixel_buffer = static_buf!(u8, NEOPIXEL_MATRIX_BUFFER_SIZE);
-
-    let spi = spi.into_async();
-    let mut neopixel = WS2812_Spi {
-        spi,
+    Ok(WS2812_Spi {
+        spi: spi.into_async(),
         buffer: neopixel_buffer,
+        timing: NEOPIXEL_TIMING,
+        chipset: LedChipset::Ws2812,
+        color_order: ColorOrder::Grb,
+    })
+}
+
+#[cfg(feature = "rmt")]
+fn neopixel_driver(output: NeopixelOutput) -> Result<impl LedDriver> {
+    crate::ws2812_rmt::Ws2812Rmt::new(output)
+}
+
+/// Something shown instead of the audio pattern for a moment, afterwards the pattern fades back in
+pub enum LedOverride {
+    /// Blink white a few times
+    Identify,
+    /// The startup animation
+    Demo,
+    /// See [`render_test_pattern`]
+    TestPattern,
+    /// See [`render_timing_pattern`]
+    TimingPattern,
+}
+
+/// Interrupts the neopixel task with an [`LedOverride`]
+pub static LED_OVERRIDE: Signal<CriticalSectionRawMutex, LedOverride> = Signal::new();
+
+/// While set, the neopixel task keeps the current frame and drops new ones
+pub static FREEZE: AtomicBool = AtomicBool::new(false);
+
+/// How often a frozen neopixel task checks whether it was unfrozen
+const FREEZE_POLL_PERIOD: embassy_time::Duration = embassy_time::Duration::from_millis(20);
+
+/// The master switch, while cleared the neopixel task keeps the LEDs black and drops new frames.
+/// Change it with [`set_leds_enabled`], so the task notices right away.
+pub static LEDS_ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Wakes the neopixel task when [`LEDS_ENABLED`] changed
+static LEDS_ENABLED_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+pub fn set_leds_enabled(enabled: bool) {
+    LEDS_ENABLED.store(enabled, Ordering::Relaxed);
+    LEDS_ENABLED_CHANGED.signal(());
+}
+
+pub type Frame = [RGB8; TOTAL_NEOPIXEL_LENGTH];
+
+/// Hands the frames from the audio processing to the neopixel task without allocating: a new frame
+/// goes into the buffer that doesn't hold the latest one, then becomes the latest. The neopixel
+/// task always copies out the newest complete frame, the ones it didn't get to are dropped.
+pub struct FrameBuffers {
+    frames: [Mutex<RefCell<Frame>>; 2],
+    /// Index of the newest complete frame
+    latest: AtomicUsize,
+    /// Set while the latest frame wasn't taken yet
+    ready: Signal<CriticalSectionRawMutex, ()>,
+}
+
+const BLACK_FRAME: Frame = [RGB8::new(0, 0, 0); TOTAL_NEOPIXEL_LENGTH];
+
+impl FrameBuffers {
+    const fn new() -> Self {
+        Self {
+            frames: [const { Mutex::new(RefCell::new(BLACK_FRAME)) }; 2],
+            latest: AtomicUsize::new(0),
+            ready: Signal::new(),
+        }
+    }
+
+    /// Called by the audio processing for every rendered frame
+    pub fn publish(&self, frame: &Frame) {
+        if self.ready.signaled() {
+            stats::count_dropped_frame();
+        }
+        // the neopixel task only reads the latest, so the other one is free. Both copies happen in
+        // a critical section, so neither side can see a half written frame.
+        let next = 1 - self.latest.load(Ordering::Acquire);
+        critical_section::with(|cs| *self.frames[next].borrow_ref_mut(cs) = *frame);
+        self.latest.store(next, Ordering::Release);
+        self.ready.signal(());
+    }
+
+    /// Wait until there is a frame that wasn't taken yet
+    async fn wait(&self) {
+        self.ready.wait().await;
+    }
+
+    /// Copy the latest frame into `frame`, whether it was taken before or not
+    fn take_latest(&self, frame: &mut Frame) {
+        self.ready.reset();
+        let latest = self.latest.load(Ordering::Acquire);
+        critical_section::with(|cs| *frame = *self.frames[latest].borrow_ref(cs));
+        stats::count_new_frame();
+    }
+
+    /// Like [`FrameBuffers::take_latest`], but only if it wasn't taken yet
+    fn try_take(&self, frame: &mut Frame) -> bool {
+        if !self.ready.signaled() {
+            return false;
+        }
+        self.take_latest(frame);
+        true
+    }
+}
+
+/// From the audio processing to the neopixel task
+pub static FRAMES: FrameBuffers = FrameBuffers::new();
+
+#[embassy_executor::task]
+pub async fn neopixel_task(output: NeopixelOutput) -> ! {
+    log::info!("Neopixel task started");
+
+    let mut neopixel = match neopixel_driver(output) {
+        Ok(neopixel) => neopixel,
+        Err(e) => panic!("Failed to set up the LEDs: {e:?}"),
     };
+    update_led_format(&mut neopixel);
 
     neopixel_demo(&mut neopixel).await;
 
+    // blend from what is shown towards the latest frame, instead of jumping to it
+    let mut shown = [RGB8::new(0, 0, 0); TOTAL_NEOPIXEL_LENGTH];
+    let mut from = shown;
+    let mut target = [RGB8::new(0, 0, 0); TOTAL_NEOPIXEL_LENGTH];
+    FRAMES.wait().await;
+    FRAMES.take_latest(&mut target);
+    let mut blend_start = embassy_time::Instant::now();
+    let mut blend_duration = BLEND_DURATION;
+    let mut written_leds = led_count();
+    // fade to black once the frames stop coming, e.g. the audio stopped, instead of freezing
+    let mut last_frame = embassy_time::Instant::now();
+    let mut faded = false;
+
     loop {
-        let new_data = pixel_signal.wait().await;
+        if !LEDS_ENABLED.load(Ordering::Relaxed) {
+            log::info!("LEDs off");
+            let black = [RGB8::new(0, 0, 0); TOTAL_NEOPIXEL_LENGTH];
+            if let Err(e) = neopixel.write_async(&black[..led_count()]).await {
+                log::info!("Failed to write colors: {e:?}");
+            }
+            // the audio keeps being processed, its frames are just not shown
+            while !LEDS_ENABLED.load(Ordering::Relaxed) {
+                LEDS_ENABLED_CHANGED.wait().await;
+            }
+            log::info!("LEDs on");
+            // fade the latest frame in from black
+            shown = black;
+            from = shown;
+            FRAMES.try_take(&mut target);
+            blend_start = embassy_time::Instant::now();
+            blend_duration = BLEND_DURATION;
+        }
+
+        update_led_format(&mut neopixel);
+        let leds = led_count();
+        if leds < written_leds {
+            // a panel was taken out of the layout, don't leave it showing the last frame
+            let black = [RGB8::new(0, 0, 0); TOTAL_NEOPIXEL_LENGTH];
+            if let Err(e) = neopixel.write_async(&black[..written_leds]).await {
+                log::info!("Failed to write colors: {e:?}");
+            }
+        }
+        written_leds = leds;
+        let progress =
+            blend_start.elapsed().as_micros() as f32 / blend_duration.as_micros().max(1) as f32;
+        blend_frames(&from, &target, progress, &mut shown);
         let write_result = neopixel
-            .write_async(&new_data)
+            .write_async(&shown[..leds])
             .await
             .map_err(|err| error_with_location!("Failed to write to neopixel: {:?}", err));
-        if let Err(e) = write_result {
-            log::error!("{e:?}");
+        match write_result {
+            Ok(()) => stats::count_led_frame(),
+            Err(e) => log::error!("{e:?}"),
         }
+
+        // once the target is shown, there's nothing to do until the next one or the fade
+        let settings = device_settings();
+        let frame_period = (settings.target_fps > 0)
+            .then(|| embassy_time::Duration::from_micros(1_000_000 / settings.target_fps as u64));
+        let fade_at = (!faded && settings.fade_timeout_ms > 0).then(|| {
+            last_frame + embassy_time::Duration::from_millis(settings.fade_timeout_ms.into())
+        });
+        let next_tick = async {
+            if progress < 1.0 {
+                Timer::after(INTERPOLATION_PERIOD).await
+            } else if let Some(fade_at) = fade_at {
+                Timer::at(fade_at).await
+            } else {
+                core::future::pending().await
+            }
+        };
+        let next_event = select4(
+            FRAMES.wait(),
+            LED_OVERRIDE.wait(),
+            next_tick,
+            LEDS_ENABLED_CHANGED.wait(),
+        );
+        match next_event.await {
+            Either4::First(()) => {
+                // no faster than the target FPS, whatever comes in meanwhile replaces the frame
+                if let Some(period) = frame_period {
+                    Timer::at(last_frame + period).await;
+                }
+                if FREEZE.load(Ordering::Relaxed) {
+                    log::info!("Frame frozen");
+                    // nothing is written, so the LEDs hold whatever they show right now. Switching
+                    // them off still works.
+                    while FREEZE.load(Ordering::Relaxed) && LEDS_ENABLED.load(Ordering::Relaxed) {
+                        Timer::after(FREEZE_POLL_PERIOD).await;
+                    }
+                    log::info!("Frame unfrozen");
+                }
+                // the latest frame, not the one that woke us, a freeze or the pacing took a while
+                from = shown;
+                FRAMES.take_latest(&mut target);
+                blend_start = embassy_time::Instant::now();
+                // back right away, even in the middle of a fade
+                blend_duration = BLEND_DURATION;
+                last_frame = blend_start;
+                faded = false;
+            }
+            Either4::Second(led_override) => {
+                match led_override {
+                    LedOverride::Identify => identify_blink(&mut neopixel).await,
+                    LedOverride::Demo => neopixel_demo(&mut neopixel).await,
+                    LedOverride::TestPattern => test_pattern(&mut neopixel).await,
+                    LedOverride::TimingPattern => timing_pattern(&mut neopixel).await,
+                }
+                // fade the latest frame back in from black
+                shown = [RGB8::new(0, 0, 0); TOTAL_NEOPIXEL_LENGTH];
+                from = shown;
+                FRAMES.try_take(&mut target);
+                blend_start = embassy_time::Instant::now();
+                blend_duration = BLEND_DURATION;
+            }
+            Either4::Third(()) => {
+                if fade_at.is_some_and(|at| embassy_time::Instant::now() >= at) {
+                    log::info!("No new frame for {} ms, fading out", settings.fade_timeout_ms);
+                    from = shown;
+                    target = [RGB8::new(0, 0, 0); TOTAL_NEOPIXEL_LENGTH];
+                    blend_start = embassy_time::Instant::now();
+                    blend_duration =
+                        embassy_time::Duration::from_millis(settings.fade_duration_ms.into());
+                    faded = true;
+                }
+            }
+            // handled at the top of the loop
+            Either4::Fourth(()) => {}
+        }
+    }
+}
+
+/// Brightness of the identify blinks, full white on every LED would draw ~15 A
+const IDENTIFY_BRIGHTNESS: u8 = 48;
+
+/// How long the test pattern pixel stays on each LED, ~4 s for the whole matrix
+const TEST_PATTERN_STEP: embassy_time::Duration = embassy_time::Duration::from_millis(15);
+
+async fn test_pattern(neopixel: &mut impl LedDriver) {
+    let layout = led_layout();
+    let leds = led_count();
+    for step in 0..=leds {
+        let colors = render_test_pattern(&layout, step);
+        if let Err(e) = neopixel.write_async(&colors[..leds]).await {
+            log::info!("Failed to write colors: {e:?}");
+        }
+        Timer::after(TEST_PATTERN_STEP).await;
+    }
+    // leave the finished pattern up for a moment
+    Timer::after_secs(1).await;
+}
+
+/// How long the timing pattern is repeated, enough to set up the scope trigger
+const TIMING_PATTERN_DURATION: embassy_time::Duration = embassy_time::Duration::from_secs(10);
+
+/// Write the same frame over and over, so the scope triggers on every one of them
+async fn timing_pattern(neopixel: &mut impl LedDriver) {
+    let colors = render_timing_pattern();
+    let started = embassy_time::Instant::now();
+    while started.elapsed() < TIMING_PATTERN_DURATION {
+        if let Err(e) = neopixel.write_async(&colors[..led_count()]).await {
+            log::info!("Failed to write colors: {e:?}");
+        }
+        Timer::after(INTERPOLATION_PERIOD).await;
     }
 }
 
+async fn identify_blink(neopixel: &mut impl LedDriver) {
+    let white = RGB8::new(IDENTIFY_BRIGHTNESS, IDENTIFY_BRIGHTNESS, IDENTIFY_BRIGHTNESS);
+    for _ in 0..3 {
+        for color in [white, RGB8::new(0, 0, 0)] {
+            let colors = [color; TOTAL_NEOPIXEL_LENGTH];
+            if let Err(e) = neopixel.write_async(&colors[..led_count()]).await {
+                log::info!("Failed to write colors: {e:?}");
+            }
+            Timer::after_millis(250).await;
+        }
+    }
+}
+
+/// Time between the interpolated frames, ~120 Hz
+const INTERPOLATION_PERIOD: embassy_time::Duration = embassy_time::Duration::from_millis(8);
+
+/// How long it takes to blend from the shown frame to a new one
+const BLEND_DURATION: embassy_time::Duration = embassy_time::Duration::from_millis(30);
+
 #[embassy_executor::task]
 pub async fn config_task(_config_signal: &'static Signal<CriticalSectionRawMutex, AppConfig>) -> ! {
     loop {
@@ -69,88 +425,385 @@ pub async fn config_task(_config_signal: &'static Signal<CriticalSectionRawMutex
     }
 }
 
-async fn neopixel_demo(neopixel: &mut WS2812_Spi<'_, '_, Async, NEOPIXEL_MATRIX_BUFFER_SIZE>) {
+async fn neopixel_demo(neopixel: &mut impl LedDriver) {
     let started = esp_hal::time::Instant::now();
-    let mut i = 0;
+    let layout = led_layout();
     loop {
-        // Demo: Three sine waves cycling through the 16x16 matrix
-        // Red starts at 0, Blue at 1/3, Green at 2/3 of the cycle
-        let mut colors = [RGB8::new(0, 0, 0); TOTAL_NEOPIXEL_LENGTH];
+        let t = started.elapsed().as_millis() as f32 / 1000.0;
+        let colors = render_idle(IdlePattern::RainbowCycle, [1.0, 1.0, 1.0], &layout, t);
 
-        let time_offset = (i as f32) * 0.1; // Animation speed
+        if let Err(e) = neopixel.write_async(&colors[..led_count()]).await {
+            log::info!("Failed to write colors: {e:?}");
+        }
 
-        for led_index in 0..TOTAL_NEOPIXEL_LENGTH {
-            let position =
-                (led_index as f32) / TOTAL_NEOPIXEL_LENGTH as f32 * 2.0 * core::f32::consts::PI;
+        if started.elapsed().as_secs() > 5 {
+            break;
+        }
+    }
+}
 
-            // Three sine waves offset by 2π/3 (120 degrees)
-            let red_phase = position + time_offset;
-            let blue_phase = position + time_offset + 2.0 * core::f32::consts::PI / 3.0;
-            let green_phase = position + time_offset + 4.0 * core::f32::consts::PI / 3.0;
+/// How long the audio has to be silent before switching to the idle pattern
+const IDLE_DELAY: embassy_time::Duration = embassy_time::Duration::from_secs(3);
 
-            // Calculate sine values and convert to 0-255 range
-            let red = ((libm::sinf(red_phase)) * 255.0) as u8;
-            let green = ((libm::sinf(green_phase)) * 255.0) as u8;
-            let blue = ((libm::sinf(blue_phase)) * 255.0) as u8;
+/// How often the idle pattern is redrawn if no audio arrives at all
+const IDLE_FRAME_PERIOD: embassy_time::Duration = embassy_time::Duration::from_millis(30);
 
-            colors[led_index] = RGB8::new(red, green, blue);
+/// Tracks how long the audio has been silent
+struct IdleDetector {
+    silent_since: Option<embassy_time::Instant>,
+}
+
+impl IdleDetector {
+    fn new() -> Self {
+        Self { silent_since: None }
+    }
+
+    /// Feed the energy of the latest block, `None` if there was no audio at all.
+    /// Returns true if the idle pattern should be shown.
+    fn update(&mut self, energy: Option<f32>, config: &AppConfig) -> bool {
+        let silent = match energy {
+            Some(energy) => energy < config.idle_threshold,
+            None => true,
+        };
+        if !silent {
+            // switch back immediately
+            self.silent_since = None;
+            return false;
         }
 
-        if let Err(e) = neopixel.write_async(&colors).await {
-            log::info!("Failed to write colors: {e:?}");
+        let now = embassy_time::Instant::now();
+        let silent_since = *self.silent_since.get_or_insert(now);
+        config.idle_pattern != IdlePattern::Off && now - silent_since >= IDLE_DELAY
+    }
+}
+
+/// Render the idle pattern for the current time
+fn idle_frame(config: &AppConfig) -> Frame {
+    let t = embassy_time::Instant::now().as_millis() as f32 / 1000.0;
+    let color = config
+        .pattern
+        .channels()
+        .first()
+        .map(|ch| ch.color)
+        .unwrap_or([1.0, 1.0, 1.0]);
+    let layout = config.layout();
+    let mut colors = render_idle(config.idle_pattern, color, &layout, t);
+    let leds = layout.led_count().min(TOTAL_NEOPIXEL_LENGTH);
+    limit_power(&mut colors[..leds], config.max_milliamps);
+    apply_white_balance(&mut colors[..leds], config.device().white_balance);
+    colors
+}
+
+/// How long the spectrum is measured for a noise floor
+const NOISE_FLOOR_CALIBRATION_TIME: embassy_time::Duration = embassy_time::Duration::from_secs(2);
+
+pub enum NoiseFloorCommand {
+    /// Measure the spectrum for [`NOISE_FLOOR_CALIBRATION_TIME`] and use it as the floor
+    Calibrate,
+    Clear,
+}
+
+/// Sent to whichever audio processing task is running
+pub static NOISE_FLOOR_COMMAND: Signal<CriticalSectionRawMutex, NoiseFloorCommand> = Signal::new();
+
+/// The noise floor subtracted from the spectrum, and the calibration in progress
+struct NoiseFloorState {
+    floor: Option<Box<NoiseFloor>>,
+    calibration: Option<(embassy_time::Instant, Box<NoiseFloorCalibration>)>,
+    storage_queue: &'static StorageQueue,
+}
+
+impl NoiseFloorState {
+    /// Handle a pending [`NoiseFloorCommand`], and feed a running calibration with the spectra of
+    /// the latest block
+    fn update(&mut self, left: &[f32], right: &[f32]) {
+        match NOISE_FLOOR_COMMAND.try_take() {
+            Some(NoiseFloorCommand::Calibrate) => {
+                log::info!("Calibrating the noise floor");
+                self.calibration = Some((embassy_time::Instant::now(), Box::default()));
+            }
+            Some(NoiseFloorCommand::Clear) => {
+                log::info!("Cleared the noise floor");
+                self.floor = None;
+                self.calibration = None;
+                self.storage_queue.send(StorageCommand::EraseNoiseFloor);
+            }
+            None => {}
         }
-        i += 1;
 
-        if started.elapsed().as_secs() > 5 {
-            break;
+        let Some((started, calibration)) = self.calibration.as_mut() else {
+            return;
+        };
+        calibration.add(left);
+        calibration.add(right);
+        if started.elapsed() >= NOISE_FLOOR_CALIBRATION_TIME {
+            log::info!("Calibrated the noise floor over {} spectra", calibration.blocks());
+            let floor = Box::new(calibration.floor());
+            self.storage_queue.send(StorageCommand::SaveNoiseFloor(floor.clone()));
+            self.floor = Some(floor);
+            self.calibration = None;
+        }
+    }
+}
+
+/// How often the audio processing timing is published
+const TELEMETRY_PERIOD: embassy_time::Duration = embassy_time::Duration::from_secs(1);
+
+/// The timing of [`process_fft`], published every [`TELEMETRY_PERIOD`] for the BLE telemetry
+pub static TELEMETRY: Signal<CriticalSectionRawMutex, Telemetry> = Signal::new();
+
+/// Adds up how long the blocks take until the next [`Telemetry`] is due
+struct TelemetryMeter {
+    since: esp_hal::time::Instant,
+    blocks: u32,
+    total_us: u64,
+    max_us: u32,
+}
+
+impl TelemetryMeter {
+    fn new() -> Self {
+        Self {
+            since: esp_hal::time::Instant::now(),
+            blocks: 0,
+            total_us: 0,
+            max_us: 0,
+        }
+    }
+
+    fn record(&mut self, duration: esp_hal::time::Duration) {
+        let us = duration.as_micros() as u32;
+        self.blocks += 1;
+        self.total_us += us as u64;
+        self.max_us = self.max_us.max(us);
+
+        let elapsed = self.since.elapsed();
+        if elapsed.as_millis() >= TELEMETRY_PERIOD.as_millis() {
+            TELEMETRY.signal(Telemetry {
+                fft_fps: self.blocks as f32 * 1000.0 / elapsed.as_millis() as f32,
+                fft_avg_us: (self.total_us / self.blocks as u64) as u32,
+                fft_max_us: self.max_us,
+            });
+            *self = Self::new();
         }
     }
 }
 
-/// Audio processing task for USB audio input
+/// What the audio processing tasks carry from one block to the next
+struct ProcessingState {
+    idle: IdleDetector,
+    render: RenderState,
+    /// The right half while the matrix is split, see [`StereoMode`]
+    render_right: RenderState,
+    /// Set once a split frame took longer than a block with the largest FFT, the matrix isn't
+    /// split again until the next config
+    split_too_slow: bool,
+    noise_floor: NoiseFloorState,
+    telemetry: TelemetryMeter,
+    /// Only used while `AppConfig::dither` is on
+    dither: Dither,
+    average: SpectralAverage,
+    /// Left and right, see [`DeviceSettings::high_pass_hz`]
+    high_pass: [HighPass; 2],
+    /// Both sides use the same window
+    window: WindowTable,
+}
+
+impl ProcessingState {
+    fn new(
+        noise_floor: Option<Box<NoiseFloor>>,
+        storage_queue: &'static StorageQueue,
+    ) -> Self {
+        Self {
+            idle: IdleDetector::new(),
+            render: RenderState::default(),
+            render_right: RenderState::default(),
+            split_too_slow: false,
+            noise_floor: NoiseFloorState {
+                floor: noise_floor,
+                calibration: None,
+                storage_queue,
+            },
+            telemetry: TelemetryMeter::new(),
+            dither: Dither::new(),
+            average: SpectralAverage::new(),
+            high_pass: [HighPass::new(), HighPass::new()],
+            window: WindowTable::new(),
+        }
+    }
+
+    /// The configured [`StereoMode`], or mono if splitting turned out to be too slow
+    fn stereo_mode(&self, config: &AppConfig) -> StereoMode {
+        if self.split_too_slow {
+            StereoMode::Mono
+        } else {
+            config.device().stereo_mode
+        }
+    }
+}
+
+/// Stereo frames in an [`AudioBlock`], exactly what the FFT takes
+pub const AUDIO_BLOCK_FRAMES: usize = 256;
+
+/// Size of an [`AudioBlock`], room for [`AUDIO_BLOCK_FRAMES`] stereo frames of 32-bit samples
+pub const AUDIO_BLOCK_SIZE: usize = AUDIO_BLOCK_FRAMES * 2 * 4;
+
+/// Interleaved 32-bit samples from one of the inputs
+pub struct AudioBlock {
+    /// 1 for mono, 2 for stereo
+    pub channel_count: usize,
+    pub sample_rate: u32,
+    /// The sample value that stands for full scale, the I2S microphones differ in how many bits
+    /// they fill
+    pub full_scale: f32,
+    pub data: [u8; AUDIO_BLOCK_SIZE],
+}
+
+impl AudioBlock {
+    pub const fn new() -> Self {
+        Self {
+            channel_count: 2,
+            sample_rate: SAMPLE_RATE_HZ,
+            // what both inputs were always scaled by, USB audio keeps it
+            full_scale: (1 << 23) as f32,
+            data: [0; AUDIO_BLOCK_SIZE],
+        }
+    }
+}
+
+/// Every input fills one of two static blocks while the processing works on the other, so no
+/// audio is copied or allocated on the way. Both inputs have their own pair, switching between
+/// them is just a config change.
+pub type AudioChannel = zerocopy_channel::Channel<'static, CriticalSectionRawMutex, AudioBlock>;
+pub type AudioSender = zerocopy_channel::Sender<'static, CriticalSectionRawMutex, AudioBlock>;
+pub type AudioReceiver = zerocopy_channel::Receiver<'static, CriticalSectionRawMutex, AudioBlock>;
+
+/// Whether the I2S task should bother sending its blocks, cleared while only USB audio is used
+static I2S_WANTED: AtomicBool = AtomicBool::new(true);
+
+/// Runs the blocks of whichever input the config selects through the FFT and the pattern
 #[embassy_executor::task]
-pub async fn usb_audio_processing_task(
-    audio_buffer_receiver: &'static embassy_sync::channel::Receiver<
-        'static,
-        embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
-        Box<[u8; 2048]>,
-        4,
-    >,
-    neopixel_signal: &'static Signal<CriticalSectionRawMutex, Box<[RGB8; TOTAL_NEOPIXEL_LENGTH]>>,
+pub async fn audio_processing_task(
+    mut usb_receiver: AudioReceiver,
+    mut i2s_receiver: AudioReceiver,
     config_signal: &'static Signal<CriticalSectionRawMutex, AppConfig>,
+    levels_signal: &'static Signal<CriticalSectionRawMutex, ChannelLevels>,
+    storage_queue: &'static StorageQueue,
+    // the stored noise floor, see `NoiseFloorCommand`
+    noise_floor: Option<Box<NoiseFloor>>,
 ) -> ! {
     let mut current_config = config_signal.wait().await;
-    log::info!("USB audio processing task started");
+    let mut state = ProcessingState::new(noise_floor, storage_queue);
+    // when the host last sent anything but silence, see `InputSource::Auto`
+    let mut last_usb_audio: Option<embassy_time::Instant> = None;
+    let mut last_frame = embassy_time::Instant::now();
+    // the rate the bins of the channels were last assigned for, see `DeviceSettings::auto_bands`
+    let mut bands_sample_rate = None;
+    log::info!("Audio processing task started, input: {:?}", current_config.input_source);
 
     loop {
         // Check for config updates
         if let Some(new_config) = config_signal.try_take() {
             log::info!("Received updated config");
+            if new_config.input_source != current_config.input_source {
+                log::info!("Audio input: {:?}", new_config.input_source);
+            }
             current_config = new_config;
+            state.split_too_slow = false;
+            bands_sample_rate = None;
         }
+        I2S_WANTED.store(
+            current_config.input_source != InputSource::UsbAudio,
+            Ordering::Relaxed,
+        );
+
+        // Wait for audio data.
+        // The host stops streaming when nothing plays, so keep the idle pattern going without it.
+        let (input, block) = match select3(
+            usb_receiver.receive(),
+            i2s_receiver.receive(),
+            Timer::at(last_frame + IDLE_FRAME_PERIOD),
+        )
+        .await
+        {
+            Either3::First(block) => (AudioInput::Usb, block),
+            Either3::Second(block) => (AudioInput::I2s, block),
+            Either3::Third(_) => {
+                last_frame = embassy_time::Instant::now();
+                if state.idle.update(None, &current_config) {
+                    FRAMES.publish(&idle_frame(&current_config));
+                }
+                continue;
+            }
+        };
 
-        // Wait for audio data from USB
-        let buffer = audio_buffer_receiver.receive().await;
-
-        const SAMPLE_SIZE: usize = 4 * 2; // 2 * 32-bit stereo samples
-        const SAMPLES_TO_TAKE: usize = 256;
-
-        if buffer.len() >= SAMPLES_TO_TAKE * SAMPLE_SIZE {
-            let slice = &buffer[0..SAMPLES_TO_TAKE * SAMPLE_SIZE];
-            match process_audio_samples(slice) {
-                Ok((left_samples, _right_samples)) => {
-                    assert!(left_samples.len() == SAMPLES_TO_TAKE);
-                    let color_data = process_fft(&left_samples, &current_config);
-                    neopixel_signal.signal(color_data);
+        // 32-bit samples, interleaved if stereo
+        let slice = &block.data[0..AUDIO_BLOCK_FRAMES * 4 * block.channel_count];
+        if input == AudioInput::Usb && slice.iter().any(|&b| b != 0) {
+            last_usb_audio = Some(embassy_time::Instant::now());
+        }
+        let usb_active = last_usb_audio.is_some_and(|t| {
+            t.elapsed() < embassy_time::Duration::from_millis(AUTO_INPUT_FALLBACK_MS)
+        });
+        if current_config.input_source.accepts(input, usb_active) {
+            // only the I2S slots depend on the wiring
+            let (source, swap) = match input {
+                AudioInput::I2s => {
+                    let device = current_config.device();
+                    (device.i2s_channel_source, device.i2s_swap_channels)
+                }
+                _ => (I2sChannelSource::Both, false),
+            };
+            match process_audio_samples(slice, block.channel_count, source, swap) {
+                Ok((mut left_samples, mut right_samples)) => {
+                    assert!(left_samples.len() == AUDIO_BLOCK_FRAMES);
+                    if bands_sample_rate != Some(block.sample_rate) {
+                        current_config.apply_auto_bands(block.sample_rate, FFT_LENGTH);
+                        bands_sample_rate = Some(block.sample_rate);
+                    }
+                    if state.stereo_mode(&current_config) == StereoMode::MidSide {
+                        to_mid_side(&mut left_samples, &mut right_samples);
+                    }
+                    let color_data = process_fft(
+                        &left_samples,
+                        &right_samples,
+                        &current_config,
+                        block.sample_rate,
+                        block.full_scale,
+                        &mut state,
+                        levels_signal,
+                    );
+                    if let Some(color_data) = color_data {
+                        FRAMES.publish(&color_data);
+                    }
+                    last_frame = embassy_time::Instant::now();
+                    stats::count_audio_frame(input);
                 }
                 Err(e) => {
                     log::error!("Audio processing error: {e:?}");
                 }
             }
         }
+
+        // hand the block back to its input
+        match input {
+            AudioInput::I2s => i2s_receiver.receive_done(),
+            _ => usb_receiver.receive_done(),
+        }
     }
 }
 
+/// Fill the next free I2S block with the newest stereo samples, waits while the processing is
+/// still busy with both
+async fn send_i2s_block(audio_sender: &mut AudioSender, samples: &[u8], sample_rate: u32) {
+    let block = audio_sender.send().await;
+    block.data.copy_from_slice(samples);
+    block.channel_count = 2;
+    block.sample_rate = sample_rate;
+    // unlike the rate, the bit depth can change while running
+    block.full_scale = device_settings().i2s_bit_depth.full_scale();
+    audio_sender.send_done();
+}
+
 pub struct I2sPeripherals<'a> {
     pub i2s0: esp_hal::peripherals::I2S0<'a>,
     pub dma_ch0: esp_hal::peripherals::DMA_CH0<'a>,
@@ -433,14 +1086,12 @@ fn read_fake_i2s_samples(
     written
 }
 
+/// Reads the I2S microphone board all the time, so switching to it doesn't need a reboot
 #[embassy_executor::task]
-pub async fn audio_processing_task(
+pub async fn i2s_task(
     i2s_peripherals: I2sPeripherals<'static>,
-    neopixel_signal: &'static Signal<CriticalSectionRawMutex, Box<[RGB8; TOTAL_NEOPIXEL_LENGTH]>>,
-    config_signal: &'static Signal<CriticalSectionRawMutex, AppConfig>,
+    mut audio_sender: AudioSender,
 ) -> ! {
-    let mut current_config = config_signal.wait().await;
-
     const I2S_BUFFER_SIZE: usize = 16 * 4 * 1024;
 
     #[cfg(feature = "fake-i2s")]
@@ -480,14 +1131,8 @@ pub async fn audio_processing_task(
         let mut decode_buffer_len = 0usize;
         
         loop {
-            // Check for config updates
-            if let Some(new_config) = config_signal.try_take() {
-                log::info!("Received updated config");
-                current_config = new_config;
-            }
-            
             const SAMPLE_SIZE: usize = 4 * 2; // 2 * 24 bit stereo in 32-bit containers
-            const SAMPLES_TO_TAKE: usize = 256;
+            const SAMPLES_TO_TAKE: usize = AUDIO_BLOCK_FRAMES;
             
             // Read fake samples (handles ADPCM decoding internally)
             let bytes_read = read_fake_i2s_samples(
@@ -500,18 +1145,9 @@ pub async fn audio_processing_task(
                 &mut decode_buffer_len,
             );
             
-            if bytes_read >= SAMPLES_TO_TAKE * SAMPLE_SIZE {
+            if bytes_read >= SAMPLES_TO_TAKE * SAMPLE_SIZE && I2S_WANTED.load(Ordering::Relaxed) {
                 let slice = &i2s_buffer[0..SAMPLES_TO_TAKE * SAMPLE_SIZE];
-                match process_audio_samples(slice) {
-                    Ok((left_samples, _right_samples)) => {
-                        assert!(left_samples.len() == SAMPLES_TO_TAKE);
-                        let color_data = process_fft(&left_samples, &current_config);
-                        neopixel_signal.signal(color_data);
-                    }
-                    Err(e) => {
-                        log::error!("Audio processing error: {e:?}");
-                    }
-                }
+                send_i2s_block(&mut audio_sender, slice, SAMPLE_RATE_HZ).await;
             }
             
             // Simulate timing similar to real I2S
@@ -523,11 +1159,15 @@ pub async fn audio_processing_task(
     {
         let (mut rx_buffer, rx_descriptors, _, _) = dma_buffers!(I2S_BUFFER_SIZE, 0);
 
+        // main set the config before spawning this task, a new rate needs a restart
+        let sample_rate = device_settings().i2s_sample_rate.hz();
+        log::info!("I2S at {sample_rate} Hz");
+
         let i2s = esp_hal::i2s::master::I2s::new(
             i2s_peripherals.i2s0,
             i2s_peripherals.dma_ch0,
             esp_hal::i2s::master::Config::new_tdm_philips()
-                .with_sample_rate(Rate::from_khz(48))
+                .with_sample_rate(Rate::from_hz(sample_rate))
                 .with_data_format(DataFormat::Data32Channel32),
         )
         .unwrap()
@@ -545,12 +1185,6 @@ pub async fn audio_processing_task(
         let i2s_buffer = static_buf!(u8, I2S_BUFFER_SIZE);
 
         loop {
-            // Check for config updates
-            if let Some(new_config) = config_signal.try_take() {
-                log::info!("Received updated config");
-                current_config = new_config;
-            }
-
             let available_i2s_bytes = match transfer.available() {
                 Ok(bytes) => bytes,
                 Err(err) => {
@@ -559,7 +1193,7 @@ pub async fn audio_processing_task(
             };
 
             const SAMPLE_SIZE: usize = 4 * 2; // 2 * 24 bit stereo in 32-bit containers
-            const SAMPLES_TO_TAKE: usize = 256;
+            const SAMPLES_TO_TAKE: usize = AUDIO_BLOCK_FRAMES;
 
             if available_i2s_bytes >= SAMPLES_TO_TAKE * SAMPLE_SIZE {
                 if let Err(err) = transfer.pop(i2s_buffer) {
@@ -568,18 +1202,12 @@ pub async fn audio_processing_task(
                     continue;
                 }
 
-                // we copied over the whole DMA buffer, let's take the newest 256 samples
-                let start_index = available_i2s_bytes - (SAMPLES_TO_TAKE * SAMPLE_SIZE);
-                let slice = &i2s_buffer[start_index..available_i2s_bytes];
-                match process_audio_samples(slice) {
-                    Ok((left_samples, _right_samples)) => {
-                        assert!(left_samples.len() == SAMPLES_TO_TAKE);
-                        let color_data = process_fft(&left_samples, &current_config);
-                        neopixel_signal.signal(color_data);
-                    }
-                    Err(e) => {
-                        log::error!("Audio processing error: {e:?}");
-                    }
+                // still popped while unused, so the DMA buffer doesn't overrun
+                if I2S_WANTED.load(Ordering::Relaxed) {
+                    // we copied over the whole DMA buffer, let's take the newest 256 samples
+                    let start_index = available_i2s_bytes - (SAMPLES_TO_TAKE * SAMPLE_SIZE);
+                    let slice = &i2s_buffer[start_index..available_i2s_bytes];
+                    send_i2s_block(&mut audio_sender, slice, sample_rate).await;
                 }
             }
             embassy_futures::yield_now().await;
@@ -587,225 +1215,150 @@ pub async fn audio_processing_task(
     }
 }
 
+/// Split interleaved 32-bit samples into left and right, a mono source drives both sides.
+/// `source` and `swap` pick the sides of stereo frames, see [`I2sChannelSource::split`].
 fn process_audio_samples(
     buffer: &[u8],
+    channel_count: usize,
+    source: I2sChannelSource,
+    swap: bool,
 ) -> Result<(heapless::Vec<i32, 512>, heapless::Vec<i32, 512>)> {
-    if buffer.len() % 8 != 0 {
+    let frame_size = 4 * channel_count;
+    if buffer.len() % frame_size != 0 {
         return Err(error_with_location!(
-            "Buffer length must be a multiple of 8"
+            "Buffer length must be a multiple of {}", frame_size
         ));
     }
 
     let mut left_samples = heapless::Vec::new();
     let mut right_samples = heapless::Vec::new();
 
-    for chunk in buffer.chunks_exact(8) {
-        let left_value = i32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    for chunk in buffer.chunks_exact(frame_size) {
+        let first = i32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        let (left_value, right_value) = if channel_count > 1 {
+            let second = i32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+            source.split(swap, first, second)
+        } else {
+            (first, first)
+        };
         let _ = left_samples.push(left_value);
-
-        let right_value = i32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
         let _ = right_samples.push(right_value);
     }
 
     Ok((left_samples, right_samples))
 }
 
-fn hann_window(buffer: &mut [f32]) {
-    let n = buffer.len();
-    if n == 0 {
-        return;
-    }
-    let denom = (n - 1) as f32;
-    for (i, v) in buffer.iter_mut().enumerate() {
-        // Hann window: w[n] = 0.5 * (1 - cos(2π n / (N-1)))
-        let phase = (i as f32) / denom;
-        let w = 0.5 * (1.0 - libm::cosf(2.0 * core::f32::consts::PI * phase));
-        *v *= w;
-    }
-}
 //
 
-fn process_fft(samples: &[i32], config: &AppConfig) -> Box<[RGB8; TOTAL_NEOPIXEL_LENGTH]> {
-    // static mut LAST_PRINT: u64 = 0;
-    // static mut PROGRAM_START: Option<esp_hal::time::Instant> = None;
-    // let program_start = unsafe {
-    //     if matches!(PROGRAM_START, None) {
-    //         PROGRAM_START = Some(esp_hal::time::Instant::now());
-    //     }
-    //     PROGRAM_START.unwrap()
-    // };
-    // let function_start = program_start.elapsed().as_millis();
-
-    // Take up to 512 samples, pad with zeros if needed
-    let mut fft_input = [0.0f32; 512];
-    let sample_count = core::cmp::min(samples.len(), 512);
-    let padding_count = 512 - sample_count;
-    let left_padding = padding_count / 2;
-    let _right_padding = padding_count - left_padding;
-
-    // Normalize from signed 24-bit integer to -1.0..1.0 float and copy samples
-    const MAX_VALUE: f32 = (1 << 23) as f32;
-    for (i, &sample) in samples.iter().take(sample_count).enumerate() {
-        fft_input[left_padding + i] = (sample as f32) / MAX_VALUE;
-    }
-
-    // apply window to the populated region before FFT
-    if config.use_hann_window {
-        hann_window(&mut fft_input[left_padding..left_padding + sample_count]);
-    }
-
-    // Perform FFT
-    let spectrum = rfft_512(&mut fft_input);
+fn process_fft(
+    left_samples: &[i32],
+    right_samples: &[i32],
+    config: &AppConfig,
+    sample_rate: u32,
+    full_scale: f32,
+    state: &mut ProcessingState,
+    levels_signal: &Signal<CriticalSectionRawMutex, ChannelLevels>,
+) -> Option<Frame> {
+    let started = esp_hal::time::Instant::now();
 
-    // 16x16 panel (256 LEDs total)
-    let mut colors = [RGB8::new(0, 0, 0); MATRIX_LENGTH];
+    let [left_filter, right_filter] = &mut state.high_pass;
+    let window = &mut state.window;
+    let mut spectrum = |samples: &[i32], filter: &mut HighPass| {
+        power_spectrum(samples, full_scale, sample_rate, config, filter, window)
+    };
+    let left = spectrum(left_samples, left_filter);
+    let right = spectrum(right_samples, right_filter);
+    let averaged = state.average.add(&left, &right, config.spectral_averages);
+    let Some((left, right)) = averaged else {
+        // no frame until enough blocks are in
+        state.telemetry.record(started.elapsed());
+        return None;
+    };
 
-    fn calculate_channel(spectrum: &[Complex32], channel_cfg: &ChannelConfig) -> f32 {
-        fn norm_one_bucket(c: &Complex32, channel_cfg: &ChannelConfig) -> f32 {
-            // step 1: premult
-            let c = c.scale(channel_cfg.premult);
-            // step 2: from complex to real (squared, because that's faster)
-            let val = c.norm_sqr() * 0.001 / 255.0;
+    state.noise_floor.update(&left, &right);
 
-            // step 3: noise gate
-            if val < channel_cfg.noise_gate {
-                return 0.0;
-            }
+    // published even while idle, for the diagnostics characteristic
+    let noise_floor = state.noise_floor.floor.as_deref();
+    let levels = channel_levels(&left, &right, config, sample_rate, noise_floor);
+    levels_signal.signal(levels.clone());
 
-            // step 4: exponent
-            if channel_cfg.exponent == 1 {
-                libm::sqrtf(val)
-            } else if channel_cfg.exponent == 2 {
-                val
-            } else if channel_cfg.exponent % 2 == 0 {
-                libm::powf(val, channel_cfg.exponent as f32 / 2.0)
-            } else {
-                libm::powf(libm::sqrtf(val), channel_cfg.exponent as f32)
+    // only idle if both channels are silent, a mono source might be on either side
+    let energy = total_energy(&left).max(total_energy(&right));
+    let colors = if state.idle.update(Some(energy), config) {
+        idle_frame(config)
+    } else {
+        let t = embassy_time::Instant::now().as_millis() as f32 / 1000.0;
+        let was_limited = state.render.power_limited;
+        let mut colors = match state.stereo_mode(config) {
+            StereoMode::Mono => {
+                render_frame(&levels, config, &mut state.render, &mut state.dither, t)
             }
-        }
-
-        let buckets = spectrum[channel_cfg.start_index..=channel_cfg.end_index + 1]
-            .iter()
-            .map(|c| norm_one_bucket(c, channel_cfg));
-
-        match channel_cfg.aggregate {
-            common::config::AggregationMethod::Sum => buckets.sum::<f32>(),
-            common::config::AggregationMethod::Max => buckets.reduce(f32::max).unwrap_or(0.0),
-            common::config::AggregationMethod::Average => {
-                let len = buckets.len() as f32;
-                if len == 0.0 {
-                    0.0
-                } else {
-                    buckets.sum::<f32>() / len
+            StereoMode::LeftRightSplit | StereoMode::MidSide => {
+                let left_levels = side_levels(&left, config, sample_rate, noise_floor);
+                let right_levels = side_levels(&right, config, sample_rate, noise_floor);
+                let colors = render_split_frame(
+                    &left_levels,
+                    &right_levels,
+                    config,
+                    &mut state.render,
+                    &mut state.render_right,
+                    &mut state.dither,
+                    t,
+                );
+                // the FFT always runs for both sides, but the largest one leaves the least room
+                // for rendering twice
+                let block_us = AUDIO_BLOCK_FRAMES as u64 * 1_000_000 / sample_rate as u64;
+                if config.fft_size == FFTSize::Size512
+                    && started.elapsed().as_micros() > block_us
+                {
+                    log::warn!("Splitting the matrix takes longer than a block, back to mono");
+                    state.split_too_slow = true;
                 }
+                colors
             }
-        }
-    }
-
-    match &config.pattern {
-        common::config::NeopixelMatrixPattern::Stripes(channels) => {
-            let channel_colors = channels.clone().map(|channel| {
-                let f = calculate_channel(spectrum, &channel);
-                let clamped = f.min(1.0);
-                RGB8::new(
-                    (clamped * channel.color[0] * 255.0) as u8,
-                    (clamped * channel.color[1] * 255.0) as u8,
-                    (clamped * channel.color[2] * 255.0) as u8,
-                )
-            });
-
-            // create a striped pattern, with 8-pixel stripes
-            for i in 0..256 {
-                let row = i / 16;
-                let col = i % 16;
-
-                colors[i] = if row < 8 && col < 8 {
-                    channel_colors[0]
-                } else if row < 8 && col >= 8 {
-                    channel_colors[1]
-                } else if row >= 8 && col < 8 {
-                    channel_colors[2]
-                } else {
-                    channel_colors[3]
-                };
+        };
+        if state.render.power_limited {
+            stats::note_power_limited_frame();
+            if !was_limited {
+                log::info!("Power limit: dimming frames to {} mA", config.max_milliamps);
             }
-
-            Box::new(colors)
         }
-        common::config::NeopixelMatrixPattern::Bars(channels) => {
-            let channel_strengths = channels.clone().map(|channel| {
-                let f = calculate_channel(spectrum, &channel);
-
-                f.min(1.0)
-            });
+        let device = config.device();
+        apply_white_balance(&mut colors, device.white_balance);
+        apply_overlay(&mut colors, device.overlay, t);
+        colors
+    };
 
-            // create a bar pattern, with 2x16-pixel bars
-            for i in 0..8 {
-                let channel_cfg = &channels[i];
-                let pixels = (channel_strengths[i] * 16.0) as usize;
-                for y in 0..pixels {
-                    for x in 0..2 {
-                        let pixel_x = i * 2 + x;
-                        let pixel_y = 15 - y; // bottom to top
-                        let pixel = xy(&mut colors, pixel_x, pixel_y);
-                        *pixel = RGB8::new(
-                            (channel_strengths[i] * channel_cfg.color[0] * 255.0) as u8,
-                            (channel_strengths[i] * channel_cfg.color[1] * 255.0) as u8,
-                            (channel_strengths[i] * channel_cfg.color[2] * 255.0) as u8,
-                        );
-                    }
-                }
-            }
+    state.telemetry.record(started.elapsed());
+    Some(colors)
+}
 
-            Box::new(colors)
-        }
-        common::config::NeopixelMatrixPattern::Quarters(channels) => {
-            let channel_colors = channels.clone().map(|channel| {
-                let f = calculate_channel(spectrum, &channel);
-                let clamped = f.min(1.0);
-                RGB8::new(
-                    (clamped * channel.color[0] * 255.0) as u8,
-                    (clamped * channel.color[1] * 255.0) as u8,
-                    (clamped * channel.color[2] * 255.0) as u8,
-                )
-            });
+/// FFT one audio channel and return the squared magnitude of each bin. `full_scale` is the
+/// sample value that becomes 1.0, `high_pass` runs before the window.
+fn power_spectrum(
+    samples: &[i32],
+    full_scale: f32,
+    sample_rate: u32,
+    config: &AppConfig,
+    high_pass: &mut HighPass,
+    window: &mut WindowTable,
+) -> [f32; SPECTRUM_LENGTH] {
+    // Normalize to -1.0..1.0 float, take out the DC offset, pad and window
+    let mut normalized = [0.0f32; FFT_LENGTH];
+    let normalized = &mut normalized[..samples.len().min(FFT_LENGTH)];
+    for (n, &sample) in normalized.iter_mut().zip(samples) {
+        *n = sample as f32 / full_scale;
+    }
+    high_pass.apply(normalized, config.device().high_pass_hz, sample_rate);
+    let mut fft_input =
+        prepare_fft_input(normalized.iter().copied(), config.window_function, window);
 
-            // create a quartered pattern
-            for i in 0..4 {
-                for y in 0..8 {
-                    for x in 0..8 {
-                        let (offset_x, offset_y) = match i {
-                            0 => (0, 0), // Top-left
-                            1 => (8, 0), // Top-right
-                            2 => (0, 8), // Bottom-left
-                            3 => (8, 8), // Bottom-right
-                            _ => (0, 0), // Should not happen
-                        };
-                        let pixel_x = offset_x + x;
-                        let pixel_y = offset_y + y;
-                        let pixel = xy(&mut colors, pixel_x, pixel_y);
-                        *pixel = channel_colors[i];
-                    }
-                }
-            }
+    // Perform FFT
+    let spectrum = rfft_512(&mut fft_input);
 
-            Box::new(colors)
-        }
+    let mut power_spectrum = [0.0f32; SPECTRUM_LENGTH];
+    for (p, c) in power_spectrum.iter_mut().zip(spectrum.iter()) {
+        *p = c.norm_sqr();
     }
-}
-
-/// Convert from x,y coordinates to the linear NeoPixel index
-/// The XY coordinates are 0-indexed, with (0,0) at the top-left
-/// x goes right, y goes down
-fn xy<T>(arr: &mut [T], x: usize, y: usize) -> &mut T {
-    // the strip starts at top left, goes down, then one right and up, one right and down, ...
-    // so even columns go down, odd columns go up.
-    let index = if x % 2 == 0 {
-        // Even columns go down
-        (x * MATRIX_WIDTH) + y
-    } else {
-        // Odd columns go up
-        (x * MATRIX_WIDTH) + (MATRIX_WIDTH - 1 - y)
-    };
-    &mut arr[index]
-}
+    power_spectrum
+}
\ No newline at end of file
@@ -5,7 +5,7 @@
 //#![feature(generic_const_exprs)]
 
 extern crate alloc;
-use alloc::{boxed::Box, format};
+use alloc::format;
 use embassy_executor::Spawner;
 use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
 use esp_hal_embassy::Executor;
@@ -15,13 +15,16 @@ use core::{panic::PanicInfo, ptr::addr_of_mut};
 
 use esp_hal::{
     delay::Delay,
-    dma::{DmaRxBuf, DmaTxBuf},
-    dma_buffers,
     rng::TrngSource,
     system::{CpuControl, Stack},
-    time::Rate,
     timer::{AnyTimer, timg::TimerGroup},
 };
+#[cfg(not(feature = "rmt"))]
+use esp_hal::{
+    dma::{DmaRxBuf, DmaTxBuf},
+    dma_buffers,
+    time::Rate,
+};
 
 use anyhow::{Result};
 
@@ -29,16 +32,25 @@ use esp_hal::peripherals::Peripherals;
 
 use static_cell::StaticCell;
 
-use smart_leds::RGB8;
-
 use rtt_target::{ChannelMode, rprintln, rtt_init_print};
 
 mod bluetooth;
+mod device_config;
 mod lights;
+#[cfg(feature = "serial_console")]
+mod serial_console;
+mod stats;
+mod storage;
 pub mod util;
 mod usb_audio;
+#[cfg(feature = "usb_mic")]
+mod usb_mic;
+#[cfg(feature = "usb_serial")]
+mod usb_serial;
 
 mod ws2812;
+#[cfg(feature = "rmt")]
+mod ws2812_rmt;
 
 use lights::*;
 
@@ -111,13 +123,35 @@ async fn _main(spawner: Spawner) -> Result<!> {
         StaticCell::new();
     let config_signal = &*CONFIG_SIGNAL.init(Signal::new());
 
-    let initial_config = common::config::AppConfig::default();
-    config_signal.signal(initial_config.clone());
+    // the config saved by the last BLE write, if it's still valid for this firmware.
+    // Without flash access the device still works, it just doesn't remember the config.
+    let mut config_storage = storage::ConfigStorage::new(peripherals.FLASH)
+        .inspect_err(|e| log::error!("[main] Config storage not available: {e:?}"))
+        .ok();
+    let initial_config = config_storage.as_mut().and_then(|s| s.load()).unwrap_or_else(|| {
+        info!("[main] No stored config, using the default");
+        common::config::AppConfig::default()
+    });
+    device_config::set(initial_config.clone(), config_signal);
+    let device_name = config_storage
+        .as_mut()
+        .and_then(|s| s.load_name())
+        .unwrap_or_else(|| common::persist::DEFAULT_DEVICE_NAME.into());
+    let noise_floor = config_storage.as_mut().and_then(|s| s.load_noise_floor());
+    if let Some(enabled) = config_storage.as_mut().and_then(|s| s.load_enabled()) {
+        lights::set_leds_enabled(enabled);
+    }
+    if let Some(storage) = config_storage.as_mut() {
+        device_config::init_slots(storage.load_slots());
+    }
+
+    static STORAGE_QUEUE: StaticCell<storage::StorageQueue> = StaticCell::new();
+    let storage_queue = &*STORAGE_QUEUE.init(storage::StorageQueue::new());
 
-    static NEOPIXEL_SIGNAL: StaticCell<
-        Signal<CriticalSectionRawMutex, Box<[RGB8; TOTAL_NEOPIXEL_LENGTH]>>,
-    > = StaticCell::new();
-    let neopixel_signal = &*NEOPIXEL_SIGNAL.init(Signal::new());
+    // per-channel levels, for the diagnostics characteristic
+    static LEVELS_SIGNAL: StaticCell<Signal<CriticalSectionRawMutex, common::dsp::ChannelLevels>> =
+        StaticCell::new();
+    let levels_signal = &*LEVELS_SIGNAL.init(Signal::new());
 
     // Initialize RNG for Bluetooth and enable esp_preempt
     let _rng_source = TrngSource::new(peripherals.RNG, peripherals.ADC1);
@@ -129,9 +163,35 @@ async fn _main(spawner: Spawner) -> Result<!> {
         .spawn(config_task(config_signal))
         .map_err(|e| error_with_location!("Failed to spawn config task: {:?}", e))?;
 
+    if let Some(config_storage) = config_storage {
+        spawner
+            .spawn(storage::storage_task(config_storage, storage_queue))
+            .map_err(|e| error_with_location!("Failed to spawn storage task: {:?}", e))?;
+    }
+
+    // Text commands over UART0, whose TX the log already uses
+    #[cfg(feature = "serial_console")]
+    {
+        let rx = esp_hal::uart::UartRx::new(peripherals.UART0, esp_hal::uart::Config::default())
+            .map_err(|e| error_with_location!("Failed to set up the console UART: {:?}", e))?
+            .with_rx(peripherals.GPIO44)
+            .into_async();
+        spawner
+            .spawn(serial_console::serial_console_task(rx, config_signal, storage_queue))
+            .map_err(|e| error_with_location!("Failed to spawn serial console task: {:?}", e))?;
+    }
+
     // Start Bluetooth task
     info!("[main] Starting Bluetooth task ...");
-    bluetooth::init_bluetooth(&spawner, peripherals.BT, config_signal, initial_config)
+    bluetooth::init_bluetooth(
+        &spawner,
+        peripherals.BT,
+        config_signal,
+        levels_signal,
+        storage_queue,
+        initial_config,
+        device_name,
+    )
         .map_err(|e| error_with_location!("Failed to start Bluetooth task: {:?}", e))?;
     for _ in 0..10 {
         embassy_futures::yield_now().await;
@@ -139,24 +199,33 @@ async fn _main(spawner: Spawner) -> Result<!> {
     info!("[main] Bluetooth task started");
 
     // Neopixel setup:
-    //  DMA TX buffer size:
-    //    256 LEDs * 3 bytes (r g b) * 4 (4 SPI bytes are used for one ws2812 byte) + 1 or 2 reset sequences of 140 bytes each
-    //    2 * 140 + 256 * 3 * 4 = 3352
-    //    ==> round up to 4 kB
-    let (rx_buffer, rx_descriptors, tx_buffer, tx_descriptors) = dma_buffers!(1, 4 * 1024);
-    let dma_rx_buf = DmaRxBuf::new(rx_descriptors, rx_buffer)
-        .map_err(|err| error_with_location!("Failed to create DMA RX buffer: {:?}", err))?;
-    let dma_tx_buf = DmaTxBuf::new(tx_descriptors, tx_buffer)
-        .map_err(|err| error_with_location!("Failed to create DMA TX buffer: {:?}", err))?;
-
-    let spi: esp_hal::spi::master::SpiDmaBus<'_, esp_hal::Blocking> =
+    //  DMA TX buffer size, a whole frame has to fit so it goes out without gaps:
+    //    512 LEDs (two panels) * 4 bytes (r g b w) * 4 (4 SPI bytes are used for one ws2812 byte) + 1 or 2 reset sequences of 140 bytes each
+    //    2 * 140 + 512 * 4 * 4 = 8472
+    //    ==> round up to 9 kB, which also leaves room for the longer reset of Ws2812Timing::WS2812B_V5
+    #[cfg(not(feature = "rmt"))]
+    let neopixel_output: NeopixelOutput = {
+        let (rx_buffer, rx_descriptors, tx_buffer, tx_descriptors) = dma_buffers!(1, 9 * 1024);
+        let dma_rx_buf = DmaRxBuf::new(rx_descriptors, rx_buffer)
+            .map_err(|err| error_with_location!("Failed to create DMA RX buffer: {:?}", err))?;
+        let dma_tx_buf = DmaTxBuf::new(tx_descriptors, tx_buffer)
+            .map_err(|err| error_with_location!("Failed to create DMA TX buffer: {:?}", err))?;
+
         esp_hal::spi::master::Spi::new(
             peripherals.SPI2,
-            esp_hal::spi::master::Config::default().with_frequency(Rate::from_khz(4_500)),
+            esp_hal::spi::master::Config::default()
+                .with_frequency(Rate::from_khz(lights::NEOPIXEL_TIMING.spi_khz)),
         )?
         .with_mosi(neopixel_data_pin)
         .with_dma(peripherals.DMA_CH1)
-        .with_buffers(dma_rx_buf, dma_tx_buf);
+        .with_buffers(dma_rx_buf, dma_tx_buf)
+    };
+    // SPI2 and DMA_CH1 stay free
+    #[cfg(feature = "rmt")]
+    let neopixel_output = ws2812_rmt::RmtOutput {
+        rmt: peripherals.RMT,
+        pin: neopixel_data_pin.into(),
+    };
 
     // // UART setup
     // let config = esp_hal::uart::Config::default().with_baudrate(115200);
@@ -164,67 +233,46 @@ async fn _main(spawner: Spawner) -> Result<!> {
     //     .with_rx(peripherals.GPIO17)
     //     .with_tx(peripherals.GPIO8);
 
-    // Choose between USB Audio or I2S input
-    const USE_USB_AUDIO: bool = true;
-
-    // I2S peripherals declaration (needed for both branches)
-    let i2s_peripherals = if !USE_USB_AUDIO {
-        Some(I2sPeripherals {
-            i2s0: peripherals.I2S0,
-            dma_ch0: peripherals.DMA_CH0,
-            gpio0: peripherals.GPIO0,
-            gpio4: peripherals.GPIO4,
-            gpio6: peripherals.GPIO6,
-            gpio5: peripherals.GPIO5,
-        })
-    } else {
-        None
+    // Each input fills its own pair of static blocks, the config picks which of them drives the
+    // lights
+    static USB_AUDIO_BLOCKS: StaticCell<[AudioBlock; 2]> = StaticCell::new();
+    static USB_AUDIO_CHANNEL: StaticCell<AudioChannel> = StaticCell::new();
+    let usb_audio_blocks = USB_AUDIO_BLOCKS.init([AudioBlock::new(), AudioBlock::new()]);
+    let (usb_audio_sender, usb_audio_receiver) = USB_AUDIO_CHANNEL
+        .init(embassy_sync::zerocopy_channel::Channel::new(usb_audio_blocks))
+        .split();
+
+    static I2S_AUDIO_BLOCKS: StaticCell<[AudioBlock; 2]> = StaticCell::new();
+    static I2S_AUDIO_CHANNEL: StaticCell<AudioChannel> = StaticCell::new();
+    let i2s_audio_blocks = I2S_AUDIO_BLOCKS.init([AudioBlock::new(), AudioBlock::new()]);
+    let (i2s_audio_sender, i2s_audio_receiver) = I2S_AUDIO_CHANNEL
+        .init(embassy_sync::zerocopy_channel::Channel::new(i2s_audio_blocks))
+        .split();
+
+    // USB Audio setup
+    log::info!("[main] Initializing USB Audio...");
+    // ESP32-S3 USB OTG uses GPIO19 and GPIO20
+    usb_audio::init_usb_audio(
+        &spawner,
+        peripherals.USB0,
+        peripherals.GPIO20,
+        peripherals.GPIO19,
+        usb_audio_sender,
+        config_signal,
+        storage_queue,
+    )
+    .map_err(|e| error_with_location!("Failed to initialize USB audio: {:?}", e))?;
+    log::info!("[main] USB Audio initialized");
+
+    let i2s_peripherals = I2sPeripherals {
+        i2s0: peripherals.I2S0,
+        dma_ch0: peripherals.DMA_CH0,
+        gpio0: peripherals.GPIO0,
+        gpio4: peripherals.GPIO4,
+        gpio6: peripherals.GPIO6,
+        gpio5: peripherals.GPIO5,
     };
 
-    if USE_USB_AUDIO {
-        // USB Audio setup
-        log::info!("[main] Initializing USB Audio...");
-        
-        // Create a static channel for passing audio data from USB to audio processing
-        use embassy_sync::channel;
-        type AudioChannel = channel::Channel<
-            embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
-            Box<[u8; 2048]>,
-            4,
-        >;
-        type AudioSender<'a> = channel::Sender<'a, embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex, Box<[u8; 2048]>, 4>;
-        type AudioReceiver<'a> = channel::Receiver<'a, embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex, Box<[u8; 2048]>, 4>;
-        
-        static AUDIO_BUFFER_CHANNEL: StaticCell<AudioChannel> = StaticCell::new();
-        static AUDIO_SENDER: StaticCell<AudioSender<'static>> = StaticCell::new();
-        static AUDIO_RECEIVER: StaticCell<AudioReceiver<'static>> = StaticCell::new();
-        
-        let audio_channel = &*AUDIO_BUFFER_CHANNEL.init(channel::Channel::new());
-        let audio_sender = AUDIO_SENDER.init(audio_channel.sender());
-        let audio_receiver = AUDIO_RECEIVER.init(audio_channel.receiver());
-        
-        // ESP32-S3 USB OTG uses GPIO19 and GPIO20
-        usb_audio::init_usb_audio(
-            &spawner,
-            peripherals.USB0,
-            peripherals.GPIO20,
-            peripherals.GPIO19,
-            audio_sender,
-        )
-        .map_err(|e| error_with_location!("Failed to initialize USB audio: {:?}", e))?;
-        
-        // Start USB audio processing task
-        spawner
-            .spawn(lights::usb_audio_processing_task(
-                audio_receiver,
-                neopixel_signal,
-                config_signal,
-            ))
-            .map_err(|e| error_with_location!("Failed to spawn USB audio processing task: {:?}", e))?;
-        
-        log::info!("[main] USB Audio initialized");
-    }
-
     let mut cpu_control = CpuControl::new(peripherals.CPU_CTRL);
     let _guard = cpu_control
         .start_app_core(unsafe { &mut *addr_of_mut!(APP_CORE_STACK) }, move || {
@@ -232,18 +280,20 @@ async fn _main(spawner: Spawner) -> Result<!> {
             let executor = EXECUTOR.init(Executor::new());
             executor.run(|spawner| {
                 // start Neopixel task
-                spawner.spawn(neopixel_task(spi, neopixel_signal)).ok();
-
-                // Start I2S audio processing task if not using USB audio
-                if let Some(peripherals) = i2s_peripherals {
-                    spawner
-                        .spawn(audio_processing_task(
-                            peripherals,
-                            neopixel_signal,
-                            config_signal,
-                        ))
-                        .ok();
-                }
+                spawner.spawn(neopixel_task(neopixel_output)).ok();
+
+                spawner.spawn(i2s_task(i2s_peripherals, i2s_audio_sender)).ok();
+
+                spawner
+                    .spawn(audio_processing_task(
+                        usb_audio_receiver,
+                        i2s_audio_receiver,
+                        config_signal,
+                        levels_signal,
+                        storage_queue,
+                        noise_floor,
+                    ))
+                    .ok();
             });
         })
         .unwrap();
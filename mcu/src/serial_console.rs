@@ -0,0 +1,100 @@
+//! Text commands over the UART the log goes out on, to tune the config on the bench without BLE.
+//! The commands are in [`common::console`], the answers are printed next to the log lines.
+
+use common::config::AppConfig;
+use common::config_presets::PRESETS;
+use common::console::{
+    CONSOLE_HELP, ConsoleCommand, MAX_CONSOLE_LINE, parse_command, set_channel_field,
+};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use esp_hal::{Async, uart::UartRx};
+use esp_println::println;
+
+use crate::device_config;
+use crate::storage::{StorageCommand, StorageQueue};
+
+#[embassy_executor::task]
+pub async fn serial_console_task(
+    mut rx: UartRx<'static, Async>,
+    config_signal: &'static Signal<CriticalSectionRawMutex, AppConfig>,
+    storage_queue: &'static StorageQueue,
+) -> ! {
+    log::info!("[console] Ready, type help");
+    let mut line = heapless::Vec::<u8, MAX_CONSOLE_LINE>::new();
+    let mut too_long = false;
+    let mut buf = [0u8; 32];
+    loop {
+        let len = match rx.read_async(&mut buf).await {
+            Ok(len) => len,
+            Err(e) => {
+                log::warn!("[console] UART error: {e:?}");
+                continue;
+            }
+        };
+        for &byte in &buf[..len] {
+            if byte != b'\r' && byte != b'\n' {
+                too_long |= line.push(byte).is_err();
+                continue;
+            }
+            if too_long {
+                println!("error: the line is too long");
+            } else if !line.is_empty() {
+                match core::str::from_utf8(&line) {
+                    Ok(text) => run(text, config_signal, storage_queue),
+                    Err(_) => println!("error: not UTF-8"),
+                }
+            }
+            line.clear();
+            too_long = false;
+        }
+    }
+}
+
+fn run(
+    line: &str,
+    config_signal: &'static Signal<CriticalSectionRawMutex, AppConfig>,
+    storage_queue: &'static StorageQueue,
+) {
+    let command = match parse_command(line) {
+        Ok(command) => command,
+        Err(e) => {
+            println!("error: {e:?}, see help");
+            return;
+        }
+    };
+    // main sets the config before any task runs
+    let Some(mut config) = device_config::current() else {
+        println!("error: no config yet");
+        return;
+    };
+    match command {
+        ConsoleCommand::Help => println!("{CONSOLE_HELP}"),
+        ConsoleCommand::Config => match serde_json::to_string(&config) {
+            Ok(json) => println!("{json}"),
+            Err(e) => println!("error: {e}"),
+        },
+        ConsoleCommand::Presets => {
+            for preset in &PRESETS {
+                println!("{}", preset.name);
+            }
+        }
+        ConsoleCommand::Preset(index) => {
+            log::info!("[console] Running preset {}", PRESETS[index].name);
+            device_config::set((PRESETS[index].config)(), config_signal);
+            println!("ok");
+        }
+        ConsoleCommand::Set { channel, field } => {
+            match set_channel_field(&mut config, channel, field) {
+                Ok(()) => {
+                    device_config::set(config, config_signal);
+                    println!("ok");
+                }
+                Err(e) => println!("error: {e:?}"),
+            }
+        }
+        ConsoleCommand::Save => {
+            storage_queue.send(StorageCommand::Save(config));
+            println!("ok");
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! Counters the busy tasks bump, sampled by the BLE status task (see [`common::status`]).
+//!
+//! Only relaxed atomics, so counting costs next to nothing on the hot paths.
+
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicU32, Ordering};
+
+use common::status::{AudioInput, DeviceStatus};
+use critical_section::Mutex;
+use embassy_time::Instant;
+
+static LED_FRAMES: AtomicU32 = AtomicU32::new(0);
+static AUDIO_FRAMES: AtomicU32 = AtomicU32::new(0);
+static AUDIO_INPUT: AtomicU8 = AtomicU8::new(AudioInput::None as u8);
+static USB_OVERFLOWS: AtomicU32 = AtomicU32::new(0);
+static USB_UNDERRUNS: AtomicU32 = AtomicU32::new(0);
+static USB_PACKETS: AtomicU32 = AtomicU32::new(0);
+static DROPPED_BLOCKS: AtomicU32 = AtomicU32::new(0);
+static POWER_LIMITED: AtomicBool = AtomicBool::new(false);
+static NEW_FRAMES: AtomicU32 = AtomicU32::new(0);
+static DROPPED_FRAMES: AtomicU32 = AtomicU32::new(0);
+static LATEST_STATUS: Mutex<RefCell<Option<DeviceStatus>>> = Mutex::new(RefCell::new(None));
+
+/// Called by the neopixel task for every frame written to the LEDs
+pub fn count_led_frame() {
+    LED_FRAMES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Called by the audio processing tasks for every block run through the FFT
+pub fn count_audio_frame(input: AudioInput) {
+    AUDIO_FRAMES.fetch_add(1, Ordering::Relaxed);
+    AUDIO_INPUT.store(input as u8, Ordering::Relaxed);
+}
+
+/// Called by the USB streaming task for every packet it had to drop
+pub fn count_usb_overflow() {
+    USB_OVERFLOWS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Called by the USB streaming task for every frame the host skipped
+pub fn count_usb_underruns(frames: u32) {
+    USB_UNDERRUNS.fetch_add(frames, Ordering::Relaxed);
+}
+
+/// Called by the USB streaming task for every packet the host sent
+pub fn count_usb_packet() {
+    USB_PACKETS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Called by the audio inputs for every block they skipped because the processing was still busy
+pub fn count_dropped_block() {
+    DROPPED_BLOCKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Called by the audio processing for every frame it dimmed to stay within the power limit
+pub fn note_power_limited_frame() {
+    POWER_LIMITED.store(true, Ordering::Relaxed);
+}
+
+/// Called by the neopixel task for every new frame it picks up
+pub fn count_new_frame() {
+    NEW_FRAMES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Called by the audio processing for every frame it replaced before the neopixel task took it
+pub fn count_dropped_frame() {
+    DROPPED_FRAMES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// The last status sampled by the [`StatusSampler`], for readers that must not reset the counters
+pub fn latest_status() -> Option<DeviceStatus> {
+    critical_section::with(|cs| LATEST_STATUS.borrow_ref(cs).clone())
+}
+
+/// Turns the counters into rates, by resetting them on every sample
+pub struct StatusSampler {
+    last_sample: Instant,
+}
+
+impl StatusSampler {
+    pub fn new() -> Self {
+        // drop whatever was counted while no one was looking
+        LED_FRAMES.store(0, Ordering::Relaxed);
+        AUDIO_FRAMES.store(0, Ordering::Relaxed);
+        AUDIO_INPUT.store(AudioInput::None as u8, Ordering::Relaxed);
+        USB_OVERFLOWS.store(0, Ordering::Relaxed);
+        USB_UNDERRUNS.store(0, Ordering::Relaxed);
+        USB_PACKETS.store(0, Ordering::Relaxed);
+        DROPPED_BLOCKS.store(0, Ordering::Relaxed);
+        POWER_LIMITED.store(false, Ordering::Relaxed);
+        NEW_FRAMES.store(0, Ordering::Relaxed);
+        DROPPED_FRAMES.store(0, Ordering::Relaxed);
+        Self {
+            last_sample: Instant::now(),
+        }
+    }
+
+    /// The status since the last call
+    pub fn sample(&mut self) -> DeviceStatus {
+        let now = Instant::now();
+        let elapsed_s = (now - self.last_sample).as_millis().max(1) as f32 / 1000.0;
+        self.last_sample = now;
+
+        let led_frames = LED_FRAMES.swap(0, Ordering::Relaxed);
+        let audio_frames = AUDIO_FRAMES.swap(0, Ordering::Relaxed);
+        let audio_input = match AUDIO_INPUT.swap(AudioInput::None as u8, Ordering::Relaxed) {
+            x if x == AudioInput::Usb as u8 => AudioInput::Usb,
+            x if x == AudioInput::I2s as u8 => AudioInput::I2s,
+            _ => AudioInput::None,
+        };
+
+        let status = DeviceStatus {
+            uptime_s: now.as_secs() as u32,
+            free_heap: esp_alloc::HEAP.free() as u32,
+            led_fps: led_frames as f32 / elapsed_s,
+            audio_fps: audio_frames as f32 / elapsed_s,
+            audio_input,
+            usb_overflows: USB_OVERFLOWS.swap(0, Ordering::Relaxed),
+            usb_underruns: USB_UNDERRUNS.swap(0, Ordering::Relaxed),
+            usb_packets: USB_PACKETS.swap(0, Ordering::Relaxed),
+            dropped_blocks: DROPPED_BLOCKS.swap(0, Ordering::Relaxed),
+            power_limited: POWER_LIMITED.swap(false, Ordering::Relaxed),
+            frame_fps: NEW_FRAMES.swap(0, Ordering::Relaxed) as f32 / elapsed_s,
+            dropped_frames: DROPPED_FRAMES.swap(0, Ordering::Relaxed),
+        };
+        // once per second, quiet unless something was lost
+        let level = if status.usb_overflows > 0 || status.dropped_blocks > 0 {
+            log::Level::Warn
+        } else {
+            log::Level::Debug
+        };
+        log::log!(
+            level,
+            "[stats] USB packets: {}, overflows: {}, underruns: {}, dropped blocks: {}, processed: {}, \
+             free heap: {}",
+            status.usb_packets,
+            status.usb_overflows,
+            status.usb_underruns,
+            status.dropped_blocks,
+            audio_frames,
+            status.free_heap,
+        );
+        critical_section::with(|cs| *LATEST_STATUS.borrow_ref_mut(cs) = Some(status.clone()));
+        status
+    }
+}
+
+impl Default for StatusSampler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
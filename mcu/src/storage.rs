@@ -0,0 +1,289 @@
+//! Keeps the config in flash, so it survives a reboot. See [`common::persist`] for the format.
+//!
+//! The config is stored at the start of the `nvs` data partition, the device name, the noise
+//! floor, the on/off switch and the preset slots in the next sectors. Nothing else on the device
+//! uses the partition.
+
+use alloc::boxed::Box;
+use common::config::AppConfig;
+use common::dsp::NoiseFloor;
+use common::persist::{
+    DeviceName, MAX_STORED_CONFIG_SIZE, MAX_STORED_NAME_SIZE, MAX_STORED_SLOT_SIZE, SLOT_COUNT,
+    STORED_ENABLED_SIZE, STORED_HEADER_SIZE, STORED_NOISE_FLOOR_SIZE, SlotName,
+    decode_stored_config, decode_stored_enabled, decode_stored_name, decode_stored_noise_floor,
+    decode_stored_slot, encode_stored_config, encode_stored_enabled, encode_stored_name,
+    encode_stored_noise_floor, encode_stored_slot, stored_config_len,
+};
+use embassy_futures::select::{Either, select};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embassy_time::{Duration, Timer};
+use embedded_storage::{ReadStorage, Storage};
+use esp_bootloader_esp_idf::partitions;
+use esp_hal::peripherals::FLASH;
+use esp_storage::FlashStorage;
+use log::{info, warn};
+
+use crate::error_with_location;
+use anyhow::Result;
+
+/// Saves are delayed until no new config arrived for this long, so dragging a slider in the app
+/// doesn't wear out the flash
+const SAVE_DELAY: Duration = Duration::from_secs(3);
+
+/// Offset of the device name in the nvs partition, a sector of its own so saving the config
+/// doesn't rewrite it
+const NAME_OFFSET: u32 = 4096;
+
+/// Offset of the noise floor in the nvs partition, again a sector of its own
+const NOISE_FLOOR_OFFSET: u32 = 2 * 4096;
+
+/// Offset of the on/off switch in the nvs partition, again a sector of its own
+const ENABLED_OFFSET: u32 = 3 * 4096;
+
+/// Offset of the first preset slot in the nvs partition, each slot has a sector of its own
+const SLOTS_OFFSET: u32 = 4 * 4096;
+
+/// How many commands can wait while [`storage_task`] is busy writing the flash
+const QUEUE_LEN: usize = 8;
+
+pub enum StorageCommand {
+    /// Store this config, debounced by [`SAVE_DELAY`]
+    Save(AppConfig),
+    /// Forget the stored config, the next boot uses the default again
+    Erase,
+    /// Store the name the device advertises, right away
+    SaveName(DeviceName),
+    /// Store a freshly calibrated noise floor, right away
+    SaveNoiseFloor(Box<NoiseFloor>),
+    /// Forget the noise floor
+    EraseNoiseFloor,
+    /// Store whether the LEDs are switched on, right away
+    SaveEnabled(bool),
+    /// Store a config into a preset slot, right away
+    SaveSlot(usize, SlotName, AppConfig),
+}
+
+/// The commands for [`storage_task`], in the order they were sent. Only [`StorageCommand::Save`]
+/// is coalesced, by the task itself.
+pub struct StorageQueue(Channel<CriticalSectionRawMutex, StorageCommand, QUEUE_LEN>);
+
+impl StorageQueue {
+    pub const fn new() -> Self {
+        Self(Channel::new())
+    }
+
+    /// Queue a command without waiting, so it can be sent from any task
+    pub fn send(&self, command: StorageCommand) {
+        if self.0.try_send(command).is_err() {
+            warn!("[storage] queue is full, dropping a command");
+        }
+    }
+}
+
+pub struct ConfigStorage {
+    flash: FlashStorage<'static>,
+    /// Start of the nvs partition
+    offset: u32,
+}
+
+impl ConfigStorage {
+    pub fn new(flash: FLASH<'static>) -> Result<Self> {
+        let mut flash = FlashStorage::new(flash);
+
+        let mut pt_mem = [0u8; partitions::PARTITION_TABLE_MAX_LEN];
+        let pt = partitions::read_partition_table(&mut flash, &mut pt_mem)
+            .map_err(|e| error_with_location!("Failed to read partition table: {:?}", e))?;
+        let nvs = pt
+            .find_partition(partitions::PartitionType::Data(
+                partitions::DataPartitionSubType::Nvs,
+            ))
+            .map_err(|e| error_with_location!("Failed to search partition table: {:?}", e))?
+            .ok_or_else(|| error_with_location!("No nvs partition"))?;
+        let last_slot = SLOTS_OFFSET as usize + (SLOT_COUNT - 1) * 4096;
+        if (nvs.len() as usize) < last_slot + MAX_STORED_SLOT_SIZE {
+            return Err(error_with_location!("nvs partition is too small"));
+        }
+
+        Ok(Self {
+            flash,
+            offset: nvs.offset(),
+        })
+    }
+
+    /// The stored config, `None` if there is none or it can't be used.
+    pub fn load(&mut self) -> Option<AppConfig> {
+        let mut buffer = [0u8; MAX_STORED_CONFIG_SIZE];
+        if let Err(e) = self
+            .flash
+            .read(self.offset, &mut buffer[..STORED_HEADER_SIZE])
+        {
+            warn!("[storage] Failed to read config header: {e:?}");
+            return None;
+        }
+        // blank flash or a different firmware version
+        let len = stored_config_len(&buffer)?;
+        if let Err(e) = self.flash.read(self.offset, &mut buffer[..len]) {
+            warn!("[storage] Failed to read config: {e:?}");
+            return None;
+        }
+        let config = decode_stored_config(&buffer[..len]);
+        if config.is_none() {
+            warn!("[storage] Stored config is corrupt");
+        }
+        config
+    }
+
+    /// The stored device name, `None` if the device was never renamed
+    pub fn load_name(&mut self) -> Option<DeviceName> {
+        let mut buffer = [0u8; MAX_STORED_NAME_SIZE];
+        if let Err(e) = self.flash.read(self.offset + NAME_OFFSET, &mut buffer) {
+            warn!("[storage] Failed to read device name: {e:?}");
+            return None;
+        }
+        decode_stored_name(&buffer)
+    }
+
+    /// The stored noise floor, `None` if it was never calibrated
+    pub fn load_noise_floor(&mut self) -> Option<Box<NoiseFloor>> {
+        let mut buffer = [0u8; STORED_NOISE_FLOOR_SIZE];
+        if let Err(e) = self.flash.read(self.offset + NOISE_FLOOR_OFFSET, &mut buffer) {
+            warn!("[storage] Failed to read noise floor: {e:?}");
+            return None;
+        }
+        decode_stored_noise_floor(&buffer).map(Box::new)
+    }
+
+    /// Whether the LEDs were left switched on, `None` if they were never switched
+    pub fn load_enabled(&mut self) -> Option<bool> {
+        let mut buffer = [0u8; STORED_ENABLED_SIZE];
+        if let Err(e) = self.flash.read(self.offset + ENABLED_OFFSET, &mut buffer) {
+            warn!("[storage] Failed to read the on/off switch: {e:?}");
+            return None;
+        }
+        decode_stored_enabled(&buffer)
+    }
+
+    /// The saved preset slots, `None` for the unused ones
+    pub fn load_slots(&mut self) -> [Option<(SlotName, AppConfig)>; SLOT_COUNT] {
+        core::array::from_fn(|index| {
+            let mut buffer = [0u8; MAX_STORED_SLOT_SIZE];
+            if let Err(e) = self.flash.read(slot_offset(self.offset, index), &mut buffer) {
+                warn!("[storage] Failed to read preset slot {index}: {e:?}");
+                return None;
+            }
+            decode_stored_slot(&buffer)
+        })
+    }
+
+    fn save(&mut self, config: &AppConfig) {
+        let Ok(stored) = encode_stored_config(config) else {
+            warn!("[storage] Config is too large to be stored");
+            return;
+        };
+        match self.flash.write(self.offset, &stored) {
+            Ok(()) => info!("[storage] Saved config ({} bytes)", stored.len()),
+            Err(e) => warn!("[storage] Failed to save config: {e:?}"),
+        }
+    }
+
+    fn erase(&mut self) {
+        // a blank header is enough for load to ignore the rest
+        match self.flash.write(self.offset, &[0xff; STORED_HEADER_SIZE]) {
+            Ok(()) => info!("[storage] Erased config"),
+            Err(e) => warn!("[storage] Failed to erase config: {e:?}"),
+        }
+    }
+
+    fn save_name(&mut self, name: &str) {
+        match self
+            .flash
+            .write(self.offset + NAME_OFFSET, &encode_stored_name(name))
+        {
+            Ok(()) => info!("[storage] Saved device name {name:?}"),
+            Err(e) => warn!("[storage] Failed to save device name: {e:?}"),
+        }
+    }
+
+    fn save_noise_floor(&mut self, floor: &NoiseFloor) {
+        match self
+            .flash
+            .write(self.offset + NOISE_FLOOR_OFFSET, &encode_stored_noise_floor(floor))
+        {
+            Ok(()) => info!("[storage] Saved noise floor"),
+            Err(e) => warn!("[storage] Failed to save noise floor: {e:?}"),
+        }
+    }
+
+    fn erase_noise_floor(&mut self) {
+        // a blank magic is enough for load to ignore the rest
+        match self.flash.write(self.offset + NOISE_FLOOR_OFFSET, &[0xff; 4]) {
+            Ok(()) => info!("[storage] Erased noise floor"),
+            Err(e) => warn!("[storage] Failed to erase noise floor: {e:?}"),
+        }
+    }
+
+    fn save_slot(&mut self, index: usize, name: &str, config: &AppConfig) {
+        let Ok(stored) = encode_stored_slot(name, config) else {
+            warn!("[storage] Config is too large to be stored");
+            return;
+        };
+        match self.flash.write(slot_offset(self.offset, index), &stored) {
+            Ok(()) => info!("[storage] Saved preset slot {index} as {name:?}"),
+            Err(e) => warn!("[storage] Failed to save preset slot {index}: {e:?}"),
+        }
+    }
+
+    fn save_enabled(&mut self, enabled: bool) {
+        match self
+            .flash
+            .write(self.offset + ENABLED_OFFSET, &encode_stored_enabled(enabled))
+        {
+            Ok(()) => info!("[storage] Saved LEDs {}", if enabled { "on" } else { "off" }),
+            Err(e) => warn!("[storage] Failed to save the on/off switch: {e:?}"),
+        }
+    }
+}
+
+/// Flash address of a preset slot, `nvs` is the start of the partition
+fn slot_offset(nvs: u32, index: usize) -> u32 {
+    nvs + SLOTS_OFFSET + index as u32 * 4096
+}
+
+#[embassy_executor::task]
+pub async fn storage_task(
+    mut storage: ConfigStorage,
+    storage_queue: &'static StorageQueue,
+) -> ! {
+    // a config waiting for the changes to stop, everything else is done right away
+    let mut pending: Option<AppConfig> = None;
+    loop {
+        let command = if pending.is_some() {
+            match select(storage_queue.0.receive(), Timer::after(SAVE_DELAY)).await {
+                Either::First(command) => command,
+                Either::Second(()) => {
+                    if let Some(config) = pending.take() {
+                        storage.save(&config);
+                    }
+                    continue;
+                }
+            }
+        } else {
+            storage_queue.0.receive().await
+        };
+
+        match command {
+            StorageCommand::Save(config) => pending = Some(config),
+            StorageCommand::Erase => {
+                pending = None;
+                storage.erase();
+            }
+            StorageCommand::SaveName(name) => storage.save_name(&name),
+            StorageCommand::SaveNoiseFloor(floor) => storage.save_noise_floor(&floor),
+            StorageCommand::EraseNoiseFloor => storage.erase_noise_floor(),
+            StorageCommand::SaveEnabled(enabled) => storage.save_enabled(enabled),
+            StorageCommand::SaveSlot(index, name, config) => {
+                storage.save_slot(index, &name, &config)
+            }
+        }
+    }
+}
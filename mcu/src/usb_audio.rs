@@ -1,6 +1,7 @@
-use alloc::boxed::Box;
 use embassy_executor::Spawner;
-use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use common::config::AppConfig;
+use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, NoopRawMutex};
+use embassy_sync::signal::Signal;
 use embassy_sync::zerocopy_channel;
 use embassy_usb::class::uac1;
 use embassy_usb::class::uac1::speaker::{self, Speaker, Volume};
@@ -10,17 +11,26 @@ use esp_hal::otg_fs::{Usb, asynch::{Driver as UsbDriver, Config as UsbConfig}};
 use esp_hal::peripherals;
 use heapless::Vec;
 use static_cell::StaticCell;
-use core::sync::atomic::{Atomic, AtomicU32, Ordering};
+use core::sync::atomic::{Atomic, AtomicBool, AtomicU32, Ordering};
 
 use anyhow::Result;
 use crate::error_with_location;
+use crate::lights::{AUDIO_BLOCK_FRAMES, AudioSender};
+use crate::storage::StorageQueue;
 
-// Stereo input
+// Stereo input, or mono with the `usb_mono` feature so mono hosts don't have to upmix
+#[cfg(not(feature = "usb_mono"))]
 pub const INPUT_CHANNEL_COUNT: usize = 2;
+#[cfg(feature = "usb_mono")]
+pub const INPUT_CHANNEL_COUNT: usize = 1;
 
 // Sample rate - match existing I2S configuration (48 kHz)
 pub const SAMPLE_RATE_HZ: u32 = 48_000;
 
+// Rates offered to the host, some players (e.g. Spotify on macOS) only open the device at 44.1 kHz.
+// The buffers are sized for the highest one.
+pub const SAMPLE_RATES_HZ: [u32; 2] = [SAMPLE_RATE_HZ, 44_100];
+
 // Use 32 bit samples to match existing I2S processing
 pub const SAMPLE_WIDTH: uac1::SampleWidth = uac1::SampleWidth::Width4Byte;
 pub const SAMPLE_WIDTH_BIT: usize = SAMPLE_WIDTH.in_bit();
@@ -31,10 +41,14 @@ pub const SAMPLE_SIZE_PER_S: usize = (SAMPLE_RATE_HZ as usize) * INPUT_CHANNEL_C
 pub const USB_FRAME_SIZE: usize = SAMPLE_SIZE_PER_S.div_ceil(1000);
 
 // Select front left and right audio channels.
+#[cfg(not(feature = "usb_mono"))]
 pub const AUDIO_CHANNELS: [uac1::Channel; INPUT_CHANNEL_COUNT] = [
     uac1::Channel::LeftFront,
     uac1::Channel::RightFront,
 ];
+// A single front center channel
+#[cfg(feature = "usb_mono")]
+pub const AUDIO_CHANNELS: [uac1::Channel; INPUT_CHANNEL_COUNT] = [uac1::Channel::CenterFront];
 
 // For ESP32-S3, use a more conservative packet size
 // Full-speed USB typically supports up to 1023 bytes for isochronous endpoints
@@ -45,14 +59,15 @@ pub const USB_MAX_SAMPLE_COUNT: usize = USB_MAX_PACKET_SIZE / SAMPLE_SIZE;
 // Global volume state - store f32 bit pattern as u32
 static VOLUME_LEFT: AtomicU32 = AtomicU32::new(0x3f800000); // 1.0f32 = full volume
 static VOLUME_RIGHT: AtomicU32 = AtomicU32::new(0x3f800000); // 1.0f32 = full volume
+// The rate the host selected, the FFT needs it to map the configured Hz ranges to bins
+static CURRENT_SAMPLE_RATE_HZ: AtomicU32 = AtomicU32::new(SAMPLE_RATE_HZ);
+// Set while the host muted every channel, the samples are dropped then so the matrix goes idle
+static MUTED: AtomicBool = AtomicBool::new(false);
 
 fn volume_to_u32(volume: Volume) -> u32 {
     let f = match volume {
         Volume::Muted => 0.0f32,
-        Volume::DeciBel(db) => {
-            // Convert dB to linear scale: 10^(dB/20)
-            libm::powf(10.0, db / 20.0)
-        }
+        Volume::DeciBel(db) => common::dsp::db_to_scale(db),
     };
     f.to_bits()
 }
@@ -61,19 +76,47 @@ fn u32_to_scale(value: u32) -> f32 {
     f32::from_bits(value)
 }
 
+/// Sample rate of the audio the host is currently streaming
+pub fn sample_rate_hz() -> u32 {
+    CURRENT_SAMPLE_RATE_HZ.load(Ordering::Relaxed)
+}
+
 // The data type that is exchanged via the zero-copy channel (a sample vector).
 pub type SampleBlock = Vec<u32, USB_MAX_SAMPLE_COUNT>;
 
+/// Tees the volume-scaled samples into the microphone, see `usb_mic`
+pub type CaptureSender = zerocopy_channel::Sender<'static, NoopRawMutex, SampleBlock>;
+
+// the microphone and the serial port add their own functions
+#[cfg(feature = "usb_mic")]
+const MIC_DESCRIPTOR_SIZE: usize = 256;
+#[cfg(not(feature = "usb_mic"))]
+const MIC_DESCRIPTOR_SIZE: usize = 0;
+#[cfg(feature = "usb_serial")]
+const SERIAL_DESCRIPTOR_SIZE: usize = 128;
+#[cfg(not(feature = "usb_serial"))]
+const SERIAL_DESCRIPTOR_SIZE: usize = 0;
+const CONFIG_DESCRIPTOR_SIZE: usize = 256 + MIC_DESCRIPTOR_SIZE + SERIAL_DESCRIPTOR_SIZE;
+
 // Feedback is provided in 10.14 format for full-speed endpoints.
 pub const FEEDBACK_REFRESH_PERIOD: uac1::FeedbackRefresh = uac1::FeedbackRefresh::Period8Frames;
+// Must match FEEDBACK_REFRESH_PERIOD
+const FEEDBACK_PERIOD_MS: u32 = 8;
 
-/// Apply volume scaling to a 32-bit sample
-fn apply_volume(sample: u32, scale: f32) -> u32 {
-    // Treat as signed 32-bit for proper audio scaling
-    let signed = sample as i32;
-    let scaled = (signed as f32 * scale) as i32;
-    scaled as u32
-}
+// The measured rate is averaged over about this many feedback periods, so a late block doesn't
+// make the host jump back and forth
+const FEEDBACK_SMOOTHING: u32 = 4;
+
+// The feedback never leaves nominal rate +- this many parts per thousand, whatever was measured.
+// Clock drift is a lot smaller than that, anything larger is a hiccup of the processing.
+const FEEDBACK_MAX_DEVIATION_PERMILLE: u32 = 5;
+
+// Audio frames (one sample per channel) the receiver task took from the channel, reset by the
+// feedback handler every period
+static FRAMES_CONSUMED: AtomicU32 = AtomicU32::new(0);
+
+// A gap longer than this between two packets counts as underruns, one per missing frame
+const UNDERRUN_GAP: embassy_time::Duration = embassy_time::Duration::from_micros(1500);
 
 struct Disconnected {}
 
@@ -87,26 +130,43 @@ impl From<EndpointError> for Disconnected {
 }
 
 /// Sends feedback messages to the host.
+///
+/// The feedback tells the host how many samples per frame (1 ms) the device consumes, measured
+/// from what the receiver task actually took from the channel, so the host follows the device
+/// instead of drifting apart from it.
 async fn feedback_handler<'d>(
     feedback: &mut speaker::Feedback<'d, UsbDriver<'d>>,
 ) -> Result<(), Disconnected> {
     let mut packet: Vec<u8, 4> = Vec::new();
+    let mut ticker = embassy_time::Ticker::every(embassy_time::Duration::from_millis(
+        FEEDBACK_PERIOD_MS as u64,
+    ));
+    let mut smoothed: Option<u32> = None;
+    FRAMES_CONSUMED.store(0, Ordering::Relaxed);
 
     loop {
-        // For ESP32-S3, we'll use a simpler fixed feedback approach
-        // The feedback value tells the host how many samples we're consuming
-        // For 48kHz with 10.14 format: 48 << 14 = 786432
-        let feedback_value = (SAMPLE_RATE_HZ << 14) / 1000; // Per frame (1ms)
-        
+        // Send feedback every FEEDBACK_REFRESH_PERIOD (8 frames = 8ms)
+        ticker.next().await;
+
+        // 10.14 format, for 48kHz: 48 << 14 = 786432, re-read so a rate switch takes effect
+        let nominal = (sample_rate_hz() << 14) / 1000;
+        let consumed = FRAMES_CONSUMED.swap(0, Ordering::Relaxed);
+        let measured = (consumed << 14) / FEEDBACK_PERIOD_MS;
+
+        // starts at the nominal rate, the first periods after connecting are all over the place
+        let average = smoothed.map_or(nominal, |average| {
+            average - average / FEEDBACK_SMOOTHING + measured / FEEDBACK_SMOOTHING
+        });
+        let max_deviation = nominal * FEEDBACK_MAX_DEVIATION_PERMILLE / 1000;
+        let feedback_value = average.clamp(nominal - max_deviation, nominal + max_deviation);
+        smoothed = Some(feedback_value);
+
         packet.clear();
         packet.push(feedback_value as u8).unwrap();
         packet.push((feedback_value >> 8) as u8).unwrap();
         packet.push((feedback_value >> 16) as u8).unwrap();
 
         feedback.write_packet(&packet).await?;
-        
-        // Send feedback every FEEDBACK_REFRESH_PERIOD (8 frames = 8ms)
-        embassy_time::Timer::after(embassy_time::Duration::from_millis(8)).await;
     }
 }
 
@@ -114,16 +174,31 @@ async fn feedback_handler<'d>(
 async fn stream_handler<'d>(
     stream: &mut speaker::Stream<'d, UsbDriver<'d>>,
     sender: &mut zerocopy_channel::Sender<'static, NoopRawMutex, SampleBlock>,
-) -> Result<(), Disconnected> {
+    let mut last_packet: Option<embassy_time::Instant> = None;
     loop {
         let mut usb_data = [0u8; USB_MAX_PACKET_SIZE];
         let data_size = stream.read_packet(&mut usb_data).await?;
 
+        let now = embassy_time::Instant::now();
+        if let Some(last) = last_packet
+            && now - last > UNDERRUN_GAP
+        {
+            // one packet per frame is expected, everything beyond the first is missing
+            let gap_frames = (now - last).as_millis().saturating_sub(1).max(1);
+            crate::stats::count_usb_underruns(gap_frames as u32);
+        }
+        last_packet = Some(now);
+
+        crate::stats::count_usb_packet();
         let word_count = data_size / SAMPLE_SIZE;
 
         if word_count * SAMPLE_SIZE == data_size {
-            // Obtain a buffer from the channel
-            let samples = sender.send().await;
+            // Obtain a buffer from the channel, drop the packet if the processing is behind
+            // instead of stalling the endpoint
+            let Some(samples) = sender.try_send() else {
+                crate::stats::count_usb_overflow();
+                continue;
+            };
             samples.clear();
 
             for w in 0..word_count {
@@ -149,15 +224,35 @@ async fn stream_handler<'d>(
 #[embassy_executor::task]
 pub async fn usb_audio_receiver_task(
     mut usb_audio_receiver: zerocopy_channel::Receiver<'static, NoopRawMutex, SampleBlock>,
-    audio_buffer_sender: &'static embassy_sync::channel::Sender<
-        'static,
-        embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
-        Box<[u8; 2048]>,
-        4,
-    >,
+    mut audio_sender: AudioSender,
+    mut capture: Option<CaptureSender>,
 ) {
+    // the host sends about a millisecond per packet, collected here until a block is full
+    const BLOCK_BYTES: usize = AUDIO_BLOCK_FRAMES * INPUT_CHANNEL_COUNT * 4;
+    let mut filled = 0;
+    // the processing is busy with both blocks, counted once until one is free again
+    let mut dropping = false;
+    let mut was_muted = false;
     loop {
         let samples = usb_audio_receiver.receive().await;
+        FRAMES_CONSUMED.fetch_add((samples.len() / INPUT_CHANNEL_COUNT) as u32, Ordering::Relaxed);
+
+        if MUTED.load(Ordering::Relaxed) {
+            usb_audio_receiver.receive_done();
+            was_muted = true;
+            continue;
+        }
+        if was_muted {
+            // Unmuted: whatever is still queued was recorded while muted, drop it instead of
+            // playing it back as a burst
+            usb_audio_receiver.receive_done();
+            while let Some(samples) = usb_audio_receiver.try_receive() {
+                FRAMES_CONSUMED.fetch_add((samples.len() / INPUT_CHANNEL_COUNT) as u32, Ordering::Relaxed);
+                usb_audio_receiver.receive_done();
+            }
+            was_muted = false;
+            continue;
+        }
         
         // Get current volume settings (stored as f32 bit patterns)
         let vol_left = VOLUME_LEFT.load(Ordering::Relaxed);
@@ -165,29 +260,41 @@ pub async fn usb_audio_receiver_task(
         let scale_left = u32_to_scale(vol_left);
         let scale_right = u32_to_scale(vol_right);
         
-        // USB audio samples are already interleaved stereo: [L, R, L, R, ...]
-        // Each sample is a u32 (4 bytes)
-        // Apply volume scaling and convert to bytes
-        let mut buffer = Box::new([0u8; 2048]);
-        let mut buffer_pos = 0;
-        
+        // USB audio samples are already interleaved: [L, R, L, R, ...], each a u32
+        // Apply volume scaling and write them straight into the next free block
+        let mut scaled_samples = SampleBlock::new();
         for (i, sample) in samples.iter().enumerate() {
-            if buffer_pos + 4 <= buffer.len() {
-                // Apply volume: left channel on even indices, right channel on odd
-                let scale = if i % 2 == 0 { scale_left } else { scale_right };
-                let scaled_sample = apply_volume(*sample, scale);
-                
-                let sample_bytes = scaled_sample.to_le_bytes();
-                buffer[buffer_pos..buffer_pos + 4].copy_from_slice(&sample_bytes);
-                buffer_pos += 4;
-            } else {
-                break;
+            // Apply volume: the samples are interleaved, the first channel is left (or mono)
+            let scale = if i % INPUT_CHANNEL_COUNT == 0 { scale_left } else { scale_right };
+            let scaled_sample = common::dsp::apply_volume(*sample, scale);
+            let _ = scaled_samples.push(scaled_sample);
+
+            // Waiting for the processing would back up into the isochronous endpoint, skip the
+            // samples instead, the lights should show the newest audio anyway
+            let Some(block) = audio_sender.try_send() else {
+                if !dropping {
+                    crate::stats::count_dropped_block();
+                    dropping = true;
+                }
+                continue;
+            };
+            dropping = false;
+            block.data[filled..filled + 4].copy_from_slice(&scaled_sample.to_le_bytes());
+            filled += 4;
+            if filled == BLOCK_BYTES {
+                block.channel_count = INPUT_CHANNEL_COUNT;
+                block.sample_rate = sample_rate_hz();
+                audio_sender.send_done();
+                filled = 0;
             }
         }
-        
-        // Send to audio processing if we have data
-        if buffer_pos > 0 {
-            audio_buffer_sender.send(buffer).await;
+
+        // Tee into the microphone, dropped if the host isn't recording so the lights never wait for it
+        if let Some(capture) = capture.as_mut()
+            && let Some(block) = capture.try_send()
+        {
+            block.clone_from(&scaled_samples);
+            capture.send_done();
         }
 
         // Notify the channel that the buffer is now ready to be reused
@@ -229,24 +336,38 @@ async fn usb_task(mut usb_device: UsbDevice<'static, UsbDriver<'static>>) {
 
 /// Checks for changes on the control monitor of the class.
 ///
-/// In this case, monitor changes of volume or mute state.
+/// In this case, monitor changes of sample rate, volume or mute state.
 #[embassy_executor::task]
 async fn usb_control_task(control_monitor: speaker::ControlMonitor<'static>) {
     loop {
         control_monitor.changed().await;
 
-        // Update volume for each channel
-        if let Some(volume) = control_monitor.volume(uac1::Channel::LeftFront) {
+        let rate = control_monitor.sample_rate_hz();
+        if CURRENT_SAMPLE_RATE_HZ.swap(rate, Ordering::Relaxed) != rate {
+            log::info!("USB sample rate changed to {rate} Hz");
+        }
+
+        // Update volume for each channel, a mono stream only has the first one
+        if let Some(volume) = control_monitor.volume(AUDIO_CHANNELS[0]) {
             let volume_bits = volume_to_u32(volume);
             VOLUME_LEFT.store(volume_bits, Ordering::Relaxed);
             log::info!("Left volume changed to {:?} (scale: {:.3})", volume, u32_to_scale(volume_bits));
         }
         
-        if let Some(volume) = control_monitor.volume(uac1::Channel::RightFront) {
+        if let Some(&channel) = AUDIO_CHANNELS.get(1)
+            && let Some(volume) = control_monitor.volume(channel)
+        {
             let volume_bits = volume_to_u32(volume);
             VOLUME_RIGHT.store(volume_bits, Ordering::Relaxed);
             log::info!("Right volume changed to {:?} (scale: {:.3})", volume, u32_to_scale(volume_bits));
         }
+
+        let muted = AUDIO_CHANNELS
+            .iter()
+            .all(|&channel| matches!(control_monitor.volume(channel), Some(Volume::Muted)));
+        if MUTED.swap(muted, Ordering::Relaxed) != muted {
+            log::info!("USB audio {}", if muted { "muted" } else { "unmuted" });
+        }
     }
 }
 
@@ -255,20 +376,17 @@ pub fn init_usb_audio(
     usb0: peripherals::USB0<'static>,
     usb_dp: peripherals::GPIO20<'static>,
     usb_dm: peripherals::GPIO19<'static>,
-    audio_buffer_sender: &'static embassy_sync::channel::Sender<
-        'static,
-        embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
-        Box<[u8; 2048]>,
-        4,
-    >,
+    audio_sender: AudioSender,
+    config_signal: &'static Signal<CriticalSectionRawMutex, AppConfig>,
+    storage_queue: &'static StorageQueue,
 ) -> Result<()> {
     log::info!("Initializing USB Audio...");
 
     // Configure all required buffers in a static way.
     log::debug!("USB packet size is {} bytes", USB_MAX_PACKET_SIZE);
     
-    static CONFIG_DESCRIPTOR: StaticCell<[u8; 256]> = StaticCell::new();
-    let config_descriptor = CONFIG_DESCRIPTOR.init([0; 256]);
+    static CONFIG_DESCRIPTOR: StaticCell<[u8; CONFIG_DESCRIPTOR_SIZE]> = StaticCell::new();
+    let config_descriptor = CONFIG_DESCRIPTOR.init([0; CONFIG_DESCRIPTOR_SIZE]);
 
     static BOS_DESCRIPTOR: StaticCell<[u8; 32]> = StaticCell::new();
     let bos_descriptor = BOS_DESCRIPTOR.init([0; 32]);
@@ -315,11 +433,17 @@ pub fn init_usb_audio(
         state,
         USB_MAX_PACKET_SIZE as u16,
         SAMPLE_WIDTH,
-        &[SAMPLE_RATE_HZ],
+        &SAMPLE_RATES_HZ,
         &AUDIO_CHANNELS,
         FEEDBACK_REFRESH_PERIOD,
     );
 
+    #[cfg(feature = "usb_mic")]
+    let mic_endpoint = crate::usb_mic::add_microphone(&mut builder);
+
+    #[cfg(feature = "usb_serial")]
+    let serial = crate::usb_serial::add_serial(&mut builder);
+
     // Create the USB device
     let usb_device = builder.build();
 
@@ -345,8 +469,33 @@ pub fn init_usb_audio(
     spawner
         .spawn(usb_task(usb_device))
         .map_err(|_| error_with_location!("Failed to spawn usb_task"))?;
+    #[cfg(feature = "usb_serial")]
+    spawner
+        .spawn(crate::usb_serial::usb_serial_task(serial, config_signal, storage_queue))
+        .map_err(|_| error_with_location!("Failed to spawn usb_serial_task"))?;
+    #[cfg(not(feature = "usb_serial"))]
+    let _ = (config_signal, storage_queue);
+    #[cfg(feature = "usb_mic")]
+    let capture = {
+        // a few blocks of slack, the host reads once per frame just like it writes
+        static CAPTURE_BLOCKS: StaticCell<[SampleBlock; 4]> = StaticCell::new();
+        let capture_blocks = CAPTURE_BLOCKS.init([Vec::new(), Vec::new(), Vec::new(), Vec::new()]);
+
+        static CAPTURE_CHANNEL: StaticCell<zerocopy_channel::Channel<'_, NoopRawMutex, SampleBlock>> =
+            StaticCell::new();
+        let capture_channel = CAPTURE_CHANNEL.init(zerocopy_channel::Channel::new(capture_blocks));
+        let (capture_sender, capture_receiver) = capture_channel.split();
+
+        spawner
+            .spawn(crate::usb_mic::usb_mic_task(mic_endpoint, capture_receiver))
+            .map_err(|_| error_with_location!("Failed to spawn usb_mic_task"))?;
+        Some(capture_sender)
+    };
+    #[cfg(not(feature = "usb_mic"))]
+    let capture = None;
+
     spawner
-        .spawn(usb_audio_receiver_task(receiver, audio_buffer_sender))
+        .spawn(usb_audio_receiver_task(receiver, audio_sender, capture))
         .map_err(|_| error_with_location!("Failed to spawn usb_audio_receiver_task"))?;
 
     log::info!("USB Audio initialized successfully");
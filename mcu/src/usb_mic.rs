@@ -0,0 +1,198 @@
+//! UAC1 microphone, so the host can record the samples the FFT sees. Only for diagnostics.
+//!
+//! embassy-usb only ships the speaker class, so the descriptors are written by hand,
+//! following the USB Audio Device Class 1.0 spec:
+//!
+//! ```text
+//! Input Terminal (microphone) -> Output Terminal (USB streaming) -> isochronous IN endpoint
+//! ```
+
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::zerocopy_channel;
+use embassy_usb::Builder;
+use embassy_usb::driver::{
+    Driver, Endpoint, EndpointError, EndpointIn, SynchronizationType, UsageType,
+};
+use esp_hal::otg_fs::asynch::Driver as UsbDriver;
+
+use crate::usb_audio::{
+    INPUT_CHANNEL_COUNT, SAMPLE_RATE_HZ, SAMPLE_SIZE, SAMPLE_WIDTH_BIT, SampleBlock,
+    USB_MAX_PACKET_SIZE,
+};
+
+pub type MicEndpoint = <UsbDriver<'static> as Driver<'static>>::EndpointIn;
+
+// class codes
+const USB_AUDIO_CLASS: u8 = 0x01;
+const USB_AUDIOCONTROL_SUBCLASS: u8 = 0x01;
+const USB_AUDIOSTREAMING_SUBCLASS: u8 = 0x02;
+const PROTOCOL_NONE: u8 = 0x00;
+
+// class-specific descriptor types
+const CS_INTERFACE: u8 = 0x24;
+const CS_ENDPOINT: u8 = 0x25;
+
+// AudioControl descriptor subtypes
+const HEADER: u8 = 0x01;
+const INPUT_TERMINAL: u8 = 0x02;
+const OUTPUT_TERMINAL: u8 = 0x03;
+
+// AudioStreaming descriptor subtypes
+const AS_GENERAL: u8 = 0x01;
+const FORMAT_TYPE: u8 = 0x02;
+const FORMAT_TYPE_I: u8 = 0x01;
+const EP_GENERAL: u8 = 0x01;
+
+const TERMINAL_TYPE_MICROPHONE: u16 = 0x0201;
+const TERMINAL_TYPE_USB_STREAMING: u16 = 0x0101;
+const FORMAT_PCM: u16 = 0x0001;
+
+const INPUT_TERMINAL_ID: u8 = 1;
+const OUTPUT_TERMINAL_ID: u8 = 2;
+
+/// Front left + front right, or no spatial location for mono
+const CHANNEL_CONFIG: u16 = if INPUT_CHANNEL_COUNT == 1 { 0x0000 } else { 0x0003 };
+
+/// Header + input terminal + output terminal, including their length and type bytes
+const AUDIOCONTROL_TOTAL_LENGTH: u16 = 9 + 12 + 9;
+
+/// Add the microphone function to the USB device and return its streaming endpoint.
+pub fn add_microphone(builder: &mut Builder<'static, UsbDriver<'static>>) -> MicEndpoint {
+    let mut func = builder.function(USB_AUDIO_CLASS, USB_AUDIOCONTROL_SUBCLASS, PROTOCOL_NONE);
+
+    // AudioControl interface
+    let mut interface = func.interface();
+    let streaming_interface = u8::from(interface.interface_number()) + 1;
+    let mut alt = interface.alt_setting(
+        USB_AUDIO_CLASS,
+        USB_AUDIOCONTROL_SUBCLASS,
+        PROTOCOL_NONE,
+        None,
+    );
+    let [total_lo, total_hi] = AUDIOCONTROL_TOTAL_LENGTH.to_le_bytes();
+    alt.descriptor(
+        CS_INTERFACE,
+        &[
+            HEADER,
+            0x00,
+            0x01,
+            total_lo,
+            total_hi,
+            1,
+            streaming_interface,
+        ],
+    );
+    let [mic_lo, mic_hi] = TERMINAL_TYPE_MICROPHONE.to_le_bytes();
+    let [ch_lo, ch_hi] = CHANNEL_CONFIG.to_le_bytes();
+    alt.descriptor(
+        CS_INTERFACE,
+        &[
+            INPUT_TERMINAL,
+            INPUT_TERMINAL_ID,
+            mic_lo,
+            mic_hi,
+            0, // no associated terminal
+            INPUT_CHANNEL_COUNT as u8,
+            ch_lo,
+            ch_hi,
+            0, // no channel names
+            0, // no terminal name
+        ],
+    );
+    let [usb_lo, usb_hi] = TERMINAL_TYPE_USB_STREAMING.to_le_bytes();
+    alt.descriptor(
+        CS_INTERFACE,
+        &[
+            OUTPUT_TERMINAL,
+            OUTPUT_TERMINAL_ID,
+            usb_lo,
+            usb_hi,
+            0, // no associated terminal
+            INPUT_TERMINAL_ID,
+            0, // no terminal name
+        ],
+    );
+
+    // AudioStreaming interface, alternate setting 0 has no endpoint (zero bandwidth)
+    let mut interface = func.interface();
+    interface.alt_setting(
+        USB_AUDIO_CLASS,
+        USB_AUDIOSTREAMING_SUBCLASS,
+        PROTOCOL_NONE,
+        None,
+    );
+    let mut alt = interface.alt_setting(
+        USB_AUDIO_CLASS,
+        USB_AUDIOSTREAMING_SUBCLASS,
+        PROTOCOL_NONE,
+        None,
+    );
+    let [pcm_lo, pcm_hi] = FORMAT_PCM.to_le_bytes();
+    alt.descriptor(
+        CS_INTERFACE,
+        &[AS_GENERAL, OUTPUT_TERMINAL_ID, 1, pcm_lo, pcm_hi],
+    );
+    let [rate0, rate1, rate2, _] = SAMPLE_RATE_HZ.to_le_bytes();
+    alt.descriptor(
+        CS_INTERFACE,
+        &[
+            FORMAT_TYPE,
+            FORMAT_TYPE_I,
+            INPUT_CHANNEL_COUNT as u8,
+            SAMPLE_SIZE as u8,
+            SAMPLE_WIDTH_BIT as u8,
+            1, // one discrete sample rate
+            rate0,
+            rate1,
+            rate2,
+        ],
+    );
+    let endpoint = alt.endpoint_isochronous_in(
+        None,
+        USB_MAX_PACKET_SIZE as u16,
+        1,
+        SynchronizationType::Asynchronous,
+        UsageType::DataEndpoint,
+        // bRefresh and bSynchAddress of the audio class endpoint descriptor
+        &[0, 0],
+    );
+    alt.descriptor(CS_ENDPOINT, &[EP_GENERAL, 0x00, 0x00, 0x00, 0x00]);
+
+    endpoint
+}
+
+/// Sends the teed sample blocks to the host while it is recording.
+#[embassy_executor::task]
+pub async fn usb_mic_task(
+    mut endpoint: MicEndpoint,
+    mut receiver: zerocopy_channel::Receiver<'static, NoopRawMutex, SampleBlock>,
+) {
+    let mut packet = [0u8; USB_MAX_PACKET_SIZE];
+    loop {
+        // only enabled while the host selected the streaming alternate setting
+        endpoint.wait_enabled().await;
+        log::info!("USB microphone connected");
+
+        loop {
+            let samples = receiver.receive().await;
+            let mut len = 0;
+            for sample in samples.iter() {
+                if len + SAMPLE_SIZE > packet.len() {
+                    break;
+                }
+                packet[len..len + SAMPLE_SIZE].copy_from_slice(&sample.to_le_bytes());
+                len += SAMPLE_SIZE;
+            }
+            receiver.receive_done();
+
+            match endpoint.write(&packet[..len]).await {
+                Ok(()) => {}
+                Err(EndpointError::Disabled) => break,
+                Err(EndpointError::BufferOverflow) => {
+                    log::warn!("USB microphone packet of {len} bytes is too large");
+                }
+            }
+        }
+        log::info!("USB microphone disconnected");
+    }
+}
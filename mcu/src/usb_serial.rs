@@ -0,0 +1,139 @@
+//! CDC-ACM serial port, to read and write the config over USB when Bluetooth isn't an option.
+//! The protocol is in [`common::serial`].
+
+use alloc::boxed::Box;
+use common::config::{AppConfig, ConfigError};
+use common::serial::{
+    FrameReader, MAX_SERIAL_FRAME_SIZE, SerialError, SerialRequest, SerialResponse,
+};
+use common::transfer::MAX_CHUNKED_CONFIG_SIZE;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_usb::Builder;
+use embassy_usb::class::cdc_acm::{CdcAcmClass, State};
+use embassy_usb::driver::EndpointError;
+use esp_hal::otg_fs::asynch::Driver as UsbDriver;
+use static_cell::StaticCell;
+
+use crate::device_config;
+use crate::lights::{LED_OVERRIDE, LedOverride};
+use crate::stats;
+use crate::storage::StorageQueue;
+
+/// Max packet size of the bulk endpoints, the most full-speed allows
+const SERIAL_PACKET_SIZE: usize = 64;
+
+pub type SerialClass = CdcAcmClass<'static, UsbDriver<'static>>;
+
+/// Add the serial port function to the USB device.
+pub fn add_serial(builder: &mut Builder<'static, UsbDriver<'static>>) -> SerialClass {
+    static STATE: StaticCell<State<'static>> = StaticCell::new();
+    CdcAcmClass::new(builder, STATE.init(State::new()), SERIAL_PACKET_SIZE as u16)
+}
+
+/// Answers the requests of a host that opened the port.
+#[embassy_executor::task]
+pub async fn usb_serial_task(
+    mut class: SerialClass,
+    config_signal: &'static Signal<CriticalSectionRawMutex, AppConfig>,
+    storage_queue: &'static StorageQueue,
+) {
+    // a whole config in each direction, too much for the task's stack
+    let mut reader = Box::new(FrameReader::<MAX_SERIAL_FRAME_SIZE>::new());
+    let mut response = Box::new([0u8; MAX_SERIAL_FRAME_SIZE]);
+    let mut packet = [0u8; SERIAL_PACKET_SIZE];
+    loop {
+        // DTR, i.e. a program opened the port
+        class.wait_connection().await;
+        log::info!("USB serial connected");
+        reader.clear();
+
+        'connected: loop {
+            let len = match class.read_packet(&mut packet).await {
+                Ok(len) => len,
+                Err(EndpointError::Disabled) => break,
+                Err(EndpointError::BufferOverflow) => {
+                    log::warn!("USB serial packet is too large");
+                    continue;
+                }
+            };
+            let mut data = &packet[..len];
+            while let Some(frame) = reader.feed(&mut data) {
+                let len = respond(frame, config_signal, storage_queue, &mut response[..]);
+                if write_frame(&mut class, &response[..len]).await.is_err() {
+                    break 'connected;
+                }
+            }
+        }
+        log::info!("USB serial disconnected");
+    }
+}
+
+/// Handle one received frame and encode the response into `out`, returns its length
+fn respond(
+    frame: Result<&mut [u8], SerialError>,
+    config_signal: &Signal<CriticalSectionRawMutex, AppConfig>,
+    storage_queue: &StorageQueue,
+    out: &mut [u8],
+) -> usize {
+    let request = frame.and_then(|frame| {
+        SerialRequest::from_frame(frame).map_err(|_| SerialError::Malformed)
+    });
+    let config_bytes;
+    let response = match request {
+        Err(e) => {
+            log::warn!("[serial] Dropped frame: {e:?}");
+            SerialResponse::Error(e)
+        }
+        Ok(SerialRequest::ReadConfig) => {
+            match device_config::current().map(|c| c.to_bytes::<MAX_CHUNKED_CONFIG_SIZE>()) {
+                Some(Ok(bytes)) => {
+                    config_bytes = bytes;
+                    SerialResponse::Config(&config_bytes)
+                }
+                _ => SerialResponse::Error(SerialError::Unavailable),
+            }
+        }
+        Ok(SerialRequest::WriteConfig(bytes)) => {
+            match device_config::apply(bytes, config_signal, storage_queue) {
+                Ok(()) => {
+                    log::info!("[serial] Applied new config");
+                    SerialResponse::Ok
+                }
+                Err(ConfigError::UnsupportedVersion(version)) => {
+                    log::warn!("[serial] Unsupported config version {version}");
+                    SerialResponse::Error(SerialError::UnsupportedVersion(version))
+                }
+                Err(ConfigError::Invalid) => {
+                    log::warn!("[serial] Invalid config data");
+                    SerialResponse::Error(SerialError::InvalidConfig)
+                }
+                Err(ConfigError::TooManyLeds(leds)) => {
+                    log::warn!("[serial] Config has {leds} LEDs, more than the firmware supports");
+                    SerialResponse::Error(SerialError::TooManyLeds(leds as u32))
+                }
+            }
+        }
+        Ok(SerialRequest::GetStatus) => match stats::latest_status() {
+            Some(status) => SerialResponse::Status(status),
+            None => SerialResponse::Error(SerialError::Unavailable),
+        },
+        Ok(SerialRequest::Identify) => {
+            LED_OVERRIDE.signal(LedOverride::Identify);
+            SerialResponse::Ok
+        }
+    };
+    // can't fail, `out` fits the largest message
+    response.to_frame(out).map(|frame| frame.len()).unwrap_or(0)
+}
+
+/// Write a frame in packets. A full last packet is followed by an empty one, otherwise the host
+/// waits for more before handing the data to the program.
+async fn write_frame(class: &mut SerialClass, frame: &[u8]) -> Result<(), EndpointError> {
+    for packet in frame.chunks(SERIAL_PACKET_SIZE) {
+        class.write_packet(packet).await?;
+    }
+    if frame.len().is_multiple_of(SERIAL_PACKET_SIZE) {
+        class.write_packet(&[]).await?;
+    }
+    Ok(())
+}
@@ -1,8 +1,12 @@
 use alloc::string::String;
 use log::{Metadata, Record};
 
+use core::cell::RefCell;
 use core::fmt::Write;
 
+use critical_section::Mutex;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+
 use anyhow::Result;
 
 use rtt_target::rprintln;
@@ -45,11 +49,75 @@ impl log::Log for MultiLogger {
         // UART — use esp_println::println! which writes directly to UART (avoid log! macros here
         // to prevent recursion)
         esp_println::println!("{}", buf);
+
+        // BLE, picked up by the log task in bluetooth.rs. Not the BLE stack itself, at debug level
+        // sending a line would log more lines.
+        if !record.target().starts_with("trouble_host") {
+            push_log_line(&buf);
+        }
     }
 
     fn flush(&self) {}
 }
 
+/// Longest log line sent over BLE, longer ones are cut off so a line fits into one notification
+pub const LOG_LINE_SIZE: usize = 128;
+
+/// Lines kept for the BLE log, the oldest are dropped when no one drains them
+const LOG_BUFFER_LINES: usize = 32;
+
+pub type LogLine = heapless::String<LOG_LINE_SIZE>;
+
+struct LogBuffer {
+    lines: heapless::Deque<LogLine, LOG_BUFFER_LINES>,
+    /// Lines dropped since the last one was taken out
+    dropped: u32,
+}
+
+static LOG_BUFFER: Mutex<RefCell<LogBuffer>> = Mutex::new(RefCell::new(LogBuffer {
+    lines: heapless::Deque::new(),
+    dropped: 0,
+}));
+
+/// Signaled whenever a line was added to the BLE log
+pub static LOG_AVAILABLE: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+fn push_log_line(text: &str) {
+    let mut line = LogLine::new();
+    for c in text.chars() {
+        if line.push(c).is_err() {
+            break;
+        }
+    }
+
+    critical_section::with(|cs| {
+        let mut buffer = LOG_BUFFER.borrow_ref_mut(cs);
+        if buffer.lines.is_full() {
+            buffer.lines.pop_front();
+            buffer.dropped = buffer.dropped.saturating_add(1);
+        }
+        // can't fail, there's room after the pop
+        let _ = buffer.lines.push_back(line);
+    });
+    LOG_AVAILABLE.signal(());
+}
+
+/// Take the oldest line out of the BLE log.
+///
+/// If lines were dropped before it, a line saying how many comes first.
+pub fn pop_log_line() -> Option<LogLine> {
+    critical_section::with(|cs| {
+        let mut buffer = LOG_BUFFER.borrow_ref_mut(cs);
+        if buffer.dropped > 0 && !buffer.lines.is_empty() {
+            let mut line = LogLine::new();
+            let _ = write!(&mut line, "[... {} lines dropped]", buffer.dropped);
+            buffer.dropped = 0;
+            return Some(line);
+        }
+        buffer.lines.pop_front()
+    })
+}
+
 #[macro_use]
 mod static_cell_helpers {
     #[macro_export]
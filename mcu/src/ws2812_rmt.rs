@@ -0,0 +1,102 @@
+//! Drives the LEDs with the RMT peripheral instead of SPI, selected with the `rmt` feature.
+//!
+//! Every data bit is one pulse code, so the timing is set in 12.5 ns steps instead of depending on
+//! how a bit fits into SPI bytes. It also leaves SPI2 and its DMA channel free.
+
+use common::config::{ColorOrder, LedChipset};
+use common::ws2812::wire_bytes;
+use esp_hal::Async;
+use esp_hal::gpio::{AnyPin, Level};
+use esp_hal::peripherals::RMT;
+use esp_hal::rmt::{Channel, PulseCode, Rmt, Tx, TxChannelConfig, TxChannelCreator};
+use esp_hal::time::Rate;
+use smart_leds::RGB8;
+use static_cell::ConstStaticCell;
+
+use crate::error_with_location;
+use crate::lights::TOTAL_NEOPIXEL_LENGTH;
+use crate::ws2812::LedDriver;
+use anyhow::Result;
+
+/// The RMT counts in 12.5 ns ticks
+const RMT_CLOCK_MHZ: u32 = 80;
+
+/// 0 bit: 0.4 µs high, 0.85 µs low
+const ZERO: PulseCode = PulseCode::new(Level::High, 32, Level::Low, 68);
+
+/// 1 bit: 0.8 µs high, 0.45 µs low
+const ONE: PulseCode = PulseCode::new(Level::High, 64, Level::Low, 36);
+
+/// 2 x 150 µs low after the pixels so the LEDs latch, long enough for the WS2812B-V5 as well
+const RESET: PulseCode = PulseCode::new(Level::Low, 12_000, Level::Low, 12_000);
+
+/// One pulse code per data bit of an RGBW frame, the reset and the end marker
+const MAX_PULSES: usize = TOTAL_NEOPIXEL_LENGTH * 4 * 8 + 2;
+
+/// What main hands to the neopixel task, the channel is set up on the core that runs the task so
+/// its interrupt ends up there
+pub struct RmtOutput {
+    pub rmt: RMT<'static>,
+    pub pin: AnyPin<'static>,
+}
+
+pub struct Ws2812Rmt {
+    channel: Channel<'static, Async, Tx>,
+    /// Static, an RGBW frame of two panels takes 64 kB of pulse codes
+    pulses: &'static mut [PulseCode; MAX_PULSES],
+    chipset: LedChipset,
+    color_order: ColorOrder,
+}
+
+impl Ws2812Rmt {
+    pub fn new(output: RmtOutput) -> Result<Self> {
+        let rmt = Rmt::new(output.rmt, Rate::from_mhz(RMT_CLOCK_MHZ))
+            .map_err(|e| error_with_location!("Failed to initialize RMT: {:?}", e))?
+            .into_async();
+        let config = TxChannelConfig::default()
+            .with_clk_divider(1)
+            .with_idle_output_level(Level::Low)
+            .with_idle_output(true);
+        let channel = rmt
+            .channel0
+            .configure_tx(output.pin, config)
+            .map_err(|e| error_with_location!("Failed to configure RMT channel: {:?}", e))?;
+
+        // like static_cell_init!, panics if a second driver is created, but initialized at
+        // compile time: passing the 64 kB array to init would copy it through the stack
+        static PULSES: ConstStaticCell<[PulseCode; MAX_PULSES]> =
+            ConstStaticCell::new([PulseCode::end_marker(); MAX_PULSES]);
+        let pulses = PULSES.take();
+
+        Ok(Self {
+            channel,
+            pulses,
+            chipset: LedChipset::Ws2812,
+            color_order: ColorOrder::Grb,
+        })
+    }
+}
+
+impl LedDriver for Ws2812Rmt {
+    type Error = esp_hal::rmt::Error;
+
+    fn set_format(&mut self, chipset: LedChipset, color_order: ColorOrder) {
+        self.chipset = chipset;
+        self.color_order = color_order;
+    }
+
+    async fn write_async(&mut self, pixels: &[RGB8]) -> Result<(), Self::Error> {
+        let mut len = 0;
+        for byte in wire_bytes(pixels, self.chipset, self.color_order) {
+            for bit in (0..8).rev() {
+                self.pulses[len] = if byte & (1 << bit) != 0 { ONE } else { ZERO };
+                len += 1;
+            }
+        }
+        self.pulses[len] = RESET;
+        self.pulses[len + 1] = PulseCode::end_marker();
+        // far more codes than the channel RAM holds, so this relies on the driver refilling it
+        // while sending. Not yet checked on hardware, an error here is logged by the task.
+        self.channel.transmit(&self.pulses[..len + 2]).await
+    }
+}